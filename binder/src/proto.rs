@@ -24,6 +24,7 @@ pub enum ECallCommand {
     FinalizeEnclave,
     RunTest,
     Raw,
+    RepairDatabase,
     Unimplemented,
 }
 
@@ -36,6 +37,7 @@ impl From<u32> for ECallCommand {
             0x0000_1002 => ECallCommand::FinalizeEnclave,
             0x0000_1003 => ECallCommand::RunTest,
             0x0000_1004 => ECallCommand::Raw,
+            0x0000_1005 => ECallCommand::RepairDatabase,
             _ => ECallCommand::Unimplemented,
         }
     }
@@ -50,6 +52,7 @@ impl From<ECallCommand> for u32 {
             ECallCommand::FinalizeEnclave => 0x0000_1002,
             ECallCommand::RunTest => 0x0000_1003,
             ECallCommand::Raw => 0x0000_1004,
+            ECallCommand::RepairDatabase => 0x0000_1005,
             ECallCommand::Unimplemented => 0xffff_ffff,
         }
     }
@@ -69,6 +72,26 @@ impl StartServiceInput {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StartServiceOutput;
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RepairDatabaseInput {
+    pub config: teaclave_config::RuntimeConfig,
+}
+
+impl RepairDatabaseInput {
+    pub fn new(config: teaclave_config::RuntimeConfig) -> Self {
+        Self { config }
+    }
+}
+
+/// What was kept by a database repair, and what was lost.
+#[derive(Default, Serialize, Deserialize, Debug)]
+pub struct RepairDatabaseOutput {
+    pub tables: usize,
+    pub entries: usize,
+    pub corrupted_records: usize,
+    pub lost_files: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct InitEnclaveInput;
 
@@ -46,6 +46,21 @@ start_storage_server() {
   wait_port 6789
 }
 
+# A second storage service following the one on port 17778, listening on 17788
+start_storage_follower() {
+  mkdir -p storage_follower
+  pushd storage_follower
+  rm -rf mock_db
+  ln -sf ../teaclave_storage_service ../teaclave_storage_service_enclave.signed.so \
+    ../enclave_info.toml ../auditors .
+  sed -e '/^storage /s/17778/17788/g' \
+      -e 's/^# primary_address/primary_address/' \
+      ../runtime.config.toml > runtime.config.toml
+  ./teaclave_storage_service &
+  popd
+  wait_port 17788
+}
+
 run_unit_tests() {
   trap cleanup INT TERM ERR
 
@@ -117,6 +132,7 @@ run_functional_tests() {
   ./teaclave_storage_service &
   ./teaclave_access_control_service &
   wait_port 7776 17776 17778 17779 # wait for access control, authentication, storage service
+  start_storage_follower
   ./teaclave_management_service &
   ./teaclave_scheduler_service &
   wait_port 17777 17780 # wait for management service and scheduler_service
@@ -204,8 +204,13 @@ pub struct Cache<T> {
     map: HashMap<CacheKey, CacheEntry<T>>,
     cap: usize,
     id: u64,
+    hits: u64,
+    misses: u64,
 }
 
+// The list nodes are owned by the cache, and only reached through it.
+unsafe impl<T: Send> Send for Cache<T> {}
+
 impl<T> Cache<T> {
     pub fn new(capacity: usize) -> Cache<T> {
         assert!(capacity > 0);
@@ -214,6 +219,8 @@ impl<T> Cache<T> {
             map: HashMap::with_capacity(1024),
             cap: capacity,
             id: 0,
+            hits: 0,
+            misses: 0,
         }
     }
 
@@ -234,11 +241,18 @@ impl<T> Cache<T> {
         return self.cap;
     }
 
+    /// How many lookups found their element, and how many did not.
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
     /// Insert a new element into the cache. The returned `CacheHandle` can be used for further
     /// operations on that element.
     /// If the capacity has been reached, the least recently used element is removed from the
-    /// cache.
+    /// cache. An element which is already cached under the key is replaced, as readers may cache
+    /// the same block concurrently.
     pub fn insert(&mut self, key: &CacheKey, elem: T) {
+        self.remove(key);
         if self.list.count() >= self.cap {
             if let Some(removed_key) = self.list.remove_last() {
                 assert!(self.map.remove(&removed_key).is_some());
@@ -255,8 +269,12 @@ impl<T> Cache<T> {
     /// If the element has been preempted from the cache in the meantime, this returns None.
     pub fn get<'a>(&'a mut self, key: &CacheKey) -> Option<&'a T> {
         match self.map.get(key) {
-            None => None,
+            None => {
+                self.misses += 1;
+                None
+            }
             Some(&(ref elem, ref lru_handle)) => {
+                self.hits += 1;
                 self.list.reinsert_front(*lru_handle);
                 Some(elem)
             }
@@ -285,6 +303,7 @@ pub mod tests {
         run_tests!(
             test_blockcache_cache_add_rm,
             test_blockcache_cache_capacity,
+            test_blockcache_cache_insert_twice,
             test_blockcache_lru_remove,
             test_blockcache_lru_1,
             test_blockcache_lru_reinsert,
@@ -320,6 +339,7 @@ pub mod tests {
         assert_eq!(cache.remove(&h_521), Some(521));
         assert_eq!(cache.get(&h_521), None);
         assert_eq!(cache.remove(&h_521), None);
+        assert_eq!(cache.hits_and_misses(), (2, 1));
 
         assert_eq!(cache.count(), 4);
     }
@@ -348,6 +368,27 @@ pub mod tests {
         assert_eq!(cache.get(&h_899), Some(&899));
     }
 
+    fn test_blockcache_cache_insert_twice() {
+        let mut cache = Cache::new(2);
+
+        let h_123 = make_key(1, 2, 3);
+        let h_521 = make_key(1, 2, 4);
+        let h_372 = make_key(3, 4, 5);
+
+        cache.insert(&h_123, 123);
+        cache.insert(&h_123, 124);
+        assert_eq!(cache.count(), 1);
+        assert_eq!(cache.get(&h_123), Some(&124));
+
+        // The replaced element leaves nothing behind to be evicted.
+        cache.insert(&h_521, 521);
+        cache.insert(&h_372, 372);
+        assert_eq!(cache.count(), 2);
+        assert_eq!(cache.get(&h_123), None);
+        assert_eq!(cache.get(&h_521), Some(&521));
+        assert_eq!(cache.get(&h_372), Some(&372));
+    }
+
     fn test_blockcache_lru_remove() {
         let mut lru = LRUList::<usize>::new();
 
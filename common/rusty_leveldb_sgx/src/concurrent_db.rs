@@ -0,0 +1,653 @@
+//! concurrent_db contains a handle to a database which can be shared across threads, with memtable
+//! flushes and compactions running in a background thread.
+
+use crate::cmp::{Cmp, InternalKeyCmp};
+use crate::db_impl::DB;
+use crate::db_iter::DBIterator;
+use crate::disk_env::DBPersistKey;
+use crate::error::{Result, Status, StatusCode};
+use crate::key_types::LookupKey;
+use crate::memtable::MemTable;
+use crate::merging_iter::MergingIter;
+use crate::options::Options;
+use crate::snapshot::Snapshot;
+use crate::types::{LdbIterator, SequenceNumber, Shared};
+use crate::version::Version;
+use crate::version_set::VersionSet;
+use crate::write_batch::WriteBatch;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+/// ConcurrentDB is a DB which can be cloned and shared across threads.
+///
+/// Writes are applied one after the other. Reads don't wait for them, as they go to a view of the
+/// memtables and the current version which is published after every change. Memtable flushes and
+/// compactions are run by a background thread, which only holds the database while it picks the
+/// next compaction and when it installs the results.
+#[derive(Clone)]
+pub struct ConcurrentDB {
+    inner: Arc<Inner>,
+    // Stops the background thread once the last handle is dropped
+    _worker: Arc<Worker>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    // Notified when there is background work, and when the background thread is done with it.
+    cond: Condvar,
+    view: Mutex<ReadView>,
+
+    cmp: Arc<Box<dyn Cmp>>,
+    internal_cmp: Arc<Box<dyn Cmp>>,
+    vset: Shared<VersionSet>,
+}
+
+struct State {
+    db: DB,
+    // Whether the background thread is working without holding the state.
+    compacting: bool,
+    closing: bool,
+    // The first error of the background thread, which is returned by all writes after it.
+    bg_error: Option<Status>,
+}
+
+struct Worker {
+    inner: Arc<Inner>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.inner.state.lock().unwrap().closing = true;
+        self.inner.cond.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// ReadView holds what reads need to see a consistent state of the database.
+#[derive(Clone)]
+struct ReadView {
+    mem: Arc<MemTable>,
+    imm: Option<Arc<MemTable>>,
+    // The current version, and a copy of it which is read without locking.
+    current: Shared<Version>,
+    version: Arc<Version>,
+    last_seq: SequenceNumber,
+}
+
+impl ReadView {
+    fn new(db: &DB) -> ReadView {
+        let current = db.current();
+        let version = Arc::new(current.borrow().clone());
+        let (mem, imm) = db.memtables();
+        ReadView {
+            mem,
+            imm,
+            current,
+            version,
+            last_seq: db.last_seq(),
+        }
+    }
+
+    /// update catches up with the database. The version is only copied if it has changed.
+    fn update(&mut self, db: &DB) {
+        let current = db.current();
+        if !Arc::ptr_eq(&current, &self.current) {
+            self.version = Arc::new(current.borrow().clone());
+            self.current = current;
+        }
+        let (mem, imm) = db.memtables();
+        self.mem = mem;
+        self.imm = imm;
+        self.last_seq = db.last_seq();
+    }
+
+    /// get works like DB::get_at(), but doesn't record seeks to trigger compactions.
+    fn get(&self, seq: SequenceNumber, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let lkey = LookupKey::new(key, seq);
+        for mem in Some(&self.mem).into_iter().chain(self.imm.as_ref()) {
+            match mem.get(&lkey) {
+                (Some(v), _) => return Ok(Some(v)),
+                // deleted entry
+                (None, true) => return Ok(None),
+                // not found entry
+                (None, false) => {}
+            }
+        }
+        if let Ok(Some((v, _))) = self.version.get(lkey.internal_key()) {
+            return Ok(Some(v));
+        }
+        Ok(None)
+    }
+
+    fn new_iter(&self, inner: &Inner, ss: Snapshot) -> Result<DBIterator> {
+        let mut iters: Vec<Box<dyn LdbIterator>> = vec![];
+        if self.mem.len() > 0 {
+            iters.push(Box::new(self.mem.iter()));
+        }
+        if let Some(ref imm) = self.imm {
+            if imm.len() > 0 {
+                iters.push(Box::new(imm.iter()));
+            }
+        }
+        iters.extend(self.version.new_iters()?);
+
+        Ok(DBIterator::new(
+            inner.cmp.clone(),
+            inner.vset.clone(),
+            MergingIter::new(inner.internal_cmp.clone(), iters),
+            ss,
+        ))
+    }
+}
+
+impl ConcurrentDB {
+    /// Opens or creates a database like DB::open(), and starts its background thread.
+    pub fn open<P: AsRef<Path>>(name: P, opt: Options) -> Result<ConcurrentDB> {
+        let cmp = opt.cmp.clone();
+        let mut db = DB::open(name, opt)?;
+        db.set_background_compaction();
+
+        let inner = Arc::new(Inner {
+            view: Mutex::new(ReadView::new(&db)),
+            cond: Condvar::new(),
+            internal_cmp: Arc::new(Box::new(InternalKeyCmp(cmp.clone()))),
+            cmp,
+            vset: db.version_set(),
+            state: Mutex::new(State {
+                db,
+                compacting: false,
+                closing: false,
+                bg_error: None,
+            }),
+        });
+        let thread = {
+            let inner = inner.clone();
+            thread::Builder::new()
+                .name("leveldb-compaction".to_string())
+                .spawn(move || inner.run_background_work())?
+        };
+        // There may be work left from recovery.
+        inner.cond.notify_all();
+
+        Ok(ConcurrentDB {
+            _worker: Arc::new(Worker {
+                inner: inner.clone(),
+                thread: Some(thread),
+            }),
+            inner,
+        })
+    }
+
+    // WRITE //
+
+    /// Adds a single entry, see DB::put().
+    pub fn put(&self, k: &[u8], v: &[u8]) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.put(k, v);
+        self.write(wb, false)
+    }
+
+    /// Deletes a single entry, see DB::delete().
+    pub fn delete(&self, k: &[u8]) -> Result<()> {
+        let mut wb = WriteBatch::new();
+        wb.delete(k);
+        self.write(wb, false)
+    }
+
+    /// Writes an entire WriteBatch, see DB::write(). It waits if the memtable is full while the
+    /// background thread is still flushing the previous one.
+    pub fn write(&self, batch: WriteBatch, sync: bool) -> Result<()> {
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if let Some(ref e) = state.bg_error {
+                return Err(e.clone());
+            }
+            if !state.db.write_stalled() {
+                break;
+            }
+            state = self.inner.cond.wait(state).unwrap();
+        }
+
+        state.db.write(batch, sync)?;
+        self.inner.publish(&state.db);
+        if state.db.needs_background_work() {
+            self.inner.cond.notify_all();
+        }
+        Ok(())
+    }
+
+    /// See DB::record_writes().
+    pub fn record_writes(&self) {
+        self.inner.state.lock().unwrap().db.record_writes()
+    }
+
+    /// See DB::take_recorded_writes().
+    pub fn take_recorded_writes(&self) -> Vec<Vec<u8>> {
+        self.inner.state.lock().unwrap().db.take_recorded_writes()
+    }
+
+    /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
+    pub fn flush(&self) -> Result<()> {
+        self.inner.state.lock().unwrap().db.flush()
+    }
+
+    // READ //
+
+    /// get_at reads the value for a given key at or before snapshot. It returns Ok(None) if the
+    /// entry wasn't found, and Err(_) if an error occurred.
+    pub fn get_at(&self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.view().get(snapshot.sequence(), key)
+    }
+
+    /// get is a simplified version of get_at(), translating errors to None.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let view = self.inner.view();
+        view.get(view.last_seq, key).unwrap_or(None)
+    }
+
+    /// Returns a snapshot at the current state, see DB::get_snapshot().
+    pub fn get_snapshot(&self) -> Snapshot {
+        self.inner.state.lock().unwrap().db.get_snapshot()
+    }
+
+    /// new_iter returns a DBIterator over the current state of the database.
+    pub fn new_iter(&self) -> Result<DBIterator> {
+        // The view is taken along with the snapshot, so that it contains all of its entries.
+        let (ss, view) = {
+            let mut state = self.inner.state.lock().unwrap();
+            (state.db.get_snapshot(), self.inner.view())
+        };
+        view.new_iter(&self.inner, ss)
+    }
+
+    /// new_iter_at returns a DBIterator at the supplied snapshot.
+    pub fn new_iter_at(&self, ss: Snapshot) -> Result<DBIterator> {
+        self.inner.view().new_iter(&self.inner, ss)
+    }
+
+    // STATISTICS //
+
+    /// See DB::get_property().
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        self.inner.state.lock().unwrap().db.get_property(name)
+    }
+
+    /// See DB::get_approximate_sizes().
+    pub fn get_approximate_sizes(&self, ranges: &[(&[u8], &[u8])]) -> Vec<usize> {
+        self.inner
+            .state
+            .lock()
+            .unwrap()
+            .db
+            .get_approximate_sizes(ranges)
+    }
+
+    // MAINTENANCE //
+
+    /// compact_range compacts the specified key range right away, see DB::compact_range().
+    pub fn compact_range(&self, from: &[u8], to: &[u8]) -> Result<()> {
+        let mut state = self.inner.lock_idle();
+        let r = state.db.compact_range(from, to);
+        self.inner.publish(&state.db);
+        r
+    }
+
+    /// rotate_key makes `key` the current encryption key, see DB::rotate_key(). The tables under
+    /// earlier keys are rewritten by the background thread.
+    pub fn rotate_key(&self, key: DBPersistKey) -> Result<()> {
+        let mut state = self.inner.lock_idle();
+        let r = state.db.rotate_key(key);
+        self.inner.publish(&state.db);
+        self.inner.cond.notify_all();
+        r
+    }
+
+    /// See DB::compact_old_key_tables().
+    pub fn compact_old_key_tables(&self, max: usize) -> Result<usize> {
+        let mut state = self.inner.lock_idle();
+        let r = state.db.compact_old_key_tables(max);
+        self.inner.publish(&state.db);
+        r
+    }
+}
+
+impl Inner {
+    fn view(&self) -> ReadView {
+        self.view.lock().unwrap().clone()
+    }
+
+    /// publish updates the view of the reads after a change, with the state held.
+    fn publish(&self, db: &DB) {
+        self.view.lock().unwrap().update(db);
+    }
+
+    /// lock_idle waits until the background thread is done with its work, so that the caller can
+    /// compact without it. It doesn't start any other work while the state is held.
+    fn lock_idle(&self) -> MutexGuard<State> {
+        let mut state = self.state.lock().unwrap();
+        while state.compacting {
+            state = self.cond.wait(state).unwrap();
+        }
+        state
+    }
+
+    fn run_background_work(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.closing {
+            let mut work = None;
+            if state.bg_error.is_none() && state.db.needs_background_work() {
+                match state.db.start_background_work() {
+                    Ok(w) => work = w,
+                    Err(e) => {
+                        state.bg_error = Some(e);
+                        self.cond.notify_all();
+                    }
+                }
+                // Trivial moves are done when picking the work.
+                self.publish(&state.db);
+            }
+            let work = match work {
+                Some(work) => work,
+                None => {
+                    state = self.cond.wait(state).unwrap();
+                    continue;
+                }
+            };
+
+            state.compacting = true;
+            drop(state);
+            // A panic must not leave the writers waiting for work which never finishes.
+            let finished = panic::catch_unwind(AssertUnwindSafe(|| work.run()));
+
+            state = self.state.lock().unwrap();
+            state.compacting = false;
+            let finished = match finished {
+                Ok(finished) => state.db.finish_background_work(finished),
+                Err(_) => Err(Status::new(StatusCode::Unknown, "background work panicked")),
+            };
+            if let Err(e) = finished {
+                state.bg_error = Some(e);
+            }
+            self.publish(&state.db);
+            self.cond.notify_all();
+        }
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::options;
+    use crate::test_util::LdbIteratorIter;
+    use crate::types::NUM_LEVELS;
+
+    use std::time::Duration;
+
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_concurrent_db_put_get,
+            test_concurrent_db_background_compaction,
+            test_concurrent_db_concurrent_reads_and_writes,
+            test_concurrent_db_snapshot,
+            test_concurrent_db_compact_range,
+            test_concurrent_db_reopen,
+            test_concurrent_db_stress,
+        )
+    }
+
+    fn wait_for_background_work(db: &ConcurrentDB) {
+        let mut state = db.inner.state.lock().unwrap();
+        while state.compacting || (state.bg_error.is_none() && state.db.needs_background_work()) {
+            state = db
+                .inner
+                .cond
+                .wait_timeout(state, Duration::from_millis(10))
+                .unwrap()
+                .0;
+        }
+        assert!(state.bg_error.is_none());
+    }
+
+    fn num_files(db: &ConcurrentDB) -> usize {
+        let current = db.inner.vset.borrow().current();
+        let current = current.borrow();
+        (0..NUM_LEVELS).map(|l| current.num_level_files(l)).sum()
+    }
+
+    fn key(i: usize) -> Vec<u8> {
+        format!("key{:06}", i).into_bytes()
+    }
+
+    fn test_concurrent_db_put_get() {
+        let db = ConcurrentDB::open("db", options::for_test()).unwrap();
+        db.put(b"abc", b"def").unwrap();
+        db.put(b"abd", b"deg").unwrap();
+        db.delete(b"abc").unwrap();
+
+        assert_eq!(None, db.get(b"abc"));
+        assert_eq!(Some(b"deg".to_vec()), db.get(b"abd"));
+        assert_eq!(
+            vec![(b"abd".to_vec(), b"deg".to_vec())],
+            LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).collect::<Vec<_>>()
+        );
+        db.flush().unwrap();
+    }
+
+    fn test_concurrent_db_background_compaction() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        let db = ConcurrentDB::open("db", opt).unwrap();
+
+        for i in 0..2000 {
+            db.put(&key(i), b"value").unwrap();
+        }
+        wait_for_background_work(&db);
+
+        assert!(num_files(&db) > 0);
+        for i in 0..2000 {
+            assert_eq!(Some(b"value".to_vec()), db.get(&key(i)));
+        }
+        assert_eq!(
+            2000,
+            LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).count()
+        );
+    }
+
+    fn test_concurrent_db_concurrent_reads_and_writes() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        let db = ConcurrentDB::open("db", opt).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        db.put(&key(w * 500 + i), b"value").unwrap();
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        // A key is never lost once it was seen.
+                        let seen: Vec<_> = LdbIteratorIter::wrap(&mut db.new_iter().unwrap())
+                            .map(|(k, _)| k)
+                            .collect();
+                        for k in seen {
+                            assert_eq!(Some(b"value".to_vec()), db.get(&k));
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in writers.into_iter().chain(readers) {
+            t.join().unwrap();
+        }
+
+        wait_for_background_work(&db);
+        assert!(num_files(&db) > 0);
+        for i in 0..2000 {
+            assert_eq!(Some(b"value".to_vec()), db.get(&key(i)));
+        }
+    }
+
+    fn test_concurrent_db_snapshot() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        let db = ConcurrentDB::open("db", opt).unwrap();
+
+        for i in 0..500 {
+            db.put(&key(i), b"old").unwrap();
+        }
+        let ss = db.get_snapshot();
+        let mut iter = db.new_iter().unwrap();
+        for i in 0..500 {
+            db.put(&key(i), b"new").unwrap();
+        }
+        wait_for_background_work(&db);
+
+        assert_eq!(Some(b"old".to_vec()), db.get_at(&ss, &key(7)).unwrap());
+        assert_eq!(Some(b"new".to_vec()), db.get(&key(7)));
+        // The iterator keeps the tables it reads, even if they were compacted meanwhile.
+        let entries: Vec<_> = LdbIteratorIter::wrap(&mut iter).collect();
+        assert_eq!(500, entries.len());
+        assert!(entries.iter().all(|(_, v)| v == b"old"));
+        assert_eq!(
+            500,
+            LdbIteratorIter::wrap(&mut db.new_iter_at(ss).unwrap()).count()
+        );
+    }
+
+    fn test_concurrent_db_compact_range() {
+        let db = ConcurrentDB::open("db", options::for_test()).unwrap();
+        for i in 0..100 {
+            db.put(&key(i), b"value").unwrap();
+        }
+        db.compact_range(&key(0), &key(100)).unwrap();
+        wait_for_background_work(&db);
+
+        let current = db.inner.vset.borrow().current();
+        assert_eq!(0, current.borrow().num_level_files(0));
+        assert!(num_files(&db) > 0);
+        assert_eq!(Some(b"value".to_vec()), db.get(&key(42)));
+        assert_eq!(
+            Some("0".to_string()),
+            db.get_property("leveldb.num-files-at-level0")
+        );
+        let sizes = db.get_approximate_sizes(&[(&key(0), &key(100))]);
+        assert!(sizes[0] > 0);
+    }
+
+    fn test_concurrent_db_reopen() {
+        let mut opt = options::for_test();
+        opt.write_buffer_size = 1024;
+        {
+            let db = ConcurrentDB::open("db", opt.clone()).unwrap();
+            for i in 0..1000 {
+                db.put(&key(i), b"value").unwrap();
+            }
+            db.delete(&key(3)).unwrap();
+            db.flush().unwrap();
+        }
+        {
+            let db = ConcurrentDB::open("db", opt).unwrap();
+            assert_eq!(None, db.get(&key(3)));
+            assert_eq!(Some(b"value".to_vec()), db.get(&key(999)));
+            assert_eq!(
+                999,
+                LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).count()
+            );
+        }
+    }
+
+    // Writes, compactions, a key rotation and reads run at the same time, and the database is
+    // reopened afterwards.
+    fn test_concurrent_db_stress() {
+        let name = "concurrent_stress_db";
+        let (old_key, new_key) = ([5u8; 16], [6u8; 16]);
+        let mut opt = Options::new_disk_db_with(old_key);
+        opt.write_buffer_size = 1024;
+        let env = opt.env.clone();
+        {
+            let db = ConcurrentDB::open(name, opt).unwrap();
+            let writers: Vec<_> = (0..4)
+                .map(|w| {
+                    let db = db.clone();
+                    thread::spawn(move || {
+                        for i in 0..500 {
+                            db.put(&key(w * 500 + i), b"value").unwrap();
+                            if i % 5 == 0 {
+                                db.delete(&key(w * 500 + i)).unwrap();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let compactor = {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        db.compact_range(&key(0), &key(2000)).unwrap();
+                    }
+                })
+            };
+            let rotator = {
+                let db = db.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    db.rotate_key(new_key).unwrap();
+                    db.compact_old_key_tables(usize::MAX).unwrap();
+                })
+            };
+            let reader = {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        let seen: Vec<_> = LdbIteratorIter::wrap(&mut db.new_iter().unwrap())
+                            .map(|(k, _)| k)
+                            .collect();
+                        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+                    }
+                })
+            };
+            let threads = writers.into_iter().chain([compactor, rotator, reader]);
+            for t in threads {
+                t.join().unwrap();
+            }
+            wait_for_background_work(&db);
+            db.flush().unwrap();
+        }
+
+        let mut opt = Options::new_disk_db_with_keys(vec![old_key, new_key]);
+        opt.write_buffer_size = 1024;
+        {
+            let db = ConcurrentDB::open(name, opt).unwrap();
+            for i in 0..2000 {
+                let expected = if i % 5 == 0 {
+                    None
+                } else {
+                    Some(b"value".to_vec())
+                };
+                assert_eq!(expected, db.get(&key(i)));
+            }
+            assert_eq!(
+                1600,
+                LdbIteratorIter::wrap(&mut db.new_iter().unwrap()).count()
+            );
+            assert_eq!(0, db.compact_old_key_tables(usize::MAX).unwrap());
+        }
+        env.rmdir(Path::new(name)).unwrap();
+    }
+}
@@ -6,6 +6,7 @@
 use std::untrusted::path::PathEx;
 
 use crate::db_iter::DBIterator;
+use crate::disk_env::DBPersistKey;
 
 use crate::cmp::{Cmp, InternalKeyCmp};
 use crate::env::{Env, FileLock};
@@ -37,29 +38,37 @@ use std::mem;
 use std::ops::Drop;
 use std::path::Path;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// DB contains the actual database implemenation. As opposed to the original, this implementation
-/// is not concurrent (yet).
+/// is not concurrent; ConcurrentDB wraps it to be shared across threads.
 pub struct DB {
     name: PathBuf,
     path: PathBuf,
     lock: Option<FileLock>,
 
-    internal_cmp: Rc<Box<dyn Cmp>>,
+    internal_cmp: Arc<Box<dyn Cmp>>,
     fpol: InternalFilterPolicy<BoxedFilterPolicy>,
     opt: Options,
 
-    mem: MemTable,
-    imm: Option<MemTable>,
+    // The memtables are shared with the reads of a ConcurrentDB.
+    mem: Arc<MemTable>,
+    imm: Option<Arc<MemTable>>,
 
-    log: Option<LogWriter<BufWriter<Box<dyn Write>>>>,
+    log: Option<LogWriter<BufWriter<Box<dyn Write + Send>>>>,
     log_num: Option<FileNum>,
     cache: Shared<TableCache>,
     vset: Shared<VersionSet>,
     snaps: SnapshotList,
 
     cstats: [CompactionStats; NUM_LEVELS],
+
+    // Serialized batches written since they were last taken, if recording
+    recorded_writes: Option<Vec<Vec<u8>>>,
+    // Whether tables written under an earlier key may be left
+    rekeying: bool,
+    // Whether memtable flushes and compactions are left to a background thread
+    background_compaction: bool,
 }
 
 impl DB {
@@ -78,15 +87,16 @@ impl DB {
 
         let cache = share(TableCache::new(&name, opt.clone(), opt.max_open_files - 10));
         let vset = VersionSet::new(&name, opt.clone(), cache.clone());
+        let rekeying = opt.env.current_key_generation() > 0;
 
         DB {
             name: name.to_owned(),
             path,
             lock: None,
-            internal_cmp: Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
+            internal_cmp: Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone()))),
             fpol: InternalFilterPolicy::new(opt.filter_policy.clone()),
 
-            mem: MemTable::new(opt.cmp.clone()),
+            mem: Arc::new(MemTable::new(opt.cmp.clone())),
             imm: None,
 
             opt,
@@ -98,10 +108,14 @@ impl DB {
             snaps: SnapshotList::new(),
 
             cstats: Default::default(),
+
+            recorded_writes: None,
+            rekeying,
+            background_compaction: false,
         }
     }
 
-    fn current(&self) -> Shared<Version> {
+    pub(crate) fn current(&self) -> Shared<Version> {
         self.vset.borrow().current()
     }
 
@@ -185,6 +199,10 @@ impl DB {
         let mut max_seq = 0;
         let filenames = self.opt.env.children(&self.path)?;
         let mut expected = self.vset.borrow().live_files();
+        let (log_num, prev_log_num) = {
+            let vset = self.vset.borrow();
+            (vset.log_num, vset.prev_log_num)
+        };
         let mut log_files = vec![];
 
         for file in &filenames {
@@ -199,10 +217,7 @@ impl DB {
             match parse_file_name(&file) {
                 Ok((num, typ)) => {
                     expected.remove(&num);
-                    if typ == FileType::Log
-                        && (num >= self.vset.borrow().log_num
-                            || num == self.vset.borrow().prev_log_num)
-                    {
+                    if typ == FileType::Log && (num >= log_num || num == prev_log_num) {
                         log_files.push(num);
                     }
                 }
@@ -247,7 +262,7 @@ impl DB {
         let mut compactions = 0;
         let mut max_seq = 0;
         let mut save_manifest = false;
-        let cmp: Rc<Box<dyn Cmp>> = self.opt.cmp.clone();
+        let cmp: Arc<Box<dyn Cmp>> = self.opt.cmp.clone();
         let mut mem = MemTable::new(cmp.clone());
         {
             let logfile = self.opt.env.open_sequential_file(Path::new(&filename))?;
@@ -275,7 +290,7 @@ impl DB {
                 }
 
                 batch.set_contents(&scratch);
-                batch.insert_into_memtable(batch.sequence(), &mut mem);
+                batch.insert_into_memtable(batch.sequence(), &mem);
 
                 let last_seq = batch.sequence() + batch.count() as u64 - 1;
                 if last_seq > max_seq {
@@ -291,8 +306,14 @@ impl DB {
             }
         }
 
-        // Check if we can reuse the last log file.
-        if self.opt.reuse_logs && is_last && compactions == 0 {
+        // Check if we can reuse the last log file. A log written under an earlier key is not
+        // reused, so that it goes away with the memtable flush.
+        if self.opt.reuse_logs
+            && is_last
+            && compactions == 0
+            && self.opt.env.key_generation(Path::new(&filename))?
+                == self.opt.env.current_key_generation()
+        {
             assert!(self.log.is_none());
             log!(self.opt.log, "reusing log file {:?}", filename);
             let oldsize = self.opt.env.size_of(Path::new(&filename))?;
@@ -300,7 +321,7 @@ impl DB {
             let lw = LogWriter::new_with_off(BufWriter::new(oldfile), oldsize);
             self.log = Some(lw);
             self.log_num = Some(log_num);
-            self.mem = mem;
+            self.mem = Arc::new(mem);
         } else if mem.len() > 0 {
             // Log is not reused, so write out the accumulated memtable.
             save_manifest = true;
@@ -419,15 +440,35 @@ impl DB {
         let log = self.log.as_mut().unwrap();
         let next = self.vset.borrow().last_seq + 1;
 
-        batch.insert_into_memtable(next, &mut self.mem);
-        log.add_record(&batch.encode(next))?;
+        batch.insert_into_memtable(next, &self.mem);
+        let encoded = batch.encode(next);
+        log.add_record(&encoded)?;
         if sync {
             log.flush()?;
         }
         self.vset.borrow_mut().last_seq += entries;
+        if let Some(recorded) = self.recorded_writes.as_mut() {
+            recorded.push(encoded);
+        }
         Ok(())
     }
 
+    /// Keep a copy of every batch written from now on, e.g. to replicate the writes to another
+    /// database. The copies are serialized batches, to be loaded with `WriteBatch::set_contents`.
+    pub fn record_writes(&mut self) {
+        if self.recorded_writes.is_none() {
+            self.recorded_writes = Some(Vec::new());
+        }
+    }
+
+    /// Returns the batches written since the last call, if writes are recorded.
+    pub fn take_recorded_writes(&mut self) -> Vec<Vec<u8>> {
+        match self.recorded_writes.as_mut() {
+            Some(recorded) => std::mem::take(recorded),
+            None => Vec::new(),
+        }
+    }
+
     /// flush makes sure that all pending changes (e.g. from put()) are stored on disk.
     pub fn flush(&mut self) -> Result<()> {
         assert!(self.log.is_some());
@@ -560,6 +601,82 @@ impl DB {
         self.cstats[level].add(cs);
     }
 
+    /// get_property returns the value of a property of the database, or None if it is unknown.
+    /// Like in LevelDB, the properties are:
+    ///
+    /// - "leveldb.num-files-at-level<N>": the number of tables at level N.
+    /// - "leveldb.stats": the tables and compactions of each level, and the hits of the block
+    ///   cache.
+    /// - "leveldb.sstables": the tables of each level with their key ranges.
+    pub fn get_property(&self, name: &str) -> Option<String> {
+        let name = name.strip_prefix("leveldb.")?;
+        let current = self.current();
+        let current = current.borrow();
+        if let Some(level) = name.strip_prefix("num-files-at-level") {
+            let level: usize = level.parse().ok()?;
+            if level >= NUM_LEVELS {
+                return None;
+            }
+            return Some(current.num_level_files(level).to_string());
+        }
+        match name {
+            "stats" => Some(self.stats(&current)),
+            "sstables" => Some(current.sstables_summary()),
+            _ => None,
+        }
+    }
+
+    fn stats(&self, current: &Version) -> String {
+        let mb = |bytes: usize| bytes as f64 / 1048576.0;
+        let mut acc = String::with_capacity(512);
+        acc.push_str("                               Compactions\n");
+        acc.push_str("Level  Files Size(MB) Time(sec) Read(MB) Write(MB)\n");
+        acc.push_str("--------------------------------------------------\n");
+        for level in 0..NUM_LEVELS {
+            let files = current.num_level_files(level);
+            let cs = &self.cstats[level];
+            if files == 0 && cs.micros == 0 {
+                continue;
+            }
+            acc.push_str(&format!(
+                "{:>3} {:>8} {:>8.0} {:>9.0} {:>8.0} {:>9.0}\n",
+                level,
+                files,
+                mb(current.num_level_bytes(level)),
+                cs.micros as f64 / 1e6,
+                mb(cs.read),
+                mb(cs.written)
+            ));
+        }
+        let cache = self.opt.block_cache.borrow();
+        let (hits, misses) = cache.hits_and_misses();
+        acc.push_str(&format!(
+            "Block cache: {} of {} blocks, {} hits, {} misses\n",
+            cache.count(),
+            cache.cap(),
+            hits,
+            misses
+        ));
+        acc
+    }
+
+    /// get_approximate_sizes returns the approximate number of bytes the tables take up for each
+    /// range [start, limit) of user keys. Entries which are only in the memtables are not counted.
+    pub fn get_approximate_sizes(&self, ranges: &[(&[u8], &[u8])]) -> Vec<usize> {
+        let current = self.current();
+        let vset = self.vset.borrow();
+        ranges
+            .iter()
+            .map(|&(start, limit)| {
+                let start = LookupKey::new(start, MAX_SEQUENCE_NUMBER);
+                let limit = LookupKey::new(limit, MAX_SEQUENCE_NUMBER);
+                let start = vset.approximate_offset(&current, start.internal_key());
+                let limit = vset.approximate_offset(&current, limit.internal_key());
+                limit.saturating_sub(start)
+            })
+            .collect()
+    }
+
     /// Trigger a compaction based on where this key is located in the different levels.
     fn record_read_sample<'a>(&mut self, k: InternalKey<'a>) {
         let current = self.current();
@@ -581,7 +698,14 @@ impl DB {
             Ok(())
         } else if self.mem.len() == 0 {
             Ok(())
+        } else if !force && self.imm.is_some() {
+            // The background thread is still flushing the previous memtable, see write_stalled().
+            Ok(())
         } else {
+            if self.imm.is_some() {
+                // Forced while the background thread is idle.
+                self.compact_memtable()?;
+            }
             // Create new memtable.
             let logn = self.vset.borrow_mut().new_file_number();
             let logf = self
@@ -595,8 +719,8 @@ impl DB {
                 self.log = Some(LogWriter::new(BufWriter::new(logf.unwrap())));
                 self.log_num = Some(logn);
 
-                let mut imm = MemTable::new(self.opt.cmp.clone());
-                mem::swap(&mut imm, &mut self.mem);
+                let imm =
+                    mem::replace(&mut self.mem, Arc::new(MemTable::new(self.opt.cmp.clone())));
                 self.imm = Some(imm);
                 self.maybe_do_compaction()
             }
@@ -605,7 +729,9 @@ impl DB {
 
     /// maybe_do_compaction starts a blocking compaction if it makes sense.
     fn maybe_do_compaction(&mut self) -> Result<()> {
-        if self.imm.is_some() {
+        if self.background_compaction {
+            Ok(())
+        } else if self.imm.is_some() {
             self.compact_memtable()
         } else if self.vset.borrow().needs_compaction() {
             let c = self.vset.borrow_mut().pick_compaction();
@@ -614,11 +740,128 @@ impl DB {
             } else {
                 Ok(())
             }
+        } else if self.rekeying {
+            // Rewrite the tables under an earlier key one at a time, when there is nothing else
+            // to compact.
+            match self.old_key_tables()?.into_iter().next() {
+                Some((level, f)) => self.rekey_table(level, f),
+                None => {
+                    self.rekeying = false;
+                    Ok(())
+                }
+            }
         } else {
             Ok(())
         }
     }
 
+    /// rotate_key makes `key` the current encryption key of the database. New logs, manifests and
+    /// tables are written under it right away, and the tables written under earlier keys are
+    /// rewritten by later compactions; compact_old_key_tables() does so immediately. The earlier
+    /// keys have to be passed to Options::new_disk_db_with_keys() until no such table is left.
+    pub fn rotate_key(&mut self, key: DBPersistKey) -> Result<()> {
+        self.opt.env.add_key(key)?;
+        self.vset.borrow_mut().roll_manifest();
+        if self.mem.len() > 0 {
+            // Switches to a new log, and flushes the memtable with a new manifest.
+            self.make_room_for_write(true)?;
+        } else {
+            let logn = self.vset.borrow_mut().new_file_number();
+            let logf = self
+                .opt
+                .env
+                .open_writable_file(Path::new(&log_file_name(&self.path, logn)))?;
+            self.log = Some(LogWriter::new(BufWriter::new(logf)));
+            self.log_num = Some(logn);
+
+            let mut ve = VersionEdit::new();
+            ve.set_log_num(logn);
+            self.vset.borrow_mut().log_and_apply(ve)?;
+            self.delete_obsolete_files()?;
+        }
+        self.rekeying = true;
+        Ok(())
+    }
+
+    /// compact_old_key_tables rewrites up to `max` tables which are written under an earlier key,
+    /// and returns how many of them are left.
+    pub fn compact_old_key_tables(&mut self, max: usize) -> Result<usize> {
+        for _ in 0..max {
+            match self.old_key_tables()?.into_iter().next() {
+                Some((level, f)) => self.rekey_table(level, f)?,
+                None => break,
+            }
+        }
+        let left = self.old_key_tables()?.len();
+        self.rekeying = left > 0;
+        Ok(left)
+    }
+
+    /// old_key_tables returns the live tables written under an earlier key, with their levels.
+    fn old_key_tables(&self) -> Result<Vec<(usize, FileMetaData)>> {
+        let current_generation = self.opt.env.current_key_generation();
+        let mut tables = vec![];
+        if current_generation == 0 {
+            return Ok(tables);
+        }
+        let current = self.current();
+        let current = current.borrow();
+        for (level, files) in current.files.iter().enumerate() {
+            for f in files {
+                let f = f.borrow();
+                let name = table_file_name(&self.path, f.num);
+                if self.opt.env.key_generation(Path::new(&name))? < current_generation {
+                    tables.push((level, f.clone()));
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    /// rekey_table rewrites a table under the current key at the same level. Level-0 tables are
+    /// compacted into level 1 instead, as the order of overlapping level-0 tables is given by
+    /// their file numbers.
+    fn rekey_table(&mut self, level: usize, f: FileMetaData) -> Result<()> {
+        if level == 0 {
+            let c = self
+                .vset
+                .borrow_mut()
+                .compact_range(0, &f.smallest, &f.largest);
+            return match c {
+                Some(c) => self.start_compaction(c),
+                None => Ok(()),
+            };
+        }
+
+        let num = self.vset.borrow_mut().new_file_number();
+        let (fmd, stats) = self.compaction_context().rewrite_table(&f, num)?;
+        self.install_rewritten_table(level, f, fmd, stats)
+    }
+
+    /// install_rewritten_table replaces a table with its copy written by rewrite_table().
+    fn install_rewritten_table(
+        &mut self,
+        level: usize,
+        f: FileMetaData,
+        fmd: FileMetaData,
+        stats: CompactionStats,
+    ) -> Result<()> {
+        log!(
+            self.opt.log,
+            "Rewrote table {:06} as {:06} under the current key at L{}",
+            f.num,
+            fmd.num,
+            level
+        );
+        self.add_stats(level, stats);
+
+        let mut ve = VersionEdit::new();
+        ve.delete_file(level, f.num);
+        ve.add_file(level, fmd);
+        self.vset.borrow_mut().log_and_apply(ve)?;
+        self.delete_obsolete_files()
+    }
+
     /// compact_range triggers an immediate compaction on the specified key range. Repeatedly
     /// calling this without actually adding new keys is not useful.
     ///
@@ -638,6 +881,10 @@ impl DB {
 
         // Compact memtable.
         self.make_room_for_write(true)?;
+        if self.imm.is_some() {
+            // Not flushed by make_room_for_write() with background compaction.
+            self.compact_memtable()?;
+        }
 
         let mut ifrom = LookupKey::new(from, MAX_SEQUENCE_NUMBER)
             .internal_key()
@@ -689,46 +936,72 @@ impl DB {
                     level,
                     level + 1
                 );
-                log!(
-                    self.opt.log,
-                    "Summary: {}",
-                    self.vset.borrow().current_summary()
-                );
+                let summary = self.vset.borrow().current_summary();
+                log!(self.opt.log, "Summary: {}", summary);
                 Ok(())
             }
         } else {
-            let smallest = if self.snaps.empty() {
-                self.vset.borrow().last_seq
-            } else {
-                self.snaps.oldest()
-            };
-            let mut state = CompactionState::new(compaction, smallest);
-            if let Err(e) = self.do_compaction_work(&mut state) {
+            let mut state = CompactionState::new(compaction, self.smallest_snapshot());
+            let r = self.compaction_context().do_compaction_work(&mut state);
+            self.finish_compaction(state, r)
+        }
+    }
+
+    /// smallest_snapshot returns the oldest sequence number which may still be read.
+    fn smallest_snapshot(&self) -> SequenceNumber {
+        if self.snaps.empty() {
+            self.vset.borrow().last_seq
+        } else {
+            self.snaps.oldest()
+        }
+    }
+
+    /// finish_compaction installs the results of do_compaction_work(), or cleans up after it.
+    fn finish_compaction(
+        &mut self,
+        mut state: CompactionState,
+        r: Result<CompactionStats>,
+    ) -> Result<()> {
+        match r {
+            Ok(stats) => self.add_stats(state.compaction.level(), stats),
+            Err(e) => {
                 state.cleanup(&self.opt.env, &self.path);
                 log!(self.opt.log, "Compaction work failed: {}", e);
             }
-            self.install_compaction_results(state)?;
-            log!(
-                self.opt.log,
-                "Compaction finished: {}",
-                self.vset.borrow().current_summary()
-            );
-
-            self.delete_obsolete_files()
         }
+        self.install_compaction_results(state)?;
+        let summary = self.vset.borrow().current_summary();
+        log!(self.opt.log, "Compaction finished: {}", summary);
+
+        self.delete_obsolete_files()
     }
 
     fn compact_memtable(&mut self) -> Result<()> {
         assert!(self.imm.is_some());
 
-        let mut ve = VersionEdit::new();
-        let base = self.current();
-
+        let num = self.vset.borrow_mut().new_file_number();
         let imm = self.imm.take().unwrap();
-        if let Err(e) = self.write_l0_table(&imm, &mut ve, Some(&base.borrow())) {
-            self.imm = Some(imm);
-            return Err(e);
+        match self.compaction_context().build_l0_table(&imm, num) {
+            Ok((fmd, stats)) => self.install_memtable_table(num, fmd, stats),
+            Err(e) => {
+                self.vset.borrow_mut().reuse_file_number(num);
+                self.imm = Some(imm);
+                Err(e)
+            }
         }
+    }
+
+    /// install_memtable_table adds the table written from the immutable memtable to the current
+    /// version, which makes the old log obsolete.
+    fn install_memtable_table(
+        &mut self,
+        num: FileNum,
+        fmd: FileMetaData,
+        stats: CompactionStats,
+    ) -> Result<()> {
+        let mut ve = VersionEdit::new();
+        let base = self.current();
+        self.add_l0_table(num, fmd, stats, &mut ve, Some(&base));
         ve.set_log_num(self.log_num.unwrap_or(0));
         self.vset.borrow_mut().log_and_apply(ve)?;
         if let Err(e) = self.delete_obsolete_files() {
@@ -742,18 +1015,97 @@ impl DB {
         &mut self,
         memt: &MemTable,
         ve: &mut VersionEdit,
-        base: Option<&Version>,
+        base: Option<&Shared<Version>>,
     ) -> Result<()> {
-        let start_ts = self.opt.env.micros();
         let num = self.vset.borrow_mut().new_file_number();
+        let (fmd, stats) = self.compaction_context().build_l0_table(memt, num)?;
+        self.add_l0_table(num, fmd, stats, ve, base);
+        Ok(())
+    }
+
+    /// add_l0_table adds a table written by build_l0_table() to the edit. Given a base version, the
+    /// table is placed at a higher level if it doesn't overlap with the tables there.
+    fn add_l0_table(
+        &mut self,
+        num: FileNum,
+        fmd: FileMetaData,
+        stats: CompactionStats,
+        ve: &mut VersionEdit,
+        base: Option<&Shared<Version>>,
+    ) {
+        // Wrote empty table.
+        if fmd.size == 0 {
+            self.vset.borrow_mut().reuse_file_number(num);
+            return;
+        }
+
+        let mut level = 0;
+        if let Some(b) = base {
+            level = b.borrow().pick_memtable_output_level(
+                parse_internal_key(&fmd.smallest).2,
+                parse_internal_key(&fmd.largest).2,
+            );
+        }
+
+        self.add_stats(level, stats);
+        ve.add_file(level, fmd);
+    }
+
+    fn compaction_context(&self) -> CompactionContext {
+        CompactionContext {
+            opt: self.opt.clone(),
+            path: self.path.clone(),
+            vset: self.vset.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+
+    fn install_compaction_results(&mut self, mut cs: CompactionState) -> Result<()> {
+        log!(
+            self.opt.log,
+            "Compacted {} L{} files + {} L{} files => {}B",
+            cs.compaction.num_inputs(0),
+            cs.compaction.level(),
+            cs.compaction.num_inputs(1),
+            cs.compaction.level() + 1,
+            cs.total_bytes
+        );
+        cs.compaction.add_input_deletions();
+        let level = cs.compaction.level();
+        for output in &cs.outputs {
+            cs.compaction.edit().add_file(level + 1, output.clone());
+        }
+        self.vset
+            .borrow_mut()
+            .log_and_apply(cs.compaction.into_edit())
+    }
+}
+
+/// CompactionContext holds what is needed to write tables, so that they can be written without
+/// holding the DB.
+pub(crate) struct CompactionContext {
+    opt: Options,
+    path: PathBuf,
+    vset: Shared<VersionSet>,
+    cache: Shared<TableCache>,
+}
+
+impl CompactionContext {
+    /// build_l0_table writes the given memtable to table file `num`. The returned table is empty
+    /// if the memtable is.
+    fn build_l0_table(
+        &self,
+        memt: &MemTable,
+        num: FileNum,
+    ) -> Result<(FileMetaData, CompactionStats)> {
+        let start_ts = self.opt.env.micros();
         log!(self.opt.log, "Start write of L0 table {:06}", num);
         let fmd = build_table(&self.path, &self.opt, memt.iter(), num)?;
         log!(self.opt.log, "L0 table {:06} has {} bytes", num, fmd.size);
 
-        // Wrote empty table.
+        let mut stats = CompactionStats::default();
         if fmd.size == 0 {
-            self.vset.borrow_mut().reuse_file_number(num);
-            return Ok(());
+            return Ok((fmd, stats));
         }
 
         let cache_result = self.cache.borrow_mut().get_table(num);
@@ -771,25 +1123,29 @@ impl DB {
             return Err(e);
         }
 
-        let mut stats = CompactionStats::default();
         stats.micros = self.opt.env.micros() - start_ts;
         stats.written = fmd.size;
+        Ok((fmd, stats))
+    }
 
-        let mut level = 0;
-        if let Some(b) = base {
-            level = b.pick_memtable_output_level(
-                parse_internal_key(&fmd.smallest).2,
-                parse_internal_key(&fmd.largest).2,
-            );
-        }
-
-        self.add_stats(level, stats);
-        ve.add_file(level, fmd);
+    /// rewrite_table copies a table to table file `num`, under the current key.
+    fn rewrite_table(
+        &self,
+        f: &FileMetaData,
+        num: FileNum,
+    ) -> Result<(FileMetaData, CompactionStats)> {
+        let start_ts = self.opt.env.micros();
+        let table = self.cache.borrow_mut().get_table(f.num)?;
+        let fmd = build_table(&self.path, &self.opt, table.iter(), num)?;
 
-        Ok(())
+        let mut stats = CompactionStats::default();
+        stats.micros = self.opt.env.micros() - start_ts;
+        stats.read = f.size;
+        stats.written = fmd.size;
+        Ok((fmd, stats))
     }
 
-    fn do_compaction_work(&mut self, cs: &mut CompactionState) -> Result<()> {
+    fn do_compaction_work(&self, cs: &mut CompactionState) -> Result<CompactionStats> {
         {
             let current = self.vset.borrow().current();
             assert!(current.borrow().num_level_files(cs.compaction.level()) > 0);
@@ -896,15 +1252,10 @@ impl DB {
         for output in &cs.outputs {
             stats.written += output.size;
         }
-        self.cstats[cs.compaction.level()].add(stats);
-        Ok(())
+        Ok(stats)
     }
 
-    fn finish_compaction_output(
-        &mut self,
-        cs: &mut CompactionState,
-        largest: Vec<u8>,
-    ) -> Result<()> {
+    fn finish_compaction_output(&self, cs: &mut CompactionState, largest: Vec<u8>) -> Result<()> {
         assert!(cs.builder.is_some());
         let output_num = cs.current_output().num;
         assert!(output_num > 0);
@@ -938,25 +1289,146 @@ impl DB {
         }
         Ok(())
     }
+}
 
-    fn install_compaction_results(&mut self, mut cs: CompactionState) -> Result<()> {
-        log!(
-            self.opt.log,
-            "Compacted {} L{} files + {} L{} files => {}B",
-            cs.compaction.num_inputs(0),
-            cs.compaction.level(),
-            cs.compaction.num_inputs(1),
-            cs.compaction.level() + 1,
-            cs.total_bytes
-        );
-        cs.compaction.add_input_deletions();
-        let level = cs.compaction.level();
-        for output in &cs.outputs {
-            cs.compaction.edit().add_file(level + 1, output.clone());
+impl DB {
+    // BACKGROUND WORK //
+
+    /// set_background_compaction leaves memtable flushes and compactions to a background thread,
+    /// which runs them with start_background_work() and finish_background_work(). Operations
+    /// doing compactions themselves, like compact_range(), must not run while it is busy.
+    pub(crate) fn set_background_compaction(&mut self) {
+        self.background_compaction = true;
+    }
+
+    /// needs_background_work returns whether there is a memtable to flush or tables to compact.
+    pub(crate) fn needs_background_work(&self) -> bool {
+        self.imm.is_some() || self.rekeying || self.vset.borrow().needs_compaction()
+    }
+
+    /// write_stalled returns whether writes have to wait until the background thread has flushed
+    /// the immutable memtable.
+    pub(crate) fn write_stalled(&self) -> bool {
+        self.imm.is_some() && self.mem.approx_mem_usage() >= self.opt.write_buffer_size
+    }
+
+    /// start_background_work picks the next memtable flush or compaction. Trivial moves only
+    /// change the manifest, so they are done right away.
+    pub(crate) fn start_background_work(&mut self) -> Result<Option<BackgroundWork>> {
+        if let Some(ref imm) = self.imm {
+            let num = self.vset.borrow_mut().new_file_number();
+            return Ok(Some(BackgroundWork::FlushMemtable(
+                self.compaction_context(),
+                imm.clone(),
+                num,
+            )));
+        }
+
+        loop {
+            let compaction = if self.vset.borrow().needs_compaction() {
+                self.vset.borrow_mut().pick_compaction()
+            } else if self.rekeying {
+                match self.old_key_tables()?.into_iter().next() {
+                    Some((0, f)) => {
+                        self.vset
+                            .borrow_mut()
+                            .compact_range(0, &f.smallest, &f.largest)
+                    }
+                    Some((level, f)) => {
+                        let num = self.vset.borrow_mut().new_file_number();
+                        return Ok(Some(BackgroundWork::RewriteTable(
+                            self.compaction_context(),
+                            level,
+                            f,
+                            num,
+                        )));
+                    }
+                    None => {
+                        self.rekeying = false;
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            match compaction {
+                Some(c) if c.is_trivial_move() => self.start_compaction(c)?,
+                Some(c) => {
+                    let state = CompactionState::new(c, self.smallest_snapshot());
+                    return Ok(Some(BackgroundWork::Compaction(
+                        self.compaction_context(),
+                        state,
+                    )));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// finish_background_work installs the result of a work started by start_background_work().
+    pub(crate) fn finish_background_work(&mut self, work: FinishedWork) -> Result<()> {
+        match work {
+            FinishedWork::FlushMemtable(num, Ok((fmd, stats))) => {
+                self.imm = None;
+                self.install_memtable_table(num, fmd, stats)
+            }
+            FinishedWork::FlushMemtable(num, Err(e)) => {
+                self.vset.borrow_mut().reuse_file_number(num);
+                Err(e)
+            }
+            FinishedWork::Compaction(state, r) => self.finish_compaction(state, r),
+            FinishedWork::RewriteTable(level, f, r) => {
+                let (fmd, stats) = r?;
+                self.install_rewritten_table(level, f, fmd, stats)
+            }
+        }
+    }
+
+    /// memtables returns the memtable and the immutable memtable, for reads which don't hold the
+    /// DB.
+    pub(crate) fn memtables(&self) -> (Arc<MemTable>, Option<Arc<MemTable>>) {
+        (self.mem.clone(), self.imm.clone())
+    }
+
+    pub(crate) fn last_seq(&self) -> SequenceNumber {
+        self.vset.borrow().last_seq
+    }
+
+    pub(crate) fn version_set(&self) -> Shared<VersionSet> {
+        self.vset.clone()
+    }
+}
+
+/// BackgroundWork is a memtable flush or compaction, which runs without holding the DB.
+pub(crate) enum BackgroundWork {
+    FlushMemtable(CompactionContext, Arc<MemTable>, FileNum),
+    Compaction(CompactionContext, CompactionState),
+    RewriteTable(CompactionContext, usize, FileMetaData, FileNum),
+}
+
+/// FinishedWork is the result of a BackgroundWork, to be installed in the DB.
+pub(crate) enum FinishedWork {
+    FlushMemtable(FileNum, Result<(FileMetaData, CompactionStats)>),
+    Compaction(CompactionState, Result<CompactionStats>),
+    RewriteTable(usize, FileMetaData, Result<(FileMetaData, CompactionStats)>),
+}
+
+impl BackgroundWork {
+    pub(crate) fn run(self) -> FinishedWork {
+        match self {
+            BackgroundWork::FlushMemtable(ctx, mem, num) => {
+                FinishedWork::FlushMemtable(num, ctx.build_l0_table(&mem, num))
+            }
+            BackgroundWork::Compaction(ctx, mut state) => {
+                let r = ctx.do_compaction_work(&mut state);
+                FinishedWork::Compaction(state, r)
+            }
+            BackgroundWork::RewriteTable(ctx, level, f, num) => {
+                let r = ctx.rewrite_table(&f, num);
+                FinishedWork::RewriteTable(level, f, r)
+            }
         }
-        self.vset
-            .borrow_mut()
-            .log_and_apply(cs.compaction.into_edit())
     }
 }
 
@@ -966,11 +1438,11 @@ impl Drop for DB {
     }
 }
 
-struct CompactionState {
+pub(crate) struct CompactionState {
     compaction: Compaction,
     smallest_seq: SequenceNumber,
     outputs: Vec<FileMetaData>,
-    builder: Option<TableBuilder<Box<dyn Write>>>,
+    builder: Option<TableBuilder<Box<dyn Write + Send>>>,
     total_bytes: usize,
 }
 
@@ -1000,7 +1472,7 @@ impl CompactionState {
 }
 
 #[derive(Debug, Default)]
-struct CompactionStats {
+pub(crate) struct CompactionStats {
     micros: u64,
     read: usize,
     written: usize,
@@ -1062,17 +1534,17 @@ pub fn build_table<I: LdbIterator, P: AsRef<Path>>(
     Ok(md)
 }
 
-fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
+pub(crate) fn log_file_name(db: &Path, num: FileNum) -> PathBuf {
     db.join(format!("{:06}.log", num))
 }
 
-fn lock_file_name(db: &Path) -> PathBuf {
+pub(crate) fn lock_file_name(db: &Path) -> PathBuf {
     db.join("LOCK")
 }
 
 /// open_info_log opens an info log file in the given database. It transparently returns a
 /// /dev/null logger in case the open fails.
-fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
+pub(crate) fn open_info_log<E: Env + ?Sized, P: AsRef<Path>>(env: &E, db: P) -> Logger {
     let db = db.as_ref();
     let logfilename = db.join("LOG");
     let oldlogfilename = db.join("LOG.old");
@@ -1155,7 +1627,7 @@ pub mod tests {
     use crate::error::Status;
     use crate::key_types::LookupKey;
     use crate::mem_env::MemEnv;
-    use crate::options;
+    use crate::options::{self, CompressionType};
     use crate::test_util::LdbIteratorIter;
     use crate::version::testutil::make_version;
     use teaclave_test_utils::*;
@@ -1171,6 +1643,7 @@ pub mod tests {
             test_db_impl_build_db_sanity,
             test_db_impl_get_from_table_with_snapshot,
             test_db_impl_delete,
+            test_db_impl_record_writes,
             test_db_impl_compact_single_file,
             test_db_impl_compaction_trivial_move,
             test_db_impl_memtable_compaction,
@@ -1178,6 +1651,11 @@ pub mod tests {
             test_db_impl_compaction_trivial,
             test_db_impl_compaction_state_cleanup,
             test_db_impl_open_close_reopen,
+            test_db_impl_reopen_compressed,
+            test_db_impl_reopen_compressed_disk,
+            test_db_impl_rotate_key,
+            test_db_impl_rotate_key_reopen,
+            test_db_impl_properties,
         )
     }
 
@@ -1206,7 +1684,7 @@ pub mod tests {
     }
 
     fn build_memtable() -> MemTable {
-        let mut mt = MemTable::new(options::for_test().cmp);
+        let mt = MemTable::new(options::for_test().cmp);
         let mut i = 1;
         for k in ["abc", "def", "ghi", "jkl", "mno", "aabc", "test123"].iter() {
             mt.add(
@@ -1550,6 +2028,33 @@ pub mod tests {
         assert!(db.get(b"xyz").is_some());
     }
 
+    fn test_db_impl_record_writes() {
+        let mut db = build_db().0;
+        db.put(b"xyy", b"123").unwrap();
+        assert!(db.take_recorded_writes().is_empty());
+
+        db.record_writes();
+        db.put(b"xyy", b"456").unwrap();
+        let mut wb = WriteBatch::new();
+        wb.put(b"xyz", b"789");
+        wb.delete(b"xyy");
+        db.write(wb, false).unwrap();
+
+        let recorded = db.take_recorded_writes();
+        assert_eq!(recorded.len(), 2);
+        assert!(db.take_recorded_writes().is_empty());
+
+        // Replaying the writes on another database gives the same contents.
+        let mut replica = DB::open("replica", options::for_test()).unwrap();
+        for contents in recorded {
+            let mut wb = WriteBatch::new();
+            wb.set_contents(&contents);
+            replica.write(wb, false).unwrap();
+        }
+        assert_eq!(None, replica.get(b"xyy"));
+        assert_eq!(Some(b"789".to_vec()), replica.get(b"xyz"));
+    }
+
     fn test_db_impl_compact_single_file() {
         let mut db = build_db().0;
         set_file_to_compact(&mut db, 4);
@@ -1571,8 +2076,7 @@ pub mod tests {
         db.put("ab0".as_bytes(), "xyz".as_bytes()).unwrap();
         db.put("abz".as_bytes(), "xyz".as_bytes()).unwrap();
         assert_eq!(4, db.mem.len());
-        let mut imm = MemTable::new(db.opt.cmp.clone());
-        mem::swap(&mut imm, &mut db.mem);
+        let imm = mem::replace(&mut db.mem, Arc::new(MemTable::new(db.opt.cmp.clone())));
         db.imm = Some(imm);
         db.compact_memtable().unwrap();
 
@@ -1604,7 +2108,7 @@ pub mod tests {
         let mut db = DB::new("db", opt);
 
         // Fill up memtable.
-        db.mem = build_memtable();
+        db.mem = Arc::new(build_memtable());
 
         // Trigger memtable compaction.
         db.make_room_for_write(true).unwrap();
@@ -1620,9 +2124,12 @@ pub mod tests {
 
     fn test_db_impl_compaction() {
         let mut db = build_db().0;
-        let v = db.current();
-        v.borrow_mut().compaction_score = Some(2.0);
-        v.borrow_mut().compaction_level = Some(1);
+        {
+            // The tables of the version are kept while it is referenced.
+            let v = db.current();
+            v.borrow_mut().compaction_score = Some(2.0);
+            v.borrow_mut().compaction_level = Some(1);
+        }
 
         db.maybe_do_compaction().unwrap();
 
@@ -1721,4 +2228,192 @@ pub mod tests {
             assert_eq!(None, db.get_at(&ss, b"xx2").unwrap());
         }
     }
+
+    // Fill the database with compressible values, which end up in tables. Returns the total size
+    // of the tables.
+    fn fill_compressed_db(db: &mut DB, value: &[u8]) -> usize {
+        for i in 0..100u32 {
+            db.put(&i.to_be_bytes(), value).unwrap();
+        }
+        db.compact_range(&0u32.to_be_bytes(), &100u32.to_be_bytes())
+            .unwrap();
+
+        let env = db.opt.env.clone();
+        let files = env.children(&db.path).unwrap();
+        files
+            .iter()
+            .filter(|f| f.extension().map_or(false, |e| e == "ldb"))
+            .map(|f| env.size_of(&db.path.join(f)).unwrap())
+            .sum()
+    }
+
+    fn test_db_impl_reopen_compressed() {
+        let value = b"0123456789".repeat(100);
+        for ctype in [
+            CompressionType::CompressionSnappy,
+            CompressionType::CompressionLz4,
+        ] {
+            let mut opt = options::for_test();
+            opt.compression_type = ctype;
+
+            {
+                let mut db = DB::open("compressed_db", opt.clone()).unwrap();
+                let size = fill_compressed_db(&mut db, &value);
+                assert!(size > 0);
+                assert!(size < 100 * value.len() / 4);
+            }
+
+            // Tables are readable with any compression type set.
+            opt.compression_type = CompressionType::CompressionNone;
+            let mut db = DB::open("compressed_db", opt).unwrap();
+            for i in 0..100u32 {
+                assert_eq!(Some(value.clone()), db.get(&i.to_be_bytes()));
+            }
+        }
+    }
+
+    fn test_db_impl_reopen_compressed_disk() {
+        let value = b"0123456789".repeat(100);
+        let opt = Options::new_disk_db_with([0u8; 16]);
+        assert_eq!(opt.compression_type, CompressionType::CompressionSnappy);
+        let env = opt.env.clone();
+
+        {
+            let mut db = DB::open("compressed_disk_db", opt.clone()).unwrap();
+            let size = fill_compressed_db(&mut db, &value);
+            assert!(size < 100 * value.len() / 4);
+        }
+
+        {
+            let mut db = DB::open("compressed_disk_db", opt).unwrap();
+            for i in 0..100u32 {
+                assert_eq!(Some(value.clone()), db.get(&i.to_be_bytes()));
+            }
+        }
+        env.rmdir(Path::new("compressed_disk_db")).unwrap();
+    }
+
+    fn check_rotated_db(db: &mut DB, value: &[u8]) {
+        for i in 0..100u32 {
+            if i == 50 {
+                assert_eq!(Some(b"new".to_vec()), db.get(&i.to_be_bytes()));
+            } else {
+                assert_eq!(Some(value.to_vec()), db.get(&i.to_be_bytes()));
+            }
+        }
+        assert_eq!(Some(b"abc".to_vec()), db.get(b"unflushed"));
+        assert_eq!(Some(b"def".to_vec()), db.get(b"rotated"));
+    }
+
+    // Fill the database under the old key, with tables in several levels and an unflushed
+    // memtable, then rotate to the new key.
+    fn build_rotated_db(name: &str, value: &[u8], old_key: DBPersistKey, new_key: DBPersistKey) {
+        let mut db = DB::open(name, Options::new_disk_db_with(old_key)).unwrap();
+        fill_compressed_db(&mut db, value);
+        // Overlapping memtables end up in lower levels.
+        db.put(&50u32.to_be_bytes(), b"old").unwrap();
+        db.make_room_for_write(true).unwrap();
+        db.put(&50u32.to_be_bytes(), b"new").unwrap();
+        db.make_room_for_write(true).unwrap();
+        db.put(b"unflushed", b"abc").unwrap();
+        assert_eq!(1, db.current().borrow().num_level_files(0));
+
+        db.rotate_key(new_key).unwrap();
+        assert!(db.rotate_key(new_key).is_err());
+        db.put(b"rotated", b"def").unwrap();
+        assert_eq!(3, db.old_key_tables().unwrap().len());
+        check_rotated_db(&mut db, value);
+    }
+
+    fn test_db_impl_rotate_key() {
+        let mut db = DB::open("db", options::for_test()).unwrap();
+        assert_eq!(
+            StatusCode::NotSupported,
+            db.rotate_key([1u8; 16]).err().unwrap().code
+        );
+
+        let value = b"0123456789".repeat(100);
+        let (old_key, new_key) = ([1u8; 16], [2u8; 16]);
+        build_rotated_db("rotate_key_db", &value, old_key, new_key);
+
+        let opt = Options::new_disk_db_with_keys(vec![old_key, new_key]);
+        let env = opt.env.clone();
+        {
+            let mut db = DB::open("rotate_key_db", opt).unwrap();
+            check_rotated_db(&mut db, &value);
+
+            // Compactions rewrite the old tables when there is nothing else to do.
+            for _ in 0..100 {
+                if !db.rekeying {
+                    break;
+                }
+                db.maybe_do_compaction().unwrap();
+            }
+            assert!(!db.rekeying);
+            assert_eq!(0, db.compact_old_key_tables(usize::MAX).unwrap());
+            check_rotated_db(&mut db, &value);
+        }
+
+        // The old key is not needed anymore.
+        {
+            let opt = Options::new_disk_db_with(new_key);
+            let mut db = DB::open("rotate_key_db", opt).unwrap();
+            check_rotated_db(&mut db, &value);
+        }
+        env.rmdir(Path::new("rotate_key_db")).unwrap();
+    }
+
+    fn test_db_impl_rotate_key_reopen() {
+        let value = b"0123456789".repeat(100);
+        let (old_key, new_key) = ([3u8; 16], [4u8; 16]);
+        build_rotated_db("rotate_key_reopen_db", &value, old_key, new_key);
+
+        let opt = Options::new_disk_db_with_keys(vec![old_key, new_key]);
+        let env = opt.env.clone();
+        // Opening the database rewrites an old table, and leaves the others for later.
+        {
+            let mut db = DB::open("rotate_key_reopen_db", opt.clone()).unwrap();
+            assert!(db.compact_old_key_tables(0).unwrap() > 0);
+            check_rotated_db(&mut db, &value);
+        }
+        {
+            let mut db = DB::open("rotate_key_reopen_db", opt).unwrap();
+            check_rotated_db(&mut db, &value);
+            assert_eq!(0, db.compact_old_key_tables(usize::MAX).unwrap());
+        }
+        {
+            let opt = Options::new_disk_db_with(new_key);
+            let mut db = DB::open("rotate_key_reopen_db", opt).unwrap();
+            check_rotated_db(&mut db, &value);
+        }
+        env.rmdir(Path::new("rotate_key_reopen_db")).unwrap();
+    }
+
+    fn test_db_impl_properties() {
+        let (mut db, _) = build_db();
+        let files_at_level = |db: &DB, level: usize| {
+            db.get_property(&format!("leveldb.num-files-at-level{}", level))
+        };
+        assert_eq!(Some("2".to_string()), files_at_level(&db, 0));
+        assert_eq!(Some("3".to_string()), files_at_level(&db, 1));
+        assert_eq!(Some("0".to_string()), files_at_level(&db, 6));
+        assert_eq!(None, files_at_level(&db, NUM_LEVELS));
+        assert_eq!(None, db.get_property("leveldb.unknown"));
+        assert_eq!(None, db.get_property("stats"));
+
+        let sstables = db.get_property("leveldb.sstables").unwrap();
+        assert!(sstables.starts_with("--- level 0 ---\n 1:232['aaa' @ 22 : 1 .. 'aba' @ 25 : 1]\n"));
+
+        let sizes = db.get_approximate_sizes(&[
+            ("aaa".as_bytes(), "aaa".as_bytes()),
+            ("aaa".as_bytes(), "bab".as_bytes()),
+            ("a".as_bytes(), "z".as_bytes()),
+        ]);
+        assert_eq!(vec![0, 232, 2002], sizes);
+
+        db.compact_range(b"aaa", b"dba").unwrap();
+        let stats = db.get_property("leveldb.stats").unwrap();
+        assert!(stats.contains("Level  Files Size(MB) Time(sec) Read(MB) Write(MB)\n"));
+        assert!(stats.contains("Block cache: "));
+    }
 }
@@ -7,7 +7,7 @@ use std::io::{self, Read, Write};
 use std::io::{Seek, SeekFrom};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::untrusted::fs;
 use std::untrusted::path::PathEx;
 
@@ -22,16 +22,49 @@ type FileDescriptor = i32;
 #[derive(Clone)]
 pub struct PosixDiskEnv {
     locks: Arc<Mutex<HashMap<String, sgx_tprotected_fs::SgxFile>>>,
-    key: DBPersistKey,
+    // Key generations, from the oldest to the current one.
+    keys: Arc<RwLock<Vec<DBPersistKey>>>,
 }
 
 impl PosixDiskEnv {
     pub fn new_with(key: DBPersistKey) -> PosixDiskEnv {
+        PosixDiskEnv::new_with_keys(vec![key])
+    }
+
+    /// new_with_keys creates an env with several key generations, the last one being the current
+    /// key. New files are written under the current key, and existing files are read with the key
+    /// they were written under.
+    pub fn new_with_keys(keys: Vec<DBPersistKey>) -> PosixDiskEnv {
+        assert!(!keys.is_empty());
         PosixDiskEnv {
             locks: Arc::new(Mutex::new(HashMap::new())),
-            key,
+            keys: Arc::new(RwLock::new(keys)),
         }
     }
+
+    fn current_key(&self) -> DBPersistKey {
+        *self.keys.read().unwrap().last().unwrap()
+    }
+
+    /// open_with_any_key opens an existing file with the key it was written under, trying the
+    /// newest keys first. It returns the file and its key generation.
+    fn open_with_any_key(
+        &self,
+        opts: &sgx_tprotected_fs::OpenOptions,
+        p: &Path,
+    ) -> io::Result<(sgx_tprotected_fs::SgxFile, usize)> {
+        let keys = self.keys.read().unwrap();
+        let mut first_err = None;
+        for (generation, key) in keys.iter().enumerate().rev() {
+            match opts.open_with_key(p, *key) {
+                Ok(f) => return Ok((f, generation)),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_err.unwrap())
+    }
 }
 
 /// map_err_with_name annotates an io::Error with information about the operation and the file.
@@ -44,38 +77,35 @@ fn map_err_with_name(method: &'static str, f: &Path, e: io::Error) -> Status {
 // Note: We're using Ok(f()?) in several locations below in order to benefit from the automatic
 // error conversion using std::convert::From.
 impl Env for PosixDiskEnv {
-    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read + Send>> {
         Ok(Box::new(
-            sgx_tprotected_fs::OpenOptions::default()
-                .read(true)
-                .open_with_key(p, self.key)
+            self.open_with_any_key(sgx_tprotected_fs::OpenOptions::default().read(true), p)
+                .map(|(f, _)| f)
                 .map_err(|e| map_err_with_name("open_sgx (seq)", p, e))?,
         ))
     }
     fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>> {
-        Ok(sgx_tprotected_fs::OpenOptions::default()
-            .read(true)
-            .open_with_key(p, self.key)
-            .map(|f| {
+        Ok(self
+            .open_with_any_key(sgx_tprotected_fs::OpenOptions::default().read(true), p)
+            .map(|(f, _)| {
                 let b: Box<dyn RandomAccess> = Box::new(f);
                 b
             })
             .map_err(|e| map_err_with_name("open_sgx (randomaccess)", p, e))?)
     }
-    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>> {
         Ok(Box::new(
             sgx_tprotected_fs::OpenOptions::default()
                 .write(true)
                 .append(false)
-                .open_with_key(p, self.key)
+                .open_with_key(p, self.current_key())
                 .map_err(|e| map_err_with_name("open_sgx (write)", p, e))?,
         ))
     }
-    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>> {
         Ok(Box::new(
-            sgx_tprotected_fs::OpenOptions::default()
-                .append(true)
-                .open_with_key(p, self.key)
+            self.open_with_any_key(sgx_tprotected_fs::OpenOptions::default().append(true), p)
+                .map(|(f, _)| f)
                 .map_err(|e| map_err_with_name("open_sgx (append_sgx)", p, e))?,
         ))
     }
@@ -98,9 +128,8 @@ impl Env for PosixDiskEnv {
     }
 
     fn size_of(&self, p: &Path) -> Result<usize> {
-        let mut f = sgx_tprotected_fs::OpenOptions::default()
-            .read(true)
-            .open_with_key(p, self.key)
+        let (mut f, _) = self
+            .open_with_any_key(sgx_tprotected_fs::OpenOptions::default().read(true), p)
             .map_err(|e| map_err_with_name("size_of (open)", p, e))?;
         let size = f.seek(SeekFrom::End(0))?;
         Ok(size as usize)
@@ -136,9 +165,8 @@ impl Env for PosixDiskEnv {
             ))?;
 
         {
-            let mut f = sgx_tprotected_fs::OpenOptions::default()
-                .append(true)
-                .open_with_key(old, self.key)
+            let (mut f, _) = self
+                .open_with_any_key(sgx_tprotected_fs::OpenOptions::default().append(true), old)
                 .map_err(|e| map_err_with_name("rename (open)", old, e))?;
             f.rename(old_name, new_name)?;
         }
@@ -155,7 +183,7 @@ impl Env for PosixDiskEnv {
             let f = sgx_tprotected_fs::OpenOptions::default()
                 .write(true)
                 .append(false)
-                .open_with_key(p, self.key)
+                .open_with_key(p, self.current_key())
                 .map_err(|e| map_err_with_name("lock_sgx: ", p, e))?;
 
             locks.insert(p.to_str().unwrap().to_string(), f);
@@ -186,6 +214,24 @@ impl Env for PosixDiskEnv {
     fn micros(&self) -> u64 {
         micros()
     }
+
+    fn key_generation(&self, p: &Path) -> Result<usize> {
+        let (_, generation) = self
+            .open_with_any_key(sgx_tprotected_fs::OpenOptions::default().read(true), p)
+            .map_err(|e| map_err_with_name("key_generation (open)", p, e))?;
+        Ok(generation)
+    }
+    fn current_key_generation(&self) -> usize {
+        self.keys.read().unwrap().len() - 1
+    }
+    fn add_key(&self, key: DBPersistKey) -> Result<()> {
+        let mut keys = self.keys.write().unwrap();
+        if keys.contains(&key) {
+            return err(StatusCode::InvalidArgument, "key is already in use");
+        }
+        keys.push(key);
+        Ok(())
+    }
 }
 
 #[cfg(feature = "enclave_unit_test")]
@@ -197,7 +243,7 @@ pub mod tests {
     use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        run_tests!(test_files, test_locking, test_dirs,)
+        run_tests!(test_files, test_locking, test_dirs, test_key_rotation,)
     }
 
     fn test_files() {
@@ -283,4 +329,52 @@ pub mod tests {
         assert_eq!(env.children(dirname).unwrap().len(), 1);
         assert!(env.rmdir(dirname).is_ok());
     }
+
+    fn read_file(env: &PosixDiskEnv, p: &Path) -> Vec<u8> {
+        let mut contents = vec![];
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn test_key_rotation() {
+        let old: &Path = "old_key.xyz".as_ref();
+        let new: &Path = "new_key.xyz".as_ref();
+        let env = PosixDiskEnv::new_with([1u8; 16]);
+        assert_eq!(env.current_key_generation(), 0);
+        {
+            let mut f = env.open_writable_file(old).unwrap();
+            let _ = f.write("123".as_bytes());
+        }
+
+        env.add_key([2u8; 16]).unwrap();
+        assert!(env.add_key([1u8; 16]).is_err());
+        assert_eq!(env.current_key_generation(), 1);
+        {
+            let mut f = env.open_writable_file(new).unwrap();
+            let _ = f.write("456".as_bytes());
+        }
+        assert_eq!(env.key_generation(old).unwrap(), 0);
+        assert_eq!(env.key_generation(new).unwrap(), 1);
+        assert_eq!(read_file(&env, old), b"123");
+        assert_eq!(read_file(&env, new), b"456");
+
+        // Files keep their key when appended to.
+        {
+            let mut f = env.open_appendable_file(old).unwrap();
+            let _ = f.write("xyz".as_bytes());
+        }
+        assert_eq!(env.key_generation(old).unwrap(), 0);
+        assert_eq!(env.size_of(old).unwrap(), 6);
+        assert_eq!(read_file(&env, old), b"123xyz");
+
+        let rotated = PosixDiskEnv::new_with_keys(vec![[2u8; 16]]);
+        assert!(rotated.open_sequential_file(old).is_err());
+        assert_eq!(read_file(&rotated, new), b"456");
+
+        assert!(env.delete(old).is_ok());
+        assert!(env.delete(new).is_ok());
+    }
 }
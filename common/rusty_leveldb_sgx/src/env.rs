@@ -1,7 +1,8 @@
 //! An `env` is an abstraction layer that allows the database to run both on different platforms as
 //! well as persisting data on disk or in memory.
 
-use crate::error::Result;
+use crate::disk_env::DBPersistKey;
+use crate::error::{err, Result, StatusCode};
 
 use std::io::prelude::*;
 use std::os::unix::fs::FileExt;
@@ -9,7 +10,7 @@ use std::path::{Path, PathBuf};
 
 use sgx_tprotected_fs::SgxFile;
 
-pub trait RandomAccess {
+pub trait RandomAccess: Send + Sync {
     fn read_at(&self, off: usize, dst: &mut [u8]) -> Result<usize>;
 }
 
@@ -23,11 +24,11 @@ pub struct FileLock {
     pub id: String,
 }
 
-pub trait Env {
-    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>>;
+pub trait Env: Send + Sync {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read + Send>>;
     fn open_random_access_file(&self, p: &Path) -> Result<Box<dyn RandomAccess>>;
-    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>>;
-    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>>;
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>>;
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>>;
 
     fn exists(&self, p: &Path) -> Result<bool>;
     fn children(&self, p: &Path) -> Result<Vec<PathBuf>>;
@@ -44,14 +45,31 @@ pub trait Env {
     fn new_logger(&self, p: &Path) -> Result<Logger>;
 
     fn micros(&self) -> u64;
+
+    /// key_generation returns the generation of the key a file is encrypted with. Envs which
+    /// don't encrypt files only have generation 0.
+    fn key_generation(&self, _: &Path) -> Result<usize> {
+        Ok(0)
+    }
+    fn current_key_generation(&self) -> usize {
+        0
+    }
+    /// add_key makes a new key the current one. Files written under earlier keys remain
+    /// readable.
+    fn add_key(&self, _: DBPersistKey) -> Result<()> {
+        err(
+            StatusCode::NotSupported,
+            "env doesn't support encryption keys",
+        )
+    }
 }
 
 pub struct Logger {
-    dst: Box<dyn Write>,
+    dst: Box<dyn Write + Send>,
 }
 
 impl Logger {
-    pub fn new(w: Box<dyn Write>) -> Logger {
+    pub fn new(w: Box<dyn Write + Send>) -> Logger {
         Logger { dst: w }
     }
 
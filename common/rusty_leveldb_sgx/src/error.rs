@@ -113,6 +113,15 @@ impl From<snap::Error> for Status {
     }
 }
 
+impl From<lz4_flex::block::DecompressError> for Status {
+    fn from(e: lz4_flex::block::DecompressError) -> Status {
+        Status {
+            code: StatusCode::CompressionError,
+            err: e.to_string(),
+        }
+    }
+}
+
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::{Status, StatusCode};
@@ -30,6 +30,7 @@ extern crate sgx_types;
 
 extern crate crc;
 extern crate integer_encoding;
+extern crate lz4_flex;
 extern crate rand;
 extern crate snap;
 
@@ -52,6 +53,7 @@ mod mem_env;
 mod memtable;
 mod merging_iter;
 mod options;
+mod repair;
 mod skipmap;
 mod snapshot;
 mod table_block;
@@ -65,21 +67,25 @@ mod version_edit;
 mod version_set;
 mod write_batch;
 
+mod concurrent_db;
 mod db_impl;
 mod db_iter;
 
 pub use crate::cmp::{Cmp, DefaultCmp};
+pub use crate::concurrent_db::ConcurrentDB;
 pub use crate::db_iter::DBIterator;
 pub use crate::env::Env;
 pub use crate::error::{Result, Status, StatusCode};
 pub use crate::filter::{BloomPolicy, FilterPolicy};
 pub use crate::mem_env::MemEnv;
 pub use crate::options::{in_memory, CompressionType, Options};
+pub use crate::repair::{repair, RepairStats};
 pub use crate::skipmap::SkipMap;
-pub use crate::types::LdbIterator;
+pub use crate::snapshot::Snapshot;
+pub use crate::types::{LdbIterator, NUM_LEVELS};
 pub use crate::write_batch::WriteBatch;
 pub use db_impl::DB;
-pub use disk_env::PosixDiskEnv;
+pub use disk_env::{DBPersistKey, PosixDiskEnv};
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
@@ -93,6 +99,7 @@ pub mod tests {
             blockhandle::tests::run_tests(),
             cache::tests::run_tests(),
             cmp::tests::run_tests(),
+            concurrent_db::tests::run_tests(),
             db_impl::tests::run_tests(),
             db_iter::tests::run_tests(),
             disk_env::tests::run_tests(),
@@ -104,6 +111,7 @@ pub mod tests {
             mem_env::tests::run_tests(),
             memtable::tests::run_tests(),
             merging_iter::tests::run_tests(),
+            repair::tests::run_tests(),
             skipmap::tests::run_tests(),
             snapshot::tests::run_tests(),
             table_builder::tests::run_tests(),
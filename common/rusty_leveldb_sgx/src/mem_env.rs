@@ -159,7 +159,7 @@ impl MemFS {
         }
     }
     /// Open a file for writing.
-    fn open_w(&self, p: &Path, append: bool, truncate: bool) -> Result<Box<dyn Write>> {
+    fn open_w(&self, p: &Path, append: bool, truncate: bool) -> Result<Box<dyn Write + Send>> {
         let f = self.open(p, true)?;
         if truncate {
             f.0.lock().unwrap().clear();
@@ -284,7 +284,7 @@ impl MemEnv {
 }
 
 impl Env for MemEnv {
-    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read>> {
+    fn open_sequential_file(&self, p: &Path) -> Result<Box<dyn Read + Send>> {
         let f = self.0.open(p, false)?;
         Ok(Box::new(MemFileReader::new(f, 0)))
     }
@@ -293,10 +293,10 @@ impl Env for MemEnv {
             .open(p, false)
             .map(|m| Box::new(m) as Box<dyn RandomAccess>)
     }
-    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_writable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>> {
         self.0.open_w(p, true, true)
     }
-    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write>> {
+    fn open_appendable_file(&self, p: &Path) -> Result<Box<dyn Write + Send>> {
         self.0.open_w(p, true, false)
     }
 
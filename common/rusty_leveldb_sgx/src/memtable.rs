@@ -4,7 +4,7 @@ use crate::key_types::{LookupKey, UserKey};
 use crate::skipmap::{SkipMap, SkipMapIter};
 use crate::types::{current_key_val, LdbIterator, SequenceNumber};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use integer_encoding::FixedInt;
 
@@ -17,12 +17,12 @@ pub struct MemTable {
 impl MemTable {
     /// Returns a new MemTable.
     /// This wraps opt.cmp inside a MemtableKey-specific comparator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
-        MemTable::new_raw(Rc::new(Box::new(MemtableKeyCmp(cmp))))
+    pub fn new(cmp: Arc<Box<dyn Cmp>>) -> MemTable {
+        MemTable::new_raw(Arc::new(Box::new(MemtableKeyCmp(cmp))))
     }
 
     /// Doesn't wrap the comparator in a MemtableKeyCmp.
-    fn new_raw(cmp: Rc<Box<dyn Cmp>>) -> MemTable {
+    fn new_raw(cmp: Arc<Box<dyn Cmp>>) -> MemTable {
         MemTable {
             map: SkipMap::new(cmp),
         }
@@ -36,7 +36,7 @@ impl MemTable {
         self.map.approx_memory()
     }
 
-    pub fn add<'a>(&mut self, seq: SequenceNumber, t: ValueType, key: UserKey<'a>, value: &[u8]) {
+    pub fn add<'a>(&self, seq: SequenceNumber, t: ValueType, key: UserKey<'a>, value: &[u8]) {
         self.map
             .insert(build_memtable_key(key, value, t, seq), Vec::new())
     }
@@ -185,7 +185,7 @@ pub mod tests {
     }
 
     fn get_memtable() -> MemTable {
-        let mut mt = MemTable::new(options::for_test().cmp);
+        let mt = MemTable::new(options::for_test().cmp);
         let entries = vec![
             (ValueType::TypeValue, 115, "abc", "122"),
             (ValueType::TypeValue, 120, "abc", "123"),
@@ -206,7 +206,7 @@ pub mod tests {
     }
 
     fn test_memtable_add() {
-        let mut mt = MemTable::new(options::for_test().cmp);
+        let mt = MemTable::new(options::for_test().cmp);
         mt.add(
             123,
             ValueType::TypeValue,
@@ -383,7 +383,7 @@ pub mod tests {
     }
 
     fn test_memtable_iterator_behavior() {
-        let mut mt = MemTable::new(options::for_test().cmp);
+        let mt = MemTable::new(options::for_test().cmp);
         let entries = vec![
             (115, "abc", "122"),
             (120, "abd", "123"),
@@ -2,7 +2,7 @@ use crate::cmp::Cmp;
 use crate::types::{current_key_val, Direction, LdbIterator};
 
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 // Warning: This module is kinda messy. The original implementation is
 // not that much better though :-)
@@ -20,12 +20,12 @@ pub struct MergingIter {
     iters: Vec<Box<dyn LdbIterator>>,
     current: Option<usize>,
     direction: Direction,
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
 }
 
 impl MergingIter {
     /// Construct a new merging iterator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>, iters: Vec<Box<dyn LdbIterator>>) -> MergingIter {
+    pub fn new(cmp: Arc<Box<dyn Cmp>>, iters: Vec<Box<dyn LdbIterator>>) -> MergingIter {
         let mi = MergingIter {
             iters,
             current: None,
@@ -222,7 +222,7 @@ pub mod tests {
         let iter = skm.iter();
         let mut iter2 = skm.iter();
 
-        let mut miter = MergingIter::new(Rc::new(Box::new(DefaultCmp)), vec![Box::new(iter)]);
+        let mut miter = MergingIter::new(Arc::new(Box::new(DefaultCmp)), vec![Box::new(iter)]);
 
         loop {
             if let Some((k, v)) = miter.next() {
@@ -244,7 +244,7 @@ pub mod tests {
         let iter2 = skm.iter();
 
         let mut miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
 
@@ -263,7 +263,7 @@ pub mod tests {
     }
 
     fn test_merging_zero() {
-        let mut miter = MergingIter::new(Rc::new(Box::new(DefaultCmp)), vec![]);
+        let mut miter = MergingIter::new(Arc::new(Box::new(DefaultCmp)), vec![]);
         assert_eq!(0, LdbIteratorIter::wrap(&mut miter).count());
     }
 
@@ -272,7 +272,7 @@ pub mod tests {
         let iter = TestLdbIter::new(vec![(b("aba"), val), (b("abc"), val)]);
         let iter2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
         let miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
         test_iterator_properties(miter);
@@ -284,7 +284,7 @@ pub mod tests {
         let iter2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
 
         let mut miter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(iter), Box::new(iter2)],
         );
 
@@ -331,7 +331,7 @@ pub mod tests {
         let expected = vec![b("aba"), b("abb"), b("abc"), b("abd"), b("abe")];
 
         let mut iter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(it1), Box::new(it2)],
         );
 
@@ -349,7 +349,7 @@ pub mod tests {
         let it2 = TestLdbIter::new(vec![(b("abb"), val), (b("abd"), val)]);
 
         let mut iter = MergingIter::new(
-            Rc::new(Box::new(DefaultCmp)),
+            Arc::new(Box::new(DefaultCmp)),
             vec![Box::new(it1), Box::new(it2)],
         );
 
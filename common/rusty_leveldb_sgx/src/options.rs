@@ -9,7 +9,7 @@ use crate::infolog::{self, Logger};
 use crate::mem_env::MemEnv;
 use crate::types::{share, Shared};
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 use disk_env::DBPersistKey;
 
@@ -25,12 +25,15 @@ const DEFAULT_BITS_PER_KEY: u32 = 10; // NOTE: This may need to be optimized.
 pub enum CompressionType {
     CompressionNone = 0,
     CompressionSnappy = 1,
+    // Same id as in RocksDB
+    CompressionLz4 = 4,
 }
 
 pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
     match i {
         0 => Some(CompressionType::CompressionNone),
         1 => Some(CompressionType::CompressionSnappy),
+        4 => Some(CompressionType::CompressionLz4),
         _ => None,
     }
 }
@@ -39,8 +42,8 @@ pub fn int_to_compressiontype(i: u32) -> Option<CompressionType> {
 /// self-explanatory; the defaults are defined in the `Default` implementation.
 #[derive(Clone)]
 pub struct Options {
-    pub cmp: Rc<Box<dyn Cmp>>,
-    pub env: Rc<Box<dyn Env>>,
+    pub cmp: Arc<Box<dyn Cmp>>,
+    pub env: Arc<Box<dyn Env>>,
     pub log: Option<Shared<Logger>>,
     pub create_if_missing: bool,
     pub error_if_exists: bool,
@@ -59,9 +62,15 @@ pub struct Options {
 
 impl Options {
     pub fn new_disk_db_with(key: DBPersistKey) -> Options {
+        Options::new_disk_db_with_keys(vec![key])
+    }
+
+    /// new_disk_db_with_keys returns Options for a database on disk whose files may be encrypted
+    /// with several key generations, the last one being the current key. See DB::rotate_key().
+    pub fn new_disk_db_with_keys(keys: Vec<DBPersistKey>) -> Options {
         Options {
-            cmp: Rc::new(Box::new(DefaultCmp)),
-            env: Rc::new(Box::new(disk_env::PosixDiskEnv::new_with(key))),
+            cmp: Arc::new(Box::new(DefaultCmp)),
+            env: Arc::new(Box::new(disk_env::PosixDiskEnv::new_with_keys(keys))),
             log: None,
             create_if_missing: true,
             error_if_exists: false,
@@ -75,15 +84,16 @@ impl Options {
             block_restart_interval: 16,
             reuse_logs: true,
             reuse_manifest: true,
-            compression_type: CompressionType::CompressionNone,
-            filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            // Tables are compressed before they are encrypted by the env.
+            compression_type: CompressionType::CompressionSnappy,
+            filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
         }
     }
 
     pub fn new_mem_db() -> Options {
         Options {
-            cmp: Rc::new(Box::new(DefaultCmp)),
-            env: Rc::new(Box::new(MemEnv::new())),
+            cmp: Arc::new(Box::new(DefaultCmp)),
+            env: Arc::new(Box::new(MemEnv::new())),
             log: None,
             create_if_missing: true,
             error_if_exists: false,
@@ -98,7 +108,7 @@ impl Options {
             reuse_logs: true,
             reuse_manifest: true,
             compression_type: CompressionType::CompressionNone,
-            filter_policy: Rc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
+            filter_policy: Arc::new(Box::new(filter::BloomPolicy::new(DEFAULT_BITS_PER_KEY))),
         }
     }
 }
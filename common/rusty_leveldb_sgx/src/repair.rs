@@ -0,0 +1,517 @@
+//! repair rebuilds a database whose manifest is lost or corrupted from the logs and tables left in
+//! its directory, like LevelDB's RepairDB.
+//!
+//! Every table is copied to a new one, keeping what can still be read from it, and every log is
+//! converted to a table, skipping the corrupted records. The new tables all go to level 0 of a new
+//! manifest. The files they replace are moved to a `.lost` directory next to the database; some
+//! data may be lost, in which case it's reported in the returned statistics.
+
+use crate::cmp::{Cmp, InternalKeyCmp};
+use crate::db_impl::{build_table, lock_file_name, log_file_name, open_info_log};
+use crate::error::{err, Result, StatusCode};
+use crate::key_types::parse_internal_key;
+use crate::log::{LogReader, LogWriter};
+use crate::memtable::MemTable;
+use crate::options::Options;
+use crate::table_builder::TableBuilder;
+use crate::table_cache::{table_file_name, TableCache};
+use crate::table_reader::Table;
+use crate::types::{
+    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, SequenceNumber,
+    SGX_RECOVERY_FILE_SUFFIX,
+};
+use crate::version_edit::VersionEdit;
+use crate::version_set::{manifest_file_name, set_current_file};
+use crate::write_batch::WriteBatch;
+
+use std::cmp::Ordering;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// What a repair found and kept.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepairStats {
+    /// Number of tables in the repaired database.
+    pub tables: usize,
+    /// Number of entries in the repaired database, including deletions and overwritten values.
+    pub entries: usize,
+    /// Number of log records which couldn't be read.
+    pub corrupted_records: usize,
+    /// Number of tables and logs which couldn't be opened at all.
+    pub lost_files: usize,
+}
+
+/// Repairs the database named `name`. It must not be open, and it is opened as usual afterwards.
+///
+/// Files are read and written through the env of `opt`, so that encrypted databases are repaired
+/// with the keys they were written under.
+pub fn repair<P: AsRef<Path>>(name: P, mut opt: Options) -> Result<RepairStats> {
+    let name = name.as_ref();
+    if opt.log.is_none() {
+        let log = open_info_log(opt.env.as_ref().as_ref(), name);
+        opt.log = Some(share(log));
+    }
+
+    let lock = opt.env.lock(Path::new(&lock_file_name(name)))?;
+    let result = Repairer::new(name, opt.clone()).run();
+    opt.env.unlock(lock)?;
+    result
+}
+
+struct Repairer {
+    name: PathBuf,
+    opt: Options,
+    cache: TableCache,
+
+    next_file: FileNum,
+    last_seq: SequenceNumber,
+    tables: Vec<FileMetaData>,
+    // Files replaced by the new tables and manifest
+    obsolete: Vec<PathBuf>,
+    stats: RepairStats,
+}
+
+impl Repairer {
+    fn new(name: &Path, opt: Options) -> Repairer {
+        Repairer {
+            name: name.to_owned(),
+            cache: TableCache::new(name, opt.clone(), 1),
+            opt,
+            next_file: 1,
+            last_seq: 0,
+            tables: vec![],
+            obsolete: vec![],
+            stats: RepairStats::default(),
+        }
+    }
+
+    fn run(mut self) -> Result<RepairStats> {
+        let (tables, logs) = self.find_files()?;
+
+        // Tables are copied before the logs are converted, and in the order of their numbers, so
+        // that newer files keep larger numbers in level 0.
+        for num in tables {
+            self.salvage_table(num)?;
+        }
+        for num in logs {
+            self.convert_log(num)?;
+        }
+        self.write_manifest()?;
+        self.archive_obsolete_files();
+
+        self.stats.tables = self.tables.len();
+        log!(self.opt.log, "Repair done: {:?}", self.stats);
+        Ok(self.stats)
+    }
+
+    fn new_file_number(&mut self) -> FileNum {
+        self.next_file += 1;
+        self.next_file - 1
+    }
+
+    /// find_files returns the numbers of the tables and of the logs in the database directory.
+    fn find_files(&mut self) -> Result<(Vec<FileNum>, Vec<FileNum>)> {
+        let mut tables = vec![];
+        let mut logs = vec![];
+        for file in self.opt.env.children(&self.name)? {
+            if file.to_string_lossy().ends_with(SGX_RECOVERY_FILE_SUFFIX) {
+                continue;
+            }
+            let (num, typ) = match parse_file_name(&file) {
+                Ok(parsed) => parsed,
+                Err(_) => {
+                    log!(self.opt.log, "Repair: ignoring {:?}", file);
+                    continue;
+                }
+            };
+            if num >= self.next_file {
+                self.next_file = num + 1;
+            }
+            match typ {
+                FileType::Table => tables.push(num),
+                FileType::Log => logs.push(num),
+                FileType::Descriptor | FileType::Temp => self.obsolete.push(self.name.join(&file)),
+                FileType::Current | FileType::DBLock | FileType::InfoLog => {}
+            }
+        }
+        if tables.is_empty() && logs.is_empty() && self.obsolete.is_empty() {
+            return err(StatusCode::NotFound, "repair found no database files");
+        }
+        tables.sort();
+        logs.sort();
+        Ok((tables, logs))
+    }
+
+    /// salvage_table copies the readable entries of a table to a new table. Blocks failing their
+    /// checksum are skipped by the table iterator.
+    fn salvage_table(&mut self, num: FileNum) -> Result<()> {
+        self.obsolete.push(table_file_name(&self.name, num));
+        let table = match self.cache.get_table(num) {
+            Ok(table) => table,
+            Err(e) => {
+                log!(self.opt.log, "Repair: table {:06} is lost: {}", num, e);
+                self.stats.lost_files += 1;
+                return Ok(());
+            }
+        };
+        let _ = self.cache.evict(num);
+
+        let new_num = self.new_file_number();
+        let (md, entries, max_seq) = copy_table(&self.name, &self.opt, table, new_num)?;
+        log!(
+            self.opt.log,
+            "Repair: table {:06} copied to {:06} with {} entries",
+            num,
+            new_num,
+            entries
+        );
+        self.add_table(md, entries, max_seq);
+        Ok(())
+    }
+
+    /// convert_log writes the readable records of a log to new tables.
+    fn convert_log(&mut self, num: FileNum) -> Result<()> {
+        let filename = log_file_name(&self.name, num);
+        self.obsolete.push(filename.clone());
+        let logfile = match self.opt.env.open_sequential_file(Path::new(&filename)) {
+            Ok(f) => f,
+            Err(e) => {
+                log!(self.opt.log, "Repair: log {:06} is lost: {}", num, e);
+                self.stats.lost_files += 1;
+                return Ok(());
+            }
+        };
+
+        let mut reader = LogReader::new(logfile, true);
+        let mut mem = MemTable::new(self.opt.cmp.clone());
+        let mut max_seq = 0;
+        let mut scratch = vec![];
+        let mut batch = WriteBatch::new();
+        loop {
+            match reader.read(&mut scratch) {
+                Ok(0) => break,
+                Ok(len) if len < 12 => {
+                    self.stats.corrupted_records += 1;
+                    continue;
+                }
+                Ok(_) => {}
+                // A record failing its checksum is skipped, but there is no way past an I/O error.
+                Err(ref e) if e.code == StatusCode::Corruption => {
+                    self.stats.corrupted_records += 1;
+                    continue;
+                }
+                Err(e) => {
+                    log!(
+                        self.opt.log,
+                        "Repair: stopped reading log {:06}: {}",
+                        num,
+                        e
+                    );
+                    break;
+                }
+            }
+
+            batch.set_contents(&scratch);
+            batch.insert_into_memtable(batch.sequence(), &mem);
+            let last_seq = batch.sequence() + batch.count() as u64 - 1;
+            if last_seq > max_seq {
+                max_seq = last_seq;
+            }
+            if mem.approx_mem_usage() > self.opt.write_buffer_size {
+                self.write_memtable(&mem, max_seq)?;
+                mem = MemTable::new(self.opt.cmp.clone());
+            }
+        }
+        self.write_memtable(&mem, max_seq)?;
+        log!(self.opt.log, "Repair: log {:06} converted", num);
+        Ok(())
+    }
+
+    fn write_memtable(&mut self, mem: &MemTable, max_seq: SequenceNumber) -> Result<()> {
+        if mem.len() == 0 {
+            return Ok(());
+        }
+        let num = self.new_file_number();
+        let md = build_table(&self.name, &self.opt, mem.iter(), num)?;
+        self.add_table(md, mem.len(), max_seq);
+        Ok(())
+    }
+
+    fn add_table(&mut self, md: FileMetaData, entries: usize, max_seq: SequenceNumber) {
+        if max_seq > self.last_seq {
+            self.last_seq = max_seq;
+        }
+        // An empty table has already been deleted.
+        if md.size > 0 {
+            self.stats.entries += entries;
+            self.tables.push(md);
+        }
+    }
+
+    /// write_manifest writes a manifest with all tables at level 0, and points CURRENT to it.
+    fn write_manifest(&mut self) -> Result<()> {
+        let manifest_num = self.new_file_number();
+        let mut ve = VersionEdit::new();
+        ve.set_comparator_name(self.opt.cmp.id());
+        // Logs which couldn't be moved away mustn't be recovered again.
+        ve.set_log_num(self.next_file);
+        ve.set_next_file(self.next_file);
+        ve.set_last_seq(self.last_seq);
+        for md in &self.tables {
+            ve.add_file(0, md.clone());
+        }
+
+        {
+            let manifest = manifest_file_name(&self.name, manifest_num);
+            let manifest_file = self.opt.env.open_writable_file(Path::new(&manifest))?;
+            let mut lw = LogWriter::new(manifest_file);
+            lw.add_record(&ve.encode())?;
+            lw.flush()?;
+        }
+        set_current_file(&self.opt.env, &self.name, manifest_num)
+    }
+
+    /// archive_obsolete_files moves the replaced files out of the database directory. A file that
+    /// can't be moved is left in place; opening the database deletes it.
+    fn archive_obsolete_files(&mut self) {
+        let mut lost_dir = self.name.clone();
+        let dir_name = format!(
+            "{}.lost",
+            self.name
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default()
+        );
+        lost_dir.set_file_name(dir_name);
+        let _ = self.opt.env.mkdir(&lost_dir);
+
+        for file in &self.obsolete {
+            let to = lost_dir.join(file.file_name().unwrap_or_default());
+            if let Err(e) = self.opt.env.rename(file, &to) {
+                log!(self.opt.log, "Repair: could not archive {:?}: {}", file, e);
+            }
+        }
+    }
+}
+
+/// copy_table writes the entries of `table` to table file `num`. It returns the new table, the
+/// number of entries and the largest sequence number in it.
+fn copy_table<P: AsRef<Path>>(
+    dbname: P,
+    opt: &Options,
+    table: Table,
+    num: FileNum,
+) -> Result<(FileMetaData, usize, SequenceNumber)> {
+    let filename = table_file_name(dbname.as_ref(), num);
+    let (mut kbuf, mut vbuf) = (vec![], vec![]);
+    let mut firstkey: Option<Vec<u8>> = None;
+    let mut lastkey = vec![];
+    let mut entries = 0;
+    let mut max_seq = 0;
+    let cmp = InternalKeyCmp(opt.cmp.clone());
+
+    let r = (|| -> Result<()> {
+        let f = opt.env.open_writable_file(Path::new(&filename))?;
+        let mut builder = TableBuilder::new(opt.clone(), BufWriter::new(f));
+        let mut it = table.iter();
+        while it.advance() {
+            if !it.current(&mut kbuf, &mut vbuf) || kbuf.len() < 8 {
+                continue;
+            }
+            // The builder only takes keys in order.
+            if firstkey.is_some() && cmp.cmp(&lastkey, &kbuf) != Ordering::Less {
+                continue;
+            }
+            let (_, seq, _) = parse_internal_key(&kbuf);
+            if seq > max_seq {
+                max_seq = seq;
+            }
+            builder.add(&kbuf, &vbuf)?;
+            if firstkey.is_none() {
+                firstkey = Some(kbuf.clone());
+            }
+            lastkey.clone_from(&kbuf);
+            entries += 1;
+        }
+        builder.finish()?;
+        Ok(())
+    })();
+
+    if let Err(e) = r {
+        let _ = opt.env.delete(Path::new(&filename));
+        return Err(e);
+    }
+
+    let mut md = FileMetaData::default();
+    if firstkey.is_none() {
+        let _ = opt.env.delete(Path::new(&filename));
+    } else {
+        md.num = num;
+        md.size = opt.env.size_of(Path::new(&filename))?;
+        md.smallest = firstkey.unwrap();
+        md.largest = lastkey;
+    }
+    Ok((md, entries, max_seq))
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use crate::db_impl::DB;
+    use crate::env::Env;
+    use crate::options;
+
+    use std::io::{Read, Write};
+
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_repair_lost_manifest,
+            test_repair_corrupted_log,
+            test_repair_corrupted_tables,
+            test_repair_no_database,
+        )
+    }
+
+    fn files_of_type(env: &Box<dyn Env>, typ: FileType) -> Vec<PathBuf> {
+        let mut files: Vec<_> = env
+            .children(Path::new("db"))
+            .unwrap()
+            .into_iter()
+            .filter(|f| parse_file_name(f).map(|(_, t)| t == typ).unwrap_or(false))
+            .map(|f| Path::new("db").join(f))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn read_file(env: &Box<dyn Env>, p: &Path) -> Vec<u8> {
+        let mut contents = vec![];
+        env.open_sequential_file(p)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    fn write_file(env: &Box<dyn Env>, p: &Path, contents: &[u8]) {
+        env.open_writable_file(p)
+            .unwrap()
+            .write_all(contents)
+            .unwrap();
+    }
+
+    fn test_repair_lost_manifest() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..100 {
+                db.put(format!("key{:03}", i).as_bytes(), b"table").unwrap();
+            }
+            db.compact_range(b"key000", b"key100").unwrap();
+            db.put(b"key000", b"log").unwrap();
+            db.delete(b"key001").unwrap();
+            db.flush().unwrap();
+        }
+
+        let manifests = files_of_type(&env, FileType::Descriptor);
+        assert!(!manifests.is_empty());
+        for manifest in &manifests {
+            env.delete(manifest).unwrap();
+        }
+        env.delete(Path::new("db/CURRENT")).unwrap();
+
+        let stats = repair("db", opt.clone()).unwrap();
+        assert_eq!(2, stats.tables);
+        assert_eq!(102, stats.entries);
+        assert_eq!(0, stats.corrupted_records);
+        assert_eq!(0, stats.lost_files);
+        assert_eq!(1, files_of_type(&env, FileType::Descriptor).len());
+        assert!(files_of_type(&env, FileType::Log).is_empty());
+        assert!(!env.children(Path::new("db.lost")).unwrap().is_empty());
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(b"log".to_vec(), db.get(b"key000").unwrap());
+        assert_eq!(None, db.get(b"key001"));
+        assert_eq!(b"table".to_vec(), db.get(b"key099").unwrap());
+
+        // New writes are not shadowed by the repaired entries.
+        db.put(b"key000", b"new").unwrap();
+        assert_eq!(b"new".to_vec(), db.get(b"key000").unwrap());
+    }
+
+    fn test_repair_corrupted_log() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            db.put(b"key1", b"val1").unwrap();
+            db.put(b"key2", b"val2").unwrap();
+            db.put(b"key3", b"val3").unwrap();
+            db.flush().unwrap();
+        }
+
+        // Each record is a 7 byte header followed by a 23 byte batch; break the second one.
+        let log = files_of_type(&env, FileType::Log).pop().unwrap();
+        let mut contents = read_file(&env, &log);
+        assert_eq!(90, contents.len());
+        contents[30 + 7 + 20] ^= 0xff;
+        write_file(&env, &log, &contents);
+
+        let stats = repair("db", opt.clone()).unwrap();
+        assert_eq!(1, stats.tables);
+        assert_eq!(2, stats.entries);
+        assert_eq!(1, stats.corrupted_records);
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(b"val1".to_vec(), db.get(b"key1").unwrap());
+        assert_eq!(None, db.get(b"key2"));
+        assert_eq!(b"val3".to_vec(), db.get(b"key3").unwrap());
+    }
+
+    fn test_repair_corrupted_tables() {
+        let opt = options::for_test();
+        let env = opt.env.clone();
+        {
+            let mut db = DB::open("db", opt.clone()).unwrap();
+            for i in 0..200 {
+                db.put(format!("a{:03}", i).as_bytes(), &[b'x'; 100])
+                    .unwrap();
+            }
+            db.compact_range(b"a", b"b").unwrap();
+            for i in 0..200 {
+                db.put(format!("b{:03}", i).as_bytes(), &[b'y'; 100])
+                    .unwrap();
+            }
+            db.compact_range(b"b", b"c").unwrap();
+        }
+
+        let tables = files_of_type(&env, FileType::Table);
+        assert_eq!(2, tables.len());
+        // The first block of the first table fails its checksum, and the second table is
+        // truncated.
+        let mut contents = read_file(&env, &tables[0]);
+        contents[10] ^= 0xff;
+        write_file(&env, &tables[0], &contents);
+        let contents = read_file(&env, &tables[1]);
+        write_file(&env, &tables[1], &contents[..contents.len() / 2]);
+
+        let stats = repair("db", opt.clone()).unwrap();
+        assert_eq!(1, stats.tables);
+        assert_eq!(1, stats.lost_files);
+        assert!(stats.entries > 0 && stats.entries < 200);
+
+        let mut db = DB::open("db", opt.clone()).unwrap();
+        assert_eq!(None, db.get(b"a000"));
+        assert_eq!(vec![b'x'; 100], db.get(b"a199").unwrap());
+        assert_eq!(None, db.get(b"b000"));
+    }
+
+    fn test_repair_no_database() {
+        let opt = options::for_test();
+        let status = repair("nodb", opt).err().unwrap();
+        assert_eq!(StatusCode::NotFound, status.code);
+    }
+}
@@ -1,12 +1,11 @@
 use crate::cmp::{Cmp, MemtableKeyCmp};
-use crate::types::LdbIterator;
+use crate::types::{share, LdbIterator, Shared};
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
 
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::mem::{replace, size_of};
-use std::rc::Rc;
+use std::sync::Arc;
 
 const MAX_HEIGHT: usize = 12;
 const BRANCHING_FACTOR: u32 = 4;
@@ -30,26 +29,29 @@ struct InnerSkipMap {
     len: usize,
     // approximation of memory used.
     approx_mem: usize,
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
 }
 
+// The nodes are owned by the map, and only reached through it.
+unsafe impl Send for InnerSkipMap {}
+
 pub struct SkipMap {
-    map: Rc<RefCell<InnerSkipMap>>,
+    map: Shared<InnerSkipMap>,
 }
 
 impl SkipMap {
     /// Returns a SkipMap that wraps the comparator inside a MemtableKeyCmp.
-    pub fn new_memtable_map(cmp: Rc<Box<dyn Cmp>>) -> SkipMap {
-        SkipMap::new(Rc::new(Box::new(MemtableKeyCmp(cmp))))
+    pub fn new_memtable_map(cmp: Arc<Box<dyn Cmp>>) -> SkipMap {
+        SkipMap::new(Arc::new(Box::new(MemtableKeyCmp(cmp))))
     }
 
     /// Returns a SkipMap that uses the specified comparator.
-    pub fn new(cmp: Rc<Box<dyn Cmp>>) -> SkipMap {
+    pub fn new(cmp: Arc<Box<dyn Cmp>>) -> SkipMap {
         let mut s = Vec::new();
         s.resize(MAX_HEIGHT, None);
 
         SkipMap {
-            map: Rc::new(RefCell::new(InnerSkipMap {
+            map: share(InnerSkipMap {
                 head: Box::new(Node {
                     skips: s,
                     next: None,
@@ -60,7 +62,7 @@ impl SkipMap {
                 len: 0,
                 approx_mem: size_of::<Self>() + MAX_HEIGHT * size_of::<Option<*mut Node>>(),
                 cmp,
-            })),
+            }),
         }
     }
 
@@ -75,7 +77,7 @@ impl SkipMap {
     }
 
     /// inserts a key into the table. key may not be empty.
-    pub fn insert(&mut self, key: Vec<u8>, val: Vec<u8>) {
+    pub fn insert(&self, key: Vec<u8>, val: Vec<u8>) {
         assert!(!key.is_empty());
         self.map.borrow_mut().insert(key, val);
     }
@@ -288,37 +290,40 @@ impl InnerSkipMap {
 }
 
 pub struct SkipMapIter {
-    map: Rc<RefCell<InnerSkipMap>>,
+    map: Shared<InnerSkipMap>,
     current: *const Node,
 }
 
+// The iterator holds on to the map, which keeps the current node alive.
+unsafe impl Send for SkipMapIter {}
+
 impl LdbIterator for SkipMapIter {
+    // The links between the nodes are changed by inserts, so the map is held while following them.
     fn advance(&mut self) -> bool {
         // we first go to the next element, then return that -- in order to skip the head node
-        let r = unsafe {
-            (*self.current)
-                .next
-                .as_ref()
-                .map(|next| {
+        let map = self.map.borrow();
+        unsafe {
+            match (*self.current).next.as_ref() {
+                Some(next) => {
                     self.current = next.as_ref() as *const Node;
                     true
-                })
-                .unwrap_or(false)
-        };
-        if !r {
-            self.reset();
+                }
+                None => {
+                    self.current = map.head.as_ref();
+                    false
+                }
+            }
         }
-        r
     }
     fn reset(&mut self) {
         self.current = self.map.borrow().head.as_ref();
     }
     fn seek(&mut self, key: &[u8]) {
-        if let Some(node) = self.map.borrow().get_greater_or_equal(key) {
-            self.current = node as *const Node;
-            return;
-        }
-        self.reset();
+        let map = self.map.borrow();
+        self.current = match map.get_greater_or_equal(key) {
+            Some(node) => node as *const Node,
+            None => map.head.as_ref(),
+        };
     }
     fn valid(&self) -> bool {
         self.current != self.map.borrow().head.as_ref()
@@ -338,19 +343,16 @@ impl LdbIterator for SkipMapIter {
     }
     fn prev(&mut self) -> bool {
         // Going after the original implementation here; we just seek to the node before current().
-        if self.valid() {
-            if let Some(prev) = self
-                .map
-                .borrow()
-                .get_next_smaller(unsafe { &(*self.current).key })
-            {
+        let map = self.map.borrow();
+        if self.current != map.head.as_ref() {
+            if let Some(prev) = map.get_next_smaller(unsafe { &(*self.current).key }) {
                 self.current = prev as *const Node;
                 if !prev.key.is_empty() {
                     return true;
                 }
             }
         }
-        self.reset();
+        self.current = map.head.as_ref();
         false
     }
 }
@@ -383,7 +385,7 @@ pub mod tests {
     }
 
     pub fn make_skipmap() -> SkipMap {
-        let mut skm = SkipMap::new(options::for_test().cmp);
+        let skm = SkipMap::new(options::for_test().cmp);
         let keys = vec![
             "aba", "abb", "abc", "abd", "abe", "abf", "abg", "abh", "abi", "abj", "abk", "abl",
             "abm", "abn", "abo", "abp", "abq", "abr", "abs", "abt", "abu", "abv", "abw", "abx",
@@ -403,7 +405,7 @@ pub mod tests {
     }
 
     fn test_no_dupes() {
-        let mut skm = make_skipmap();
+        let skm = make_skipmap();
         // this should panic
         skm.insert("abc".as_bytes().to_vec(), "def".as_bytes().to_vec());
         skm.insert("abf".as_bytes().to_vec(), "def".as_bytes().to_vec());
@@ -488,7 +490,7 @@ pub mod tests {
 
     fn test_empty_skipmap_find_memtable_cmp() {
         // Regression test: Make sure comparator isn't called with empty key.
-        let cmp: Rc<Box<dyn Cmp>> = Rc::new(Box::new(MemtableKeyCmp(options::for_test().cmp)));
+        let cmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(MemtableKeyCmp(options::for_test().cmp)));
         let skm = SkipMap::new(cmp);
 
         let mut it = skm.iter();
@@ -567,7 +569,7 @@ pub mod tests {
     }
 
     fn test_skipmap_behavior() {
-        let mut skm = SkipMap::new(options::for_test().cmp);
+        let skm = SkipMap::new(options::for_test().cmp);
         let keys = vec!["aba", "abb", "abc", "abd"];
         for k in keys {
             skm.insert(k.as_bytes().to_vec(), "def".as_bytes().to_vec());
@@ -593,7 +595,7 @@ pub mod tests {
 
     fn test_skipmap_iterator_concurrent_insert() {
         // Asserts that the map can be mutated while an iterator exists; this is intentional.
-        let mut skm = make_skipmap();
+        let skm = make_skipmap();
         let mut iter = skm.iter();
 
         assert!(iter.advance());
@@ -78,6 +78,10 @@ pub fn read_table_block(
                 let decoded = Decoder::new().decompress_vec(&buf)?;
                 Ok(Block::new(opt, decoded))
             }
+            CompressionType::CompressionLz4 => {
+                let decoded = lz4_flex::block::decompress_size_prepended(&buf)?;
+                Ok(Block::new(opt, decoded))
+            }
         }
     } else {
         err(StatusCode::InvalidData, "invalid compression type")
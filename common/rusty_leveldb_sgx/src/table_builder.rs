@@ -11,7 +11,7 @@ use crate::options::{CompressionType, Options};
 
 use std::cmp::Ordering;
 use std::io::Write;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crc::crc32;
 use crc::Hasher32;
@@ -96,7 +96,7 @@ pub struct TableBuilder<Dst: Write> {
 
 impl<Dst: Write> TableBuilder<Dst> {
     pub fn new_no_filter(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
-        opt.filter_policy = Rc::new(Box::new(NoFilterPolicy::new()));
+        opt.filter_policy = Arc::new(Box::new(NoFilterPolicy::new()));
         TableBuilder::new(opt, dst)
     }
 }
@@ -108,8 +108,8 @@ impl<Dst: Write> TableBuilder<Dst> {
     /// The comparator in opt will be wrapped in a InternalKeyCmp, and the filter policy
     /// in an InternalFilterPolicy.
     pub fn new(mut opt: Options, dst: Dst) -> TableBuilder<Dst> {
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
-        opt.filter_policy = Rc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        opt.filter_policy = Arc::new(Box::new(InternalFilterPolicy::new(opt.filter_policy)));
         TableBuilder::new_raw(opt, dst)
     }
 
@@ -200,11 +200,7 @@ impl<Dst: Write> TableBuilder<Dst> {
 
     /// Calculates the checksum, writes the block to disk and updates the offset.
     fn write_block(&mut self, block: BlockContents, ctype: CompressionType) -> Result<BlockHandle> {
-        let mut data = block;
-        if ctype == CompressionType::CompressionSnappy {
-            let mut encoder = Encoder::new();
-            data = encoder.compress_vec(&data)?;
-        }
+        let (data, ctype) = compress_block(block, ctype)?;
 
         let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
 
@@ -270,6 +266,24 @@ impl<Dst: Write> TableBuilder<Dst> {
     }
 }
 
+/// Compresses a block with the given compression type. Like in LevelDB, the block is kept
+/// uncompressed if that saves less than 12.5% of its size.
+fn compress_block(
+    block: BlockContents,
+    ctype: CompressionType,
+) -> Result<(BlockContents, CompressionType)> {
+    let compressed = match ctype {
+        CompressionType::CompressionNone => return Ok((block, ctype)),
+        CompressionType::CompressionSnappy => Encoder::new().compress_vec(&block)?,
+        CompressionType::CompressionLz4 => lz4_flex::block::compress_prepend_size(&block),
+    };
+    if compressed.len() < block.len() - block.len() / 8 {
+        Ok((compressed, ctype))
+    } else {
+        Ok((block, CompressionType::CompressionNone))
+    }
+}
+
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
@@ -279,7 +293,7 @@ pub mod tests {
 
     pub fn run_tests() -> bool {
         should_panic!(test_bad_input());
-        run_tests!(test_footer, test_table_builder,)
+        run_tests!(test_footer, test_table_builder, test_compress_block,)
     }
 
     fn test_footer() {
@@ -326,7 +340,31 @@ pub mod tests {
         assert!(b.filter_block.is_some());
 
         let actual = b.finish().unwrap();
-        assert_eq!(223, actual);
+        assert_eq!(221, actual);
+    }
+
+    fn test_compress_block() {
+        let block = b"abcdefgh".repeat(64);
+        for ctype in [
+            CompressionType::CompressionSnappy,
+            CompressionType::CompressionLz4,
+        ] {
+            let (data, c) = compress_block(block.clone(), ctype).unwrap();
+            assert_eq!(c, ctype);
+            assert!(data.len() < block.len() / 2);
+        }
+
+        // Blocks which do not compress well are kept as they are.
+        let block = b"abcdefgh".to_vec();
+        for ctype in [
+            CompressionType::CompressionNone,
+            CompressionType::CompressionSnappy,
+            CompressionType::CompressionLz4,
+        ] {
+            let (data, c) = compress_block(block.clone(), ctype).unwrap();
+            assert_eq!(c, CompressionType::CompressionNone);
+            assert_eq!(data, block);
+        }
     }
 
     fn test_bad_input() {
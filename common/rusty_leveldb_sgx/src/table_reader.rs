@@ -3,7 +3,7 @@ use crate::blockhandle::BlockHandle;
 use crate::cache;
 use crate::cmp::InternalKeyCmp;
 use crate::env::RandomAccess;
-use crate::error::Result;
+use crate::error::{err, Result, StatusCode};
 use crate::filter;
 use crate::filter_block::FilterBlockReader;
 use crate::key_types::InternalKey;
@@ -13,20 +13,33 @@ use crate::table_builder::{self, Footer};
 use crate::types::{current_key_val, LdbIterator};
 
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use integer_encoding::FixedIntWriter;
 
 /// Reads the table footer.
 fn read_footer(f: &dyn RandomAccess, size: usize) -> Result<Footer> {
+    if size < table_builder::FULL_FOOTER_LENGTH {
+        return err(StatusCode::Corruption, "file is too short to be a table");
+    }
     let mut buf = vec![0; table_builder::FULL_FOOTER_LENGTH];
     f.read_at(size - table_builder::FULL_FOOTER_LENGTH, &mut buf)?;
-    Ok(Footer::decode(&buf))
+    if buf[table_builder::FOOTER_LENGTH..] != table_builder::MAGIC_FOOTER_ENCODED {
+        return err(StatusCode::Corruption, "bad magic number in table footer");
+    }
+    let footer = Footer::decode(&buf);
+    // Don't allocate blocks for handles pointing past the end of the file.
+    for handle in &[&footer.meta_index, &footer.index] {
+        if handle.offset() + handle.size() > size {
+            return err(StatusCode::Corruption, "table footer points past the end");
+        }
+    }
+    Ok(footer)
 }
 
 #[derive(Clone)]
 pub struct Table {
-    file: Rc<Box<dyn RandomAccess>>,
+    file: Arc<Box<dyn RandomAccess>>,
     file_size: usize,
     cache_id: cache::CacheID,
 
@@ -39,7 +52,7 @@ pub struct Table {
 
 impl Table {
     /// Creates a new table reader operating on unformatted keys (i.e., UserKey).
-    fn new_raw(opt: Options, file: Rc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
+    fn new_raw(opt: Options, file: Arc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
         let footer = read_footer(file.as_ref().as_ref(), size)?;
         let indexblock =
             table_block::read_table_block(opt.clone(), file.as_ref().as_ref(), &footer.index)?;
@@ -90,9 +103,9 @@ impl Table {
     /// Creates a new table reader operating on internal keys (i.e., InternalKey). This means that
     /// a different comparator (internal_key_cmp) and a different filter policy
     /// (InternalFilterPolicy) are used.
-    pub fn new(mut opt: Options, file: Rc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
-        opt.filter_policy = Rc::new(Box::new(filter::InternalFilterPolicy::new(
+    pub fn new(mut opt: Options, file: Arc<Box<dyn RandomAccess>>, size: usize) -> Result<Table> {
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(opt.cmp.clone())));
+        opt.filter_policy = Arc::new(Box::new(filter::InternalFilterPolicy::new(
             opt.filter_policy,
         )));
         Table::new_raw(opt, file, size)
@@ -119,11 +132,11 @@ impl Table {
             return Ok(block.clone());
         }
 
-        // Two times as_ref(): First time to get a ref from Rc<>, then one from Box<>.
+        // Two times as_ref(): First time to get a ref from Arc<>, then one from Box<>.
         let b =
             table_block::read_table_block(self.opt.clone(), self.file.as_ref().as_ref(), location)?;
 
-        // insert a cheap copy (Rc).
+        // insert a cheap copy (Arc).
         self.opt
             .block_cache
             .borrow_mut()
@@ -375,6 +388,8 @@ pub mod tests {
             test_table_get,
             test_table_internal_keys,
             test_table_reader_checksum,
+            test_table_reader_bad_footer,
+            test_table_compression,
         )
     }
 
@@ -417,13 +432,32 @@ pub mod tests {
         (d, size)
     }
 
+    // Build a table with compressible values and the given compression type.
+    fn build_compressible_table(ctype: CompressionType) -> (Vec<u8>, usize) {
+        let mut d = Vec::with_capacity(4096);
+        let mut opt = options::for_test();
+        opt.block_size = 512;
+        opt.compression_type = ctype;
+
+        {
+            let mut b = TableBuilder::new_raw(opt, &mut d);
+            for (k, v) in build_data() {
+                b.add(k.as_bytes(), v.repeat(100).as_bytes()).unwrap();
+            }
+            b.finish().unwrap();
+        }
+
+        let size = d.len();
+        (d, size)
+    }
+
     // Build a table containing keys in InternalKey format.
     fn build_internal_table() -> (Vec<u8>, usize) {
         let mut d = Vec::with_capacity(512);
         let mut opt = options::for_test();
         opt.block_restart_interval = 1;
         opt.block_size = 32;
-        opt.filter_policy = Rc::new(Box::new(BloomPolicy::new(4)));
+        opt.filter_policy = Arc::new(Box::new(BloomPolicy::new(4)));
 
         let mut i = 1 as u64;
         let data: Vec<(Vec<u8>, &'static str)> = build_data()
@@ -450,8 +484,8 @@ pub mod tests {
         (d, size)
     }
 
-    fn wrap_buffer(src: Vec<u8>) -> Rc<Box<dyn RandomAccess>> {
-        Rc::new(Box::new(src))
+    fn wrap_buffer(src: Vec<u8>) -> Arc<Box<dyn RandomAccess>> {
+        Arc::new(Box::new(src))
     }
 
     fn test_table_approximate_offset() {
@@ -461,7 +495,7 @@ pub mod tests {
         let table = Table::new_raw(opt, wrap_buffer(src), size).unwrap();
         let mut iter = table.iter();
 
-        let expected_offsets = vec![0, 0, 0, 44, 44, 44, 89];
+        let expected_offsets = vec![0, 0, 0, 42, 42, 42, 86];
         let mut i = 0;
         for (k, _) in LdbIteratorIter::wrap(&mut iter) {
             assert_eq!(expected_offsets[i], table.approx_offset_of(&k));
@@ -469,7 +503,7 @@ pub mod tests {
         }
 
         // Key-past-last returns offset of metaindex block.
-        assert_eq!(137, table.approx_offset_of("{aa".as_bytes()));
+        assert_eq!(132, table.approx_offset_of("{aa".as_bytes()));
     }
 
     fn test_table_block_cache_use() {
@@ -751,4 +785,45 @@ pub mod tests {
             panic!("Should have hit 5th record in table!");
         }
     }
+
+    fn test_table_reader_bad_footer() {
+        let (src, size) = build_table(build_data());
+
+        let short = Table::new_raw(options::for_test(), wrap_buffer(src.clone()), 10);
+        assert_eq!(StatusCode::Corruption, short.err().unwrap().code);
+
+        let mut bad_magic = src.clone();
+        bad_magic[size - 1] += 1;
+        let table = Table::new_raw(options::for_test(), wrap_buffer(bad_magic), size);
+        assert_eq!(StatusCode::Corruption, table.err().unwrap().code);
+
+        // A table cut in the middle, with the footer still pointing to the original blocks
+        let mut cut = src[..size / 2].to_vec();
+        cut.extend_from_slice(&src[size - table_builder::FULL_FOOTER_LENGTH..size]);
+        let len = cut.len();
+        let table = Table::new_raw(options::for_test(), wrap_buffer(cut), len);
+        assert_eq!(StatusCode::Corruption, table.err().unwrap().code);
+    }
+
+    fn test_table_compression() {
+        let (_, uncompressed_size) = build_compressible_table(CompressionType::CompressionNone);
+
+        for ctype in [
+            CompressionType::CompressionSnappy,
+            CompressionType::CompressionLz4,
+        ] {
+            let (src, size) = build_compressible_table(ctype);
+            assert!(size < uncompressed_size / 2);
+
+            let table = Table::new_raw(options::for_test(), wrap_buffer(src), size).unwrap();
+            let mut _iter = table.iter();
+            let entries: Vec<_> = LdbIteratorIter::wrap(&mut _iter).collect();
+            assert_eq!(entries.len(), build_data().len());
+            for ((k, v), (ek, ev)) in entries.into_iter().zip(build_data()) {
+                assert_eq!(k, ek.as_bytes());
+                assert_eq!(v, ev.repeat(100).as_bytes());
+                assert_eq!(Ok(Some((k.clone(), v))), table.get(&k));
+            }
+        }
+    }
 }
@@ -2,9 +2,8 @@
 
 use crate::error::{err, Result, StatusCode};
 
-use std::cell::RefCell;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 pub const NUM_LEVELS: usize = 7;
 
@@ -17,11 +16,27 @@ pub type SequenceNumber = u64;
 
 pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
 
-/// A shared thingy with interior mutability.
-pub type Shared<T> = Rc<RefCell<T>>;
+/// A shared thingy with interior mutability, which can be sent to other threads.
+pub type Shared<T> = Arc<SharedCell<T>>;
 
-pub fn share<T>(t: T) -> Rc<RefCell<T>> {
-    Rc::new(RefCell::new(t))
+pub fn share<T>(t: T) -> Shared<T> {
+    Arc::new(SharedCell(Mutex::new(t)))
+}
+
+/// SharedCell offers the borrowing interface of a RefCell on top of a mutex. Unlike with a
+/// RefCell, a second borrow waits for the first one to end instead of panicking, so a cell must
+/// not be borrowed twice at once, not even to read it: copy out what is needed instead. A cell
+/// stays usable after a thread panicked while borrowing it.
+#[derive(Debug)]
+pub struct SharedCell<T>(Mutex<T>);
+
+impl<T> SharedCell<T> {
+    pub fn borrow(&self) -> MutexGuard<T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    pub fn borrow_mut(&self) -> MutexGuard<T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
 #[derive(PartialEq)]
@@ -7,7 +7,7 @@ use crate::types::{FileMetaData, FileNum, LdbIterator, Shared, MAX_SEQUENCE_NUMB
 
 use std::cmp::Ordering;
 use std::default::Default;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// FileMetaHandle is a reference-counted FileMetaData object with interior mutability. This is
 /// necessary to provide a shared metadata container that can be modified while referenced by e.g.
@@ -20,9 +20,10 @@ pub struct GetStats {
     level: usize,
 }
 
+#[derive(Clone)]
 pub struct Version {
     table_cache: Shared<TableCache>,
-    user_cmp: Rc<Box<dyn Cmp>>,
+    user_cmp: Arc<Box<dyn Cmp>>,
     pub files: [Vec<FileMetaHandle>; NUM_LEVELS],
 
     pub file_to_compact: Option<FileMetaHandle>,
@@ -32,7 +33,7 @@ pub struct Version {
 }
 
 impl Version {
-    pub fn new(cache: Shared<TableCache>, ucmp: Rc<Box<dyn Cmp>>) -> Version {
+    pub fn new(cache: Shared<TableCache>, ucmp: Arc<Box<dyn Cmp>>) -> Version {
         Version {
             table_cache: cache,
             user_cmp: ucmp,
@@ -121,7 +122,10 @@ impl Version {
             }
         }
         // Sort by newest first.
-        levels[0].sort_by(|a, b| b.borrow().num.cmp(&a.borrow().num));
+        levels[0].sort_by(|a, b| {
+            let a = a.borrow().num;
+            b.borrow().num.cmp(&a)
+        });
 
         let icmp = InternalKeyCmp(self.user_cmp.clone());
         for level in 1..NUM_LEVELS {
@@ -148,7 +152,10 @@ impl Version {
             }
             let filedesc: Vec<(FileNum, usize)> = fs
                 .iter()
-                .map(|f| (f.borrow().num, f.borrow().size))
+                .map(|f| {
+                    let f = f.borrow();
+                    (f.num, f.size)
+                })
                 .collect();
             let desc = format!(
                 "level {}: {} files, {} bytes ({:?}); ",
@@ -162,6 +169,25 @@ impl Version {
         acc
     }
 
+    /// sstables_summary lists the tables of every level with their key ranges.
+    pub fn sstables_summary(&self) -> String {
+        let mut acc = String::with_capacity(256);
+        for level in 0..NUM_LEVELS {
+            acc.push_str(&format!("--- level {} ---\n", level));
+            for f in &self.files[level] {
+                let f = f.borrow();
+                acc.push_str(&format!(
+                    " {}:{}[{} .. {}]\n",
+                    f.num,
+                    f.size,
+                    describe_internal_key(&f.smallest),
+                    describe_internal_key(&f.largest)
+                ));
+            }
+        }
+        acc
+    }
+
     pub fn pick_memtable_output_level<'a, 'b>(&self, min: UserKey<'a>, max: UserKey<'b>) -> usize {
         let mut level = 0;
         if !self.overlap_in_level(0, min, max) {
@@ -386,7 +412,7 @@ impl Version {
 pub fn new_version_iter(
     files: Vec<FileMetaHandle>,
     cache: Shared<TableCache>,
-    ucmp: Rc<Box<dyn Cmp>>,
+    ucmp: Arc<Box<dyn Cmp>>,
 ) -> VersionIter {
     VersionIter {
         files,
@@ -402,7 +428,7 @@ pub fn new_version_iter(
 ///
 /// Note that VersionIter returns entries of type Deletion.
 pub struct VersionIter {
-    // NOTE: Maybe we need to change this to Rc to support modification of the file set after
+    // NOTE: Maybe we need to change this to Arc to support modification of the file set after
     // creation of the iterator. Versions should be immutable, though.
     files: Vec<FileMetaHandle>,
     cache: Shared<TableCache>,
@@ -500,6 +526,17 @@ pub fn total_size<'a, I: Iterator<Item = &'a FileMetaHandle>>(files: I) -> usize
     files.fold(0, |a, f| a + f.borrow().size)
 }
 
+/// describe_internal_key formats an internal key as 'user key' @ sequence : type.
+fn describe_internal_key(ikey: &[u8]) -> String {
+    let (typ, seq, ukey) = parse_internal_key(ikey);
+    format!(
+        "'{}' @ {} : {}",
+        String::from_utf8_lossy(ukey),
+        seq,
+        typ as u8
+    )
+}
+
 /// key_is_after_file returns true if the given user key is larger than the largest key in f.
 fn key_is_after_file<'a>(cmp: &InternalKeyCmp, key: UserKey<'a>, f: &FileMetaHandle) -> bool {
     let f = f.borrow();
@@ -708,7 +745,7 @@ pub mod testutil {
         let t9 = write_table(&env, f9, 1, 9);
 
         let cache = TableCache::new("db", opts.clone(), 100);
-        let mut v = Version::new(share(cache), Rc::new(Box::new(DefaultCmp)));
+        let mut v = Version::new(share(cache), Arc::new(Box::new(DefaultCmp)));
         v.files[0] = vec![t1, t2];
         v.files[1] = vec![t3, t4, t5];
         v.files[2] = vec![t6, t7];
@@ -771,14 +808,14 @@ pub mod tests {
         let v = make_version().0;
         let iters = v.new_iters().unwrap();
         let mut opt = options::for_test();
-        opt.cmp = Rc::new(Box::new(InternalKeyCmp(Rc::new(Box::new(DefaultCmp)))));
+        opt.cmp = Arc::new(Box::new(InternalKeyCmp(Arc::new(Box::new(DefaultCmp)))));
 
         let mut miter = MergingIter::new(opt.cmp.clone(), iters);
         assert_eq!(LdbIteratorIter::wrap(&mut miter).count(), 30);
 
         // Check that all elements are in order.
         let init = LookupKey::new("000".as_bytes(), MAX_SEQUENCE_NUMBER);
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
         LdbIteratorIter::wrap(&mut miter).fold(init.internal_key().to_vec(), |b, (k, _)| {
             assert!(cmp.cmp(&b, &k) == Ordering::Less);
             k
@@ -791,6 +828,13 @@ pub mod tests {
                         bytes ([(3, 218), (4, 216), (5, 217)]); level 2: 2 files, 468 bytes ([(6, \
                         218), (7, 250)]); level 3: 2 files, 400 bytes ([(8, 200), (9, 200)]); ";
         assert_eq!(expected, &v.level_summary());
+
+        let sstables = v.sstables_summary();
+        assert!(sstables.starts_with(
+            "--- level 0 ---\n 1:232['aaa' @ 22 : 1 .. 'aba' @ 25 : 1]\n 2:251['aac' @ 26 : 1 .. \
+             'bba' @ 30 : 1]\n--- level 1 ---\n"
+        ));
+        assert!(sstables.ends_with("--- level 6 ---\n"));
     }
 
     fn test_version_get_simple() {
@@ -934,7 +978,7 @@ pub mod tests {
 
     fn test_version_key_ordering() {
         let fmh = new_file(1, &[1, 0, 0], 0, &[2, 0, 0], 1);
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
 
         // Keys before file.
         for k in &[&[0][..], &[1], &[1, 0], &[0, 9, 9, 9]] {
@@ -970,7 +1014,7 @@ pub mod tests {
             new_file(2, &[2, 5, 0], 0, &[4, 0, 0], 1),
             new_file(3, &[3, 5, 1], 0, &[5, 0, 0], 1),
         ];
-        let cmp = InternalKeyCmp(Rc::new(Box::new(DefaultCmp)));
+        let cmp = InternalKeyCmp(Arc::new(Box::new(DefaultCmp)));
 
         assert!(some_file_overlaps_range(
             &cmp,
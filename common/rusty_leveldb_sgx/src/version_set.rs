@@ -7,7 +7,8 @@ use crate::merging_iter::MergingIter;
 use crate::options::Options;
 use crate::table_cache::TableCache;
 use crate::types::{
-    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, NUM_LEVELS,
+    parse_file_name, share, FileMetaData, FileNum, FileType, LdbIterator, Shared, SharedCell,
+    NUM_LEVELS,
 };
 use crate::version::{new_version_iter, total_size, FileMetaHandle, Version};
 use crate::version_edit::VersionEdit;
@@ -16,7 +17,7 @@ use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::{Arc, Weak};
 
 use std::os::unix::ffi::OsStrExt;
 
@@ -25,7 +26,7 @@ pub struct Compaction {
     max_file_size: usize,
     input_version: Option<Shared<Version>>,
     level_ixs: [usize; NUM_LEVELS],
-    cmp: Rc<Box<dyn Cmp>>,
+    cmp: Arc<Box<dyn Cmp>>,
     icmp: InternalKeyCmp,
 
     manual: bool,
@@ -179,9 +180,12 @@ pub struct VersionSet {
     pub prev_log_num: u64,
 
     current: Option<Shared<Version>>,
+    // Files of earlier versions. They are kept while they are referenced, e.g. by an iterator or
+    // a version held by another thread.
+    old_files: Vec<(FileNum, Weak<SharedCell<FileMetaData>>)>,
     compaction_ptrs: [Vec<u8>; NUM_LEVELS],
 
-    descriptor_log: Option<LogWriter<Box<dyn Write>>>,
+    descriptor_log: Option<LogWriter<Box<dyn Write + Send>>>,
 }
 
 impl VersionSet {
@@ -202,6 +206,7 @@ impl VersionSet {
             prev_log_num: 0,
 
             current: Some(v),
+            old_files: vec![],
             compaction_ptrs: Default::default(),
             descriptor_log: None,
         }
@@ -221,6 +226,11 @@ impl VersionSet {
                 }
             }
         }
+        for (num, file) in &self.old_files {
+            if file.strong_count() > 0 {
+                files.insert(*num);
+            }
+        }
         files
     }
 
@@ -232,7 +242,15 @@ impl VersionSet {
     }
 
     pub fn add_version(&mut self, v: Version) {
-        self.current = Some(share(v));
+        self.old_files.retain(|(_, f)| f.strong_count() > 0);
+        if let Some(old) = self.current.replace(share(v)) {
+            for level in 0..NUM_LEVELS {
+                for file in &old.borrow().files[level] {
+                    self.old_files
+                        .push((file.borrow().num, Arc::downgrade(file)));
+                }
+            }
+        }
     }
 
     pub fn new_file_number(&mut self) -> FileNum {
@@ -260,7 +278,11 @@ impl VersionSet {
         v.compaction_score.unwrap_or(0.0) >= 1.0 || v.file_to_compact.is_some()
     }
 
-    fn approximate_offset<'a>(&self, v: &Shared<Version>, key: InternalKey<'a>) -> usize {
+    pub(crate) fn approximate_offset<'a>(
+        &self,
+        v: &Shared<Version>,
+        key: InternalKey<'a>,
+    ) -> usize {
         let mut offset = 0;
         for level in 0..NUM_LEVELS {
             for f in &v.borrow().files[level] {
@@ -327,6 +349,8 @@ impl VersionSet {
             c.inputs[0] = current.overlapping_inputs(0, &smallest, &largest);
             assert!(!c.inputs[0].is_empty());
         }
+        // setup_other_inputs() borrows the current version again.
+        drop(current);
 
         self.setup_other_inputs(&mut c);
         Some(c)
@@ -431,11 +455,7 @@ impl VersionSet {
         // Set the list of grandparent (l+2) inputs to the files overlapped by the current overall
         // range.
         if level + 2 < NUM_LEVELS {
-            let grandparents = self.current.as_ref().unwrap().borrow().overlapping_inputs(
-                level + 2,
-                &allstart,
-                &alllimit,
-            );
+            let grandparents = current.overlapping_inputs(level + 2, &allstart, &alllimit);
             compaction.grandparents = Some(grandparents);
         }
 
@@ -479,6 +499,13 @@ impl VersionSet {
             .add_record(&edit.encode())
     }
 
+    /// roll_manifest makes the next log_and_apply() write a snapshot to a new manifest instead of
+    /// appending to the current one.
+    pub fn roll_manifest(&mut self) {
+        self.descriptor_log = None;
+        self.manifest_num = self.new_file_number();
+    }
+
     /// log_and_apply merges the given edit with the current state and generates a new version. It
     /// writes the VersionEdit to the manifest.
     pub fn log_and_apply(&mut self, mut edit: VersionEdit) -> Result<()> {
@@ -686,6 +713,12 @@ impl VersionSet {
             } else {
                 return false;
             }
+            // A manifest written under an earlier key is replaced by one under the current key.
+            let env = &self.opt.env;
+            match env.key_generation(Path::new(current_manifest_path)) {
+                Ok(generation) if generation == env.current_key_generation() => {}
+                _ => return false,
+            }
 
             assert!(self.descriptor_log.is_none());
             let s = self
@@ -738,7 +771,7 @@ impl VersionSet {
             }
         }
         assert!(iters.len() <= cap);
-        let cmp: Rc<Box<dyn Cmp>> = Rc::new(Box::new(self.cmp.clone()));
+        let cmp: Arc<Box<dyn Cmp>> = Arc::new(Box::new(self.cmp.clone()));
         Box::new(MergingIter::new(cmp, iters))
     }
 }
@@ -798,13 +831,8 @@ impl Builder {
             let files = &v.files[level];
             if level > 0 && !files.is_empty() {
                 // File must be after last file in level.
-                assert_eq!(
-                    cmp.cmp(
-                        &files[files.len() - 1].borrow().largest,
-                        &f.borrow().smallest
-                    ),
-                    Ordering::Less
-                );
+                let last_largest = files[files.len() - 1].borrow().largest.clone();
+                assert_eq!(cmp.cmp(&last_largest, &f.borrow().smallest), Ordering::Less);
             }
         }
         v.files[level].push(f);
@@ -824,9 +852,7 @@ impl Builder {
 
             let iadded = added.into_iter();
             let ibasefiles = basefiles.into_iter();
-            let merged = merge_iters(iadded, ibasefiles, |a, b| {
-                cmp.cmp(&a.borrow().smallest, &b.borrow().smallest)
-            });
+            let merged = merge_iters(iadded, ibasefiles, |a, b| cmp_smallest(cmp, a, b));
             for m in merged {
                 self.maybe_add_file(cmp, v, level, m);
             }
@@ -836,11 +862,9 @@ impl Builder {
                 continue;
             }
             for i in 1..v.files[level].len() {
-                let (prev_end, this_begin) = (
-                    &v.files[level][i - 1].borrow().largest,
-                    &v.files[level][i].borrow().smallest,
-                );
-                assert!(cmp.cmp(prev_end, this_begin) < Ordering::Equal);
+                let prev_end = v.files[level][i - 1].borrow().largest.clone();
+                let this_begin = &v.files[level][i].borrow().smallest;
+                assert!(cmp.cmp(&prev_end, this_begin) < Ordering::Equal);
             }
         }
     }
@@ -902,7 +926,16 @@ pub fn set_current_file<P: AsRef<Path>>(
 
 /// sort_files_by_smallest sorts the list of files by the smallest keys of the files.
 fn sort_files_by_smallest<C: Cmp>(cmp: &C, files: &mut Vec<FileMetaHandle>) {
-    files.sort_by(|a, b| cmp.cmp(&a.borrow().smallest, &b.borrow().smallest))
+    files.sort_by(|a, b| cmp_smallest(cmp, a, b))
+}
+
+/// cmp_smallest compares the smallest keys of two files. Both handles may be the same file, which
+/// must not be borrowed twice at once.
+fn cmp_smallest<C: Cmp>(cmp: &C, a: &FileMetaHandle, b: &FileMetaHandle) -> Ordering {
+    if Arc::ptr_eq(a, b) {
+        return Ordering::Equal;
+    }
+    cmp.cmp(&a.borrow().smallest, &b.borrow().smallest)
 }
 
 /// merge_iters merges and collects the items from two sorted iterators.
@@ -992,6 +1025,7 @@ pub mod tests {
             test_version_set_builder,
             test_version_set_log_and_apply,
             test_version_set_utils,
+            test_version_set_live_files,
             test_version_set_pick_compaction,
             test_version_set_compaction,
         )
@@ -1180,6 +1214,21 @@ pub mod tests {
         assert_eq!(3, vs.new_file_number());
     }
 
+    fn test_version_set_live_files() {
+        let (v, opt) = make_version();
+        let mut vs = VersionSet::new("db", opt.clone(), share(TableCache::new("db", opt, 100)));
+        let f = v.files[1][0].clone();
+        vs.add_version(v);
+        vs.add_version(Version::new(vs.cache.clone(), vs.opt.cmp.clone()));
+
+        // Files of replaced versions are live while they are referenced.
+        let num = f.borrow().num;
+        assert_eq!(1, vs.live_files().len());
+        assert!(vs.live_files().contains(&num));
+        drop(f);
+        assert!(vs.live_files().is_empty());
+    }
+
     fn test_version_set_pick_compaction() {
         let (mut v, opt) = make_version();
         let mut vs = VersionSet::new("db", opt.clone(), share(TableCache::new("db", opt, 100)));
@@ -1216,7 +1265,7 @@ pub mod tests {
 
     /// iterator_properties tests that it contains len elements and that they are ordered in
     /// ascending order by cmp.
-    fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Rc<Box<dyn Cmp>>) {
+    fn iterator_properties<It: LdbIterator>(mut it: It, len: usize, cmp: Arc<Box<dyn Cmp>>) {
         let mut wr = LdbIteratorIter::wrap(&mut it);
         let first = wr.next().unwrap();
         let mut count = 1;
@@ -1274,7 +1323,7 @@ pub mod tests {
             iterator_properties(
                 vs.make_input_iterator(&c),
                 12,
-                Rc::new(Box::new(vs.cmp.clone())),
+                Arc::new(Box::new(vs.cmp.clone())),
             );
 
             // Expand input range on higher level.
@@ -1289,7 +1338,7 @@ pub mod tests {
             iterator_properties(
                 vs.make_input_iterator(&c),
                 12,
-                Rc::new(Box::new(vs.cmp.clone())),
+                Arc::new(Box::new(vs.cmp.clone())),
             );
 
             // is_trivial_move
@@ -93,7 +93,7 @@ impl WriteBatch {
         }
     }
 
-    pub fn insert_into_memtable(&self, mut seq: SequenceNumber, mt: &mut MemTable) {
+    pub fn insert_into_memtable(&self, mut seq: SequenceNumber, mt: &MemTable) {
         for (k, v) in self.iter() {
             match v {
                 Some(v_) => mt.add(seq, ValueType::TypeValue, k, v_),
@@ -28,4 +28,6 @@ pub(crate) enum ConfigSource {
 pub mod build;
 mod runtime;
 
-pub use runtime::RuntimeConfig;
+pub use runtime::{
+    ExecutionConfig, FairSharePolicy, RuntimeConfig, SchedulerConfig, StorageConfig,
+};
@@ -36,6 +36,12 @@ pub struct RuntimeConfig {
     pub audit: AuditConfig,
     pub attestation: AttestationServiceConfig,
     pub mount: MountConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -90,6 +96,58 @@ pub struct MountConfig {
     pub fusion_base_dir: PathBuf,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SchedulerConfig {
+    // in seconds, applies to tasks created without a max_execution_time
+    pub default_max_execution_time: u64,
+    #[serde(default)]
+    pub fair_share: FairSharePolicy,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            default_max_execution_time: 3600,
+            fair_share: FairSharePolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExecutionConfig {
+    // number of tasks each execution service runs at once
+    pub worker_slots: u32,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        Self { worker_slots: 1 }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StorageConfig {
+    // the storage service is a follower of this primary if set
+    pub primary_address: Option<String>,
+}
+
+/// How executors are shared between the queued tasks of the same priority.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FairSharePolicy {
+    // each task creator gets a fair share
+    Creator,
+    // each data owner attribute gets a fair share, tasks created without one
+    // fall back to their creator
+    DataOwner,
+}
+
+impl Default for FairSharePolicy {
+    fn default() -> Self {
+        FairSharePolicy::Creator
+    }
+}
+
 impl RuntimeConfig {
     pub fn from_toml<T: AsRef<Path>>(path: T) -> Result<Self> {
         let contents = fs::read_to_string(path.as_ref())
@@ -160,5 +218,9 @@ fn validate_config(config: &RuntimeConfig) -> Result<()> {
         bail!("Invalid URL of attestation service");
     }
 
+    if config.execution.worker_slots == 0 {
+        bail!("Execution service needs at least one worker slot");
+    }
+
     Ok(())
 }
@@ -27,6 +27,40 @@ use anyhow::{bail, Result};
 #[derive(Default)]
 pub struct BuiltinFunctionExecutor;
 
+impl BuiltinFunctionExecutor {
+    /// Names of the builtin functions compiled into the executor.
+    pub fn functions() -> Vec<&'static str> {
+        vec![
+            #[cfg(feature = "builtin_echo")]
+            Echo::NAME,
+            #[cfg(feature = "builtin_gbdt_predict")]
+            GbdtPredict::NAME,
+            #[cfg(feature = "builtin_gbdt_train")]
+            GbdtTrain::NAME,
+            #[cfg(feature = "builtin_logistic_regression_train")]
+            LogisticRegressionTrain::NAME,
+            #[cfg(feature = "builtin_logistic_regression_predict")]
+            LogisticRegressionPredict::NAME,
+            #[cfg(feature = "builtin_online_decrypt")]
+            OnlineDecrypt::NAME,
+            #[cfg(feature = "builtin_private_join_and_compute")]
+            PrivateJoinAndCompute::NAME,
+            #[cfg(feature = "builtin_ordered_set_join")]
+            OrderedSetJoin::NAME,
+            #[cfg(feature = "builtin_ordered_set_intersect")]
+            OrderedSetIntersect::NAME,
+            #[cfg(feature = "builtin_rsa_sign")]
+            RsaSign::NAME,
+            #[cfg(feature = "builtin_principal_components_analysis")]
+            PrincipalComponentsAnalysis::NAME,
+            #[cfg(feature = "builtin_face_detection")]
+            FaceDetection::NAME,
+            #[cfg(feature = "builtin_password_check")]
+            PasswordCheck::NAME,
+        ]
+    }
+}
+
 impl TeaclaveExecutor for BuiltinFunctionExecutor {
     fn execute(
         &self,
@@ -23,7 +23,7 @@ pub use interceptor::{CredentialService, UserCredential};
 
 pub use tonic::{
     async_trait, metadata::MetadataMap, service::interceptor::InterceptedService, Code,
-    IntoRequest, Request, Response, Status,
+    IntoRequest, Request, Response, Status, Streaming,
 };
 pub mod transport {
     pub use tonic::transport::*;
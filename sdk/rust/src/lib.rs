@@ -32,18 +32,26 @@ use teaclave_proto::teaclave_authentication_service_proto::{
 };
 pub use teaclave_proto::teaclave_frontend_service::GetFunctionResponse as Function;
 pub use teaclave_proto::teaclave_frontend_service::{
-    ApproveTaskRequest, AssignDataRequest, CancelTaskRequest, CreateTaskRequest,
-    CreateTaskResponse, GetFunctionRequest, GetFunctionResponse, GetFunctionUsageStatsRequest,
-    GetFunctionUsageStatsResponse, GetTaskRequest, GetTaskResponse, InvokeTaskRequest,
-    QueryAuditLogsRequest, QueryAuditLogsResponse, RegisterFunctionRequest,
-    RegisterFunctionRequestBuilder, RegisterFunctionResponse, RegisterFusionOutputRequest,
-    RegisterFusionOutputResponse, RegisterInputFileRequest, RegisterInputFileResponse,
-    RegisterInputFromOutputRequest, RegisterInputFromOutputResponse, RegisterOutputFileRequest,
-    RegisterOutputFileResponse,
+    ApproveRecurringTaskRequest, ApproveTaskRequest, ApproveWorkflowRequest, AssignDataRequest,
+    BackupDatabaseRequest, CancelTaskRequest, CreateRecurringTaskRequest,
+    CreateRecurringTaskResponse, CreateTaskRequest, CreateTaskResponse, CreateWorkflowRequest,
+    CreateWorkflowResponse, DeleteRecurringTaskRequest, DrainExecutorRequest,
+    GetDatabaseStatsRequest, GetDatabaseStatsResponse, GetFunctionRequest, GetFunctionResponse,
+    GetFunctionUsageStatsRequest, GetFunctionUsageStatsResponse, GetQueueStateRequest,
+    GetQueueStateResponse, GetRecurringTaskRequest, GetRecurringTaskResponse, GetTaskRequest,
+    GetTaskResponse, GetWorkflowRequest, GetWorkflowResponse, InputFileSummary, InvokeTaskRequest,
+    InvokeWorkflowRequest, ListExecutorsRequest, ListExecutorsResponse, ListInputFilesRequest,
+    ListInputFilesResponse, ListOutputFilesRequest, ListOutputFilesResponse, ListTasksRequest,
+    ListTasksResponse, OutputFileSummary, QueryAuditLogsRequest, QueryAuditLogsResponse,
+    RegisterFunctionRequest, RegisterFunctionRequestBuilder, RegisterFunctionResponse,
+    RegisterFusionOutputRequest, RegisterFusionOutputResponse, RegisterInputFileRequest,
+    RegisterInputFileResponse, RegisterInputFromOutputRequest, RegisterInputFromOutputResponse,
+    RegisterOutputFileRequest, RegisterOutputFileResponse, RestoreDatabaseRequest,
+    RotateDatabaseKeyRequest, TaskRelation, TaskSummary,
 };
 pub use teaclave_types::{
     EnclaveInfo, Entry, Executor, FileCrypto, FunctionArgument, FunctionInput, FunctionOutput,
-    FunctionUsage, TaskResult,
+    FunctionUsage, TaskResult, TaskSchedule,
 };
 
 pub mod bindings;
@@ -442,6 +450,52 @@ impl FrontendClient {
         Ok(response.data_id)
     }
 
+    pub fn list_input_files_with_request(
+        &mut self,
+        request: ListInputFilesRequest,
+    ) -> Result<ListInputFilesResponse> {
+        do_request_with_credential!(self, list_input_files, request)
+    }
+
+    /// Returns all input files of the user, going through every page of the
+    /// listing.
+    pub fn list_input_files(&mut self) -> Result<Vec<InputFileSummary>> {
+        let mut files = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let request = ListInputFilesRequest::new().page_token(page_token);
+            let response = self.list_input_files_with_request(request)?;
+            files.extend(response.files);
+            if response.next_page_token.is_empty() {
+                return Ok(files);
+            }
+            page_token = response.next_page_token;
+        }
+    }
+
+    pub fn list_output_files_with_request(
+        &mut self,
+        request: ListOutputFilesRequest,
+    ) -> Result<ListOutputFilesResponse> {
+        do_request_with_credential!(self, list_output_files, request)
+    }
+
+    /// Returns all output files of the user, including the fusion outputs
+    /// shared with others.
+    pub fn list_output_files(&mut self) -> Result<Vec<OutputFileSummary>> {
+        let mut files = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let request = ListOutputFilesRequest::new().page_token(page_token);
+            let response = self.list_output_files_with_request(request)?;
+            files.extend(response.files);
+            if response.next_page_token.is_empty() {
+                return Ok(files);
+            }
+            page_token = response.next_page_token;
+        }
+    }
+
     pub fn create_task_serialized(&mut self, serialized_request: &str) -> Result<String> {
         let request = serde_json::from_str(serialized_request)?;
         let response = self.create_task_with_request(request)?;
@@ -592,6 +646,29 @@ impl FrontendClient {
         }
     }
 
+    pub fn list_tasks_with_request(
+        &mut self,
+        request: ListTasksRequest,
+    ) -> Result<ListTasksResponse> {
+        do_request_with_credential!(self, list_tasks, request)
+    }
+
+    /// Returns all tasks of the user with the relation, going through every
+    /// page of the listing.
+    pub fn list_tasks(&mut self, relation: TaskRelation) -> Result<Vec<TaskSummary>> {
+        let mut tasks = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let request = ListTasksRequest::new(relation).page_token(page_token);
+            let response = self.list_tasks_with_request(request)?;
+            tasks.extend(response.tasks);
+            if response.next_page_token.is_empty() {
+                return Ok(tasks);
+            }
+            page_token = response.next_page_token;
+        }
+    }
+
     pub fn cancel_task_with_request(&mut self, request: CancelTaskRequest) -> Result<()> {
         do_request_with_credential!(self, cancel_task, request)
     }
@@ -628,6 +705,213 @@ impl FrontendClient {
     ) -> Result<QueryAuditLogsResponse> {
         do_request_with_credential!(self, query_audit_logs, request)
     }
+
+    pub fn get_queue_state(&mut self) -> Result<GetQueueStateResponse> {
+        self.get_queue_state_with_request(GetQueueStateRequest::default())
+    }
+
+    pub fn get_queue_state_with_request(
+        &mut self,
+        request: GetQueueStateRequest,
+    ) -> Result<GetQueueStateResponse> {
+        do_request_with_credential!(self, get_queue_state, request)
+    }
+
+    pub fn create_workflow_with_request(
+        &mut self,
+        request: CreateWorkflowRequest,
+    ) -> Result<CreateWorkflowResponse> {
+        do_request_with_credential!(self, create_workflow, request)
+    }
+
+    pub fn create_workflow_serialized(&mut self, serialized_request: &str) -> Result<String> {
+        let request = serde_json::from_str(serialized_request)?;
+        let response = self.create_workflow_with_request(request)?;
+        let serialized_response = serde_json::to_string(&response)?;
+
+        Ok(serialized_response)
+    }
+
+    pub fn get_workflow_with_request(
+        &mut self,
+        request: GetWorkflowRequest,
+    ) -> Result<GetWorkflowResponse> {
+        do_request_with_credential!(self, get_workflow, request)
+    }
+
+    pub fn get_workflow(&mut self, workflow_id: &str) -> Result<GetWorkflowResponse> {
+        let request = GetWorkflowRequest::new(workflow_id.try_into()?);
+        self.get_workflow_with_request(request)
+    }
+
+    pub fn approve_workflow_with_request(&mut self, request: ApproveWorkflowRequest) -> Result<()> {
+        do_request_with_credential!(self, approve_workflow, request)
+    }
+
+    pub fn approve_workflow(&mut self, workflow_id: &str) -> Result<()> {
+        let request = ApproveWorkflowRequest::new(workflow_id.try_into()?);
+        self.approve_workflow_with_request(request)
+    }
+
+    pub fn invoke_workflow_with_request(&mut self, request: InvokeWorkflowRequest) -> Result<()> {
+        do_request_with_credential!(self, invoke_workflow, request)
+    }
+
+    pub fn invoke_workflow(&mut self, workflow_id: &str) -> Result<()> {
+        let request = InvokeWorkflowRequest::new(workflow_id.try_into()?);
+        self.invoke_workflow_with_request(request)
+    }
+
+    pub fn create_recurring_task_with_request(
+        &mut self,
+        request: CreateRecurringTaskRequest,
+    ) -> Result<CreateRecurringTaskResponse> {
+        do_request_with_credential!(self, create_recurring_task, request)
+    }
+
+    pub fn create_recurring_task_serialized(&mut self, serialized_request: &str) -> Result<String> {
+        let request = serde_json::from_str(serialized_request)?;
+        let response = self.create_recurring_task_with_request(request)?;
+        let serialized_response = serde_json::to_string(&response)?;
+
+        Ok(serialized_response)
+    }
+
+    pub fn create_recurring_task(
+        &mut self,
+        task_id: &str,
+        schedule: TaskSchedule,
+    ) -> Result<String> {
+        let request = CreateRecurringTaskRequest::new(task_id.try_into()?, schedule);
+        let response = self.create_recurring_task_with_request(request)?;
+
+        Ok(response.recurring_task_id)
+    }
+
+    pub fn get_recurring_task_with_request(
+        &mut self,
+        request: GetRecurringTaskRequest,
+    ) -> Result<GetRecurringTaskResponse> {
+        do_request_with_credential!(self, get_recurring_task, request)
+    }
+
+    pub fn get_recurring_task(
+        &mut self,
+        recurring_task_id: &str,
+    ) -> Result<GetRecurringTaskResponse> {
+        let request = GetRecurringTaskRequest::new(recurring_task_id.try_into()?);
+        self.get_recurring_task_with_request(request)
+    }
+
+    pub fn approve_recurring_task_with_request(
+        &mut self,
+        request: ApproveRecurringTaskRequest,
+    ) -> Result<()> {
+        do_request_with_credential!(self, approve_recurring_task, request)
+    }
+
+    pub fn approve_recurring_task(&mut self, recurring_task_id: &str) -> Result<()> {
+        let request = ApproveRecurringTaskRequest::new(recurring_task_id.try_into()?);
+        self.approve_recurring_task_with_request(request)
+    }
+
+    pub fn delete_recurring_task_with_request(
+        &mut self,
+        request: DeleteRecurringTaskRequest,
+    ) -> Result<()> {
+        do_request_with_credential!(self, delete_recurring_task, request)
+    }
+
+    pub fn delete_recurring_task(&mut self, recurring_task_id: &str) -> Result<()> {
+        let request = DeleteRecurringTaskRequest::new(recurring_task_id.try_into()?);
+        self.delete_recurring_task_with_request(request)
+    }
+
+    pub fn list_executors(&mut self) -> Result<ListExecutorsResponse> {
+        self.list_executors_with_request(ListExecutorsRequest::default())
+    }
+
+    pub fn list_executors_with_request(
+        &mut self,
+        request: ListExecutorsRequest,
+    ) -> Result<ListExecutorsResponse> {
+        do_request_with_credential!(self, list_executors, request)
+    }
+
+    pub fn drain_executor_with_request(&mut self, request: DrainExecutorRequest) -> Result<()> {
+        do_request_with_credential!(self, drain_executor, request)
+    }
+
+    pub fn drain_executor(&mut self, executor_id: &str) -> Result<()> {
+        let request = DrainExecutorRequest::new(executor_id.parse()?);
+        self.drain_executor_with_request(request)
+    }
+
+    /// Returns the chunks of an encrypted archive of the database, which only
+    /// the storage service can open.
+    pub fn backup_database(&mut self) -> Result<Vec<Vec<u8>>> {
+        let request = BackupDatabaseRequest::new();
+        let client = &mut self.client;
+        self.rt.block_on(async {
+            let mut stream = client.backup_database(request).await?.into_inner();
+            let mut chunks = Vec::new();
+            while let Some(chunk) = stream.message().await? {
+                chunks.push(chunk.data);
+            }
+            Ok::<_, anyhow::Error>(chunks)
+        })
+    }
+
+    /// Restores an archive made on the platform of this storage service into
+    /// its empty database.
+    pub fn restore_database(&mut self, archive: Vec<Vec<u8>>) -> Result<()> {
+        self.restore_database_from(archive, "")
+    }
+
+    /// Restores an archive made by the storage service at the source address,
+    /// which hands the archive key over to this storage service after both of
+    /// them are attested.
+    pub fn restore_database_from(
+        &mut self,
+        archive: Vec<Vec<u8>>,
+        source_address: &str,
+    ) -> Result<()> {
+        let mut requests: Vec<_> = archive
+            .into_iter()
+            .map(RestoreDatabaseRequest::new)
+            .collect();
+        if let Some(header) = requests.first_mut() {
+            header.source_address = source_address.to_string();
+        }
+        let response = self
+            .rt
+            .block_on(self.client.restore_database(tokio_stream::iter(requests)))?;
+        Ok(response.into_inner())
+    }
+
+    pub fn get_database_stats_with_request(
+        &mut self,
+        request: GetDatabaseStatsRequest,
+    ) -> Result<GetDatabaseStatsResponse> {
+        do_request_with_credential!(self, get_database_stats, request)
+    }
+
+    pub fn get_database_stats(&mut self) -> Result<GetDatabaseStatsResponse> {
+        self.get_database_stats_with_request(GetDatabaseStatsRequest::new())
+    }
+
+    pub fn rotate_database_key_with_request(
+        &mut self,
+        request: RotateDatabaseKeyRequest,
+    ) -> Result<()> {
+        do_request_with_credential!(self, rotate_database_key, request)
+    }
+
+    /// Makes a new key the current key of the database, the data written
+    /// under the earlier keys stays readable.
+    pub fn rotate_database_key(&mut self) -> Result<()> {
+        self.rotate_database_key_with_request(RotateDatabaseKeyRequest::new())
+    }
 }
 
 #[cfg(test)]
@@ -766,6 +1050,44 @@ mod tests {
         assert!(response.is_ok());
     }
 
+    #[test]
+    fn test_list_tasks() {
+        let mut client = get_frontend_client();
+        let function_id = "function-00000000-0000-0000-0000-000000000002"
+            .to_string()
+            .try_into()
+            .unwrap();
+        let request = CreateTaskRequest::new()
+            .function_id(function_id)
+            .function_arguments(hashmap!("arg1" => "arg1_value"))
+            .executor(Executor::MesaPy)
+            .outputs_ownership(hashmap!("output" => vec!["frontend_user", "mock_user"]));
+        let task_id = client.create_task_with_request(request).unwrap().task_id;
+
+        let tasks = client.list_tasks(TaskRelation::Creator).unwrap();
+        assert!(tasks.iter().any(|task| task.task_id == task_id));
+    }
+
+    #[test]
+    fn test_list_files() {
+        let mut client = get_frontend_client();
+        let url = "https://external-storage.com/filepath?presigned_token";
+        let output_id = client
+            .register_output_file(url, FileCrypto::default())
+            .unwrap();
+        let files = client.list_output_files().unwrap();
+        let file = files.iter().find(|file| file.data_id == output_id).unwrap();
+        assert!(file.cmac.is_empty());
+
+        let cmac = [0x42u8; 16];
+        let input_id = client
+            .register_input_file(url, &cmac, FileCrypto::default())
+            .unwrap();
+        let files = client.list_input_files().unwrap();
+        let file = files.iter().find(|file| file.data_id == input_id).unwrap();
+        assert_eq!(file.cmac, cmac);
+    }
+
     #[test]
     fn test_assign_data() {
         let mut client = get_frontend_client();
@@ -81,6 +81,12 @@ pub mod tests {
 
         assert!(e.enforce(("PlatformAdmin", "arbitrary_api")).unwrap());
         assert!(e.enforce(("PlatformAdmin", "query_audit_logs")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "list_executors")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "drain_executor")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "backup_database")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "restore_database")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "get_database_stats")).unwrap());
+        assert!(e.enforce(("PlatformAdmin", "rotate_database_key")).unwrap());
 
         assert!(!e.enforce(("Invalid", "register_function")).unwrap());
         assert!(!e.enforce(("Invalid", "register_input_file")).unwrap());
@@ -97,6 +103,8 @@ pub mod tests {
             .enforce(("FunctionOwner", "get_function_usage_stats"))
             .unwrap());
         assert!(!e.enforce(("FunctionOwner", "get_task")).unwrap());
+        assert!(!e.enforce(("FunctionOwner", "list_tasks")).unwrap());
+        assert!(!e.enforce(("FunctionOwner", "list_input_files")).unwrap());
         assert!(!e.enforce(("FunctionOwner", "query_audit_logs")).unwrap());
 
         assert!(e.enforce(("DataOwner", "register_input_file")).unwrap());
@@ -109,8 +117,13 @@ pub mod tests {
             .unwrap());
         assert!(e.enforce(("DataOwner", "get_input_file")).unwrap());
         assert!(e.enforce(("DataOwner", "get_output_file")).unwrap());
+        assert!(e.enforce(("DataOwner", "list_input_files")).unwrap());
+        assert!(e
+            .enforce(("DataOwnerManager", "list_output_files"))
+            .unwrap());
         assert!(e.enforce(("DataOwner", "create_task")).unwrap());
         assert!(e.enforce(("DataOwnerManager", "get_task")).unwrap());
+        assert!(e.enforce(("DataOwner", "list_tasks")).unwrap());
         assert!(e.enforce(("DataOwnerManager", "assign_data")).unwrap());
         assert!(e.enforce(("DataOwnerManager", "approve_task")).unwrap());
         assert!(e.enforce(("DataOwnerManager", "invoke_task")).unwrap());
@@ -120,7 +133,30 @@ pub mod tests {
         assert!(e
             .enforce(("DataOwnerManager", "get_function_usage_stats"))
             .unwrap());
+        assert!(e.enforce(("DataOwner", "create_workflow")).unwrap());
+        assert!(e.enforce(("DataOwner", "get_workflow")).unwrap());
+        assert!(e.enforce(("DataOwnerManager", "approve_workflow")).unwrap());
+        assert!(e.enforce(("DataOwnerManager", "invoke_workflow")).unwrap());
+        assert!(e.enforce(("DataOwner", "create_recurring_task")).unwrap());
+        assert!(e.enforce(("DataOwner", "get_recurring_task")).unwrap());
+        assert!(e
+            .enforce(("DataOwnerManager", "approve_recurring_task"))
+            .unwrap());
+        assert!(e
+            .enforce(("DataOwnerManager", "delete_recurring_task"))
+            .unwrap());
+        assert!(!e.enforce(("FunctionOwner", "create_workflow")).unwrap());
+        assert!(!e
+            .enforce(("FunctionOwner", "create_recurring_task"))
+            .unwrap());
         assert!(!e.enforce(("DataOwner", "register_function")).unwrap());
         assert!(!e.enforce(("DataOwnerManager", "query_audit_logs")).unwrap());
+        assert!(!e.enforce(("DataOwnerManager", "get_queue_state")).unwrap());
+        assert!(!e.enforce(("DataOwnerManager", "list_executors")).unwrap());
+        assert!(!e.enforce(("FunctionOwner", "drain_executor")).unwrap());
+        assert!(!e.enforce(("DataOwner", "backup_database")).unwrap());
+        assert!(!e.enforce(("DataOwnerManager", "restore_database")).unwrap());
+        assert!(!e.enforce(("FunctionOwner", "get_database_stats")).unwrap());
+        assert!(!e.enforce(("DataOwner", "rotate_database_key")).unwrap());
     }
 }
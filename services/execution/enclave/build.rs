@@ -0,0 +1,50 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Generates the heap size of the enclave from Enclave.config.xml, which the
+// worker slots share.
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR not set");
+    let config = Path::new(&manifest_dir).join("Enclave.config.xml");
+    println!("cargo:rerun-if-changed={}", config.display());
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let content = fs::read_to_string(&config).expect("Cannot read Enclave.config.xml");
+    let value = content
+        .split("<HeapMaxSize>")
+        .nth(1)
+        .and_then(|rest| rest.split("</HeapMaxSize>").next())
+        .expect("HeapMaxSize is not set in Enclave.config.xml")
+        .trim();
+    let heap_max_size = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .expect("Invalid HeapMaxSize in Enclave.config.xml");
+
+    let out_dir = env::var("OUT_DIR").expect("$OUT_DIR not set. Please build with cargo");
+    let dest_file = Path::new(&out_dir).join("enclave_config.rs");
+    fs::write(
+        dest_file,
+        format!("const ENCLAVE_HEAP_MAX_SIZE: u64 = {:#x};\n", heap_max_size),
+    )
+    .expect("Cannot generate enclave_config.rs");
+}
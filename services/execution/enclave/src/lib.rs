@@ -75,8 +75,12 @@ pub async fn start_service(config: &RuntimeConfig) -> Result<()> {
     );
 
     info!(" Starting Execution: start ...");
-    let mut service =
-        service::TeaclaveExecutionService::new(scheduler_service_endpoint, fusion_base).await?;
+    let mut service = service::TeaclaveExecutionService::new(
+        scheduler_service_endpoint,
+        fusion_base,
+        config.execution.worker_slots,
+    )
+    .await?;
 
     service.start().await
 }
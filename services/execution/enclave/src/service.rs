@@ -17,35 +17,54 @@
 
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 use crate::task_file_manager::TaskFileManager;
 use anyhow::Result;
 use teaclave_proto::teaclave_common::{ExecutorCommand, ExecutorStatus};
 use teaclave_proto::teaclave_scheduler_service::*;
 use teaclave_rpc::transport::{channel::Endpoint, Channel};
+use teaclave_rpc::Streaming;
 use teaclave_types::*;
 use teaclave_worker::Worker;
 use uuid::Uuid;
 
 static WORKER_BASE_DIR: &str = "/tmp/teaclave_agent/";
+const HEARTBEAT_INTERVAL_SECS: u64 = 3;
+// The scheduler stops overdue tasks, this is only a fallback in case the
+// executor does not receive the Stop command.
+const DEADLINE_GRACE_SECS: u64 = 30;
+// HeapMaxSize in Enclave.config.xml, shared by the worker slots
+include!(concat!(env!("OUT_DIR"), "/enclave_config.rs"));
 
 #[derive(Clone)]
 pub(crate) struct TeaclaveExecutionService {
-    #[allow(dead_code)]
     worker: Arc<Worker>,
     scheduler_client: TeaclaveSchedulerClient<Channel>,
     fusion_base: PathBuf,
     id: Uuid,
     status: ExecutorStatus,
+    worker_slots: usize,
+}
+
+// A task running in a worker slot.
+struct SlotTask {
+    task: Arc<StagedTask>,
+    handle: thread::JoinHandle<()>,
+    deadline: Option<tokio::time::Instant>,
+    // the scheduler has stopped the task, the slot is held until the worker
+    // thread returns
+    stopped: bool,
 }
 
 impl TeaclaveExecutionService {
     pub(crate) async fn new(
         scheduler_service_endpoint: Endpoint,
         fusion_base: impl AsRef<Path>,
+        worker_slots: u32,
     ) -> Result<Self> {
         let channel = scheduler_service_endpoint.connect().await?;
         let scheduler_client = TeaclaveSchedulerClient::new_with_builtin_config(channel);
@@ -56,114 +75,208 @@ impl TeaclaveExecutionService {
             fusion_base: fusion_base.as_ref().to_owned(),
             id: Uuid::new_v4(),
             status: ExecutorStatus::Idle,
+            worker_slots: worker_slots.max(1) as usize,
         })
     }
 
     pub(crate) async fn start(&mut self) -> Result<()> {
-        let (tx, rx) = mpsc::channel();
-        let mut current_task: Arc<Option<StagedTask>> = Arc::new(None);
-        let mut task_handle: Option<thread::JoinHandle<()>> = None;
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut slots: Vec<Option<SlotTask>> = (0..self.worker_slots).map(|_| None).collect();
+        // set when the executor is drained while executing
+        let mut shutdown = false;
+
+        // Tasks and stop commands are pushed by the scheduler through the
+        // subscription, heartbeats are only sent to keep the executor alive.
+        let mut subscription = self.subscribe().await?;
+        let mut heartbeat_interval =
+            tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
 
         loop {
-            std::thread::sleep(std::time::Duration::from_secs(3));
+            let deadline = slots
+                .iter()
+                .flatten()
+                .filter_map(|slot| slot.deadline)
+                .min();
+            tokio::select! {
+                message = subscription.message() => {
+                    let response = match message {
+                        Ok(Some(response)) => response,
+                        Ok(None) => {
+                            log::error!("Executor {} subscription closed", self.id);
+                            return Err(anyhow::anyhow!("SubscriptionClosed"));
+                        }
+                        Err(e) => {
+                            log::error!("Executor {} failed to receive: {}", self.id, e);
+                            return Err(e.into());
+                        }
+                    };
 
-            match self.heartbeat().await {
-                Ok(ExecutorCommand::Stop) => {
-                    log::info!("Executor {} is stopped", self.id);
-                    return Err(anyhow::anyhow!("EnclaveForceTermination"));
-                }
-                Ok(ExecutorCommand::NewTask) if self.status == ExecutorStatus::Idle => {
-                    match self.pull_task().await {
-                        Ok(task) => {
+                    match response.command.try_into() {
+                        Ok(ExecutorCommand::Stop) if !response.task_id.is_empty() => {
+                            let slot = slots
+                                .iter_mut()
+                                .flatten()
+                                .find(|slot| slot.task.task_id.to_string() == response.task_id);
+                            if let Some(slot) = slot {
+                                log::info!("Executor {} stops task {}", self.id, response.task_id);
+                                slot.stopped = true;
+                                slot.deadline = None;
+                            }
+                            if only_stopped_slots(&slots) {
+                                log::info!("Executor {} is stopped", self.id);
+                                return Err(anyhow::anyhow!("EnclaveForceTermination"));
+                            }
+                        }
+                        // A worker thread cannot be stopped on its own, so the
+                        // tasks in the other slots are interrupted as well.
+                        Ok(ExecutorCommand::Stop) => {
+                            log::info!("Executor {} is stopped", self.id);
+                            return Err(anyhow::anyhow!("EnclaveForceTermination"));
+                        }
+                        Ok(ExecutorCommand::NewTask) => {
+                            let index = match slots.iter().position(|slot| slot.is_none()) {
+                                Some(index) => index,
+                                None => {
+                                    log::error!(
+                                        "Executor {} received a task with no free slot",
+                                        self.id
+                                    );
+                                    continue;
+                                }
+                            };
+                            let task = StagedTask::from_slice(&response.staged_task)?;
+                            let deadline = Some(task.max_execution_time)
+                                .filter(|secs| *secs > 0)
+                                .and_then(|secs| secs.checked_add(DEADLINE_GRACE_SECS))
+                                .and_then(|timeout| {
+                                    tokio::time::Instant::now()
+                                        .checked_add(Duration::from_secs(timeout))
+                                });
                             self.status = ExecutorStatus::Executing;
                             self.update_task_status(&task.task_id, TaskStatus::Running)
                                 .await?;
                             let tx_task = tx.clone();
+                            // Each slot stages the files of its task in its
+                            // own directory.
+                            let inter_base =
+                                Path::new(WORKER_BASE_DIR).join(format!("slot-{}", index));
                             let fusion_base = self.fusion_base.clone();
-                            current_task = Arc::new(Some(task));
-                            let task_copy = current_task.clone();
+                            let task = Arc::new(task);
+                            let task_copy = task.clone();
                             let handle = thread::spawn(move || {
-                                let result =
-                                    invoke_task(task_copy.as_ref().as_ref().unwrap(), &fusion_base);
-                                tx_task.send(result).unwrap();
+                                let result = invoke_task(&task_copy, &inter_base, &fusion_base);
+                                tx_task.send((index, result)).unwrap();
+                            });
+                            slots[index] = Some(SlotTask {
+                                task,
+                                handle,
+                                deadline,
+                                stopped: false,
                             });
-                            task_handle = Some(handle);
                         }
+                        Ok(ExecutorCommand::Shutdown) if self.status == ExecutorStatus::Idle => {
+                            log::info!("Executor {} is drained, shutting down", self.id);
+                            return Ok(());
+                        }
+                        Ok(ExecutorCommand::Shutdown) => shutdown = true,
+                        Ok(ExecutorCommand::NoAction) => {}
                         Err(e) => {
-                            log::error!("Executor {} failed to pull task: {}", self.id, e);
+                            log::error!("Executor {} received invalid command: {}", self.id, e);
                         }
-                    };
-                }
-                Err(e) => {
-                    log::error!("Executor {} failed to heartbeat: {}", self.id, e);
-                    return Err(e);
+                    }
                 }
-                _ => {}
-            }
-
-            match rx.try_recv() {
-                Ok(result) => {
-                    let task_unwrapped = current_task.as_ref().as_ref().unwrap();
+                Some((index, result)) = rx.recv() => {
+                    let task = match slots[index].as_ref() {
+                        Some(slot) => slot.task.clone(),
+                        None => continue,
+                    };
                     match result {
                         Ok(_) => log::debug!(
                             "InvokeTask: {:?}, {:?}, success",
-                            task_unwrapped.task_id,
-                            task_unwrapped.function_id
+                            task.task_id,
+                            task.function_id
                         ),
                         Err(_) => log::debug!(
                             "InvokeTask: {:?}, {:?}, failure",
-                            task_unwrapped.task_id,
-                            task_unwrapped.function_id
+                            task.task_id,
+                            task.function_id
                         ),
                     }
                     log::debug!("InvokeTask result: {:?}", result);
-                    let task_copy = current_task.clone();
-                    match self
-                        .update_task_result(&task_copy.as_ref().as_ref().unwrap().task_id, result)
-                        .await
-                    {
-                        Ok(_) => (),
-                        Err(e) => {
-                            log::error!("UpdateResult Error: {:?}", e);
-                            continue;
+                    // The slot is released even if the result is lost, the
+                    // scheduler retries or fails the task once the executor
+                    // reports idle without it.
+                    if let Err(e) = self.update_task_result(&task.task_id, result).await {
+                        log::error!("UpdateResult Error: {:?}", e);
+                    }
+                    if let Some(slot) = slots[index].take() {
+                        if slot.handle.join().is_err() {
+                            log::error!("Worker thread of task {} panicked", task.task_id);
+                        }
+                    }
+                    // The worker threads of the stopped tasks are only freed
+                    // by a restart, which interrupts no other task now.
+                    if only_stopped_slots(&slots) {
+                        log::info!("Executor {} is stopped", self.id);
+                        return Err(anyhow::anyhow!("EnclaveForceTermination"));
+                    }
+                    if slots.iter().all(|slot| slot.is_none()) {
+                        self.status = ExecutorStatus::Idle;
+                        if shutdown {
+                            log::info!("Executor {} is drained, shutting down", self.id);
+                            return Ok(());
                         }
                     }
-                    current_task = Arc::new(None);
-                    task_handle.unwrap().join().unwrap();
-                    task_handle = None;
-                    self.status = ExecutorStatus::Idle;
                 }
-                Err(mpsc::TryRecvError::Disconnected) => {
-                    log::error!(
-                        "Executor {} failed to receive, sender disconnected",
-                        self.id
-                    );
+                _ = sleep_until_deadline(deadline) => {
+                    log::error!("Executor {} exceeded the maximum execution time", self.id);
+                    return Err(anyhow::anyhow!("EnclaveForceTermination"));
+                }
+                _ = heartbeat_interval.tick() => {
+                    match self.heartbeat().await {
+                        Ok(ExecutorCommand::Stop) => {
+                            log::info!("Executor {} is stopped", self.id);
+                            return Err(anyhow::anyhow!("EnclaveForceTermination"));
+                        }
+                        Ok(ExecutorCommand::Shutdown) if self.status == ExecutorStatus::Idle => {
+                            log::info!("Executor {} is drained, shutting down", self.id);
+                            return Ok(());
+                        }
+                        Ok(ExecutorCommand::Shutdown) => shutdown = true,
+                        Err(e) => {
+                            log::error!("Executor {} failed to heartbeat: {}", self.id, e);
+                            return Err(e);
+                        }
+                        _ => {}
+                    }
                 }
-                // received nothing
-                Err(_) => {}
             }
         }
     }
 
-    async fn pull_task(&mut self) -> Result<StagedTask> {
-        let request = PullTaskRequest {
-            executor_id: self.id.to_string(),
-        };
-        let response = self.scheduler_client.pull_task(request).await?.into_inner();
+    async fn subscribe(&mut self) -> Result<Streaming<SubscribeResponse>> {
+        let request = SubscribeRequest::new(self.id).capability(self.capability());
+        let response = self.scheduler_client.subscribe(request).await?;
 
-        log::debug!("pull_stask response: {:?}", response);
-        let staged_task = StagedTask::from_slice(&response.staged_task)?;
-        Ok(staged_task)
+        log::debug!("Executor {} subscribed", self.id);
+        Ok(response.into_inner())
     }
 
     async fn heartbeat(&mut self) -> Result<ExecutorCommand> {
-        let request = HeartbeatRequest::new(self.id, self.status);
+        let request = HeartbeatRequest::new(self.id, self.status).capability(self.capability());
         let response = self.scheduler_client.heartbeat(request).await?.into_inner();
 
         log::debug!("heartbeat_with_result response: {:?}", response);
         response.command.try_into()
     }
 
+    fn capability(&self) -> WorkerCapability {
+        let mut capability = self.worker.capability();
+        capability.memory_size = ENCLAVE_HEAP_MAX_SIZE / self.worker_slots as u64;
+        capability.slots = self.worker_slots as u32;
+        capability
+    }
+
     async fn update_task_result(
         &mut self,
         task_id: &Uuid,
@@ -184,7 +297,20 @@ impl TeaclaveExecutionService {
     }
 }
 
-fn invoke_task(task: &StagedTask, fusion_base: &PathBuf) -> Result<TaskOutputs> {
+// Whether some slots are busy, and all of them with stopped tasks.
+fn only_stopped_slots(slots: &[Option<SlotTask>]) -> bool {
+    let mut busy = slots.iter().flatten().peekable();
+    busy.peek().is_some() && busy.all(|slot| slot.stopped)
+}
+
+async fn sleep_until_deadline(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn invoke_task(task: &StagedTask, inter_base: &Path, fusion_base: &Path) -> Result<TaskOutputs> {
     let save_log = task
         .function_arguments
         .get("save_log")
@@ -199,7 +325,7 @@ fn invoke_task(task: &StagedTask, fusion_base: &PathBuf) -> Result<TaskOutputs>
     }
 
     let file_mgr = TaskFileManager::new(
-        WORKER_BASE_DIR,
+        inter_base,
         fusion_base,
         &task.task_id,
         &task.input_data,
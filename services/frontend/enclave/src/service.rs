@@ -29,27 +29,47 @@ use teaclave_proto::teaclave_authentication_service::{
 };
 use teaclave_proto::teaclave_common::UserCredential;
 use teaclave_proto::teaclave_frontend_service::{
-    ApproveTaskRequest, AssignDataRequest, CancelTaskRequest, CreateTaskRequest,
-    CreateTaskResponse, DeleteFunctionRequest, DisableFunctionRequest, GetFunctionRequest,
-    GetFunctionResponse, GetFunctionUsageStatsRequest, GetFunctionUsageStatsResponse,
-    GetInputFileRequest, GetInputFileResponse, GetOutputFileRequest, GetOutputFileResponse,
-    GetTaskRequest, GetTaskResponse, InvokeTaskRequest, ListFunctionsRequest,
-    ListFunctionsResponse, QueryAuditLogsRequest, QueryAuditLogsResponse, RegisterFunctionRequest,
-    RegisterFunctionResponse, RegisterFusionOutputRequest, RegisterFusionOutputResponse,
-    RegisterInputFileRequest, RegisterInputFileResponse, RegisterInputFromOutputRequest,
-    RegisterInputFromOutputResponse, RegisterOutputFileRequest, RegisterOutputFileResponse,
-    TeaclaveFrontend, UpdateFunctionRequest, UpdateFunctionResponse, UpdateInputFileRequest,
-    UpdateInputFileResponse, UpdateOutputFileRequest, UpdateOutputFileResponse,
+    ApproveRecurringTaskRequest, ApproveTaskRequest, ApproveWorkflowRequest, AssignDataRequest,
+    BackupDatabaseRequest, BackupDatabaseResponse, CancelTaskRequest, CreateRecurringTaskRequest,
+    CreateRecurringTaskResponse, CreateTaskRequest, CreateTaskResponse, CreateWorkflowRequest,
+    CreateWorkflowResponse, DeleteFunctionRequest, DeleteRecurringTaskRequest,
+    DisableFunctionRequest, DrainExecutorRequest, GetDatabaseStatsRequest,
+    GetDatabaseStatsResponse, GetFunctionRequest, GetFunctionResponse,
+    GetFunctionUsageStatsRequest, GetFunctionUsageStatsResponse, GetInputFileRequest,
+    GetInputFileResponse, GetOutputFileRequest, GetOutputFileResponse, GetQueueStateRequest,
+    GetQueueStateResponse, GetRecurringTaskRequest, GetRecurringTaskResponse, GetTaskRequest,
+    GetTaskResponse, GetWorkflowRequest, GetWorkflowResponse, InvokeTaskRequest,
+    InvokeWorkflowRequest, ListExecutorsRequest, ListExecutorsResponse, ListFunctionsRequest,
+    ListFunctionsResponse, ListInputFilesRequest, ListInputFilesResponse, ListOutputFilesRequest,
+    ListOutputFilesResponse, ListTasksRequest, ListTasksResponse, QueryAuditLogsRequest,
+    QueryAuditLogsResponse, RegisterFunctionRequest, RegisterFunctionResponse,
+    RegisterFusionOutputRequest, RegisterFusionOutputResponse, RegisterInputFileRequest,
+    RegisterInputFileResponse, RegisterInputFromOutputRequest, RegisterInputFromOutputResponse,
+    RegisterOutputFileRequest, RegisterOutputFileResponse, RestoreDatabaseRequest,
+    RotateDatabaseKeyRequest, TeaclaveFrontend, UpdateFunctionRequest, UpdateFunctionResponse,
+    UpdateInputFileRequest, UpdateInputFileResponse, UpdateOutputFileRequest,
+    UpdateOutputFileResponse,
 };
 use teaclave_proto::teaclave_management_service::TeaclaveManagementClient;
 use teaclave_rpc::transport::Channel;
-use teaclave_rpc::Request;
+use teaclave_rpc::{Request, Streaming};
 use teaclave_service_enclave_utils::bail;
 use teaclave_types::{Entry, EntryBuilder, TeaclaveServiceResponseResult, UserAuthClaims};
 use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
 
 macro_rules! authentication_and_forward_to_management {
     ($service: ident, $request: ident, $func: ident) => {{
+        authentication_and_forward_to_management!(
+            $service,
+            $request,
+            $func,
+            $request.get_ref().to_owned()
+        )
+    }};
+    // The message is only evaluated once the user is authorized, so that a
+    // client stream is not read before.
+    ($service: ident, $request: ident, $func: ident, $message: expr) => {{
         let function_name = stringify!($func).to_owned();
         let ip_option = $request.remote_addr().map(|s| s.ip());
         let ip = match ip_option {
@@ -106,10 +126,11 @@ macro_rules! authentication_and_forward_to_management {
         let user = claims.to_string();
         let builder = builder.user(user);
 
-        let client = $service.management_client.clone();
-        let mut client = client.lock().await;
         let meta = $request.metadata().clone();
-        let message = $request.get_ref().to_owned();
+        let message = $message;
+        // The client is cloned out of the lock, so that a streamed request
+        // does not hold up the others.
+        let mut client = $service.management_client.lock().await.clone();
 
         let mut request = Request::new(message);
         let metadata = request.metadata_mut();
@@ -233,6 +254,20 @@ impl TeaclaveFrontend for TeaclaveFrontendService {
         authentication_and_forward_to_management!(self, request, get_input_file)
     }
 
+    async fn list_input_files(
+        &self,
+        request: Request<ListInputFilesRequest>,
+    ) -> TeaclaveServiceResponseResult<ListInputFilesResponse> {
+        authentication_and_forward_to_management!(self, request, list_input_files)
+    }
+
+    async fn list_output_files(
+        &self,
+        request: Request<ListOutputFilesRequest>,
+    ) -> TeaclaveServiceResponseResult<ListOutputFilesResponse> {
+        authentication_and_forward_to_management!(self, request, list_output_files)
+    }
+
     async fn register_function(
         &self,
         request: Request<RegisterFunctionRequest>,
@@ -296,6 +331,13 @@ impl TeaclaveFrontend for TeaclaveFrontendService {
         authentication_and_forward_to_management!(self, request, get_task)
     }
 
+    async fn list_tasks(
+        &self,
+        request: Request<ListTasksRequest>,
+    ) -> TeaclaveServiceResponseResult<ListTasksResponse> {
+        authentication_and_forward_to_management!(self, request, list_tasks)
+    }
+
     async fn assign_data(
         &self,
         request: Request<AssignDataRequest>,
@@ -330,6 +372,123 @@ impl TeaclaveFrontend for TeaclaveFrontendService {
     ) -> TeaclaveServiceResponseResult<QueryAuditLogsResponse> {
         authentication_and_forward_to_management!(self, request, query_audit_logs)
     }
+
+    async fn get_queue_state(
+        &self,
+        request: Request<GetQueueStateRequest>,
+    ) -> TeaclaveServiceResponseResult<GetQueueStateResponse> {
+        authentication_and_forward_to_management!(self, request, get_queue_state)
+    }
+
+    async fn create_workflow(
+        &self,
+        request: Request<CreateWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<CreateWorkflowResponse> {
+        authentication_and_forward_to_management!(self, request, create_workflow)
+    }
+
+    async fn get_workflow(
+        &self,
+        request: Request<GetWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<GetWorkflowResponse> {
+        authentication_and_forward_to_management!(self, request, get_workflow)
+    }
+
+    async fn approve_workflow(
+        &self,
+        request: Request<ApproveWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, approve_workflow)
+    }
+
+    async fn invoke_workflow(
+        &self,
+        request: Request<InvokeWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, invoke_workflow)
+    }
+
+    async fn create_recurring_task(
+        &self,
+        request: Request<CreateRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<CreateRecurringTaskResponse> {
+        authentication_and_forward_to_management!(self, request, create_recurring_task)
+    }
+
+    async fn get_recurring_task(
+        &self,
+        request: Request<GetRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<GetRecurringTaskResponse> {
+        authentication_and_forward_to_management!(self, request, get_recurring_task)
+    }
+
+    async fn approve_recurring_task(
+        &self,
+        request: Request<ApproveRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, approve_recurring_task)
+    }
+
+    async fn delete_recurring_task(
+        &self,
+        request: Request<DeleteRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, delete_recurring_task)
+    }
+
+    async fn list_executors(
+        &self,
+        request: Request<ListExecutorsRequest>,
+    ) -> TeaclaveServiceResponseResult<ListExecutorsResponse> {
+        authentication_and_forward_to_management!(self, request, list_executors)
+    }
+
+    async fn drain_executor(
+        &self,
+        request: Request<DrainExecutorRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, drain_executor)
+    }
+
+    type BackupDatabaseStream = Streaming<BackupDatabaseResponse>;
+
+    async fn backup_database(
+        &self,
+        request: Request<BackupDatabaseRequest>,
+    ) -> TeaclaveServiceResponseResult<Self::BackupDatabaseStream> {
+        authentication_and_forward_to_management!(self, request, backup_database)
+    }
+
+    // The chunks are passed on as they come. If the stream fails, the archive
+    // ends early and is rejected as truncated.
+    async fn restore_database(
+        &self,
+        request: Request<Streaming<RestoreDatabaseRequest>>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(
+            self,
+            request,
+            restore_database,
+            request
+                .into_inner()
+                .take_while(|chunk| chunk.is_ok())
+                .filter_map(|chunk| chunk.ok())
+        )
+    }
+
+    async fn get_database_stats(
+        &self,
+        request: Request<GetDatabaseStatsRequest>,
+    ) -> TeaclaveServiceResponseResult<GetDatabaseStatsResponse> {
+        authentication_and_forward_to_management!(self, request, get_database_stats)
+    }
+
+    async fn rotate_database_key(
+        &self,
+        request: Request<RotateDatabaseKeyRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        authentication_and_forward_to_management!(self, request, rotate_database_key)
+    }
 }
 
 impl TeaclaveFrontendService {
@@ -38,6 +38,8 @@ pub(crate) enum ManagementServiceError {
     InvalidTaskId,
     #[error("invalid task")]
     InvalidTask,
+    #[error("invalid task filter")]
+    InvalidTaskFilter,
     #[error("failed to assign data to task")]
     TaskAssignDataError,
     #[error("failed to approve task")]
@@ -46,8 +48,24 @@ pub(crate) enum ManagementServiceError {
     TaskInvokeError,
     #[error("failed to cancel task, reason: {0}")]
     TaskCancelError(String),
+    #[error("invalid workflow id")]
+    InvalidWorkflowId,
+    #[error("invalid workflow")]
+    InvalidWorkflow,
+    #[error("failed to approve workflow")]
+    WorkflowApproveError,
+    #[error("failed to invoke workflow")]
+    WorkflowInvokeError,
+    #[error("invalid recurring task id")]
+    InvalidRecurringTaskId,
+    #[error("invalid recurring task")]
+    InvalidRecurringTask,
+    #[error("invalid executor id")]
+    InvalidExecutorId,
     #[error("function quota has been used up")]
     FunctionQuotaError,
+    #[error("data has been changed by another request, please retry")]
+    ConcurrentUpdate,
     #[error("audit log error, reason: {0}")]
     AuditError(String),
 }
@@ -63,7 +81,14 @@ impl From<ManagementServiceError> for Status {
             | ManagementServiceError::InvalidOutputFile
             | ManagementServiceError::InvalidFunctionId
             | ManagementServiceError::InvalidTaskId
-            | ManagementServiceError::InvalidTask => Code::InvalidArgument,
+            | ManagementServiceError::InvalidTask
+            | ManagementServiceError::InvalidTaskFilter
+            | ManagementServiceError::InvalidWorkflowId
+            | ManagementServiceError::InvalidWorkflow
+            | ManagementServiceError::InvalidRecurringTaskId
+            | ManagementServiceError::InvalidRecurringTask
+            | ManagementServiceError::InvalidExecutorId => Code::InvalidArgument,
+            ManagementServiceError::ConcurrentUpdate => Code::Aborted,
             _ => Code::Unknown,
         };
         Status::new(code, msg)
@@ -21,27 +21,48 @@ use audit::Auditor;
 use error::ManagementServiceError;
 
 use anyhow::anyhow;
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::pin::Pin;
 use std::sync::Arc;
-use teaclave_proto::teaclave_common::i32_from_task_status;
+use teaclave_proto::teaclave_common::{
+    i32_from_task_priority, i32_from_task_status, i32_to_task_priority, i32_to_task_status,
+};
 use teaclave_proto::teaclave_frontend_service::*;
 use teaclave_proto::teaclave_frontend_service::{
     from_proto_file_ids, from_proto_ownership, to_proto_file_ids, to_proto_ownership,
 };
 use teaclave_proto::teaclave_management_service::{SaveLogsRequest, TeaclaveManagement};
 use teaclave_proto::teaclave_storage_service::{
-    DeleteRequest, EnqueueRequest, GetKeysByPrefixRequest, GetRequest, PutRequest,
+    BackupRequest, BatchRequest, DeleteRequest, EnqueueRequest, GetRequest, PeekRequest,
+    PutRequest, QueueLengthRequest, RestoreRequest, RotateKeyRequest, ScanRequest, StatsRequest,
     TeaclaveStorageClient,
 };
 use teaclave_rpc::transport::{channel::Endpoint, Channel};
-use teaclave_rpc::{Request, Response};
+use teaclave_rpc::{Code, Request, Response, Status, Streaming};
 use teaclave_service_enclave_utils::ensure;
 use teaclave_types::*;
 use tokio::sync::Mutex;
 use tokio::task;
+use tokio_stream::StreamExt;
 use url::Url;
 use uuid::Uuid;
 
+// Number of tasks listed for each storage queue in GetQueueState
+const STORAGE_QUEUE_PEEK_LIMIT: u32 = 100;
+// Number of items on a page of the listings
+const LIST_MAX_PAGE_SIZE: u32 = 100;
+// Number of records a listing examines in one call, the page may be short
+// when most of them do not match.
+const LIST_MAX_EXAMINED: usize = 1000;
+// Platform limit of the number of times a lost task is re-queued
+const MAX_TASK_RETRIES: u32 = 10;
+// Platform limit of the maximum execution time of a task in seconds
+const MAX_TASK_EXECUTION_TIME: u64 = 7 * 24 * 3600;
+// Cancel requests still queued after this time, e.g. because the scheduler is
+// down, are dropped and have to be sent again.
+const CANCEL_RETENTION_SECS: u32 = 7 * 24 * 3600;
+
 #[derive(Clone)]
 pub(crate) struct TeaclaveManagementService {
     storage_client: Arc<Mutex<TeaclaveStorageClient<Channel>>>,
@@ -83,8 +104,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
         let user_id = get_request_user_id(&request)?;
         let request = request.into_inner();
 
-        let old_input_file: TeaclaveInputFile = self
-            .read_from_db(&request.data_id.try_into().map_err(tonic_error)?)
+        let (mut old_input_file, old_value): (TeaclaveInputFile, _) = self
+            .read_for_update(&request.data_id.try_into().map_err(tonic_error)?)
             .await
             .map_err(|_| ManagementServiceError::InvalidDataId)?;
 
@@ -97,10 +118,14 @@ impl TeaclaveManagement for TeaclaveManagementService {
             Url::parse(&request.url).map_err(tonic_error)?,
             old_input_file.cmac,
             old_input_file.crypto_info,
-            old_input_file.owner,
+            old_input_file.owner.clone(),
         );
 
-        self.write_to_db(&input_file).await?;
+        // Participants of recurring tasks approve the latest version in the chain.
+        old_input_file.superseded_by = Some(input_file.uuid);
+        let batch = put_to_batch(BatchRequest::new(), &input_file)?;
+        let batch = put_if_unchanged(batch, &old_input_file, Some(old_value))?;
+        self.commit_to_db(batch).await?;
 
         let response = UpdateInputFileResponse::new(input_file.external_id());
         Ok(Response::new(response))
@@ -261,6 +286,58 @@ impl TeaclaveManagement for TeaclaveManagementService {
         Ok(Response::new(response))
     }
 
+    // access control: the user owns the listed files
+    async fn list_input_files(
+        &self,
+        request: Request<ListInputFilesRequest>,
+    ) -> TeaclaveServiceResponseResult<ListInputFilesResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let request = request.into_inner();
+        ensure!(
+            is_page_token_of::<TeaclaveInputFile>(&request.page_token),
+            ManagementServiceError::InvalidDataId
+        );
+        let owners: Vec<UserID> = request.owners.into_iter().map(Into::into).collect();
+        let matches = |file: &TeaclaveInputFile| {
+            file.owner.contains(&user_id) && owners.iter().all(|owner| file.owner.contains(owner))
+        };
+
+        let (files, next_page_token) = self
+            .scan_page_from_db(request.page_token, request.page_size, matches)
+            .await?;
+        let response = ListInputFilesResponse {
+            files: files.into_iter().map(Into::into).collect(),
+            next_page_token,
+        };
+        Ok(Response::new(response))
+    }
+
+    // access control: the user owns the listed files
+    async fn list_output_files(
+        &self,
+        request: Request<ListOutputFilesRequest>,
+    ) -> TeaclaveServiceResponseResult<ListOutputFilesResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let request = request.into_inner();
+        ensure!(
+            is_page_token_of::<TeaclaveOutputFile>(&request.page_token),
+            ManagementServiceError::InvalidDataId
+        );
+        let owners: Vec<UserID> = request.owners.into_iter().map(Into::into).collect();
+        let matches = |file: &TeaclaveOutputFile| {
+            file.owner.contains(&user_id) && owners.iter().all(|owner| file.owner.contains(owner))
+        };
+
+        let (files, next_page_token) = self
+            .scan_page_from_db(request.page_token, request.page_size, matches)
+            .await?;
+        let response = ListOutputFilesResponse {
+            files: files.into_iter().map(Into::into).collect(),
+            next_page_token,
+        };
+        Ok(Response::new(response))
+    }
+
     // access control: none
     async fn register_function(
         &self,
@@ -274,54 +351,35 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .owner(user_id.clone())
             .build();
 
-        self.write_to_db(&function).await?;
-
-        let mut u = User {
-            id: user_id,
-            ..Default::default()
-        };
-        let external_id = u.external_id();
+        let func_id = function.external_id().to_string();
 
-        let user = self.read_from_db::<User>(&external_id).await;
-        match user {
-            Ok(mut us) => {
-                us.registered_functions
-                    .push(function.external_id().to_string());
-                self.write_to_db(&us).await?;
-            }
-            Err(_) => {
-                u.registered_functions
-                    .push(function.external_id().to_string());
-                self.write_to_db(&u).await?;
-            }
-        }
+        // The owner may be allowed to use the function as well, so every user
+        // is read once and updated in place.
+        let mut users = HashMap::new();
+        let (mut owner, value) = self.read_user_for_update(&user_id).await;
+        owner.registered_functions.push(func_id.clone());
+        users.insert(user_id, (owner, value));
 
         // Update allowed function list for users
-        for user_id in &function.user_allowlist {
-            let mut u = User {
-                id: user_id.into(),
-                ..Default::default()
-            };
-            let external_id = u.external_id();
-            let user = self.read_from_db::<User>(&external_id).await;
-            match user {
-                Ok(mut us) => {
-                    us.allowed_functions
-                        .push(function.external_id().to_string());
-                    self.write_to_db(&us).await?;
-                }
-                Err(_) => {
-                    u.allowed_functions.push(function.external_id().to_string());
-                    self.write_to_db(&u).await?;
-                }
+        for user_id in function.user_allowlist.iter().map(UserID::from) {
+            if !users.contains_key(&user_id) {
+                let user = self.read_user_for_update(&user_id).await;
+                users.insert(user_id.clone(), user);
             }
+            let (user, _) = users.get_mut(&user_id).unwrap();
+            user.allowed_functions.push(func_id.clone());
         }
 
         let usage = FunctionUsage {
             function_id: function.id,
             ..Default::default()
         };
-        self.write_to_db(&usage).await?;
+        let batch = put_to_batch(BatchRequest::new(), &function)?;
+        let mut batch = put_to_batch(batch, &usage)?;
+        for (user, value) in users.into_values() {
+            batch = put_if_unchanged(batch, &user, value)?;
+        }
+        self.commit_to_db(batch).await?;
 
         let response = RegisterFunctionResponse::new(function.external_id());
         Ok(Response::new(response))
@@ -470,8 +528,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .function_id
             .try_into()
             .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
-        let mut function: Function = self
-            .read_from_db(&function_id)
+        let (mut function, function_value): (Function, _) = self
+            .read_for_update(&function_id)
             .await
             .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
 
@@ -481,40 +539,32 @@ impl TeaclaveManagement for TeaclaveManagementService {
         );
         let func_id = function.external_id().to_string();
 
-        // Updated function owner
-        let u = User {
-            id: function.owner.clone(),
-            ..Default::default()
-        };
-        let external_id = u.external_id();
-        let user = self.read_from_db::<User>(&external_id).await;
-        if let Ok(mut us) = user {
-            us.allowed_functions.retain(|f| !f.eq(&func_id));
-            us.registered_functions.retain(|f| !f.eq(&func_id));
-            self.write_to_db(&us).await?;
-        } else {
-            log::warn!("Invalid user id from functions");
+        // Update the function owner and the allowed function list for users
+        let user_ids = std::iter::once(function.owner.clone())
+            .chain(function.user_allowlist.iter().map(UserID::from));
+        let mut users = HashMap::new();
+        for user_id in user_ids {
+            if users.contains_key(&user_id) {
+                continue;
+            }
+            match self.read_user_for_update(&user_id).await {
+                (user, Some(value)) => {
+                    users.insert(user_id, (user, value));
+                }
+                (_, None) => log::warn!("Invalid user id from functions"),
+            }
         }
 
-        // Update allowed function list for users
-        for user_id in &function.user_allowlist {
-            let u = User {
-                id: user_id.into(),
-                ..Default::default()
-            };
-            let external_id = u.external_id();
-            let user = self.read_from_db::<User>(&external_id).await;
-            if let Ok(mut us) = user {
-                us.allowed_functions.retain(|f| !f.eq(&func_id));
-                us.registered_functions.retain(|f| !f.eq(&func_id));
-                self.write_to_db(&us).await?;
-            } else {
-                log::warn!("Invalid user id from functions");
-            }
+        let mut batch = BatchRequest::new();
+        for (mut user, value) in users.into_values() {
+            user.allowed_functions.retain(|f| !f.eq(&func_id));
+            user.registered_functions.retain(|f| !f.eq(&func_id));
+            batch = put_if_unchanged(batch, &user, Some(value))?;
         }
 
         function.user_allowlist.clear();
-        self.write_to_db(&function).await?;
+        let batch = put_if_unchanged(batch, &function, Some(function_value))?;
+        self.commit_to_db(batch).await?;
 
         Ok(Response::new(()))
     }
@@ -547,10 +597,11 @@ impl TeaclaveManagement for TeaclaveManagementService {
                     allowed_functions: us.allowed_functions,
                 };
                 if role == UserRole::PlatformAdmin {
-                    let allowed_functions = self
-                        .get_keys_by_prefix_from_db(Function::key_prefix())
-                        .await?;
-                    response.allowed_functions = allowed_functions;
+                    let functions = self.scan_from_db::<Function>().await?;
+                    response.allowed_functions = functions
+                        .iter()
+                        .map(|f| f.external_id().to_string())
+                        .collect();
                 }
 
                 Ok(Response::new(response))
@@ -574,39 +625,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
     ) -> TeaclaveServiceResponseResult<CreateTaskResponse> {
         let user_id = get_request_user_id(&request)?;
         let role = get_request_role(&request)?;
-
-        let request = request.into_inner();
-        let function_id = request
-            .function_id
-            .try_into()
-            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
-
-        let function: Function = self
-            .read_from_db(&function_id)
-            .await
-            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
-
-        match role {
-            UserRole::DataOwner(a) | UserRole::DataOwnerManager(a) => {
-                ensure!(
-                    (function.public || function.user_allowlist.contains(&a)),
-                    ManagementServiceError::PermissionDenied
-                );
-            }
-            UserRole::PlatformAdmin => (),
-            _ => {
-                return Err(ManagementServiceError::PermissionDenied.into());
-            }
-        }
-        let task = Task::<Create>::new(
-            user_id,
-            request.executor.try_into().map_err(tonic_error)?,
-            request.function_arguments.try_into().map_err(tonic_error)?,
-            from_proto_ownership(request.inputs_ownership),
-            from_proto_ownership(request.outputs_ownership),
-            function,
-        )
-        .map_err(|_| ManagementServiceError::InvalidTask)?;
+        let task = self.new_task(user_id, &role, request.into_inner()).await?;
 
         log::debug!("CreateTask: {:?}", task);
         let ts: TaskState = task.into();
@@ -650,12 +669,72 @@ impl TeaclaveManagement for TeaclaveManagementService {
             approved_users: ts.approved_users.clone().into(),
             assigned_inputs: to_proto_file_ids(ts.assigned_inputs.external_ids()),
             assigned_outputs: to_proto_file_ids(ts.assigned_outputs.external_ids()),
+            max_retries: ts.max_retries,
+            attempts: ts.attempts.into_iter().map(Into::into).collect(),
+            max_execution_time: ts.max_execution_time,
+            priority: i32_from_task_priority(ts.priority),
+            workflow_id: ts
+                .workflow_id
+                .map(|id| ExternalID::new(Workflow::key_prefix(), id).to_string())
+                .unwrap_or_default(),
+            memory_size: ts.memory_size,
             result: Some(ts.result.into()),
             status: i32_from_task_status(ts.status),
         };
         Ok(Response::new(response))
     }
 
+    // access control: the user is a participant of the listed tasks
+    async fn list_tasks(
+        &self,
+        request: Request<ListTasksRequest>,
+    ) -> TeaclaveServiceResponseResult<ListTasksResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let request = request.into_inner();
+        let relation = TaskRelation::from_i32(request.relation)
+            .ok_or(ManagementServiceError::InvalidTaskFilter)?;
+        let statuses = request
+            .statuses
+            .into_iter()
+            .map(i32_to_task_status)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|_| ManagementServiceError::InvalidTaskFilter)?;
+        let function_id: Option<ExternalID> = match request.function_id.as_str() {
+            "" => None,
+            id => Some(
+                id.try_into()
+                    .map_err(|_| ManagementServiceError::InvalidFunctionId)?,
+            ),
+        };
+        ensure!(
+            is_page_token_of::<TaskState>(&request.page_token),
+            ManagementServiceError::InvalidTaskId
+        );
+        let matches = |ts: &TaskState| {
+            let related = match relation {
+                TaskRelation::Participant => ts.has_participant(&user_id),
+                TaskRelation::Creator => ts.has_creator(&user_id),
+                TaskRelation::PendingApproval => ts.is_pending_approval_of(&user_id),
+            };
+            related
+                && (statuses.is_empty() || statuses.contains(&ts.status))
+                && function_id
+                    .as_ref()
+                    .map_or(true, |id| &ts.function_id == id)
+                && ts.created_at >= request.created_after
+                && (request.created_before == 0 || ts.created_at < request.created_before)
+        };
+
+        let (tasks, next_page_token) = self
+            .scan_page_from_db(request.page_token, request.page_size, matches)
+            .await?;
+        let response = ListTasksResponse {
+            tasks: tasks.into_iter().map(Into::into).collect(),
+            next_page_token,
+        };
+        Ok(Response::new(response))
+    }
+
     // prerequisite:
     // 1) task.participants.contains(user_id)
     // 2) task.status == Created
@@ -677,8 +756,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .try_into()
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
-        let ts: TaskState = self
-            .read_from_db(&task_id)
+        let (ts, ts_value): (TaskState, _) = self
+            .read_for_update(&task_id)
             .await
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
@@ -687,11 +766,34 @@ impl TeaclaveManagement for TeaclaveManagementService {
             ManagementServiceError::PermissionDenied
         );
 
+        let ts_workflow_id = ts.workflow_id;
         let mut task: Task<Assign> = ts.try_into().map_err(|e| {
             log::warn!("Assign state error: {:?}", e);
             ManagementServiceError::TaskAssignDataError
         })?;
         let inputs = from_proto_file_ids(request.inputs).map_err(tonic_error)?;
+        let mut batch = BatchRequest::new();
+        // Inputs fed by upstream workflow nodes are assigned by the scheduler.
+        // The data of the nodes is fixed once anyone approves the workflow.
+        if let Some(workflow_id) = ts_workflow_id {
+            let (workflow, workflow_value): (Workflow, _) = self
+                .read_for_update(&ExternalID::new(Workflow::key_prefix(), workflow_id))
+                .await?;
+            ensure!(
+                workflow.approved_users.is_empty(),
+                ManagementServiceError::TaskAssignDataError
+            );
+            let node = workflow
+                .node_of_task(&task_id.uuid)
+                .ok_or(ManagementServiceError::TaskAssignDataError)?;
+            ensure!(
+                workflow
+                    .incoming_edges(&node.name)
+                    .all(|e| !inputs.contains_key(&e.input)),
+                ManagementServiceError::TaskAssignDataError
+            );
+            batch = batch.expect_value(workflow.key(), workflow_value);
+        }
         for (data_name, data_id) in inputs.iter() {
             let file: TeaclaveInputFile = self
                 .read_from_db(data_id)
@@ -713,7 +815,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
         log::debug!("AssignData: {:?}", task);
 
         let ts: TaskState = task.into();
-        self.write_to_db(&ts).await?;
+        let batch = put_if_unchanged(batch, &ts, Some(ts_value))?;
+        self.commit_to_db(batch).await?;
 
         Ok(Response::new(()))
     }
@@ -721,6 +824,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
     // prerequisite:
     // 1) task status == Ready
     // 2) user_id in task.participants
+    // 3) task is not a node of a workflow, which is approved as a whole
     async fn approve_task(
         &self,
         request: Request<ApproveTaskRequest>,
@@ -733,11 +837,16 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .try_into()
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
-        let ts: TaskState = self
-            .read_from_db(&task_id)
+        let (ts, ts_value): (TaskState, _) = self
+            .read_for_update(&task_id)
             .await
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
+        ensure!(
+            ts.workflow_id.is_none(),
+            ManagementServiceError::TaskApproveError
+        );
+
         let mut task: Task<Approve> = ts.try_into().map_err(|e| {
             log::warn!("Approve state error: {:?}", e);
             ManagementServiceError::TaskApproveError
@@ -749,7 +858,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
         log::debug!("ApproveTask: approve:{:?}", task);
 
         let ts: TaskState = task.into();
-        self.write_to_db(&ts).await?;
+        let batch = put_if_unchanged(BatchRequest::new(), &ts, Some(ts_value))?;
+        self.commit_to_db(batch).await?;
 
         Ok(Response::new(()))
     }
@@ -757,6 +867,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
     // prerequisite:
     // 1) task status == Approved
     // 2) user_id == task.creator
+    // 3) task is not a node of a workflow, which is invoked as a whole
     async fn invoke_task(
         &self,
         request: Request<InvokeTaskRequest>,
@@ -768,8 +879,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .try_into()
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
-        let ts: TaskState = self
-            .read_from_db(&task_id)
+        let (ts, ts_value): (TaskState, _) = self
+            .read_for_update(&task_id)
             .await
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
@@ -778,6 +889,10 @@ impl TeaclaveManagement for TeaclaveManagementService {
             ts.has_creator(&user_id),
             ManagementServiceError::PermissionDenied
         );
+        ensure!(
+            ts.workflow_id.is_none(),
+            ManagementServiceError::TaskInvokeError
+        );
 
         let function: Function = self
             .read_from_db(&ts.function_id)
@@ -786,22 +901,7 @@ impl TeaclaveManagement for TeaclaveManagementService {
 
         log::debug!("InvokeTask: get function: {:?}", function);
 
-        let usage = FunctionUsage {
-            function_id: function.id,
-            ..Default::default()
-        };
-        let external_id = usage.external_id();
-        let mut function_usage = self
-            .read_from_db::<FunctionUsage>(&external_id)
-            .await
-            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
-        let function_current_use_numbers = function_usage.use_numbers;
-
-        if let Some(quota) = function.usage_quota {
-            if quota <= function_current_use_numbers {
-                return Err(ManagementServiceError::FunctionQuotaError.into());
-            }
-        }
+        let (mut function_usage, usage_value) = self.check_function_quota(&function, 1).await?;
 
         let mut task: Task<Stage> = ts.try_into().map_err(|e| {
             log::warn!("Stage state error: {:?}", e);
@@ -813,14 +913,17 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .stage_for_running(&user_id, function)
             .map_err(|_| ManagementServiceError::PermissionDenied)?;
         log::debug!("InvokeTask: staged task: {:?}", staged_task);
-        self.enqueue_to_db(StagedTask::get_queue_key().as_bytes(), &staged_task)
-            .await?;
 
         let ts: TaskState = task.into();
-        self.write_to_db(&ts).await?;
-
-        function_usage.use_numbers = function_current_use_numbers + 1;
-        self.write_to_db(&function_usage).await?;
+        function_usage.use_numbers += 1;
+        let batch = enqueue_to_batch(
+            BatchRequest::new(),
+            StagedTask::get_queue_key(),
+            &staged_task,
+        )?;
+        let batch = put_if_unchanged(batch, &ts, Some(ts_value))?;
+        let batch = put_if_unchanged(batch, &function_usage, Some(usage_value))?;
+        self.commit_to_db(batch).await?;
         Ok(Response::new(()))
     }
 
@@ -835,8 +938,8 @@ impl TeaclaveManagement for TeaclaveManagementService {
             .task_id
             .try_into()
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
-        let ts: TaskState = self
-            .read_from_db(&task_id)
+        let (ts, ts_value): (TaskState, _) = self
+            .read_for_update(&task_id)
             .await
             .map_err(|_| ManagementServiceError::InvalidTaskId)?;
 
@@ -848,11 +951,16 @@ impl TeaclaveManagement for TeaclaveManagementService {
         match ts.status {
             // need scheduler to cancel the task
             TaskStatus::Staged | TaskStatus::Running => {
-                self.enqueue_to_db(CANCEL_QUEUE_KEY.as_bytes(), &ts).await?;
+                self.enqueue_to_db_with_ttl(
+                    CANCEL_QUEUE_KEY.as_bytes(),
+                    &ts,
+                    CANCEL_RETENTION_SECS,
+                )
+                .await?;
             }
             _ => {
-                // early cancelation
-                // race will not affect correctness/privacy
+                // early cancelation, which is only written if the task has
+                // not been staged meanwhile
                 let mut task: Task<Cancel> = ts.try_into().map_err(|e| {
                     log::warn!("Cancel state error: {:?}", e);
                     ManagementServiceError::TaskCancelError(
@@ -869,7 +977,11 @@ impl TeaclaveManagement for TeaclaveManagementService {
                     ManagementServiceError::TaskCancelError("cannot update result".to_string())
                 })?;
                 let ts: TaskState = task.into();
-                self.write_to_db(&ts).await?;
+                let mut batch = put_if_unchanged(BatchRequest::new(), &ts, Some(ts_value))?;
+                if let Some(workflow_id) = ts.workflow_id {
+                    batch = self.fail_workflow(batch, workflow_id, &ts.task_id).await?;
+                }
+                self.commit_to_db(batch).await?;
 
                 log::warn!("Canceled Task: writtenback");
             }
@@ -930,108 +1042,964 @@ impl TeaclaveManagement for TeaclaveManagementService {
         let response = QueryAuditLogsResponse::new(logs);
         Ok(Response::new(response))
     }
-}
 
-impl TeaclaveManagementService {
-    pub(crate) async fn new(storage_service_endpoint: Endpoint) -> anyhow::Result<Self> {
-        let channel = storage_service_endpoint
-            .connect()
+    // access control: role == PlatformAdmin
+    async fn get_queue_state(
+        &self,
+        request: Request<GetQueueStateRequest>,
+    ) -> TeaclaveServiceResponseResult<GetQueueStateResponse> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+
+        // The scheduler saves its state once it starts, before that nothing
+        // is queued.
+        let state: SchedulerState = self
+            .read_from_db(&SchedulerState::default().external_id())
             .await
-            .map_err(|e| anyhow!("Failed to connect to storage service, {:?}", e))?;
-        let storage_client = Arc::new(Mutex::new(TeaclaveStorageClient::new_with_builtin_config(
-            channel,
-        )));
-        let client_clone = storage_client.clone();
-        let auditor = task::spawn_blocking(move || Auditor::try_new(client_clone)).await??;
-        let service = Self {
-            storage_client,
-            auditor,
-        };
+            .unwrap_or_default();
+
+        let mut response = GetQueueStateResponse::from(state);
+        response.storage_queues = vec![
+            self.storage_queue(StagedTask::get_queue_key(), |t: StagedTask| t.task_id)
+                .await?,
+            self.storage_queue(CANCEL_QUEUE_KEY, |ts: TaskState| ts.task_id)
+                .await?,
+        ];
+        Ok(Response::new(response))
+    }
 
-        #[cfg(test_mode)]
-        service.add_mock_data().await?;
+    // access control: the requester can create every node task
+    // prerequisite:
+    // 1) the nodes and edges form a DAG
+    // 2) an edge connects an output and an input with the same owner list
+    async fn create_workflow(
+        &self,
+        request: Request<CreateWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<CreateWorkflowResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let role = get_request_role(&request)?;
+        let request = request.into_inner();
 
-        Ok(service)
-    }
+        let mut tasks: HashMap<String, TaskState> = HashMap::new();
+        let mut nodes = Vec::new();
+        for node in request.nodes {
+            let task_request = node.task.ok_or(ManagementServiceError::InvalidWorkflow)?;
+            let task = self.new_task(user_id.clone(), &role, task_request).await?;
+            let ts: TaskState = task.into();
+            nodes.push(WorkflowNode::new(&node.name, ts.task_id));
+            tasks.insert(node.name, ts);
+        }
+        let edges = request.edges.into_iter().map(WorkflowEdge::from).collect();
 
-    async fn write_to_db(&self, item: &impl Storable) -> Result<(), ManagementServiceError> {
-        let k = item.key();
-        let v = item.to_vec()?;
-        let put_request = PutRequest::new(k.as_slice(), v.as_slice());
-        let _put_response = self
-            .storage_client
-            .clone()
-            .lock()
-            .await
-            .put(put_request)
-            .await
-            .map_err(|e| ManagementServiceError::Service(e.into()))?;
-        Ok(())
+        let mut workflow = Workflow::new(user_id, nodes, edges).map_err(|e| {
+            log::warn!("Workflow validation error: {:?}", e);
+            ManagementServiceError::InvalidWorkflow
+        })?;
+
+        // Every edge leaving the same output shares one fusion file, which is
+        // assigned to the upstream node right away. The fusion files get no
+        // retention, since downstream nodes may be staged at any time after
+        // the upstream node ends.
+        let mut batch = BatchRequest::new();
+        let mut fusion_files: HashMap<(String, String), Uuid> = HashMap::new();
+        for edge in workflow.edges.iter_mut() {
+            let key = (edge.from_node.clone(), edge.output.clone());
+            if let Some(data_id) = fusion_files.get(&key) {
+                edge.data_id = *data_id;
+            } else {
+                let owners = tasks[&edge.from_node]
+                    .outputs_ownership
+                    .get(&edge.output)
+                    .cloned()
+                    .ok_or(ManagementServiceError::InvalidWorkflow)?;
+                let output_file = create_fusion_data(owners).map_err(tonic_error)?;
+                let ts = tasks.remove(&edge.from_node).unwrap();
+                let mut task: Task<Assign> = ts
+                    .try_into()
+                    .map_err(|_| ManagementServiceError::InvalidWorkflow)?;
+                task.assign_output_by_platform(&edge.output, output_file.clone())
+                    .map_err(|_| ManagementServiceError::InvalidWorkflow)?;
+                tasks.insert(edge.from_node.clone(), task.into());
+                batch = put_to_batch(batch, &output_file)?;
+
+                edge.data_id = output_file.uuid;
+                fusion_files.insert(key, output_file.uuid);
+            }
+
+            let owners = tasks[&edge.from_node]
+                .outputs_ownership
+                .get(&edge.output)
+                .cloned()
+                .ok_or(ManagementServiceError::InvalidWorkflow)?;
+            tasks[&edge.to_node]
+                .inputs_ownership
+                .check(&edge.input, &owners)
+                .map_err(|_| ManagementServiceError::InvalidWorkflow)?;
+        }
+
+        workflow.participants = UserList::unions(tasks.values().map(|ts| ts.participants.clone()));
+        for ts in tasks.values_mut() {
+            ts.workflow_id = Some(workflow.id);
+            batch = put_to_batch(batch, ts)?;
+        }
+        log::debug!("CreateWorkflow: {:?}", workflow);
+        let batch = put_to_batch(batch, &workflow)?;
+        self.commit_to_db(batch).await?;
+
+        let response = CreateWorkflowResponse::new(workflow.external_id(), workflow.nodes);
+        Ok(Response::new(response))
     }
 
-    async fn read_from_db<T: Storable>(
+    // access control: user_id in workflow.participants
+    async fn get_workflow(
         &self,
-        key: &ExternalID,
-    ) -> Result<T, ManagementServiceError> {
+        request: Request<GetWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<GetWorkflowResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let workflow_id = request
+            .into_inner()
+            .workflow_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+        let workflow: Workflow = self
+            .read_from_db(&workflow_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+
         ensure!(
-            T::match_prefix(&key.prefix),
-            anyhow!("key prefix doesn't match")
+            workflow.has_participant(&user_id),
+            ManagementServiceError::PermissionDenied
         );
 
-        let request = GetRequest::new(key.to_bytes());
-        let response = self
-            .storage_client
-            .clone()
-            .lock()
-            .await
-            .get(request)
-            .await
-            .map_err(|e| ManagementServiceError::Service(e.into()))?
-            .into_inner();
-        T::from_slice(response.value.as_slice()).map_err(ManagementServiceError::Service)
+        log::debug!("GetWorkflow: {:?}", workflow);
+
+        let response = GetWorkflowResponse::from(workflow);
+        Ok(Response::new(response))
     }
 
-    async fn get_keys_by_prefix_from_db(
+    // prerequisite:
+    // 1) workflow status == Created
+    // 2) user_id in workflow.participants
+    // 3) all data of the nodes, except the data produced by upstream nodes,
+    //    is assigned
+    async fn approve_workflow(
         &self,
-        prefix: impl Into<Vec<u8>>,
-    ) -> Result<Vec<String>, ManagementServiceError> {
-        let request = GetKeysByPrefixRequest::new(prefix.into());
-        let response = self
-            .storage_client
-            .clone()
-            .lock()
-            .await
-            .get_keys_by_prefix(request)
-            .await
-            .map_err(|e| ManagementServiceError::Service(e.into()))?;
-        Ok(response
+        request: Request<ApproveWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let user_id = get_request_user_id(&request)?;
+        let workflow_id = request
             .into_inner()
-            .keys
-            .into_iter()
-            .map(String::from_utf8)
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|_| anyhow!("cannot convert keys"))?)
-    }
-
-    async fn delete_from_db(&self, key: &ExternalID) -> Result<(), ManagementServiceError> {
-        let request = DeleteRequest::new(key.to_bytes());
-        self.storage_client
-            .clone()
-            .lock()
-            .await
-            .delete(request)
+            .workflow_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+        let (mut workflow, workflow_value): (Workflow, _) = self
+            .read_for_update(&workflow_id)
             .await
-            .map_err(|e| ManagementServiceError::Service(e.into()))?;
-        Ok(())
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+
+        ensure!(
+            workflow.has_participant(&user_id),
+            ManagementServiceError::PermissionDenied
+        );
+
+        // The approval covers the data of every node, which may not change
+        // until the workflow is approved.
+        let mut batch = BatchRequest::new();
+        for node in workflow.nodes.iter() {
+            let (ts, ts_value): (TaskState, _) = self
+                .read_for_update(&ExternalID::new(TaskState::key_prefix(), node.task_id))
+                .await?;
+            ensure!(
+                workflow.node_data_assigned(&node.name, &ts),
+                ManagementServiceError::WorkflowApproveError
+            );
+            batch = batch.expect_value(ts.key(), ts_value);
+        }
+
+        workflow.approve(&user_id).map_err(|e| {
+            log::warn!("Approve workflow error: {:?}", e);
+            ManagementServiceError::WorkflowApproveError
+        })?;
+
+        log::debug!("ApproveWorkflow: {:?}", workflow);
+        let batch = put_if_unchanged(batch, &workflow, Some(workflow_value))?;
+        self.commit_to_db(batch).await?;
+
+        Ok(Response::new(()))
     }
 
-    async fn enqueue_to_db(
+    // prerequisite:
+    // 1) workflow status == Created
+    // 2) user_id == workflow.creator
+    // 3) workflow is approved by every participant
+    // 4) all data of the nodes, except the data produced by upstream nodes,
+    //    is assigned
+    // 5) the quota of every function covers its nodes
+    async fn invoke_workflow(
+        &self,
+        request: Request<InvokeWorkflowRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let user_id = get_request_user_id(&request)?;
+        let workflow_id = request
+            .into_inner()
+            .workflow_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+        let (mut workflow, workflow_value): (Workflow, _) = self
+            .read_for_update(&workflow_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidWorkflowId)?;
+
+        ensure!(
+            workflow.has_creator(&user_id),
+            ManagementServiceError::PermissionDenied
+        );
+        ensure!(
+            workflow.status == WorkflowStatus::Created && workflow.everyone_approved(),
+            ManagementServiceError::WorkflowInvokeError
+        );
+
+        // Every node is checked, so none of them may change until the workflow
+        // is invoked.
+        let mut batch = BatchRequest::new();
+        let mut node_tasks = HashMap::new();
+        let mut invocations: HashMap<Uuid, (Function, i32)> = HashMap::new();
+        for node in workflow.nodes.iter() {
+            let (ts, ts_value): (TaskState, _) = self
+                .read_for_update(&ExternalID::new(TaskState::key_prefix(), node.task_id))
+                .await?;
+            batch = batch.expect_value(ts.key(), ts_value);
+            ensure!(
+                ts.status == TaskStatus::Created || ts.status == TaskStatus::DataAssigned,
+                ManagementServiceError::WorkflowInvokeError
+            );
+            ensure!(
+                workflow.node_data_assigned(&node.name, &ts),
+                ManagementServiceError::WorkflowInvokeError
+            );
+            let function: Function = self
+                .read_from_db(&ts.function_id)
+                .await
+                .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
+            invocations.entry(function.id).or_insert((function, 0)).1 += 1;
+            node_tasks.insert(node.name.clone(), ts);
+        }
+
+        for (function, count) in invocations.values() {
+            let (mut function_usage, usage_value) =
+                self.check_function_quota(function, *count).await?;
+            function_usage.use_numbers += count;
+            batch = put_if_unchanged(batch, &function_usage, Some(usage_value))?;
+        }
+
+        workflow.status = WorkflowStatus::Running;
+        batch = put_if_unchanged(batch, &workflow, Some(workflow_value))?;
+
+        for node in workflow.root_nodes() {
+            let ts = node_tasks.remove(&node.name).unwrap();
+            let function: Function = self
+                .read_from_db(&ts.function_id)
+                .await
+                .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
+            let (ts, staged_task) = workflow.stage_node(ts, vec![], function).map_err(|e| {
+                log::warn!("Stage workflow node error: {:?}", e);
+                ManagementServiceError::WorkflowInvokeError
+            })?;
+            log::debug!("InvokeWorkflow: staged task: {:?}", staged_task);
+            batch = enqueue_to_batch(batch, StagedTask::get_queue_key(), &staged_task)?;
+            batch = put_to_batch(batch, &ts)?;
+        }
+        self.commit_to_db(batch).await?;
+        Ok(Response::new(()))
+    }
+
+    // access control: user_id == task.creator
+    // prerequisite:
+    // 1) all data of the task is assigned
+    // 2) task is approved by every participant
+    // 3) task is not a workflow node
+    // The schedule starts once every participant approves the recurring task.
+    async fn create_recurring_task(
+        &self,
+        request: Request<CreateRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<CreateRecurringTaskResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let request = request.into_inner();
+        let schedule = request
+            .schedule()
+            .map_err(|_| ManagementServiceError::InvalidRecurringTask)?;
+        let task_id = request
+            .task_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidTaskId)?;
+        let ts: TaskState = self
+            .read_from_db(&task_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidTaskId)?;
+
+        ensure!(
+            ts.has_creator(&user_id),
+            ManagementServiceError::PermissionDenied
+        );
+
+        let recurring_task = RecurringTask::new(user_id, &ts, schedule).map_err(|e| {
+            log::warn!("Create recurring task error: {:?}", e);
+            ManagementServiceError::InvalidRecurringTask
+        })?;
+        log::debug!("CreateRecurringTask: {:?}", recurring_task);
+
+        // The scheduler starts the runs once everyone has approved.
+        let batch = put_to_batch(BatchRequest::new(), &recurring_task)?;
+        let batch = enqueue_to_batch(batch, RECURRING_QUEUE_KEY, &recurring_task)?;
+        self.commit_to_db(batch).await?;
+
+        let response = CreateRecurringTaskResponse::new(recurring_task.external_id());
+        Ok(Response::new(response))
+    }
+
+    // access control: user_id in task.participants
+    async fn get_recurring_task(
+        &self,
+        request: Request<GetRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<GetRecurringTaskResponse> {
+        let user_id = get_request_user_id(&request)?;
+        let recurring_task_id = request
+            .into_inner()
+            .recurring_task_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+        let recurring_task: RecurringTask = self
+            .read_from_db(&recurring_task_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+        let ts: TaskState = self
+            .read_from_db(&ExternalID::new(
+                TaskState::key_prefix(),
+                recurring_task.task_id,
+            ))
+            .await
+            .map_err(|_| ManagementServiceError::InvalidTaskId)?;
+
+        ensure!(
+            ts.has_participant(&user_id),
+            ManagementServiceError::PermissionDenied
+        );
+
+        // The runs are kept by the scheduler.
+        let mut state: SchedulerState = self
+            .read_from_db(&SchedulerState::default().external_id())
+            .await
+            .unwrap_or_default();
+        let run = state.recurring_runs.remove(&recurring_task.id);
+
+        let response = GetRecurringTaskResponse::new(recurring_task, run);
+        Ok(Response::new(response))
+    }
+
+    // access control: user_id in recurring_task.participants
+    // The approval covers the latest versions of the input files. A newer
+    // version withdraws the previous approvals.
+    async fn approve_recurring_task(
+        &self,
+        request: Request<ApproveRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let user_id = get_request_user_id(&request)?;
+        let recurring_task_id = request
+            .into_inner()
+            .recurring_task_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+        let (mut recurring_task, value): (RecurringTask, _) = self
+            .read_for_update(&recurring_task_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+
+        ensure!(
+            recurring_task.participants.contains(&user_id),
+            ManagementServiceError::PermissionDenied
+        );
+
+        let ts: TaskState = self
+            .read_from_db(&ExternalID::new(
+                TaskState::key_prefix(),
+                recurring_task.task_id,
+            ))
+            .await
+            .map_err(|_| ManagementServiceError::InvalidTaskId)?;
+        let mut input_versions = HashMap::new();
+        for (fname, file) in ts.assigned_inputs.into_iter() {
+            let latest = self.latest_input_file(file.external_id()).await?;
+            input_versions.insert(fname, latest.uuid);
+        }
+
+        recurring_task
+            .approve(&user_id, input_versions)
+            .map_err(|_| ManagementServiceError::PermissionDenied)?;
+        log::debug!("ApproveRecurringTask: {:?}", recurring_task);
+
+        let batch = put_if_unchanged(BatchRequest::new(), &recurring_task, Some(value))?;
+        let batch = enqueue_to_batch(batch, RECURRING_QUEUE_KEY, &recurring_task)?;
+        self.commit_to_db(batch).await?;
+        Ok(Response::new(()))
+    }
+
+    // access control: user_id == recurring_task.creator || role == PlatformAdmin
+    async fn delete_recurring_task(
+        &self,
+        request: Request<DeleteRecurringTaskRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let user_id = get_request_user_id(&request)?;
+        let role = get_request_role(&request)?;
+        let recurring_task_id = request
+            .into_inner()
+            .recurring_task_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+        let recurring_task: RecurringTask = self
+            .read_from_db(&recurring_task_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+
+        ensure!(
+            role == UserRole::PlatformAdmin || recurring_task.creator == user_id,
+            ManagementServiceError::PermissionDenied
+        );
+
+        // Runs which have been queued are not affected.
+        self.delete_from_db(&recurring_task_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidRecurringTaskId)?;
+
+        Ok(Response::new(()))
+    }
+
+    // access control: role == PlatformAdmin
+    async fn list_executors(
+        &self,
+        request: Request<ListExecutorsRequest>,
+    ) -> TeaclaveServiceResponseResult<ListExecutorsResponse> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+
+        let state: SchedulerState = self
+            .read_from_db(&SchedulerState::default().external_id())
+            .await
+            .unwrap_or_default();
+
+        let response = ListExecutorsResponse::from(state);
+        Ok(Response::new(response))
+    }
+
+    // access control: role == PlatformAdmin
+    // prerequisite: the executor is known to the scheduler
+    async fn drain_executor(
+        &self,
+        request: Request<DrainExecutorRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+        let executor_id = Uuid::parse_str(&request.into_inner().executor_id)
+            .map_err(|_| ManagementServiceError::InvalidExecutorId)?;
+
+        let state: SchedulerState = self
+            .read_from_db(&SchedulerState::default().external_id())
+            .await
+            .unwrap_or_default();
+        let executor = state
+            .executors
+            .into_iter()
+            .find(|executor| executor.executor_id == executor_id)
+            .ok_or(ManagementServiceError::InvalidExecutorId)?;
+
+        // The scheduler stops handing out tasks to the executor once it
+        // pulls the drain queue, which is shortly after.
+        if !executor.draining {
+            self.enqueue_to_db(DRAIN_QUEUE_KEY.as_bytes(), &executor)
+                .await?;
+        }
+
+        Ok(Response::new(()))
+    }
+
+    type BackupDatabaseStream =
+        Pin<Box<dyn tokio_stream::Stream<Item = Result<BackupDatabaseResponse, Status>> + Send>>;
+
+    // access control: role == PlatformAdmin
+    // The chunks are passed on as they come, with a client of its own, so
+    // that other requests to the storage service are not held up meanwhile.
+    async fn backup_database(
+        &self,
+        request: Request<BackupDatabaseRequest>,
+    ) -> TeaclaveServiceResponseResult<Self::BackupDatabaseStream> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+
+        let mut client = self.storage_client.lock().await.clone();
+        let stream = client.backup(BackupRequest::new()).await?.into_inner();
+        let stream =
+            stream.map(|chunk| chunk.map(|chunk| BackupDatabaseResponse { data: chunk.data }));
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    // access control: role == PlatformAdmin
+    // prerequisite: the database is empty
+    // The archive ends early if the stream from the client fails, and then is
+    // rejected as truncated.
+    async fn restore_database(
+        &self,
+        request: Request<Streaming<RestoreDatabaseRequest>>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+
+        let stream = request
+            .into_inner()
+            .take_while(|chunk| chunk.is_ok())
+            .filter_map(|chunk| {
+                chunk.ok().map(|chunk| {
+                    RestoreRequest::new(chunk.data).source_address(chunk.source_address)
+                })
+            });
+
+        let mut client = self.storage_client.lock().await.clone();
+        client.restore(stream).await?;
+
+        Ok(Response::new(()))
+    }
+
+    // access control: role == PlatformAdmin
+    async fn get_database_stats(
+        &self,
+        request: Request<GetDatabaseStatsRequest>,
+    ) -> TeaclaveServiceResponseResult<GetDatabaseStatsResponse> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+        let request = request
+            .into_inner()
+            .ranges
+            .into_iter()
+            .fold(StatsRequest::new(), |request, range| {
+                request.range(range.start, range.limit)
+            });
+
+        let mut client = self.storage_client.lock().await;
+        let stats = client.stats(request).await?.into_inner();
+
+        Ok(Response::new(GetDatabaseStatsResponse {
+            stats: stats.stats,
+            files_at_level: stats.files_at_level,
+            sstables: stats.sstables,
+            approximate_sizes: stats.approximate_sizes,
+        }))
+    }
+
+    // access control: role == PlatformAdmin
+    async fn rotate_database_key(
+        &self,
+        request: Request<RotateDatabaseKeyRequest>,
+    ) -> TeaclaveServiceResponseResult<()> {
+        let role = get_request_role(&request)?;
+        ensure!(
+            role == UserRole::PlatformAdmin,
+            ManagementServiceError::PermissionDenied
+        );
+
+        let mut client = self.storage_client.lock().await;
+        client.rotate_key(RotateKeyRequest::new()).await?;
+
+        Ok(Response::new(()))
+    }
+}
+
+impl TeaclaveManagementService {
+    pub(crate) async fn new(storage_service_endpoint: Endpoint) -> anyhow::Result<Self> {
+        let channel = storage_service_endpoint
+            .connect()
+            .await
+            .map_err(|e| anyhow!("Failed to connect to storage service, {:?}", e))?;
+        let storage_client = Arc::new(Mutex::new(TeaclaveStorageClient::new_with_builtin_config(
+            channel,
+        )));
+        let client_clone = storage_client.clone();
+        let auditor = task::spawn_blocking(move || Auditor::try_new(client_clone)).await??;
+        let service = Self {
+            storage_client,
+            auditor,
+        };
+
+        #[cfg(test_mode)]
+        service.add_mock_data().await?;
+
+        Ok(service)
+    }
+
+    async fn new_task(
+        &self,
+        user_id: UserID,
+        role: &UserRole,
+        request: CreateTaskRequest,
+    ) -> Result<Task<Create>, Status> {
+        let function_id = request
+            .function_id
+            .try_into()
+            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
+
+        let function: Function = self
+            .read_from_db(&function_id)
+            .await
+            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
+
+        let attribute = match role {
+            UserRole::DataOwner(a) | UserRole::DataOwnerManager(a) => {
+                ensure!(
+                    (function.public || function.user_allowlist.contains(a)),
+                    ManagementServiceError::PermissionDenied
+                );
+                a.to_owned()
+            }
+            UserRole::PlatformAdmin => String::new(),
+            _ => {
+                return Err(ManagementServiceError::PermissionDenied.into());
+            }
+        };
+        ensure!(
+            request.max_retries <= MAX_TASK_RETRIES,
+            ManagementServiceError::InvalidTask
+        );
+        ensure!(
+            request.max_execution_time <= MAX_TASK_EXECUTION_TIME,
+            ManagementServiceError::InvalidTask
+        );
+        let priority = i32_to_task_priority(request.priority).map_err(tonic_error)?;
+        let mut task = Task::<Create>::new(
+            user_id,
+            request.executor.try_into().map_err(tonic_error)?,
+            request.function_arguments.try_into().map_err(tonic_error)?,
+            from_proto_ownership(request.inputs_ownership),
+            from_proto_ownership(request.outputs_ownership),
+            function,
+        )
+        .map_err(|_| ManagementServiceError::InvalidTask)?;
+        task.set_max_retries(request.max_retries);
+        task.set_max_execution_time(request.max_execution_time);
+        task.set_memory_size(request.memory_size);
+        task.set_priority(priority, role)
+            .map_err(|_| ManagementServiceError::PermissionDenied)?;
+        task.set_creator_attribute(attribute);
+
+        Ok(task)
+    }
+
+    // Reads the usage of the function for an update, which must leave room
+    // for the given number of invocations.
+    async fn check_function_quota(
+        &self,
+        function: &Function,
+        invocations: i32,
+    ) -> Result<(FunctionUsage, Vec<u8>), ManagementServiceError> {
+        let usage = FunctionUsage {
+            function_id: function.id,
+            ..Default::default()
+        };
+        let (function_usage, value) = self
+            .read_for_update::<FunctionUsage>(&usage.external_id())
+            .await
+            .map_err(|_| ManagementServiceError::InvalidFunctionId)?;
+
+        if let Some(quota) = function.usage_quota {
+            if quota < function_usage.use_numbers + invocations {
+                return Err(ManagementServiceError::FunctionQuotaError);
+            }
+        }
+        Ok((function_usage, value))
+    }
+
+    // Inspects a storage queue of tasks, which have not been pulled by the
+    // scheduler yet or are being pulled.
+    async fn storage_queue<T: Storable>(
+        &self,
+        key: &str,
+        task_id: impl Fn(T) -> Uuid,
+    ) -> Result<StorageQueue, ManagementServiceError> {
+        let length = self
+            .storage_client
+            .clone()
+            .lock()
+            .await
+            .queue_length(QueueLengthRequest::new(key))
+            .await
+            .map_err(|e| ManagementServiceError::Service(e.into()))?
+            .into_inner();
+        let peek_request = PeekRequest::new(key, STORAGE_QUEUE_PEEK_LIMIT);
+        let values = self
+            .storage_client
+            .clone()
+            .lock()
+            .await
+            .peek(peek_request)
+            .await
+            .map_err(|e| ManagementServiceError::Service(e.into()))?
+            .into_inner()
+            .values;
+
+        let task_ids = values
+            .iter()
+            .filter_map(|value| T::from_slice(value).ok())
+            .map(|item| ExternalID::new(TaskState::key_prefix(), task_id(item)).to_string())
+            .collect();
+        Ok(StorageQueue {
+            name: key.to_string(),
+            length: length.length,
+            leased: length.leased,
+            task_ids,
+        })
+    }
+
+    // Reads the user for an update. A user who is not stored yet starts out
+    // empty, and must still be absent when the update is committed.
+    async fn read_user_for_update(&self, user_id: &UserID) -> (User, Option<Vec<u8>>) {
+        let user = User {
+            id: user_id.clone(),
+            ..Default::default()
+        };
+        match self.read_for_update(&user.external_id()).await {
+            Ok((user, value)) => (user, Some(value)),
+            Err(_) => (user, None),
+        }
+    }
+
+    // Fails the nodes waiting for a canceled node, as well as the workflow,
+    // in the batch canceling the node.
+    async fn fail_workflow(
+        &self,
+        mut batch: BatchRequest,
+        workflow_id: Uuid,
+        task_id: &Uuid,
+    ) -> Result<BatchRequest, ManagementServiceError> {
+        let (mut workflow, workflow_value): (Workflow, _) = self
+            .read_for_update(&ExternalID::new(Workflow::key_prefix(), workflow_id))
+            .await?;
+        if workflow.is_ended() {
+            return Ok(batch);
+        }
+        let node = workflow
+            .node_of_task(task_id)
+            .ok_or(ManagementServiceError::InvalidWorkflow)?;
+
+        for descendant in workflow.descendants(&node.name) {
+            let (ts, ts_value): (TaskState, _) = self
+                .read_for_update(&ExternalID::new(
+                    TaskState::key_prefix(),
+                    descendant.task_id,
+                ))
+                .await?;
+            if ts.is_ended() {
+                continue;
+            }
+            let ts = Workflow::fail_node(ts, &node.name)?;
+            batch = put_if_unchanged(batch, &ts, Some(ts_value))?;
+        }
+
+        workflow.status = WorkflowStatus::Failed;
+        put_if_unchanged(batch, &workflow, Some(workflow_value))
+    }
+
+    async fn write_to_db(&self, item: &impl Storable) -> Result<(), ManagementServiceError> {
+        let k = item.key();
+        let v = item.to_vec()?;
+        let put_request = PutRequest::new(k.as_slice(), v.as_slice());
+        let _put_response = self
+            .storage_client
+            .clone()
+            .lock()
+            .await
+            .put(put_request)
+            .await
+            .map_err(|e| ManagementServiceError::Service(e.into()))?;
+        Ok(())
+    }
+
+    async fn read_from_db<T: Storable>(
+        &self,
+        key: &ExternalID,
+    ) -> Result<T, ManagementServiceError> {
+        self.read_for_update(key).await.map(|(item, _)| item)
+    }
+
+    // Reads the item together with its stored value, which the batch updating
+    // the item can expect to be unchanged.
+    async fn read_for_update<T: Storable>(
+        &self,
+        key: &ExternalID,
+    ) -> Result<(T, Vec<u8>), ManagementServiceError> {
+        ensure!(
+            T::match_prefix(&key.prefix),
+            anyhow!("key prefix doesn't match")
+        );
+
+        let request = GetRequest::new(key.to_bytes());
+        let response = self
+            .storage_client
+            .clone()
+            .lock()
+            .await
+            .get(request)
+            .await
+            .map_err(|e| ManagementServiceError::Service(e.into()))?
+            .into_inner();
+        let item =
+            T::from_slice(response.value.as_slice()).map_err(ManagementServiceError::Service)?;
+        Ok((item, response.value))
+    }
+
+    // Follows the versions of an input file registered by UpdateInputFile.
+    async fn latest_input_file(
+        &self,
+        key: ExternalID,
+    ) -> Result<TeaclaveInputFile, ManagementServiceError> {
+        let mut file: TeaclaveInputFile = self.read_from_db(&key).await?;
+        while let Some(uuid) = file.superseded_by {
+            let key = ExternalID::new(TeaclaveInputFile::key_prefix(), uuid);
+            file = self.read_from_db(&key).await?;
+        }
+        Ok(file)
+    }
+
+    // Reads all items of a type, one page of the scan at a time.
+    async fn scan_from_db<T: Storable>(&self) -> Result<Vec<T>, ManagementServiceError> {
+        let prefix = format!("{}-", T::key_prefix());
+        let mut items = Vec::new();
+        let mut continuation_token = Vec::new();
+        loop {
+            let request =
+                ScanRequest::with_prefix(prefix.as_str()).continuation_token(continuation_token);
+            let response = self
+                .storage_client
+                .clone()
+                .lock()
+                .await
+                .scan(request)
+                .await
+                .map_err(|e| ManagementServiceError::Service(e.into()))?
+                .into_inner();
+            for item in response.items {
+                items.push(T::from_slice(&item.value)?);
+            }
+            if response.continuation_token.is_empty() {
+                return Ok(items);
+            }
+            continuation_token = response.continuation_token;
+        }
+    }
+
+    // Reads a page of the items of a type which match the filter, starting
+    // after the page token. The scan goes on until the page is full or
+    // LIST_MAX_EXAMINED records are examined, and the token of the next page
+    // is empty once all items are examined. Records which cannot be decoded
+    // are skipped.
+    async fn scan_page_from_db<T: Storable>(
+        &self,
+        page_token: String,
+        page_size: u32,
+        filter: impl Fn(&T) -> bool,
+    ) -> Result<(Vec<T>, String), ManagementServiceError> {
+        let page_size = match page_size {
+            0 => LIST_MAX_PAGE_SIZE,
+            page_size => page_size.min(LIST_MAX_PAGE_SIZE),
+        } as usize;
+        let prefix = format!("{}-", T::key_prefix());
+        let mut items = Vec::new();
+        let mut examined = 0;
+        let mut continuation_token = page_token.into_bytes();
+        loop {
+            let request = ScanRequest::with_prefix(prefix.as_str())
+                .limit((LIST_MAX_EXAMINED - examined) as u32)
+                .continuation_token(continuation_token);
+            let response = self
+                .storage_client
+                .clone()
+                .lock()
+                .await
+                .scan(request)
+                .await
+                .map_err(|e| ManagementServiceError::Service(e.into()))?
+                .into_inner();
+            let last_page = response.continuation_token.is_empty();
+            let count = response.items.len();
+            for (i, item) in response.items.into_iter().enumerate() {
+                examined += 1;
+                match T::from_slice(&item.value) {
+                    Ok(value) if filter(&value) => items.push(value),
+                    Ok(_) => (),
+                    Err(e) => log::warn!(
+                        "Skipping undecodable record {}: {:?}",
+                        String::from_utf8_lossy(&item.key),
+                        e
+                    ),
+                }
+                if items.len() == page_size || examined == LIST_MAX_EXAMINED {
+                    let next_page_token = if last_page && i + 1 == count {
+                        String::new()
+                    } else {
+                        String::from_utf8_lossy(&item.key).into_owned()
+                    };
+                    return Ok((items, next_page_token));
+                }
+            }
+            if last_page {
+                return Ok((items, String::new()));
+            }
+            continuation_token = response.continuation_token;
+        }
+    }
+
+    async fn delete_from_db(&self, key: &ExternalID) -> Result<(), ManagementServiceError> {
+        let request = DeleteRequest::new(key.to_bytes());
+        self.storage_client
+            .clone()
+            .lock()
+            .await
+            .delete(request)
+            .await
+            .map_err(|e| ManagementServiceError::Service(e.into()))?;
+        Ok(())
+    }
+
+    async fn enqueue_to_db(
         &self,
         key: &[u8],
         item: &impl Storable,
+    ) -> Result<(), ManagementServiceError> {
+        self.enqueue_to_db_with_ttl(key, item, 0).await
+    }
+
+    async fn enqueue_to_db_with_ttl(
+        &self,
+        key: &[u8],
+        item: &impl Storable,
+        ttl_secs: u32,
     ) -> Result<(), ManagementServiceError> {
         let value = item.to_vec()?;
-        let enqueue_request = EnqueueRequest::new(key, value);
+        let enqueue_request = EnqueueRequest::new(key, value).ttl(ttl_secs);
         let _enqueue_response = self
             .storage_client
             .clone()
@@ -1043,6 +2011,22 @@ impl TeaclaveManagementService {
         Ok(())
     }
 
+    // Applies all writes of a state transition at once, or none of them if
+    // another request changed the data read for the transition.
+    async fn commit_to_db(&self, batch: BatchRequest) -> Result<(), ManagementServiceError> {
+        self.storage_client
+            .clone()
+            .lock()
+            .await
+            .batch(batch)
+            .await
+            .map_err(|e| match e.code() {
+                Code::Aborted => ManagementServiceError::ConcurrentUpdate,
+                _ => ManagementServiceError::Service(e.into()),
+            })?;
+        Ok(())
+    }
+
     #[cfg(test_mode)]
     async fn add_mock_data(&self) -> anyhow::Result<()> {
         let mut output_file = create_fusion_data(vec!["mock_user1", "frontend_user"])?;
@@ -1152,6 +2136,41 @@ fn get_request_role<T>(request: &Request<T>) -> Result<UserRole, ManagementServi
     Ok(UserRole::from_str(role))
 }
 
+// Puts the updated item, if the stored item is still the one it was read from,
+// or still absent if there was none.
+fn put_if_unchanged(
+    batch: BatchRequest,
+    item: &impl Storable,
+    value: Option<Vec<u8>>,
+) -> Result<BatchRequest, ManagementServiceError> {
+    let batch = match value {
+        Some(value) => batch.expect_value(item.key(), value),
+        None => batch.expect_absent(item.key()),
+    };
+    put_to_batch(batch, item)
+}
+
+// The page token of a listing is the ID of the last item examined.
+fn is_page_token_of<T: Storable>(page_token: &str) -> bool {
+    let id: anyhow::Result<ExternalID> = page_token.try_into();
+    page_token.is_empty() || id.map_or(false, |id| id.prefix == T::key_prefix())
+}
+
+fn put_to_batch(
+    batch: BatchRequest,
+    item: &impl Storable,
+) -> Result<BatchRequest, ManagementServiceError> {
+    Ok(batch.put(item.key(), item.to_vec()?))
+}
+
+fn enqueue_to_batch(
+    batch: BatchRequest,
+    key: &str,
+    item: &impl Storable,
+) -> Result<BatchRequest, ManagementServiceError> {
+    Ok(batch.enqueue(key, item.to_vec()?))
+}
+
 fn create_fusion_data(owners: impl Into<OwnerList>) -> anyhow::Result<TeaclaveOutputFile> {
     let uuid = Uuid::new_v4();
     let url = format!("fusion:///TEACLAVE_FUSION_BASE/{}.fusion", uuid);
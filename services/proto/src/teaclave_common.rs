@@ -20,7 +20,8 @@ pub use proto::*;
 
 use teaclave_crypto::TeaclaveFile128Key;
 use teaclave_types::{
-    Entry, EntryBuilder, FileCrypto, TaskFailure, TaskOutputs, TaskResult, TaskStatus,
+    Entry, EntryBuilder, FileCrypto, TaskAttempt, TaskFailure, TaskOutputs, TaskPriority,
+    TaskResult, TaskStatus, WorkerCapability,
 };
 
 use std::convert::TryInto;
@@ -107,6 +108,26 @@ pub fn i32_from_task_status(status: TaskStatus) -> i32 {
     }
 }
 
+pub fn i32_to_task_priority(priority: i32) -> Result<TaskPriority> {
+    let ret = match proto::TaskPriority::from_i32(priority) {
+        Some(proto::TaskPriority::Low) => TaskPriority::Low,
+        Some(proto::TaskPriority::Normal) => TaskPriority::Normal,
+        Some(proto::TaskPriority::High) => TaskPriority::High,
+        Some(proto::TaskPriority::Urgent) => TaskPriority::Urgent,
+        None => bail!("invalid task priority"),
+    };
+    Ok(ret)
+}
+
+pub fn i32_from_task_priority(priority: TaskPriority) -> i32 {
+    match priority {
+        TaskPriority::Low => proto::TaskPriority::Low as i32,
+        TaskPriority::Normal => proto::TaskPriority::Normal as i32,
+        TaskPriority::High => proto::TaskPriority::High as i32,
+        TaskPriority::Urgent => proto::TaskPriority::Urgent as i32,
+    }
+}
+
 impl std::convert::TryFrom<proto::TaskOutputs> for TaskOutputs {
     type Error = Error;
     fn try_from(proto: proto::TaskOutputs) -> Result<Self> {
@@ -128,6 +149,49 @@ impl std::convert::From<TaskOutputs> for proto::TaskOutputs {
     }
 }
 
+impl std::convert::TryFrom<proto::TaskAttempt> for TaskAttempt {
+    type Error = Error;
+    fn try_from(proto: proto::TaskAttempt) -> Result<Self> {
+        let ret = TaskAttempt {
+            executor_id: uuid::Uuid::parse_str(&proto.executor_id)?,
+            reason: proto.reason,
+        };
+        Ok(ret)
+    }
+}
+impl std::convert::From<TaskAttempt> for proto::TaskAttempt {
+    fn from(attempt: TaskAttempt) -> Self {
+        proto::TaskAttempt {
+            executor_id: attempt.executor_id.to_string(),
+            reason: attempt.reason,
+        }
+    }
+}
+
+impl std::convert::From<proto::WorkerCapability> for WorkerCapability {
+    fn from(proto: proto::WorkerCapability) -> Self {
+        WorkerCapability {
+            runtimes: proto.runtimes.into_iter().collect(),
+            executors: proto.executors.into_iter().collect(),
+            builtin_functions: proto.builtin_functions.into_iter().collect(),
+            memory_size: proto.memory_size,
+            slots: proto.slots,
+        }
+    }
+}
+
+impl std::convert::From<WorkerCapability> for proto::WorkerCapability {
+    fn from(capability: WorkerCapability) -> Self {
+        proto::WorkerCapability {
+            runtimes: capability.runtimes.into_iter().collect(),
+            executors: capability.executors.into_iter().collect(),
+            builtin_functions: capability.builtin_functions.into_iter().collect(),
+            memory_size: capability.memory_size,
+            slots: capability.slots,
+        }
+    }
+}
+
 impl std::convert::TryFrom<proto::TaskFailure> for TaskFailure {
     type Error = Error;
     fn try_from(proto: proto::TaskFailure) -> Result<Self> {
@@ -227,6 +291,7 @@ pub enum ExecutorCommand {
     NoAction,
     Stop,
     NewTask,
+    Shutdown,
 }
 
 impl Default for ExecutorCommand {
@@ -242,6 +307,7 @@ impl std::convert::TryFrom<proto::ExecutorCommand> for ExecutorCommand {
             proto::ExecutorCommand::NoAction => Ok(ExecutorCommand::NoAction),
             proto::ExecutorCommand::Stop => Ok(ExecutorCommand::Stop),
             proto::ExecutorCommand::NewTask => Ok(ExecutorCommand::NewTask),
+            proto::ExecutorCommand::Shutdown => Ok(ExecutorCommand::Shutdown),
         }
     }
 }
@@ -252,6 +318,7 @@ impl std::convert::From<ExecutorCommand> for proto::ExecutorCommand {
             ExecutorCommand::NoAction => proto::ExecutorCommand::NoAction,
             ExecutorCommand::Stop => proto::ExecutorCommand::Stop,
             ExecutorCommand::NewTask => proto::ExecutorCommand::NewTask,
+            ExecutorCommand::Shutdown => proto::ExecutorCommand::Shutdown,
         }
     }
 }
@@ -263,6 +330,7 @@ impl std::convert::TryFrom<i32> for ExecutorCommand {
             Some(proto::ExecutorCommand::NoAction) => Ok(ExecutorCommand::NoAction),
             Some(proto::ExecutorCommand::Stop) => Ok(ExecutorCommand::Stop),
             Some(proto::ExecutorCommand::NewTask) => Ok(ExecutorCommand::NewTask),
+            Some(proto::ExecutorCommand::Shutdown) => Ok(ExecutorCommand::Shutdown),
             _ => bail!("invalid executor status"),
         }
     }
@@ -274,6 +342,7 @@ impl std::convert::From<ExecutorCommand> for i32 {
             ExecutorCommand::NoAction => proto::ExecutorCommand::NoAction as i32,
             ExecutorCommand::Stop => proto::ExecutorCommand::Stop as i32,
             ExecutorCommand::NewTask => proto::ExecutorCommand::NewTask as i32,
+            ExecutorCommand::Shutdown => proto::ExecutorCommand::Shutdown as i32,
         }
     }
 }
@@ -15,15 +15,21 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use crate::teaclave_common::{i32_from_task_priority, i32_from_task_status, ExecutorStatus};
 use crate::teaclave_frontend_service_proto as proto;
-use anyhow::{Error, Result};
+use anyhow::{bail, Error, Result};
 use core::convert::TryInto;
 use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
 use teaclave_types::{
     Entry, Executor, ExecutorType, ExternalID, FileAuthTag, FileCrypto, Function, FunctionArgument,
-    FunctionArguments, FunctionBuilder, FunctionInput, FunctionOutput, OwnerList, TaskFileOwners,
+    FunctionArguments, FunctionBuilder, FunctionInput, FunctionOutput, OwnerList, RecurringRun,
+    RecurringTask, SchedulerState, Storable, TaskFileOwners, TaskPriority, TaskSchedule, TaskState,
+    TaskStatus, TeaclaveInputFile, TeaclaveOutputFile, Workflow, WorkflowEdge, WorkflowNode,
+    WorkflowStatus,
 };
 use url::Url;
+use uuid::Uuid;
 
 pub use proto::teaclave_frontend_client::TeaclaveFrontendClient;
 pub use proto::teaclave_frontend_server::TeaclaveFrontend;
@@ -168,6 +174,74 @@ impl GetOutputFileResponse {
     }
 }
 
+impl ListInputFilesRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn owner(mut self, owner: impl ToString) -> Self {
+        self.owners.push(owner.to_string());
+        self
+    }
+
+    pub fn page_size(self, page_size: u32) -> Self {
+        Self { page_size, ..self }
+    }
+
+    pub fn page_token(self, page_token: impl ToString) -> Self {
+        Self {
+            page_token: page_token.to_string(),
+            ..self
+        }
+    }
+}
+
+impl From<TeaclaveInputFile> for InputFileSummary {
+    fn from(file: TeaclaveInputFile) -> Self {
+        Self {
+            data_id: file.external_id().to_string(),
+            owner: file.owner.into(),
+            cmac: file.cmac.to_bytes(),
+            superseded_by: file
+                .superseded_by
+                .map(|uuid| ExternalID::new(TeaclaveInputFile::key_prefix(), uuid).to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl ListOutputFilesRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn owner(mut self, owner: impl ToString) -> Self {
+        self.owners.push(owner.to_string());
+        self
+    }
+
+    pub fn page_size(self, page_size: u32) -> Self {
+        Self { page_size, ..self }
+    }
+
+    pub fn page_token(self, page_token: impl ToString) -> Self {
+        Self {
+            page_token: page_token.to_string(),
+            ..self
+        }
+    }
+}
+
+impl From<TeaclaveOutputFile> for OutputFileSummary {
+    fn from(file: TeaclaveOutputFile) -> Self {
+        Self {
+            data_id: file.external_id().to_string(),
+            owner: file.owner.into(),
+            cmac: file.cmac.map_or_else(Vec::new, |cmac| cmac.to_bytes()),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct RegisterFunctionRequestBuilder {
     request: RegisterFunctionRequest,
@@ -475,6 +549,34 @@ impl CreateTaskRequest {
         }
     }
 
+    pub fn max_retries(self, max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..self
+        }
+    }
+
+    pub fn max_execution_time(self, max_execution_time: u64) -> Self {
+        Self {
+            max_execution_time,
+            ..self
+        }
+    }
+
+    pub fn task_priority(self, priority: TaskPriority) -> Self {
+        Self {
+            priority: i32_from_task_priority(priority),
+            ..self
+        }
+    }
+
+    pub fn memory_size(self, memory_size: u64) -> Self {
+        Self {
+            memory_size,
+            ..self
+        }
+    }
+
     pub fn inputs_ownership(self, map: impl Into<TaskFileOwners>) -> Self {
         Self {
             inputs_ownership: to_proto_ownership(map.into()),
@@ -506,6 +608,70 @@ impl GetTaskRequest {
     }
 }
 
+impl ListTasksRequest {
+    pub fn new(relation: TaskRelation) -> Self {
+        Self {
+            relation: relation as i32,
+            ..Default::default()
+        }
+    }
+
+    pub fn status(mut self, status: TaskStatus) -> Self {
+        self.statuses.push(i32_from_task_status(status));
+        self
+    }
+
+    pub fn function_id(self, function_id: ExternalID) -> Self {
+        Self {
+            function_id: function_id.to_string(),
+            ..self
+        }
+    }
+
+    pub fn created_after(self, created_after: u64) -> Self {
+        Self {
+            created_after,
+            ..self
+        }
+    }
+
+    pub fn created_before(self, created_before: u64) -> Self {
+        Self {
+            created_before,
+            ..self
+        }
+    }
+
+    pub fn page_size(self, page_size: u32) -> Self {
+        Self { page_size, ..self }
+    }
+
+    pub fn page_token(self, page_token: impl ToString) -> Self {
+        Self {
+            page_token: page_token.to_string(),
+            ..self
+        }
+    }
+}
+
+impl From<TaskState> for TaskSummary {
+    fn from(ts: TaskState) -> Self {
+        Self {
+            task_id: ts.external_id().to_string(),
+            creator: ts.creator.to_string(),
+            function_id: ts.function_id.to_string(),
+            participants: ts.participants.into(),
+            approved_users: ts.approved_users.into(),
+            created_at: ts.created_at,
+            workflow_id: ts
+                .workflow_id
+                .map(|id| ExternalID::new(Workflow::key_prefix(), id).to_string())
+                .unwrap_or_default(),
+            status: i32_from_task_status(ts.status),
+        }
+    }
+}
+
 impl AssignDataRequest {
     pub fn new(
         task_id: ExternalID,
@@ -692,3 +858,337 @@ impl QueryAuditLogsResponse {
         Self { logs }
     }
 }
+
+impl From<teaclave_types::QueuedTask> for QueuedTask {
+    fn from(task: teaclave_types::QueuedTask) -> Self {
+        Self {
+            task_id: ExternalID::new(TaskState::key_prefix(), task.task_id).to_string(),
+            creator: task.creator,
+            priority: i32_from_task_priority(task.priority),
+            share_group: task.share_group,
+            share_group_running: task.share_group_running,
+        }
+    }
+}
+
+impl From<SchedulerState> for GetQueueStateResponse {
+    fn from(state: SchedulerState) -> Self {
+        let queued_tasks = state.queued_tasks.into_iter().map(Into::into).collect();
+        let mut pending_retries: Vec<PendingRetry> = state
+            .pending_retries
+            .into_iter()
+            .map(|(task_id, retry_at)| PendingRetry {
+                task_id: ExternalID::new(TaskState::key_prefix(), task_id).to_string(),
+                retry_at: retry_at
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        pending_retries.sort_by_key(|retry| retry.retry_at);
+
+        Self {
+            queued_tasks,
+            pending_retries,
+            running_tasks: state.executors_tasks.values().map(|t| t.len() as u32).sum(),
+            storage_queues: Vec::new(),
+        }
+    }
+}
+
+impl From<teaclave_types::ExecutorInfo> for ExecutorInfo {
+    fn from(executor: teaclave_types::ExecutorInfo) -> Self {
+        let status = if executor.executing {
+            ExecutorStatus::Executing
+        } else {
+            ExecutorStatus::Idle
+        };
+        Self {
+            executor_id: executor.executor_id.to_string(),
+            last_heartbeat: executor
+                .last_heartbeat
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            status: status.into(),
+            task_ids: executor
+                .task_ids
+                .into_iter()
+                .map(|task_id| ExternalID::new(TaskState::key_prefix(), task_id).to_string())
+                .collect(),
+            draining: executor.draining,
+            slots: executor.slots,
+        }
+    }
+}
+
+impl From<SchedulerState> for ListExecutorsResponse {
+    fn from(state: SchedulerState) -> Self {
+        Self {
+            executors: state.executors.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl DrainExecutorRequest {
+    pub fn new(executor_id: Uuid) -> Self {
+        Self {
+            executor_id: executor_id.to_string(),
+        }
+    }
+}
+
+impl BackupDatabaseRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RestoreDatabaseRequest {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            data: data.into(),
+            source_address: String::new(),
+        }
+    }
+
+    pub fn source_address(self, source_address: impl Into<String>) -> Self {
+        Self {
+            source_address: source_address.into(),
+            ..self
+        }
+    }
+}
+
+impl GetDatabaseStatsRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn range(mut self, start: impl Into<Vec<u8>>, limit: impl Into<Vec<u8>>) -> Self {
+        self.ranges.push(DatabaseKeyRange {
+            start: start.into(),
+            limit: limit.into(),
+        });
+        self
+    }
+}
+
+impl RotateDatabaseKeyRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CreateWorkflowRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node(mut self, name: impl ToString, task: CreateTaskRequest) -> Self {
+        self.nodes.push(proto::WorkflowNode {
+            name: name.to_string(),
+            task: Some(task),
+        });
+        self
+    }
+
+    pub fn edge(
+        mut self,
+        from_node: impl ToString,
+        output: impl ToString,
+        to_node: impl ToString,
+        input: impl ToString,
+    ) -> Self {
+        self.edges
+            .push(WorkflowEdge::new(from_node, output, to_node, input).into());
+        self
+    }
+}
+
+impl CreateWorkflowResponse {
+    pub fn new(workflow_id: ExternalID, nodes: Vec<WorkflowNode>) -> Self {
+        Self {
+            workflow_id: workflow_id.to_string(),
+            tasks: nodes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl GetWorkflowRequest {
+    pub fn new(workflow_id: ExternalID) -> Self {
+        Self {
+            workflow_id: workflow_id.to_string(),
+        }
+    }
+}
+
+impl ApproveWorkflowRequest {
+    pub fn new(workflow_id: ExternalID) -> Self {
+        Self {
+            workflow_id: workflow_id.to_string(),
+        }
+    }
+}
+
+impl InvokeWorkflowRequest {
+    pub fn new(workflow_id: ExternalID) -> Self {
+        Self {
+            workflow_id: workflow_id.to_string(),
+        }
+    }
+}
+
+impl From<WorkflowNode> for WorkflowTask {
+    fn from(node: WorkflowNode) -> Self {
+        Self {
+            name: node.name,
+            task_id: ExternalID::new(TaskState::key_prefix(), node.task_id).to_string(),
+        }
+    }
+}
+
+impl From<WorkflowEdge> for proto::WorkflowEdge {
+    fn from(edge: WorkflowEdge) -> Self {
+        Self {
+            from_node: edge.from_node,
+            output: edge.output,
+            to_node: edge.to_node,
+            input: edge.input,
+        }
+    }
+}
+
+impl From<proto::WorkflowEdge> for WorkflowEdge {
+    fn from(proto: proto::WorkflowEdge) -> Self {
+        WorkflowEdge::new(proto.from_node, proto.output, proto.to_node, proto.input)
+    }
+}
+
+pub fn i32_to_workflow_status(status: i32) -> Result<WorkflowStatus> {
+    let ret = match proto::WorkflowStatus::from_i32(status) {
+        Some(proto::WorkflowStatus::Created) => WorkflowStatus::Created,
+        Some(proto::WorkflowStatus::Running) => WorkflowStatus::Running,
+        Some(proto::WorkflowStatus::Finished) => WorkflowStatus::Finished,
+        Some(proto::WorkflowStatus::Failed) => WorkflowStatus::Failed,
+        None => bail!("invalid workflow status"),
+    };
+    Ok(ret)
+}
+
+pub fn i32_from_workflow_status(status: WorkflowStatus) -> i32 {
+    match status {
+        WorkflowStatus::Created => proto::WorkflowStatus::Created as i32,
+        WorkflowStatus::Running => proto::WorkflowStatus::Running as i32,
+        WorkflowStatus::Finished => proto::WorkflowStatus::Finished as i32,
+        WorkflowStatus::Failed => proto::WorkflowStatus::Failed as i32,
+    }
+}
+
+impl From<Workflow> for GetWorkflowResponse {
+    fn from(workflow: Workflow) -> Self {
+        Self {
+            workflow_id: workflow.external_id().to_string(),
+            creator: workflow.creator.to_string(),
+            tasks: workflow.nodes.into_iter().map(Into::into).collect(),
+            edges: workflow.edges.into_iter().map(Into::into).collect(),
+            participants: workflow.participants.into(),
+            approved_users: workflow.approved_users.into(),
+            status: i32_from_workflow_status(workflow.status),
+        }
+    }
+}
+
+impl CreateRecurringTaskRequest {
+    pub fn new(task_id: ExternalID, schedule: TaskSchedule) -> Self {
+        let request = Self {
+            task_id: task_id.to_string(),
+            ..Default::default()
+        };
+        match schedule {
+            TaskSchedule::Interval(interval_seconds) => Self {
+                interval_seconds,
+                ..request
+            },
+            TaskSchedule::Cron(cron) => Self { cron, ..request },
+        }
+    }
+
+    pub fn schedule(&self) -> Result<TaskSchedule> {
+        match (self.interval_seconds, self.cron.is_empty()) {
+            (0, false) => Ok(TaskSchedule::Cron(self.cron.clone())),
+            (interval_seconds, true) if interval_seconds > 0 => {
+                Ok(TaskSchedule::Interval(interval_seconds))
+            }
+            _ => bail!("exactly one of interval_seconds and cron should be set"),
+        }
+    }
+}
+
+impl CreateRecurringTaskResponse {
+    pub fn new(recurring_task_id: ExternalID) -> Self {
+        Self {
+            recurring_task_id: recurring_task_id.to_string(),
+        }
+    }
+}
+
+impl GetRecurringTaskRequest {
+    pub fn new(recurring_task_id: ExternalID) -> Self {
+        Self {
+            recurring_task_id: recurring_task_id.to_string(),
+        }
+    }
+}
+
+impl ApproveRecurringTaskRequest {
+    pub fn new(recurring_task_id: ExternalID) -> Self {
+        Self {
+            recurring_task_id: recurring_task_id.to_string(),
+        }
+    }
+}
+
+impl DeleteRecurringTaskRequest {
+    pub fn new(recurring_task_id: ExternalID) -> Self {
+        Self {
+            recurring_task_id: recurring_task_id.to_string(),
+        }
+    }
+}
+
+impl GetRecurringTaskResponse {
+    // The runs are unknown until the scheduler picks up the recurring task.
+    pub fn new(recurring_task: RecurringTask, run: Option<RecurringRun>) -> Self {
+        let (interval_seconds, cron) = match &recurring_task.schedule {
+            TaskSchedule::Interval(interval_seconds) => (*interval_seconds, String::new()),
+            TaskSchedule::Cron(cron) => (0, cron.clone()),
+        };
+        let response = Self {
+            recurring_task_id: recurring_task.external_id().to_string(),
+            task_id: ExternalID::new(TaskState::key_prefix(), recurring_task.task_id).to_string(),
+            creator: recurring_task.creator.to_string(),
+            participants: recurring_task.participants.into(),
+            approved_users: recurring_task.approved_users.into(),
+            interval_seconds,
+            cron,
+            ..Default::default()
+        };
+        match run {
+            Some(run) => Self {
+                next_run: run
+                    .next_run
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+                last_task_id: run
+                    .last_task_id
+                    .map(|task_id| ExternalID::new(TaskState::key_prefix(), task_id).to_string())
+                    .unwrap_or_default(),
+                runs: run.runs,
+                ..response
+            },
+            None => response,
+        }
+    }
+}
@@ -44,6 +44,10 @@ pub type GetInputFileRequest = crate::teaclave_frontend_service::GetInputFileReq
 pub type GetInputFileResponse = crate::teaclave_frontend_service::GetInputFileResponse;
 pub type GetOutputFileRequest = crate::teaclave_frontend_service::GetOutputFileRequest;
 pub type GetOutputFileResponse = crate::teaclave_frontend_service::GetOutputFileResponse;
+pub type ListInputFilesRequest = crate::teaclave_frontend_service::ListInputFilesRequest;
+pub type ListInputFilesResponse = crate::teaclave_frontend_service::ListInputFilesResponse;
+pub type ListOutputFilesRequest = crate::teaclave_frontend_service::ListOutputFilesRequest;
+pub type ListOutputFilesResponse = crate::teaclave_frontend_service::ListOutputFilesResponse;
 pub type RegisterFunctionRequest = crate::teaclave_frontend_service::RegisterFunctionRequest;
 pub type RegisterFunctionRequestBuilder =
     crate::teaclave_frontend_service::RegisterFunctionRequestBuilder;
@@ -66,12 +70,34 @@ pub type CreateTaskRequest = crate::teaclave_frontend_service::CreateTaskRequest
 pub type CreateTaskResponse = crate::teaclave_frontend_service::CreateTaskResponse;
 pub type GetTaskRequest = crate::teaclave_frontend_service::GetTaskRequest;
 pub type GetTaskResponse = crate::teaclave_frontend_service::GetTaskResponse;
+pub type ListTasksRequest = crate::teaclave_frontend_service::ListTasksRequest;
+pub type ListTasksResponse = crate::teaclave_frontend_service::ListTasksResponse;
+pub type TaskRelation = crate::teaclave_frontend_service::TaskRelation;
 pub type AssignDataRequest = crate::teaclave_frontend_service::AssignDataRequest;
 pub type ApproveTaskRequest = crate::teaclave_frontend_service::ApproveTaskRequest;
 pub type InvokeTaskRequest = crate::teaclave_frontend_service::InvokeTaskRequest;
 pub type CancelTaskRequest = crate::teaclave_frontend_service::CancelTaskRequest;
 pub type QueryAuditLogsRequest = crate::teaclave_frontend_service::QueryAuditLogsRequest;
 pub type QueryAuditLogsResponse = crate::teaclave_frontend_service::QueryAuditLogsResponse;
+pub type GetQueueStateRequest = crate::teaclave_frontend_service::GetQueueStateRequest;
+pub type GetQueueStateResponse = crate::teaclave_frontend_service::GetQueueStateResponse;
+pub type CreateWorkflowRequest = crate::teaclave_frontend_service::CreateWorkflowRequest;
+pub type CreateWorkflowResponse = crate::teaclave_frontend_service::CreateWorkflowResponse;
+pub type GetWorkflowRequest = crate::teaclave_frontend_service::GetWorkflowRequest;
+pub type GetWorkflowResponse = crate::teaclave_frontend_service::GetWorkflowResponse;
+pub type ApproveWorkflowRequest = crate::teaclave_frontend_service::ApproveWorkflowRequest;
+pub type InvokeWorkflowRequest = crate::teaclave_frontend_service::InvokeWorkflowRequest;
+pub type CreateRecurringTaskRequest = crate::teaclave_frontend_service::CreateRecurringTaskRequest;
+pub type CreateRecurringTaskResponse =
+    crate::teaclave_frontend_service::CreateRecurringTaskResponse;
+pub type GetRecurringTaskRequest = crate::teaclave_frontend_service::GetRecurringTaskRequest;
+pub type GetRecurringTaskResponse = crate::teaclave_frontend_service::GetRecurringTaskResponse;
+pub type ApproveRecurringTaskRequest =
+    crate::teaclave_frontend_service::ApproveRecurringTaskRequest;
+pub type DeleteRecurringTaskRequest = crate::teaclave_frontend_service::DeleteRecurringTaskRequest;
+pub type ListExecutorsRequest = crate::teaclave_frontend_service::ListExecutorsRequest;
+pub type ListExecutorsResponse = crate::teaclave_frontend_service::ListExecutorsResponse;
+pub type DrainExecutorRequest = crate::teaclave_frontend_service::DrainExecutorRequest;
 
 impl SaveLogsRequest {
     pub fn new(entries: Vec<Entry>) -> Self {
@@ -22,12 +22,14 @@ pub use proto::teaclave_scheduler_client::TeaclaveSchedulerClient;
 pub use proto::teaclave_scheduler_server::TeaclaveScheduler;
 pub use proto::teaclave_scheduler_server::TeaclaveSchedulerServer;
 pub use proto::{
-    HeartbeatRequest, PublishTaskRequest, PullTaskRequest, UpdateTaskResultRequest,
-    UpdateTaskStatusRequest,
+    HeartbeatRequest, PublishTaskRequest, PullTaskRequest, SubscribeRequest,
+    UpdateTaskResultRequest, UpdateTaskStatusRequest,
 };
 pub use proto::{HeartbeatResponse, PullTaskResponse, SubscribeResponse};
 use teaclave_types::Storable;
-use teaclave_types::{StagedTask, TaskFailure, TaskOutputs, TaskResult, TaskStatus};
+use teaclave_types::{
+    StagedTask, TaskFailure, TaskOutputs, TaskResult, TaskStatus, WorkerCapability,
+};
 use uuid::Uuid;
 
 impl_custom_server!(TeaclaveSchedulerServer, TeaclaveScheduler);
@@ -38,8 +40,14 @@ impl HeartbeatRequest {
         Self {
             executor_id: executor_id.to_string(),
             status: status.into(),
+            capability: None,
         }
     }
+
+    pub fn capability(mut self, capability: WorkerCapability) -> Self {
+        self.capability = Some(capability.into());
+        self
+    }
 }
 
 impl HeartbeatResponse {
@@ -50,6 +58,54 @@ impl HeartbeatResponse {
     }
 }
 
+impl SubscribeRequest {
+    pub fn new(executor_id: Uuid) -> Self {
+        Self {
+            executor_id: executor_id.to_string(),
+            capability: None,
+        }
+    }
+
+    pub fn capability(mut self, capability: WorkerCapability) -> Self {
+        self.capability = Some(capability.into());
+        self
+    }
+}
+
+impl SubscribeResponse {
+    pub fn new_task(staged_task: &StagedTask) -> Self {
+        Self {
+            command: ExecutorCommand::NewTask.into(),
+            staged_task: staged_task.to_vec().unwrap(),
+            task_id: String::new(),
+        }
+    }
+
+    pub fn stop() -> Self {
+        Self {
+            command: ExecutorCommand::Stop.into(),
+            staged_task: Vec::new(),
+            task_id: String::new(),
+        }
+    }
+
+    pub fn stop_task(task_id: Uuid) -> Self {
+        Self {
+            command: ExecutorCommand::Stop.into(),
+            staged_task: Vec::new(),
+            task_id: task_id.to_string(),
+        }
+    }
+
+    pub fn shutdown() -> Self {
+        Self {
+            command: ExecutorCommand::Shutdown.into(),
+            staged_task: Vec::new(),
+            task_id: String::new(),
+        }
+    }
+}
+
 impl PullTaskResponse {
     pub fn new(staged_task: StagedTask) -> Self {
         Self {
@@ -16,12 +16,18 @@
 // under the License.
 
 use crate::teaclave_storage_service_proto as proto;
+pub use proto::batch_operation::Operation as BatchOperationKind;
 pub use proto::teaclave_storage_client::TeaclaveStorageClient;
 pub use proto::teaclave_storage_server::TeaclaveStorage;
 pub use proto::teaclave_storage_server::TeaclaveStorageServer;
 pub use proto::{
+    AckRequest, BackupChunk, BackupRequest, BatchOperation, BatchPrecondition, BatchRequest,
     DeleteRequest, DequeueRequest, DequeueResponse, EnqueueRequest, GetKeysByPrefixRequest,
-    GetKeysByPrefixResponse, GetRequest, GetResponse, PutRequest,
+    GetKeysByPrefixResponse, GetRequest, GetResponse, KeyRange, KeyValue, NackRequest,
+    OpenArchiveKeyRequest, OpenArchiveKeyResponse, PeekRequest, PeekResponse, PromoteRequest,
+    PutRequest, QueueLengthRequest, QueueLengthResponse, ReplicateRequest, ReplicationEntry,
+    RestoreRequest, RotateKeyRequest, ScanRequest, ScanResponse, StatsRequest, StatsResponse,
+    WatchEvent, WatchEventKind, WatchRequest,
 };
 
 impl_custom_server!(TeaclaveStorageServer, TeaclaveStorage);
@@ -46,8 +52,13 @@ impl PutRequest {
         Self {
             key: key.into(),
             value: value.into(),
+            ttl_secs: 0,
         }
     }
+
+    pub fn ttl(self, ttl_secs: u32) -> Self {
+        Self { ttl_secs, ..self }
+    }
 }
 
 impl DeleteRequest {
@@ -61,13 +72,25 @@ impl EnqueueRequest {
         Self {
             key: key.into(),
             value: value.into(),
+            ttl_secs: 0,
         }
     }
+
+    pub fn ttl(self, ttl_secs: u32) -> Self {
+        Self { ttl_secs, ..self }
+    }
 }
 
 impl DequeueRequest {
     pub fn new(key: impl Into<Vec<u8>>) -> Self {
-        Self { key: key.into() }
+        Self {
+            key: key.into(),
+            lease_secs: 0,
+        }
+    }
+
+    pub fn lease(self, lease_secs: u32) -> Self {
+        Self { lease_secs, ..self }
     }
 }
 
@@ -75,10 +98,122 @@ impl DequeueResponse {
     pub fn new(value: impl Into<Vec<u8>>) -> Self {
         Self {
             value: value.into(),
+            receipt: vec![],
+        }
+    }
+}
+
+impl AckRequest {
+    pub fn new(key: impl Into<Vec<u8>>, receipt: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            receipt: receipt.into(),
         }
     }
 }
 
+impl NackRequest {
+    pub fn new(key: impl Into<Vec<u8>>, receipt: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            receipt: receipt.into(),
+        }
+    }
+}
+
+impl PeekRequest {
+    pub fn new(key: impl Into<Vec<u8>>, limit: u32) -> Self {
+        Self {
+            key: key.into(),
+            limit,
+        }
+    }
+}
+
+impl WatchRequest {
+    pub fn new(prefix: impl Into<Vec<u8>>) -> Self {
+        Self {
+            prefixes: vec![prefix.into()],
+        }
+    }
+
+    pub fn prefix(mut self, prefix: impl Into<Vec<u8>>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+}
+
+impl WatchEvent {
+    pub fn put(key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind: WatchEventKind::Put as i32,
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    pub fn delete(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            kind: WatchEventKind::Delete as i32,
+            key: key.into(),
+            value: Vec::new(),
+        }
+    }
+}
+
+impl BackupRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RestoreRequest {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            data: data.into(),
+            source_address: String::new(),
+        }
+    }
+
+    pub fn source_address(self, source_address: impl Into<String>) -> Self {
+        Self {
+            source_address: source_address.into(),
+            ..self
+        }
+    }
+}
+
+impl OpenArchiveKeyRequest {
+    pub fn new(sealed_key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            sealed_key: sealed_key.into(),
+        }
+    }
+}
+
+impl ReplicationEntry {
+    pub fn new(batch: impl Into<Vec<u8>>, reset: bool) -> Self {
+        Self {
+            batch: batch.into(),
+            reset,
+            end_of_snapshot: false,
+        }
+    }
+
+    pub fn end_of_snapshot(self) -> Self {
+        Self {
+            end_of_snapshot: true,
+            ..self
+        }
+    }
+}
+
+impl QueueLengthRequest {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
 impl GetKeysByPrefixRequest {
     pub fn new(prefix: impl Into<Vec<u8>>) -> Self {
         Self {
@@ -93,6 +228,113 @@ impl GetKeysByPrefixResponse {
     }
 }
 
+impl ScanRequest {
+    pub fn with_prefix(prefix: impl Into<Vec<u8>>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_range(start_key: impl Into<Vec<u8>>, end_key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            start_key: start_key.into(),
+            end_key: end_key.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(self, limit: u32) -> Self {
+        Self { limit, ..self }
+    }
+
+    pub fn continuation_token(self, continuation_token: impl Into<Vec<u8>>) -> Self {
+        Self {
+            continuation_token: continuation_token.into(),
+            ..self
+        }
+    }
+}
+
+impl RotateKeyRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StatsRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn range(mut self, start: impl Into<Vec<u8>>, limit: impl Into<Vec<u8>>) -> Self {
+        self.ranges.push(KeyRange {
+            start: start.into(),
+            limit: limit.into(),
+        });
+        self
+    }
+}
+
+impl BatchRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        self.operation(BatchOperationKind::Put(PutRequest::new(key, value)))
+    }
+
+    pub fn put_with_ttl(
+        self,
+        key: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        ttl_secs: u32,
+    ) -> Self {
+        let request = PutRequest::new(key, value).ttl(ttl_secs);
+        self.operation(BatchOperationKind::Put(request))
+    }
+
+    pub fn delete(self, key: impl Into<Vec<u8>>) -> Self {
+        self.operation(BatchOperationKind::Delete(DeleteRequest::new(key)))
+    }
+
+    pub fn enqueue(self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        self.operation(BatchOperationKind::Enqueue(EnqueueRequest::new(key, value)))
+    }
+
+    /// The batch is only applied if the key still holds the value.
+    pub fn expect_value(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        self.preconditions.push(BatchPrecondition {
+            key: key.into(),
+            exists: true,
+            value: value.into(),
+        });
+        self
+    }
+
+    /// The batch is only applied if the key does not exist.
+    pub fn expect_absent(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.preconditions.push(BatchPrecondition {
+            key: key.into(),
+            exists: false,
+            value: vec![],
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    fn operation(mut self, operation: BatchOperationKind) -> Self {
+        self.operations.push(BatchOperation {
+            operation: Some(operation),
+        });
+        self
+    }
+}
+
 #[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
 pub enum TeaclaveStorageRequest {
@@ -101,7 +343,26 @@ pub enum TeaclaveStorageRequest {
     Delete(DeleteRequest),
     Enqueue(EnqueueRequest),
     Dequeue(DequeueRequest),
+    Ack(AckRequest),
+    Nack(NackRequest),
+    Peek(PeekRequest),
+    QueueLength(QueueLengthRequest),
     GetKeysByPrefix(GetKeysByPrefixRequest),
+    Batch(BatchRequest),
+    Scan(ScanRequest),
+    // Takes the snapshot of a backup
+    Backup(BackupRequest),
+    // The steps of a restore, as the proxy decrypts the archive
+    StartRestore,
+    RestoreRecords(Vec<(Vec<u8>, Vec<u8>)>),
+    FinishRestore,
+    AbortRestore,
+    // Starts the replication to the follower registered with the id
+    Replicate(u64),
+    // An entry received by a follower from the primary
+    Replicated(ReplicationEntry),
+    Promote(PromoteRequest),
+    RotateKey(RotateKeyRequest),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -110,6 +371,11 @@ pub enum TeaclaveStorageRequest {
 pub enum TeaclaveStorageResponse {
     Get(GetResponse),
     Dequeue(DequeueResponse),
+    Peek(PeekResponse),
+    QueueLength(QueueLengthResponse),
     GetKeysByPrefix(GetKeysByPrefixResponse),
+    Scan(ScanResponse),
+    // The id of the snapshot taken for a backup
+    Backup(u64),
     Empty(()),
 }
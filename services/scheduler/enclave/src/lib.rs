@@ -88,7 +88,8 @@ async fn start_service(config: &RuntimeConfig) -> Result<()> {
     info!(" Starting Scheduler: setup storage endpoint finished ...");
 
     let service_resources =
-        service::TeaclaveSchedulerResources::new(storage_service_endpoint).await?;
+        service::TeaclaveSchedulerResources::new(storage_service_endpoint, &config.scheduler)
+            .await?;
 
     let service_resources = Arc::new(Mutex::new(service_resources));
 
@@ -156,8 +157,10 @@ register_ecall_handler!(
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
 
     pub fn run_tests() -> bool {
-        true
+        run_tests!(service::tests::test_retry_backoff,)
     }
 }
@@ -17,24 +17,44 @@
 
 use crate::error::SchedulerServiceError;
 
+use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 #[allow(unused_imports)]
 use std::untrusted::time::SystemTimeEx;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
 
 use anyhow::{anyhow, Result};
+use teaclave_config::{FairSharePolicy, SchedulerConfig};
 use teaclave_proto::teaclave_common::{ExecutorCommand, ExecutorStatus};
 use teaclave_proto::teaclave_scheduler_service::*;
 use teaclave_proto::teaclave_storage_service::*;
 use teaclave_rpc::transport::{channel::Endpoint, Channel};
-use teaclave_rpc::{Request, Response};
+use teaclave_rpc::{Request, Response, Status, Streaming};
 use teaclave_types::*;
 use uuid::Uuid;
 
 const EXECUTOR_TIMEOUT_SECS: u64 = 30;
+// The queues are watched, and polled at this interval in case the watch
+// breaks. Retries, timeouts and recurring tasks are checked at least as often.
+const QUEUE_POLL_INTERVAL_SECS: u64 = 2;
+// Heartbeats are not saved one by one, the executor view in the saved state is
+// refreshed at this interval instead.
+const EXECUTOR_REPORT_INTERVAL_SECS: u64 = 10;
+const RETRY_BACKOFF_BASE_SECS: u64 = 5;
+const RETRY_BACKOFF_MAX_SECS: u64 = 300;
+const SUBSCRIBER_CHANNEL_SIZE: usize = 16;
+// Entries pulled from the storage queues return to the queues if they are not
+// acked in time, e.g. because the scheduler crashed.
+const QUEUE_LEASE_SECS: u32 = 60;
+// Staged tasks are deleted when their task ends. The retention only bounds the
+// ones left behind, e.g. when the deletion fails.
+const STAGED_TASK_RETENTION_SECS: u32 = 30 * 24 * 3600;
+
+type SubscriberSender = mpsc::Sender<std::result::Result<SubscribeResponse, Status>>;
 
 #[derive(Clone)]
 pub(crate) struct TeaclaveSchedulerService {
@@ -43,12 +63,36 @@ pub(crate) struct TeaclaveSchedulerService {
 
 pub struct TeaclaveSchedulerResources {
     storage_client: Arc<Mutex<TeaclaveStorageClient<Channel>>>,
-    // map executor_id to task_id
     task_queue: VecDeque<StagedTask>,
-    executors_tasks: HashMap<Uuid, Uuid>,
+    // map executor_id to the tasks running in its worker slots
+    executors_tasks: HashMap<Uuid, HashSet<Uuid>>,
     executors_last_heartbeat: HashMap<Uuid, SystemTime>,
     executors_status: HashMap<Uuid, ExecutorStatus>,
+    // executors which did not advertise a capability can run any task
+    executors_capability: HashMap<Uuid, WorkerCapability>,
     tasks_to_cancel: HashSet<Uuid>,
+    // map task_id to the time when the task is re-queued
+    pending_retries: HashMap<Uuid, SystemTime>,
+    // map task_id to the time when the running task is stopped
+    task_deadlines: HashMap<Uuid, SystemTime>,
+    // map overdue task_id to the executor whose worker slot it holds until the
+    // executor reports the task
+    stopped_tasks: HashMap<Uuid, Uuid>,
+    default_max_execution_time: u64,
+    // map running task_id to its share group
+    task_share_groups: HashMap<Uuid, String>,
+    fair_share: FairSharePolicy,
+    // executors which receive tasks and commands through Subscribe
+    subscribers: HashMap<Uuid, SubscriberSender>,
+    // map recurring_task_id to its runs
+    recurring_runs: HashMap<Uuid, RecurringRun>,
+    // executors which get no new tasks and are shut down once idle
+    draining_executors: HashSet<Uuid>,
+    executors_reported_at: SystemTime,
+    // receipts of the queue entries pulled since the state was last saved
+    pulled_receipts: Vec<(&'static str, Vec<u8>)>,
+    // the last attempt to save the state failed
+    state_unsaved: bool,
 }
 
 pub struct TeaclaveSchedulerDeamon {
@@ -57,21 +101,33 @@ pub struct TeaclaveSchedulerDeamon {
 
 impl TeaclaveSchedulerDeamon {
     pub async fn run(&self) -> Result<()> {
+        let mut watch = None;
         loop {
-            std::thread::sleep(std::time::Duration::from_secs(2));
+            self.wait_for_queues(&mut watch).await;
 
             let mut resources = self.resources.lock().await;
 
-            let key = StagedTask::get_queue_key().as_bytes();
+            // Storage errors are logged and the affected tasks are handled
+            // again in a later round, the daemon keeps running.
+            let mut state_changed = resources.state_unsaved;
 
             log::debug!("Pulling task/cancel queue");
-            while let Ok(canceled_task) = resources.pull_cancel_queue().await {
-                resources.tasks_to_cancel.insert(canceled_task.task_id);
+            if resources.pull_queues().await {
+                state_changed = true;
+            }
+
+            if resources.requeue_due_retries().await {
+                state_changed = true;
             }
 
-            while let Ok(staged_task) = resources.pull_staged_task::<StagedTask>(key).await {
-                log::debug!("deamon: Pulled staged task: {:?}", staged_task);
-                resources.task_queue.push_back(staged_task);
+            if resources.stop_overdue_tasks().await {
+                state_changed = true;
+            }
+
+            match resources.run_recurring_tasks().await {
+                Ok(true) => state_changed = true,
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to run recurring tasks: {:?}", e),
             }
 
             let current_time = SystemTime::now();
@@ -82,37 +138,72 @@ impl TeaclaveSchedulerDeamon {
                     .unwrap_or_else(|_| Duration::from_secs(EXECUTOR_TIMEOUT_SECS + 1))
                     > Duration::from_secs(EXECUTOR_TIMEOUT_SECS)
                 {
-                    // executor lost
+                    // executor lost, or gone after being drained
                     to_remove.push(*executor_id);
-                    log::warn!("Executor {} lost", executor_id);
+                    if resources.draining_executors.contains(executor_id) {
+                        log::info!("Drained executor {} is gone", executor_id);
+                    } else {
+                        log::warn!("Executor {} lost", executor_id);
+                    }
                 }
             }
 
             for executor_id in to_remove {
                 resources.executors_last_heartbeat.remove(&executor_id);
                 resources.executors_status.remove(&executor_id);
-                if let Some(task_id) = resources.executors_tasks.remove(&executor_id) {
+                resources.executors_capability.remove(&executor_id);
+                resources.subscribers.remove(&executor_id);
+                resources.release_stopped_slots(&executor_id);
+                if resources.draining_executors.remove(&executor_id) {
+                    state_changed = true;
+                }
+                let task_ids = resources
+                    .executors_tasks
+                    .remove(&executor_id)
+                    .unwrap_or_default();
+                for task_id in task_ids {
+                    resources.task_share_groups.remove(&task_id);
+                    state_changed = true;
                     // report task faliure
-                    let ts = resources.get_task_state(&task_id).await?;
+                    let ts = match resources.get_task_state(&task_id).await {
+                        Ok(ts) => ts,
+                        Err(e) => {
+                            log::warn!("Cannot get task {} of lost executor: {:?}", task_id, e);
+                            continue;
+                        }
+                    };
                     if ts.is_ended() {
                         continue;
                     }
 
-                    log::warn!("Executor {} lost, canceling task {}", executor_id, task_id);
-
-                    let mut task: Task<Fail> = ts.try_into()?;
+                    log::warn!(
+                        "Executor {} lost, task {} is interrupted",
+                        executor_id,
+                        task_id
+                    );
+                    if let Err(e) = resources
+                        .retry_or_fail_task(ts, executor_id, "Runtime Error: Executor Timeout")
+                        .await
+                    {
+                        log::error!("Failed to retry task {}: {:?}", task_id, e);
+                    }
+                }
+            }
 
-                    log::debug!("Task failed because of Executor lost: Task {:?}", task);
-                    // Only TaskStatus::Running/Staged is allowed here.
-                    let result_err =
-                        TaskResult::Err(TaskFailure::new("Runtime Error: Executor Timeout"));
+            if !state_changed && !resources.executors_last_heartbeat.is_empty() {
+                state_changed = current_time
+                    .duration_since(resources.executors_reported_at)
+                    .map_or(true, |elapsed| {
+                        elapsed >= Duration::from_secs(EXECUTOR_REPORT_INTERVAL_SECS)
+                    });
+            }
 
-                    // Updating task result means we have finished execution
-                    task.update_result(result_err)?;
+            if state_changed && resources.try_save_state().await {
+                resources.executors_reported_at = current_time;
+            }
 
-                    let ts = TaskState::from(task);
-                    resources.put_into_db(&ts).await?;
-                }
+            if let Err(e) = resources.dispatch().await {
+                log::error!("Failed to dispatch tasks: {:?}", e);
             }
         }
     }
@@ -132,10 +223,66 @@ impl TeaclaveSchedulerDeamon {
             resources: resources.clone(),
         }
     }
+
+    // Wait until an element is added to one of the queues, or the poll
+    // interval passes. A broken watch is opened again after the interval.
+    async fn wait_for_queues(&self, watch: &mut Option<Streaming<WatchEvent>>) {
+        let interval = Duration::from_secs(QUEUE_POLL_INTERVAL_SECS);
+        let stream = match watch {
+            Some(stream) => stream,
+            None => {
+                tokio::time::sleep(interval).await;
+                *watch = self.watch_queues().await;
+                return;
+            }
+        };
+        if let Ok(Err(e)) = tokio::time::timeout(interval, next_queue_event(stream)).await {
+            log::warn!("Queue watch is broken: {:?}", e);
+            *watch = None;
+        }
+    }
+
+    async fn watch_queues(&self) -> Option<Streaming<WatchEvent>> {
+        let request = WatchRequest::new(CANCEL_QUEUE_KEY)
+            .prefix(DRAIN_QUEUE_KEY)
+            .prefix(RECURRING_QUEUE_KEY)
+            .prefix(StagedTask::get_queue_key());
+        let resources = self.resources.lock().await;
+        let mut client = resources.storage_client.lock().await;
+        match client.watch(request).await {
+            Ok(response) => Some(response.into_inner()),
+            Err(e) => {
+                log::warn!("Failed to watch queues: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+// Other keys starting with a queue key are watched as well, and skipped.
+async fn next_queue_event(stream: &mut Streaming<WatchEvent>) -> std::result::Result<(), Status> {
+    let queues = [
+        CANCEL_QUEUE_KEY,
+        DRAIN_QUEUE_KEY,
+        RECURRING_QUEUE_KEY,
+        StagedTask::get_queue_key(),
+    ];
+    loop {
+        match stream.message().await? {
+            Some(event) if queues.iter().any(|queue| event.key == queue.as_bytes()) => {
+                return Ok(())
+            }
+            Some(_) => continue,
+            None => return Err(Status::unavailable("watch is closed")),
+        }
+    }
 }
 
 impl TeaclaveSchedulerResources {
-    pub(crate) async fn new(storage_service_endpoint: Endpoint) -> Result<Self> {
+    pub(crate) async fn new(
+        storage_service_endpoint: Endpoint,
+        config: &SchedulerConfig,
+    ) -> Result<Self> {
         let channel = storage_service_endpoint
             .connect()
             .await
@@ -146,26 +293,797 @@ impl TeaclaveSchedulerResources {
         let task_queue = VecDeque::new();
         let executors_tasks = HashMap::new();
         let executors_status = HashMap::new();
+        let executors_capability = HashMap::new();
         let tasks_to_cancel = HashSet::new();
+        let pending_retries = HashMap::new();
+        let task_deadlines = HashMap::new();
+        let stopped_tasks = HashMap::new();
+        let task_share_groups = HashMap::new();
         let executors_last_heartbeat = HashMap::new();
+        let subscribers = HashMap::new();
+        let recurring_runs = HashMap::new();
+        let draining_executors = HashSet::new();
 
-        let resources = TeaclaveSchedulerResources {
+        let mut resources = TeaclaveSchedulerResources {
             storage_client,
             task_queue,
             executors_tasks,
             executors_last_heartbeat,
             executors_status,
+            executors_capability,
             tasks_to_cancel,
+            pending_retries,
+            task_deadlines,
+            stopped_tasks,
+            default_max_execution_time: config.default_max_execution_time,
+            task_share_groups,
+            fair_share: config.fair_share,
+            subscribers,
+            recurring_runs,
+            draining_executors,
+            executors_reported_at: SystemTime::now(),
+            pulled_receipts: Vec::new(),
+            state_unsaved: false,
         };
 
+        resources.restore().await?;
+
         Ok(resources)
     }
 
-    async fn pull_staged_task<T: Storable>(
+    // Rebuild the task queue and the executor assignments from the state
+    // persisted in the storage service. Staged/Running tasks which are
+    // neither queued nor assigned to an executor are re-queued if their staged
+    // task is still available, otherwise they are marked as failed. A task
+    // which cannot be restored is logged and skipped.
+    async fn restore(&mut self) -> Result<()> {
+        let state: SchedulerState = self
+            .get_from_db(&SchedulerState::default().external_id())
+            .await
+            .unwrap_or_default();
+
+        for task_id in state.task_queue.iter() {
+            let ts = match self.get_task_state(task_id).await {
+                Ok(ts) => ts,
+                Err(e) => {
+                    log::warn!("Restore: cannot get task {}: {:?}", task_id, e);
+                    continue;
+                }
+            };
+            if ts.is_ended() {
+                self.remove_staged_task_or_log(task_id).await;
+                continue;
+            }
+            if let Ok(staged_task) = self.get_staged_task(task_id).await {
+                self.task_queue.push_back(staged_task);
+            }
+        }
+
+        let now = SystemTime::now();
+        let assigned = state
+            .executors_tasks
+            .into_iter()
+            .flat_map(|(executor_id, task_ids)| {
+                task_ids.into_iter().map(move |t| (executor_id, t))
+            });
+        for (executor_id, task_id) in assigned {
+            match self.get_task_state(&task_id).await {
+                Ok(ts) if !ts.is_ended() => {
+                    // The executor is given a full timeout to show up again
+                    // before its tasks are considered lost.
+                    self.executors_tasks
+                        .entry(executor_id)
+                        .or_default()
+                        .insert(task_id);
+                    self.executors_last_heartbeat.insert(executor_id, now);
+                    if let Some(deadline) = state.task_deadlines.get(&task_id) {
+                        self.task_deadlines.insert(task_id, *deadline);
+                    }
+                    if let Ok(staged_task) = self.get_staged_task(&task_id).await {
+                        let share_group = self.share_group(&staged_task).to_owned();
+                        self.task_share_groups.insert(task_id, share_group);
+                    }
+                }
+                _ => self.remove_staged_task_or_log(&task_id).await,
+            }
+        }
+
+        for (task_id, retry_at) in state.pending_retries.into_iter() {
+            match self.get_task_state(&task_id).await {
+                Ok(ts) if !ts.is_ended() => {
+                    self.pending_retries.insert(task_id, retry_at);
+                }
+                _ => self.remove_staged_task_or_log(&task_id).await,
+            }
+        }
+
+        self.tasks_to_cancel = state.tasks_to_cancel;
+        self.recurring_runs = state.recurring_runs;
+        // Draining executors which do not show up again time out as lost.
+        for executor in state.executors.iter().filter(|e| e.draining) {
+            self.draining_executors.insert(executor.executor_id);
+            self.executors_last_heartbeat
+                .entry(executor.executor_id)
+                .or_insert(now);
+        }
+
+        // Drain the storage queues first, otherwise tasks which are still
+        // waiting there would be mistaken for orphans.
+        self.pull_queues().await;
+
+        let mut known_tasks: HashSet<Uuid> = self.task_queue.iter().map(|t| t.task_id).collect();
+        known_tasks.extend(self.executors_tasks.values().flatten());
+        known_tasks.extend(self.pending_retries.keys());
+        let task_states = self.scan_from_db::<TaskState>().await.unwrap_or_else(|e| {
+            log::error!("Restore: cannot scan for orphaned tasks: {:?}", e);
+            Vec::new()
+        });
+        for ts in task_states {
+            if known_tasks.contains(&ts.task_id)
+                || !matches!(ts.status, TaskStatus::Staged | TaskStatus::Running)
+            {
+                continue;
+            }
+
+            if ts.status == TaskStatus::Staged {
+                if let Ok(staged_task) = self.get_staged_task(&ts.task_id).await {
+                    log::warn!("Restore: re-queue orphaned task {}", ts.task_id);
+                    self.task_queue.push_back(staged_task);
+                    continue;
+                }
+            }
+
+            log::warn!("Restore: fail orphaned task {}", ts.task_id);
+            let task_id = ts.task_id;
+            if let Err(e) = self
+                .fail_task(ts, "Runtime Error: Task lost during scheduler restart")
+                .await
+            {
+                log::error!("Restore: cannot fail orphaned task {}: {:?}", task_id, e);
+            }
+        }
+
+        log::info!(
+            "Restored scheduler state: {} queued tasks, {} assigned tasks",
+            self.task_queue.len(),
+            self.assigned_tasks().count()
+        );
+        self.try_save_state().await;
+        Ok(())
+    }
+
+    // Move the entries of the storage queues into the scheduler. Returns
+    // whether any entry is pulled. The entries are leased, and only acked
+    // once the state holding them is saved.
+    async fn pull_queues(&mut self) -> bool {
+        let mut pulled = false;
+        while let Ok(canceled_task) = self.pull_queue::<TaskState>(CANCEL_QUEUE_KEY).await {
+            self.tasks_to_cancel.insert(canceled_task.task_id);
+            pulled = true;
+        }
+
+        while let Ok(executor) = self.pull_queue::<ExecutorInfo>(DRAIN_QUEUE_KEY).await {
+            log::info!("Draining executor {}", executor.executor_id);
+            self.draining_executors.insert(executor.executor_id);
+            pulled = true;
+        }
+
+        while let Ok(recurring_task) = self.pull_queue::<RecurringTask>(RECURRING_QUEUE_KEY).await {
+            self.track_recurring_task(&recurring_task, SystemTime::now());
+            pulled = true;
+        }
+
+        let key = StagedTask::get_queue_key();
+        while let Ok(staged_task) = self.pull_queue::<StagedTask>(key).await {
+            log::debug!("deamon: Pulled staged task: {:?}", staged_task);
+            pulled = true;
+            // An entry which was not acked before a crash is pulled again.
+            if self.has_task(&staged_task.task_id) {
+                continue;
+            }
+            if let Ok(ts) = self.get_task_state(&staged_task.task_id).await {
+                if ts.is_ended() {
+                    continue;
+                }
+            }
+            let task_id = staged_task.task_id;
+            if let Err(e) = self.enqueue_task(staged_task).await {
+                // The entry is not acked, and is pulled again once its lease
+                // expires.
+                log::error!("Cannot enqueue task {}: {:?}", task_id, e);
+                self.pulled_receipts.pop();
+            }
+        }
+        pulled
+    }
+
+    fn has_task(&self, task_id: &Uuid) -> bool {
+        self.task_queue.iter().any(|t| &t.task_id == task_id)
+            || self.executors_tasks.values().any(|t| t.contains(task_id))
+            || self.pending_retries.contains_key(task_id)
+    }
+
+    async fn save_state(&self) -> Result<()> {
+        let state = SchedulerState {
+            task_queue: self.task_queue.iter().map(|t| t.task_id).collect(),
+            executors_tasks: self.executors_tasks.clone(),
+            tasks_to_cancel: self.tasks_to_cancel.clone(),
+            pending_retries: self.pending_retries.clone(),
+            task_deadlines: self.task_deadlines.clone(),
+            queued_tasks: self.queued_tasks(),
+            recurring_runs: self.recurring_runs.clone(),
+            executors: self.executors(),
+        };
+        self.put_into_db(&state).await
+    }
+
+    // Save the state and ack the entries pulled into it. Returns whether the
+    // state is saved, otherwise it is saved again in the next round.
+    async fn try_save_state(&mut self) -> bool {
+        match self.save_state().await {
+            Ok(()) => {
+                self.state_unsaved = false;
+                self.ack_pulled_entries().await;
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to save scheduler state: {:?}", e);
+                self.state_unsaved = true;
+                false
+            }
+        }
+    }
+
+    // Assign the task to the executor and start its execution deadline. The
+    // platform default is filled into the task for the executor to enforce.
+    fn assign_task(&mut self, executor_id: Uuid, task: &mut StagedTask) {
+        if task.max_execution_time == 0 {
+            task.max_execution_time = self.default_max_execution_time;
+        }
+        let deadline = SystemTime::now().checked_add(Duration::from_secs(task.max_execution_time));
+        let share_group = self.share_group(task).to_owned();
+        self.executors_tasks
+            .entry(executor_id)
+            .or_default()
+            .insert(task.task_id);
+        if let Some(deadline) = deadline {
+            self.task_deadlines.insert(task.task_id, deadline);
+        }
+        self.task_share_groups.insert(task.task_id, share_group);
+    }
+
+    fn unassign_task(&mut self, executor_id: &Uuid, task_id: &Uuid) {
+        if let Some(task_ids) = self.executors_tasks.get_mut(executor_id) {
+            task_ids.remove(task_id);
+            if task_ids.is_empty() {
+                self.executors_tasks.remove(executor_id);
+            }
+        }
+        self.task_deadlines.remove(task_id);
+        self.task_share_groups.remove(task_id);
+    }
+
+    // (executor_id, task_id) of each task running in a worker slot
+    fn assigned_tasks(&self) -> impl Iterator<Item = (Uuid, Uuid)> + '_ {
+        self.executors_tasks
+            .iter()
+            .flat_map(|(executor_id, task_ids)| {
+                task_ids.iter().map(move |task_id| (*executor_id, *task_id))
+            })
+    }
+
+    // Stopped tasks keep their slots busy until the executor reports them.
+    fn running_tasks(&self, executor_id: &Uuid) -> usize {
+        let stopped = self
+            .stopped_tasks
+            .values()
+            .filter(|e| *e == executor_id)
+            .count();
+        self.executors_tasks.get(executor_id).map_or(0, |t| t.len()) + stopped
+    }
+
+    fn release_stopped_slots(&mut self, executor_id: &Uuid) {
+        self.stopped_tasks.retain(|_, e| e != executor_id);
+    }
+
+    // Executors which did not advertise a capability run one task at a time.
+    fn free_slots(&self, executor_id: &Uuid) -> usize {
+        let slots = self
+            .executors_capability
+            .get(executor_id)
+            .map_or(1, |capability| capability.concurrent_tasks());
+        slots.saturating_sub(self.running_tasks(executor_id))
+    }
+
+    // Fail the running tasks which passed their deadline, and stop the worker
+    // slots running them. The tasks in the other slots of their executors
+    // keep running. Returns whether any task is stopped.
+    async fn stop_overdue_tasks(&mut self) -> bool {
+        let now = SystemTime::now();
+        let executors_tasks = &self.executors_tasks;
+        self.task_deadlines
+            .retain(|task_id, _| executors_tasks.values().any(|t| t.contains(task_id)));
+
+        let overdue: Vec<(Uuid, Uuid)> = self
+            .assigned_tasks()
+            .filter(|(_, task_id)| {
+                self.task_deadlines
+                    .get(task_id)
+                    .map_or(false, |deadline| *deadline <= now)
+            })
+            .collect();
+
+        for (executor_id, task_id) in overdue.iter() {
+            log::warn!("Task {} exceeded its maximum execution time", task_id);
+            self.unassign_task(executor_id, task_id);
+            self.stopped_tasks.insert(*task_id, *executor_id);
+            self.fail_unless_ended(task_id, "Runtime Error: Task Execution Timeout")
+                .await;
+
+            // Executors which are not subscribed get Stop in their next
+            // heartbeat.
+            if self.subscribers.contains_key(executor_id) {
+                self.push_to_subscriber(executor_id, SubscribeResponse::stop_task(*task_id));
+            }
+        }
+        !overdue.is_empty()
+    }
+
+    // Record the lost execution in the task state, and re-queue the task
+    // after a backoff if its retry budget allows, otherwise fail it.
+    async fn retry_or_fail_task(
+        &mut self,
+        ts: TaskState,
+        executor_id: Uuid,
+        reason: &str,
+    ) -> Result<()> {
+        let task_id = ts.task_id;
+        let mut task: Task<Retry> = ts.try_into()?;
+        task.record_attempt(TaskAttempt::new(executor_id, reason));
+
+        if !task.can_retry() {
+            return self.fail_task(TaskState::from(task), reason).await;
+        }
+
+        let backoff = retry_backoff(task.attempts());
+        log::warn!(
+            "Retrying task {} in {:?}, attempt {}",
+            task_id,
+            backoff,
+            task.attempts()
+        );
+        let ts = TaskState::from(task);
+        self.put_into_db(&ts).await?;
+        self.pending_retries
+            .insert(task_id, SystemTime::now() + backoff);
+        Ok(())
+    }
+
+    // Move the tasks whose backoff has elapsed back into the task queue.
+    // Returns whether any task is moved.
+    async fn requeue_due_retries(&mut self) -> bool {
+        let now = SystemTime::now();
+        let due: Vec<Uuid> = self
+            .pending_retries
+            .iter()
+            .filter(|(_, retry_at)| **retry_at <= now)
+            .map(|(task_id, _)| *task_id)
+            .collect();
+
+        for task_id in due.iter() {
+            self.pending_retries.remove(task_id);
+            match self.get_staged_task(task_id).await {
+                Ok(staged_task) => self.task_queue.push_back(staged_task),
+                Err(e) => {
+                    log::warn!("Cannot re-queue task {}: {:?}", task_id, e);
+                    self.fail_unless_ended(task_id, "Runtime Error: Staged task lost")
+                        .await;
+                }
+            }
+        }
+        !due.is_empty()
+    }
+
+    // Start the runs of a recurring task once every participant has approved
+    // it, the schedule starts from now.
+    fn track_recurring_task(&mut self, recurring_task: &RecurringTask, now: SystemTime) {
+        let id = recurring_task.id;
+        if !recurring_task.everyone_approved() || self.recurring_runs.contains_key(&id) {
+            return;
+        }
+        match recurring_task.schedule.next_after(now) {
+            Ok(next_run) => {
+                let run = RecurringRun {
+                    next_run,
+                    last_task_id: None,
+                    runs: 0,
+                };
+                self.recurring_runs.insert(id, run);
+            }
+            Err(e) => log::warn!("Invalid schedule of recurring task {}: {:?}", id, e),
+        }
+    }
+
+    // Queue a run of each recurring task which is due. Only the due recurring
+    // tasks are read, the new ones come from the recurring queue. Runs missed
+    // while the scheduler was down are collapsed into one. Returns whether the
+    // runs of any recurring task are changed.
+    async fn run_recurring_tasks(&mut self) -> Result<bool> {
+        let now = SystemTime::now();
+        let due: Vec<Uuid> = self
+            .recurring_runs
+            .iter()
+            .filter(|(_, run)| run.next_run <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut state_changed = false;
+        for id in due {
+            let key = ExternalID::new(RecurringTask::key_prefix(), id);
+            state_changed = true;
+            let recurring_task = match self.find_in_db::<RecurringTask>(&key).await? {
+                Some(recurring_task) => recurring_task,
+                None => {
+                    log::debug!("Recurring task {} is deleted", id);
+                    self.recurring_runs.remove(&id);
+                    continue;
+                }
+            };
+            let next_run = match recurring_task.schedule.next_after(now) {
+                Ok(next_run) => next_run,
+                Err(e) => {
+                    log::warn!("Invalid schedule of recurring task {}: {:?}", id, e);
+                    self.recurring_runs.remove(&id);
+                    continue;
+                }
+            };
+            if let Some(run) = self.recurring_runs.get_mut(&id) {
+                run.next_run = next_run;
+            }
+            // The run is skipped until everyone approves the new input
+            // versions.
+            if !recurring_task.everyone_approved() {
+                continue;
+            }
+
+            match self.run_recurring_task(&recurring_task).await {
+                Ok(task_id) => {
+                    log::debug!("Recurring task {} runs as task {}", id, task_id);
+                    if let Some(run) = self.recurring_runs.get_mut(&id) {
+                        run.last_task_id = Some(task_id);
+                        run.runs += 1;
+                    }
+                }
+                Err(e) => log::warn!("Cannot run recurring task {}: {:?}", id, e),
+            }
+        }
+        Ok(state_changed)
+    }
+
+    // Copy the template task with the approved versions of its inputs and new
+    // outputs of the run, and put the copy into the task queue.
+    async fn run_recurring_task(&mut self, recurring_task: &RecurringTask) -> Result<Uuid> {
+        let template = self.get_task_state(&recurring_task.task_id).await?;
+        let function: Function = self.get_from_db(&template.function_id).await?;
+
+        let usage_key = ExternalID::new(FunctionUsage::key_prefix(), function.id);
+        let (mut function_usage, usage_value): (FunctionUsage, _) =
+            self.get_for_update(&usage_key).await?;
+        if let Some(quota) = function.usage_quota {
+            anyhow::ensure!(
+                function_usage.use_numbers < quota,
+                "Function quota is exhausted"
+            );
+        }
+
+        let mut inputs = Vec::new();
+        for (fname, uuid) in recurring_task.input_versions.iter() {
+            let key = ExternalID::new(TeaclaveInputFile::key_prefix(), *uuid);
+            let input: TeaclaveInputFile = self.get_from_db(&key).await?;
+            inputs.push((fname.to_owned(), input));
+        }
+
+        let (ts, staged_task) = recurring_task.materialize(&template, inputs, function)?;
+        let mut batch = BatchRequest::new();
+        for (_, output) in ts.assigned_outputs.clone().into_iter() {
+            batch = batch.put(output.key(), output.to_vec()?);
+        }
+        function_usage.use_numbers += 1;
+        // The management service may count an invocation of the function
+        // meanwhile, in which case this run fails and is not counted.
+        let batch = batch
+            .put(ts.key(), ts.to_vec()?)
+            .expect_value(function_usage.key(), usage_value)
+            .put(function_usage.key(), function_usage.to_vec()?)
+            .put_with_ttl(
+                staged_task.key(),
+                staged_task.to_vec()?,
+                STAGED_TASK_RETENTION_SECS,
+            );
+        self.commit_to_db(batch).await?;
+        self.task_queue.push_back(staged_task);
+        Ok(ts.task_id)
+    }
+
+    // Push stop commands to the subscribed executors whose tasks have been
+    // canceled, and queued tasks to the idle subscribed executors.
+    async fn dispatch(&mut self) -> Result<()> {
+        let mut state_changed = false;
+
+        let to_stop: Vec<(Uuid, Uuid)> = self
+            .assigned_tasks()
+            .filter(|(executor_id, task_id)| {
+                self.subscribers.contains_key(executor_id) && self.tasks_to_cancel.contains(task_id)
+            })
+            .collect();
+        for (executor_id, task_id) in to_stop {
+            log::debug!(
+                "Pushing stop command to executor {} because of task {} cancelation",
+                executor_id,
+                task_id
+            );
+            self.tasks_to_cancel.remove(&task_id);
+            self.push_to_subscriber(&executor_id, SubscribeResponse::stop_task(task_id));
+            self.cancel_task_or_log(task_id).await;
+            state_changed = true;
+        }
+
+        // Canceled tasks may wait for an executor which can run them or for
+        // their retry, so they are canceled before matching.
+        let canceled: Vec<Uuid> = self
+            .task_queue
+            .iter()
+            .map(|task| task.task_id)
+            .filter(|task_id| self.tasks_to_cancel.contains(task_id))
+            .collect();
+        let canceled_retries: Vec<Uuid> = self
+            .pending_retries
+            .keys()
+            .filter(|task_id| self.tasks_to_cancel.contains(*task_id))
+            .cloned()
+            .collect();
+        for task_id in canceled.into_iter().chain(canceled_retries) {
+            self.task_queue.retain(|task| task.task_id != task_id);
+            self.pending_retries.remove(&task_id);
+            self.tasks_to_cancel.remove(&task_id);
+            self.cancel_task_or_log(task_id).await;
+            state_changed = true;
+        }
+
+        let drained: Vec<Uuid> = self
+            .subscribers
+            .keys()
+            .filter(|executor_id| {
+                self.draining_executors.contains(*executor_id)
+                    && self.running_tasks(executor_id) == 0
+            })
+            .cloned()
+            .collect();
+        for executor_id in drained {
+            log::info!("Shutting down drained executor {}", executor_id);
+            self.push_to_subscriber(&executor_id, SubscribeResponse::shutdown());
+            self.subscribers.remove(&executor_id);
+        }
+
+        let idle_executors: Vec<Uuid> = self
+            .subscribers
+            .keys()
+            .filter(|executor_id| self.free_slots(executor_id) > 0)
+            .cloned()
+            .collect();
+        for executor_id in idle_executors {
+            // One task at a time, so that the share groups stay fair between
+            // the slots.
+            while self.free_slots(&executor_id) > 0 {
+                let (index, mut task) = match self.take_task_for(&executor_id) {
+                    Some(entry) => entry,
+                    None => break,
+                };
+
+                // Same as pull_task, the assignment must be durable before the
+                // task is handed out.
+                self.assign_task(executor_id, &mut task);
+                if let Err(e) = self.save_state().await {
+                    self.unassign_task(&executor_id, &task.task_id);
+                    self.task_queue.insert(index, task);
+                    return Err(e);
+                }
+
+                log::debug!("Pushing task {} to executor {}", task.task_id, executor_id);
+                let response = SubscribeResponse::new_task(&task);
+                if !self.push_to_subscriber(&executor_id, response) {
+                    self.unassign_task(&executor_id, &task.task_id);
+                    self.task_queue.insert(index, task);
+                    state_changed = true;
+                    break;
+                }
+            }
+        }
+
+        if state_changed {
+            self.try_save_state().await;
+        }
+        Ok(())
+    }
+
+    fn can_run(&self, executor_id: &Uuid, task: &StagedTask) -> bool {
+        !self.draining_executors.contains(executor_id)
+            && self
+                .executors_capability
+                .get(executor_id)
+                .map_or(true, |capability| capability.can_run(task))
+    }
+
+    fn executors(&self) -> Vec<ExecutorInfo> {
+        let mut executors: Vec<ExecutorInfo> = self
+            .executors_last_heartbeat
+            .iter()
+            .map(|(executor_id, last_heartbeat)| {
+                let mut task_ids: Vec<Uuid> = self
+                    .executors_tasks
+                    .get(executor_id)
+                    .map(|t| t.iter().cloned().collect())
+                    .unwrap_or_default();
+                task_ids.sort();
+                ExecutorInfo {
+                    executor_id: *executor_id,
+                    last_heartbeat: *last_heartbeat,
+                    executing: self.executors_status.get(executor_id)
+                        == Some(&ExecutorStatus::Executing),
+                    task_ids,
+                    draining: self.draining_executors.contains(executor_id),
+                    slots: self
+                        .executors_capability
+                        .get(executor_id)
+                        .map_or(1, |capability| capability.concurrent_tasks())
+                        as u32,
+                }
+            })
+            .collect();
+        executors.sort_by_key(|executor| executor.executor_id);
+        executors
+    }
+
+    // Take the next queued task which the executor is able to run, together
+    // with its position in the queue.
+    fn take_task_for(&mut self, executor_id: &Uuid) -> Option<(usize, StagedTask)> {
+        let running = self.running_tasks_per_group();
+        let index = self
+            .task_queue
+            .iter()
+            .enumerate()
+            .filter(|(_, task)| self.can_run(executor_id, task))
+            .min_by_key(|(index, task)| self.dispatch_order(&running, *index, task))
+            .map(|(index, _)| index)?;
+        self.task_queue.remove(index).map(|task| (index, task))
+    }
+
+    // Tasks of a higher priority class go first. Within a class, the share
+    // group with the fewest running tasks goes first, and ties are broken by
+    // the position in the queue.
+    fn dispatch_order(
         &self,
-        key: &[u8],
+        running: &HashMap<String, u32>,
+        index: usize,
+        task: &StagedTask,
+    ) -> (Reverse<TaskPriority>, u32, usize) {
+        let share_group_running = running.get(self.share_group(task)).copied().unwrap_or(0);
+        (Reverse(task.priority), share_group_running, index)
+    }
+
+    fn share_group<'a>(&self, task: &'a StagedTask) -> &'a str {
+        match self.fair_share {
+            FairSharePolicy::DataOwner if !task.user_attribute.is_empty() => &task.user_attribute,
+            _ => &task.user_id,
+        }
+    }
+
+    fn running_tasks_per_group(&self) -> HashMap<String, u32> {
+        let mut running = HashMap::new();
+        for task_id in self.executors_tasks.values().flatten() {
+            if let Some(share_group) = self.task_share_groups.get(task_id) {
+                *running.entry(share_group.to_owned()).or_insert(0) += 1;
+            }
+        }
+        running
+    }
+
+    // The queued tasks in the order they are handed out to executors which
+    // are able to run all of them.
+    fn queued_tasks(&self) -> Vec<QueuedTask> {
+        let running = self.running_tasks_per_group();
+        let mut queued: Vec<(usize, &StagedTask)> = self.task_queue.iter().enumerate().collect();
+        queued.sort_by_key(|(index, task)| self.dispatch_order(&running, *index, task));
+        queued
+            .into_iter()
+            .map(|(_, task)| {
+                let share_group = self.share_group(task);
+                QueuedTask {
+                    task_id: task.task_id,
+                    creator: task.user_id.clone(),
+                    priority: task.priority,
+                    share_group: share_group.to_owned(),
+                    share_group_running: running.get(share_group).copied().unwrap_or(0),
+                }
+            })
+            .collect()
+    }
+
+    fn push_to_subscriber(&mut self, executor_id: &Uuid, response: SubscribeResponse) -> bool {
+        let sent = match self.subscribers.get(executor_id) {
+            Some(sender) => sender.try_send(Ok(response)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            log::warn!("Executor {} is no longer subscribed", executor_id);
+            self.subscribers.remove(executor_id);
+        }
+        sent
+    }
+
+    // The staged task is kept in the storage service until the task ends, so
+    // that it can be re-queued after a restart.
+    async fn enqueue_task(&mut self, staged_task: StagedTask) -> Result<()> {
+        self.put_into_db_with_ttl(&staged_task, STAGED_TASK_RETENTION_SECS)
+            .await?;
+        self.task_queue.push_back(staged_task);
+        Ok(())
+    }
+
+    async fn get_staged_task(&self, task_id: &Uuid) -> Result<StagedTask> {
+        let key = ExternalID::new(StagedTask::key_prefix(), task_id.to_owned());
+        self.get_from_db(&key).await
+    }
+
+    async fn remove_staged_task(&self, task_id: &Uuid) -> Result<()> {
+        self.delete_from_db(&staged_task_id(task_id)).await
+    }
+
+    // A staged task left behind is only dead weight, the task is not run again.
+    async fn remove_staged_task_or_log(&self, task_id: &Uuid) {
+        if let Err(e) = self.remove_staged_task(task_id).await {
+            log::warn!("Cannot remove staged task {}: {:?}", task_id, e);
+        }
+    }
+
+    // Fail the task unless it has ended meanwhile. Errors are only logged, the
+    // task is no longer tracked by the scheduler either way.
+    async fn fail_unless_ended(&mut self, task_id: &Uuid, reason: &str) {
+        let result = match self.get_task_state(task_id).await {
+            Ok(ts) if ts.is_ended() => Ok(()),
+            Ok(ts) => self.fail_task(ts, reason).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            log::error!("Cannot fail task {}: {:?}", task_id, e);
+        }
+    }
+
+    async fn fail_task(&mut self, ts: TaskState, reason: &str) -> Result<()> {
+        let task_id = ts.task_id;
+        let mut task: Task<Fail> = ts.try_into()?;
+
+        log::debug!("Task failed: {}, Task {:?}", reason, task);
+        // Only TaskStatus::Running/Staged is allowed here.
+        let result_err = TaskResult::Err(TaskFailure::new(reason));
+
+        // Updating task result means we have finished execution
+        task.update_result(result_err)?;
+
+        let ts = TaskState::from(task);
+        let batch = BatchRequest::new()
+            .put(ts.key(), ts.to_vec()?)
+            .delete(staged_task_id(&task_id).to_bytes());
+        self.commit_to_db(batch).await?;
+        self.advance_workflow(&ts).await;
+        Ok(())
+    }
+
+    async fn pull_queue<T: Storable>(
+        &mut self,
+        key: &'static str,
     ) -> std::result::Result<T, SchedulerServiceError> {
-        let dequeue_request = DequeueRequest::new(key);
+        let dequeue_request = DequeueRequest::new(key).lease(QUEUE_LEASE_SECS);
         let dequeue_response = self
             .storage_client
             .clone()
@@ -175,25 +1093,29 @@ impl TeaclaveSchedulerResources {
             .await
             .map_err(|_| SchedulerServiceError::StorageError)?
             .into_inner();
+        // An entry which cannot be parsed is acked as well, so that it is not
+        // pulled again and again.
+        self.pulled_receipts.push((key, dequeue_response.receipt));
         T::from_slice(dequeue_response.value.as_slice()).map_err(SchedulerServiceError::Service)
     }
 
-    async fn pull_cancel_queue(&self) -> std::result::Result<TaskState, SchedulerServiceError> {
-        let dequeue_request = DequeueRequest::new(CANCEL_QUEUE_KEY.as_bytes());
-        let dequeue_response = self
-            .storage_client
-            .clone()
-            .lock()
-            .await
-            .dequeue(dequeue_request)
-            .await
-            .map_err(|_| SchedulerServiceError::StorageError)?
-            .into_inner();
-        TaskState::from_slice(dequeue_response.value.as_slice())
-            .map_err(SchedulerServiceError::Service)
+    // Remove the pulled entries from the storage queues for good. An entry
+    // which fails to be acked is pulled again after its lease expires.
+    async fn ack_pulled_entries(&mut self) {
+        for (key, receipt) in std::mem::take(&mut self.pulled_receipts) {
+            let request = AckRequest::new(key, receipt);
+            let client = self.storage_client.clone();
+            let result = client.lock().await.ack(request).await;
+            if let Err(e) = result {
+                log::warn!("Cannot ack the entry pulled from {}: {:?}", key, e);
+            }
+        }
     }
 
-    async fn cancel_task(&self, task_id: Uuid) -> std::result::Result<(), SchedulerServiceError> {
+    async fn cancel_task(
+        &mut self,
+        task_id: Uuid,
+    ) -> std::result::Result<(), SchedulerServiceError> {
         let ts = self.get_task_state(&task_id).await?;
         let mut task: Task<Cancel> = ts.try_into()?;
 
@@ -203,36 +1125,253 @@ impl TeaclaveSchedulerResources {
         task.update_result(result_err)?;
 
         let ts = TaskState::from(task);
-        self.put_into_db(&ts).await?;
+        let batch = BatchRequest::new()
+            .put(ts.key(), ts.to_vec()?)
+            .delete(staged_task_id(&task_id).to_bytes());
+        self.commit_to_db(batch).await?;
+        self.advance_workflow(&ts).await;
+
+        Ok(())
+    }
+
+    // The task is no longer tracked by the scheduler, so it is only logged if
+    // it fails to be canceled.
+    async fn cancel_task_or_log(&mut self, task_id: Uuid) {
+        if let Err(e) = self.cancel_task(task_id).await {
+            log::error!("Cannot cancel task {}: {:?}", task_id, e);
+        }
+    }
+
+    // Stage the downstream nodes of a workflow node which has finished, or
+    // fail them if it has not. Errors are only logged since the node itself
+    // has been handled.
+    async fn advance_workflow(&mut self, ts: &TaskState) {
+        let workflow_id = match ts.workflow_id {
+            Some(workflow_id) => workflow_id,
+            None => return,
+        };
+        if let Err(e) = self.try_advance_workflow(workflow_id, ts).await {
+            log::error!("Failed to advance workflow {}: {:?}", workflow_id, e);
+        }
+    }
 
+    async fn try_advance_workflow(&mut self, workflow_id: Uuid, ts: &TaskState) -> Result<()> {
+        let key = ExternalID::new(Workflow::key_prefix(), workflow_id);
+        let mut workflow: Workflow = self.get_from_db(&key).await?;
+        if workflow.is_ended() {
+            return Ok(());
+        }
+        let node = workflow
+            .node_of_task(&ts.task_id)
+            .ok_or_else(|| anyhow!("Task {} is not a workflow node", ts.task_id))?
+            .clone();
+
+        if !node_succeeded(ts) {
+            log::debug!("Workflow {} failed at node {}", workflow_id, node.name);
+            let batch = self
+                .fail_descendants(BatchRequest::new(), &workflow, &node.name)
+                .await?;
+            workflow.status = WorkflowStatus::Failed;
+            let batch = batch.put(workflow.key(), workflow.to_vec()?);
+            return self.commit_to_db(batch).await;
+        }
+
+        let downstream: Vec<WorkflowNode> = workflow
+            .downstream_nodes(&node.name)
+            .into_iter()
+            .cloned()
+            .collect();
+        for next in downstream.iter() {
+            if !self.upstream_succeeded(&workflow, &next.name).await? {
+                continue;
+            }
+            if let Err(e) = self.stage_workflow_node(&workflow, next).await {
+                log::warn!("Cannot stage workflow node {}: {:?}", next.name, e);
+                let mut batch = BatchRequest::new();
+                let next_ts = self.get_task_state(&next.task_id).await?;
+                if !next_ts.is_ended() {
+                    let mut task: Task<Fail> = next_ts.try_into()?;
+                    task.update_result(TaskResult::Err(TaskFailure::new(
+                        "Runtime Error: Cannot stage workflow node",
+                    )))?;
+                    let next_ts = TaskState::from(task);
+                    batch = batch.put(next_ts.key(), next_ts.to_vec()?);
+                }
+                let batch = self.fail_descendants(batch, &workflow, &next.name).await?;
+                workflow.status = WorkflowStatus::Failed;
+                let batch = batch.put(workflow.key(), workflow.to_vec()?);
+                return self.commit_to_db(batch).await;
+            }
+        }
+
+        let mut finished = true;
+        for node in workflow.nodes.iter() {
+            if !node_succeeded(&self.get_task_state(&node.task_id).await?) {
+                finished = false;
+                break;
+            }
+        }
+        if finished {
+            log::debug!("Workflow {} finished", workflow_id);
+            workflow.status = WorkflowStatus::Finished;
+            self.put_into_db(&workflow).await?;
+        }
         Ok(())
     }
 
+    async fn upstream_succeeded(&self, workflow: &Workflow, name: &str) -> Result<bool> {
+        for upstream in workflow.upstream_nodes(name) {
+            if !node_succeeded(&self.get_task_state(&upstream.task_id).await?) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Turn the fusion outputs of the upstream nodes into inputs of the node,
+    // and put the node into the task queue.
+    async fn stage_workflow_node(
+        &mut self,
+        workflow: &Workflow,
+        node: &WorkflowNode,
+    ) -> Result<()> {
+        let ts = self.get_task_state(&node.task_id).await?;
+        // The node has been staged by another upstream node.
+        if !matches!(ts.status, TaskStatus::Created | TaskStatus::DataAssigned) {
+            return Ok(());
+        }
+
+        let mut batch = BatchRequest::new();
+        let mut inputs = Vec::new();
+        for edge in workflow.incoming_edges(&node.name) {
+            let key = ExternalID::new(TeaclaveOutputFile::key_prefix(), edge.data_id);
+            let output: TeaclaveOutputFile = self.get_from_db(&key).await?;
+            let input = TeaclaveInputFile::from_output(output)?;
+            batch = batch.put(input.key(), input.to_vec()?);
+            inputs.push((edge.input.clone(), input));
+        }
+
+        let function: Function = self.get_from_db(&ts.function_id).await?;
+        let (ts, staged_task) = workflow.stage_node(ts, inputs, function)?;
+        log::debug!("Staged workflow node {}: {:?}", node.name, staged_task);
+        let batch = batch.put(ts.key(), ts.to_vec()?).put_with_ttl(
+            staged_task.key(),
+            staged_task.to_vec()?,
+            STAGED_TASK_RETENTION_SECS,
+        );
+        self.commit_to_db(batch).await?;
+        self.task_queue.push_back(staged_task);
+        Ok(())
+    }
+
+    // Add the failure of the nodes waiting for the given node to the batch.
+    async fn fail_descendants(
+        &self,
+        mut batch: BatchRequest,
+        workflow: &Workflow,
+        name: &str,
+    ) -> Result<BatchRequest> {
+        for descendant in workflow.descendants(name) {
+            let ts = self.get_task_state(&descendant.task_id).await?;
+            if ts.is_ended() {
+                continue;
+            }
+            let ts = Workflow::fail_node(ts, name)?;
+            batch = batch.put(ts.key(), ts.to_vec()?);
+        }
+        Ok(batch)
+    }
+
     async fn get_task_state(&self, task_id: &Uuid) -> Result<TaskState> {
         let key = ExternalID::new(TaskState::key_prefix(), task_id.to_owned());
         self.get_from_db(&key).await
     }
 
     async fn get_from_db<T: Storable>(&self, key: &ExternalID) -> Result<T> {
+        self.get_for_update(key).await.map(|(item, _)| item)
+    }
+
+    // Get the item together with its stored value, which a batch updating the
+    // item can expect to be unchanged.
+    async fn get_for_update<T: Storable>(&self, key: &ExternalID) -> Result<(T, Vec<u8>)> {
         anyhow::ensure!(T::match_prefix(&key.prefix), "Key prefix doesn't match.");
         let get_request = GetRequest::new(key.to_bytes());
         let storage = self.storage_client.clone();
         let mut storage = storage.lock().await;
 
         let response = storage.get(get_request).await?.into_inner();
-        T::from_slice(response.value.as_slice())
+        let item = T::from_slice(response.value.as_slice())?;
+        Ok((item, response.value))
     }
 
     async fn put_into_db(&self, item: &impl Storable) -> Result<()> {
+        self.put_into_db_with_ttl(item, 0).await
+    }
+
+    async fn put_into_db_with_ttl(&self, item: &impl Storable, ttl_secs: u32) -> Result<()> {
         let k = item.key();
         let v = item.to_vec()?;
-        let put_request = PutRequest::new(k.as_slice(), v.as_slice());
+        let put_request = PutRequest::new(k.as_slice(), v.as_slice()).ttl(ttl_secs);
         let cli = self.storage_client.clone();
         let mut client = cli.lock().await;
 
         let _put_response = client.put(put_request).await?;
         Ok(())
     }
+
+    async fn delete_from_db(&self, key: &ExternalID) -> Result<()> {
+        let delete_request = DeleteRequest::new(key.to_bytes());
+        let cli = self.storage_client.clone();
+        let mut client = cli.lock().await;
+
+        let _delete_response = client.delete(delete_request).await?;
+        Ok(())
+    }
+
+    // All writes of a state transition are applied at once, so that a crash
+    // cannot leave the task half updated.
+    async fn commit_to_db(&self, batch: BatchRequest) -> Result<()> {
+        let cli = self.storage_client.clone();
+        let mut client = cli.lock().await;
+
+        let _batch_response = client.batch(batch).await?;
+        Ok(())
+    }
+
+    // Get an item, or None if there is no such item.
+    async fn find_in_db<T: Storable>(&self, key: &ExternalID) -> Result<Option<T>> {
+        let request = ScanRequest::with_prefix(key.to_bytes()).limit(1);
+        let cli = self.storage_client.clone();
+        let mut client = cli.lock().await;
+
+        let response = client.scan(request).await?.into_inner();
+        match response.items.first() {
+            Some(item) => Ok(Some(T::from_slice(&item.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Get all items of a type, one page of the scan at a time.
+    async fn scan_from_db<T: Storable>(&self) -> Result<Vec<T>> {
+        let prefix = format!("{}-", T::key_prefix());
+        let mut items = Vec::new();
+        let mut continuation_token = Vec::new();
+        loop {
+            let request =
+                ScanRequest::with_prefix(prefix.as_str()).continuation_token(continuation_token);
+            let cli = self.storage_client.clone();
+            let mut client = cli.lock().await;
+
+            let response = client.scan(request).await?.into_inner();
+            for item in response.items {
+                items.push(T::from_slice(&item.value)?);
+            }
+            if response.continuation_token.is_empty() {
+                return Ok(items);
+            }
+            continuation_token = response.continuation_token;
+        }
+    }
 }
 
 #[teaclave_rpc::async_trait]
@@ -248,17 +1387,48 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
 
         let staged_task =
             StagedTask::from_slice(&request.get_ref().staged_task).map_err(tonic_error)?;
-        resources.task_queue.push_back(staged_task);
+        resources
+            .enqueue_task(staged_task)
+            .await
+            .map_err(tonic_error)?;
+        resources.save_state().await.map_err(tonic_error)?;
+        if let Err(e) = resources.dispatch().await {
+            log::error!("Failed to dispatch tasks: {:?}", e);
+        }
         Ok(Response::new(()))
     }
 
+    type SubscribeStream = ReceiverStream<std::result::Result<SubscribeResponse, Status>>;
+
     // Subscriber
     async fn subscribe(
         &self,
-        _request: Request<()>,
-    ) -> TeaclaveServiceResponseResult<SubscribeResponse> {
-        // TODO: subscribe a specific topic
-        unimplemented!()
+        request: Request<SubscribeRequest>,
+    ) -> TeaclaveServiceResponseResult<Self::SubscribeStream> {
+        let request = request.into_inner();
+        let executor_id = Uuid::parse_str(&request.executor_id).map_err(tonic_error)?;
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CHANNEL_SIZE);
+
+        let mut resources = self.resources.lock().await;
+        resources.subscribers.insert(executor_id, tx);
+        if let Some(capability) = request.capability {
+            resources
+                .executors_capability
+                .insert(executor_id, capability.into());
+        }
+        resources
+            .executors_last_heartbeat
+            .insert(executor_id, SystemTime::now());
+        log::debug!("Executor {} subscribed", executor_id);
+
+        // The executor shows up in the executor view right away.
+        if let Err(e) = resources.save_state().await {
+            log::error!("Failed to save scheduler state: {:?}", e);
+        }
+        if let Err(e) = resources.dispatch().await {
+            log::error!("Failed to dispatch tasks: {:?}", e);
+        }
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
     async fn heartbeat(
@@ -269,19 +1439,30 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
 
         let mut command = ExecutorCommand::NoAction;
 
-        let executor_id = Uuid::parse_str(&request.get_ref().executor_id).map_err(tonic_error)?;
-        let status = request.get_ref().status.try_into().map_err(tonic_error)?;
+        let request = request.into_inner();
+        let executor_id = Uuid::parse_str(&request.executor_id).map_err(tonic_error)?;
+        let status = request.status.try_into().map_err(tonic_error)?;
 
         resources.executors_status.insert(executor_id, status);
+        if let Some(capability) = request.capability {
+            resources
+                .executors_capability
+                .insert(executor_id, capability.into());
+        }
 
         resources
             .executors_last_heartbeat
             .insert(executor_id, SystemTime::now());
 
         // check if the executor need to be stopped
-        if let Some(task_id) = resources.executors_tasks.get(&executor_id) {
-            match status {
-                ExecutorStatus::Executing => {
+        let task_ids: Vec<Uuid> = resources
+            .executors_tasks
+            .get(&executor_id)
+            .map(|t| t.iter().cloned().collect())
+            .unwrap_or_default();
+        match status {
+            ExecutorStatus::Executing => {
+                for task_id in task_ids.iter() {
                     if resources.tasks_to_cancel.contains(task_id) {
                         command = ExecutorCommand::Stop;
                         let task_id = task_id.to_owned();
@@ -292,16 +1473,68 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
                             task_id
                         );
                         resources.cancel_task(task_id).await.map_err(tonic_error)?;
+                        resources.save_state().await.map_err(tonic_error)?;
                         return Ok(Response::new(HeartbeatResponse::new(command)));
                     }
                 }
-                ExecutorStatus::Idle => {
-                    resources.executors_tasks.remove(&executor_id);
+                // Executors which are not subscribed run one task, which is
+                // stopped together with the executor.
+                if !resources.subscribers.contains_key(&executor_id)
+                    && resources.stopped_tasks.values().any(|e| *e == executor_id)
+                {
+                    log::debug!(
+                        "Sending stop command to executor {} because its task is overdue",
+                        executor_id
+                    );
+                    resources.release_stopped_slots(&executor_id);
+                    let command = ExecutorCommand::Stop;
+                    return Ok(Response::new(HeartbeatResponse::new(command)));
+                }
+            }
+            // None of the slots is running a task.
+            ExecutorStatus::Idle if !task_ids.is_empty() => {
+                resources.release_stopped_slots(&executor_id);
+                for task_id in task_ids.iter() {
+                    resources.unassign_task(&executor_id, task_id);
+                    // The executor has run the task and freed the slot
+                    // without reporting the result, e.g., the update failed.
+                    // A task pushed after the heartbeat was sent is not
+                    // running yet.
+                    let ts = match resources.get_task_state(task_id).await {
+                        Ok(ts) if ts.status == TaskStatus::Running => ts,
+                        _ => continue,
+                    };
+                    log::warn!("Result of task {} is lost", task_id);
+                    if let Err(e) = resources
+                        .retry_or_fail_task(ts, executor_id, "Runtime Error: Task result lost")
+                        .await
+                    {
+                        log::error!("Failed to retry task {}: {:?}", task_id, e);
+                    }
+                }
+                if let Err(e) = resources.save_state().await {
+                    log::error!("Failed to save scheduler state: {:?}", e);
                 }
             }
+            ExecutorStatus::Idle => resources.release_stopped_slots(&executor_id),
         }
 
-        if !resources.task_queue.is_empty() {
+        if resources.draining_executors.contains(&executor_id) {
+            if resources.running_tasks(&executor_id) == 0 {
+                log::info!("Shutting down drained executor {}", executor_id);
+                command = ExecutorCommand::Shutdown;
+            }
+        } else if resources.subscribers.contains_key(&executor_id) {
+            // Subscribed executors get their tasks pushed through the stream.
+            if let Err(e) = resources.dispatch().await {
+                log::error!("Failed to dispatch tasks: {:?}", e);
+            }
+        } else if resources.free_slots(&executor_id) > 0
+            && resources
+                .task_queue
+                .iter()
+                .any(|task| resources.can_run(&executor_id, task))
+        {
             command = ExecutorCommand::NewTask;
         }
 
@@ -314,18 +1547,24 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
         request: Request<PullTaskRequest>,
     ) -> TeaclaveServiceResponseResult<PullTaskResponse> {
         let request = request.get_ref();
+        let executor_id = Uuid::parse_str(&request.executor_id).map_err(tonic_error)?;
         let mut resources = self.resources.lock().await;
-        match resources.task_queue.pop_front() {
-            Some(task) => match resources.tasks_to_cancel.take(&task.task_id) {
+        match resources.take_task_for(&executor_id) {
+            Some((index, mut task)) => match resources.tasks_to_cancel.take(&task.task_id) {
                 Some(task_id) => {
                     resources.cancel_task(task_id).await?;
+                    resources.save_state().await.map_err(tonic_error)?;
                     Err(SchedulerServiceError::TaskCanceled.into())
                 }
                 None => {
-                    resources.executors_tasks.insert(
-                        Uuid::parse_str(&request.executor_id).map_err(tonic_error)?,
-                        task.task_id,
-                    );
+                    resources.assign_task(executor_id, &mut task);
+                    // The assignment must be durable before the task is handed
+                    // out, otherwise it would be lost on a scheduler restart.
+                    if let Err(e) = resources.save_state().await {
+                        resources.unassign_task(&executor_id, &task.task_id);
+                        resources.task_queue.insert(index, task);
+                        return Err(tonic_error(e));
+                    }
                     Ok(Response::new(PullTaskResponse::new(task)))
                 }
             },
@@ -358,21 +1597,34 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
         &self,
         request: Request<UpdateTaskResultRequest>,
     ) -> TeaclaveServiceResponseResult<()> {
-        let resources = self.resources.lock().await;
+        let mut resources = self.resources.lock().await;
 
         let request = request.into_inner();
+        let task_id = Uuid::parse_str(&request.task_id).map_err(tonic_error)?;
+        // The task has been failed when it was stopped, only its slot is freed.
+        if resources.stopped_tasks.remove(&task_id).is_some() {
+            log::debug!(
+                "Stopped task {} is reported, its result is dropped",
+                task_id
+            );
+            if let Err(e) = resources.dispatch().await {
+                log::error!("Failed to dispatch tasks: {:?}", e);
+            }
+            return Ok(Response::new(()));
+        }
         let ts = resources
-            .get_task_state(&Uuid::parse_str(&request.task_id).map_err(tonic_error)?)
+            .get_task_state(&task_id)
             .await
             .map_err(tonic_error)?;
         let mut task: Task<Finish> = ts.try_into().map_err(tonic_error)?;
         let task_result: TaskResult = request.result.try_into().map_err(tonic_error)?;
+        let mut batch = BatchRequest::new();
         if let TaskResult::Ok(outputs) = task_result.clone() {
             for (key, auth_tag) in outputs.tags_map.iter() {
                 let outfile = task
                     .update_output_cmac(key, auth_tag)
                     .map_err(tonic_error)?;
-                resources.put_into_db(outfile).await.map_err(tonic_error)?;
+                batch = batch.put(outfile.key(), outfile.to_vec().map_err(tonic_error)?);
             }
         };
 
@@ -381,7 +1633,70 @@ impl TeaclaveScheduler for TeaclaveSchedulerService {
         log::debug!("UpdateTaskResult: Task {:?}", task);
 
         let ts = TaskState::from(task);
-        resources.put_into_db(&ts).await.map_err(tonic_error)?;
+        let batch = batch
+            .put(ts.key(), ts.to_vec().map_err(tonic_error)?)
+            .delete(staged_task_id(&ts.task_id).to_bytes());
+        resources.commit_to_db(batch).await.map_err(tonic_error)?;
+        resources.advance_workflow(&ts).await;
+
+        // The slot is free to take the next task right away.
+        resources.executors_tasks.retain(|_, task_ids| {
+            task_ids.remove(&ts.task_id);
+            !task_ids.is_empty()
+        });
+        resources.task_share_groups.remove(&ts.task_id);
+        resources.save_state().await.map_err(tonic_error)?;
+        if let Err(e) = resources.dispatch().await {
+            log::error!("Failed to dispatch tasks: {:?}", e);
+        }
         Ok(Response::new(()))
     }
 }
+
+fn staged_task_id(task_id: &Uuid) -> ExternalID {
+    ExternalID::new(StagedTask::key_prefix(), task_id.to_owned())
+}
+
+fn node_succeeded(ts: &TaskState) -> bool {
+    ts.status == TaskStatus::Finished && ts.result.is_ok()
+}
+
+// Exponential backoff before the given retry attempt, starting from
+// RETRY_BACKOFF_BASE_SECS and capped by RETRY_BACKOFF_MAX_SECS.
+fn retry_backoff(attempt: u32) -> Duration {
+    let factor = 1u64
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    let secs = RETRY_BACKOFF_BASE_SECS
+        .saturating_mul(factor)
+        .min(RETRY_BACKOFF_MAX_SECS);
+    Duration::from_secs(secs)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+
+    pub fn test_retry_backoff() {
+        assert_eq!(
+            retry_backoff(1),
+            Duration::from_secs(RETRY_BACKOFF_BASE_SECS)
+        );
+        assert_eq!(
+            retry_backoff(2),
+            Duration::from_secs(RETRY_BACKOFF_BASE_SECS * 2)
+        );
+        assert_eq!(
+            retry_backoff(3),
+            Duration::from_secs(RETRY_BACKOFF_BASE_SECS * 4)
+        );
+        assert_eq!(
+            retry_backoff(10),
+            Duration::from_secs(RETRY_BACKOFF_MAX_SECS)
+        );
+        assert_eq!(
+            retry_backoff(u32::MAX),
+            Duration::from_secs(RETRY_BACKOFF_MAX_SECS)
+        );
+    }
+}
@@ -15,11 +15,56 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use teaclave_binder::proto::{ECallCommand, RepairDatabaseInput, RepairDatabaseOutput};
+use teaclave_binder::TeeBinder;
+use teaclave_config::RuntimeConfig;
 use teaclave_service_app_utils::launch_teaclave_service;
+use teaclave_types::TeeServiceResult;
 
 const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 
 fn main() -> Result<()> {
-    launch_teaclave_service(PACKAGE_NAME)
+    match std::env::args().nth(1).as_deref() {
+        Some("repair") => repair_database(),
+        Some(command) => bail!("Unknown command: {}", command),
+        None => launch_teaclave_service(PACKAGE_NAME),
+    }
+}
+
+/// Rebuild the database of a stopped storage service from what is left of
+/// its files, e.g. after its manifest is corrupted.
+fn repair_database() -> Result<()> {
+    env_logger::init_from_env(
+        env_logger::Env::new()
+            .filter_or("TEACLAVE_LOG", "RUST_LOG")
+            .write_style_or("TEACLAVE_LOG_STYLE", "RUST_LOG_STYLE"),
+    );
+
+    let config =
+        RuntimeConfig::from_toml("runtime.config.toml").context("Failed to load config file.")?;
+    let tee = TeeBinder::new(PACKAGE_NAME).context("Failed to new the enclave.")?;
+    let input = RepairDatabaseInput::new(config);
+    let result = tee.invoke::<RepairDatabaseInput, TeeServiceResult<RepairDatabaseOutput>>(
+        ECallCommand::RepairDatabase,
+        input,
+    );
+    tee.finalize();
+
+    let output = match result {
+        Err(e) => bail!("TEE invocation error: {:?}", e),
+        Ok(Err(e)) => bail!("Failed to repair the database: {:?}", e),
+        Ok(Ok(output)) => output,
+    };
+    println!(
+        "Repaired the database: {} tables with {} entries",
+        output.tables, output.entries
+    );
+    if output.corrupted_records > 0 || output.lost_files > 0 {
+        println!(
+            "Dropped {} corrupted log records and {} unreadable files",
+            output.corrupted_records, output.lost_files
+        );
+    }
+    Ok(())
 }
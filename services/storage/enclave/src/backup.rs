@@ -0,0 +1,402 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+// A backup archive is a list of chunks. The first chunk holds the magic, the
+// base iv of the archive and its key sealed by the storage enclave. Every
+// following chunk is a list of records (u32 key length, key, u32 value
+// length, value) after a flag marking the last chunk, encrypted with AES-GCM
+// under the archive key. The iv of a chunk is derived from its index, so that
+// chunks cannot be reordered, dropped or moved between archives without
+// failing the decryption.
+//
+// The archive key is random and never leaves the storage enclaves unsealed.
+// A storage enclave on another platform cannot unseal it, and gets it from
+// the storage enclave which made the archive instead, over a connection on
+// which both of them are attested. So only the storage enclaves can read an
+// archive, and nobody else can make one which restores.
+//
+// The archive is read from a snapshot while it is sent, and restored chunk by
+// chunk as it is received. A marker key is kept from the first to the last
+// chunk of a restore, and the storage service refuses the other requests
+// while it is there. The next restore drops an interrupted one.
+
+use crate::error::StorageServiceError;
+use crate::replication;
+use anyhow::{anyhow, ensure, Result};
+use rand::RngCore;
+use rusty_leveldb::{ConcurrentDB, LdbIterator, Snapshot, WriteBatch};
+use sgx_tseal::seal::SealedData;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use teaclave_attestation::{verifier, AttestedTlsConfig};
+use teaclave_config::build::AS_ROOT_CA_CERT;
+use teaclave_crypto::AesGcm256Key;
+use teaclave_proto::teaclave_storage_service::{
+    BackupChunk, OpenArchiveKeyRequest, TeaclaveStorageClient,
+};
+use teaclave_rpc::Status;
+use teaclave_service_enclave_utils::{bail, create_trusted_storage_endpoint};
+use teaclave_types::EnclaveInfo;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+
+const ARCHIVE_MAGIC: &[u8] = b"TCLVDB02";
+const ARCHIVE_IV_LENGTH: usize = 12;
+const ARCHIVE_KEY_LENGTH: usize = 32;
+const ARCHIVE_KEY_AAD: &[u8] = b"teaclave_db_backup";
+// Number of chunks read ahead of the client
+const ARCHIVE_CHANNEL_SIZE: usize = 4;
+const CHUNK_SIZE: usize = 1024 * 1024;
+const LAST_CHUNK: u8 = 1;
+const RESTORE_MARKER_KEY: &[u8] = b"backup_restore_in_progress";
+
+pub(crate) type BackupStream = Pin<Box<dyn Stream<Item = Result<BackupChunk, Status>> + Send>>;
+
+pub(crate) type Record = (Vec<u8>, Vec<u8>);
+
+#[derive(Default)]
+struct Snapshots {
+    next_id: u64,
+    taken: HashMap<u64, Snapshot>,
+}
+
+// The snapshots of the backups are taken by the storage thread in between
+// two requests, so that they hold no half of a request. The proxy takes them
+// to read the archives.
+#[derive(Clone, Default)]
+pub(crate) struct Backups {
+    snapshots: Arc<Mutex<Snapshots>>,
+}
+
+impl Backups {
+    pub(crate) fn insert(&self, snapshot: Snapshot) -> u64 {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let id = snapshots.next_id;
+        snapshots.next_id += 1;
+        snapshots.taken.insert(id, snapshot);
+        id
+    }
+
+    pub(crate) fn take(&self, id: u64) -> Option<Snapshot> {
+        self.snapshots.lock().unwrap().taken.remove(&id)
+    }
+}
+
+// The archive is read in a thread of its own, which waits while the client
+// is behind, and stops once it is gone.
+pub(crate) fn archive_stream(db: ConcurrentDB, snapshot: Snapshot) -> BackupStream {
+    let (sender, receiver) = mpsc::channel(ARCHIVE_CHANNEL_SIZE);
+    thread::spawn(move || {
+        let sent = write_archive(&db, snapshot, |data| {
+            sender.blocking_send(Ok(BackupChunk { data })).is_ok()
+        });
+        match sent {
+            Ok(chunks) => log::info!("Created backup archive of {} chunks", chunks),
+            Err(e) => {
+                log::warn!("Cannot create backup archive: {:?}", e);
+                let status = Status::internal("cannot create backup archive");
+                let _ = sender.blocking_send(Err(status));
+            }
+        }
+    });
+    Box::pin(ReceiverStream::new(receiver))
+}
+
+/// Encrypt a consistent snapshot of the database into an archive with a new
+/// archive key, and send it chunk by chunk until send returns false. Returns
+/// the number of chunks sent.
+pub(crate) fn write_archive(
+    db: &ConcurrentDB,
+    snapshot: Snapshot,
+    mut send: impl FnMut(Vec<u8>) -> bool,
+) -> Result<usize> {
+    let mut base_iv = [0u8; ARCHIVE_IV_LENGTH];
+    rand::thread_rng().fill_bytes(&mut base_iv);
+    let mut archive_key = [0u8; ARCHIVE_KEY_LENGTH];
+    rand::thread_rng().fill_bytes(&mut archive_key);
+    let key = &archive_key[..];
+    let sealed_key = seal_archive_key(key)?;
+    if !send([ARCHIVE_MAGIC, &base_iv[..], &sealed_key[..]].concat()) {
+        return Ok(0);
+    }
+
+    let mut it = db.new_iter_at(snapshot)?;
+    it.seek_to_first();
+
+    let mut index = 1;
+    let mut records = Vec::new();
+    let mut k = Vec::new();
+    let mut v = Vec::new();
+    while it.valid() && it.current(&mut k, &mut v) {
+        if !records.is_empty() && records.len() + 8 + k.len() + v.len() > CHUNK_SIZE {
+            let chunk = seal_chunk(key, &base_iv, index, 0, &records)?;
+            if !send(chunk) {
+                return Ok(index);
+            }
+            index += 1;
+            records.clear();
+        }
+        push_record(&mut records, &k);
+        push_record(&mut records, &v);
+        it.advance();
+    }
+    let chunk = seal_chunk(key, &base_iv, index, LAST_CHUNK, &records)?;
+    if !send(chunk) {
+        return Ok(index);
+    }
+
+    Ok(index + 1)
+}
+
+/// Split the first chunk of an archive into its base iv and sealed key.
+pub(crate) fn parse_header(header: &[u8]) -> Result<(&[u8], &[u8])> {
+    ensure!(
+        header.len() > ARCHIVE_MAGIC.len() + ARCHIVE_IV_LENGTH && header.starts_with(ARCHIVE_MAGIC),
+        "Invalid archive header"
+    );
+    Ok(header[ARCHIVE_MAGIC.len()..].split_at(ARCHIVE_IV_LENGTH))
+}
+
+/// Decrypts the chunks of an archive after its header, in order.
+pub(crate) struct ArchiveReader {
+    key: Vec<u8>,
+    base_iv: Vec<u8>,
+    index: usize,
+    finished: bool,
+}
+
+impl ArchiveReader {
+    pub(crate) fn new(base_iv: &[u8], key: Vec<u8>) -> Result<Self> {
+        ensure!(
+            key.len() == ARCHIVE_KEY_LENGTH,
+            "Invalid archive key length"
+        );
+        Ok(Self {
+            key,
+            base_iv: base_iv.to_vec(),
+            index: 1,
+            finished: false,
+        })
+    }
+
+    pub(crate) fn read_chunk(&mut self, chunk: &[u8]) -> Result<Vec<Record>> {
+        ensure!(!self.finished, "Unexpected chunk after the last one");
+        let index = self.index;
+        let iv = chunk_iv(&self.base_iv, index);
+        let mut plaintext = chunk.to_vec();
+        AesGcm256Key::new(&self.key, &iv)?
+            .decrypt(&mut plaintext)
+            .map_err(|_| anyhow!("Cannot decrypt chunk {}", index))?;
+
+        let (flag, mut rest) = plaintext
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty chunk {}", index))?;
+        let mut records = Vec::new();
+        while !rest.is_empty() {
+            let k = pop_record(&mut rest)?;
+            let v = pop_record(&mut rest)?;
+            records.push((k, v));
+        }
+        self.finished = *flag == LAST_CHUNK;
+        self.index += 1;
+
+        Ok(records)
+    }
+
+    pub(crate) fn finished(&self) -> bool {
+        self.finished
+    }
+}
+
+fn seal_archive_key(key: &[u8]) -> Result<Vec<u8>> {
+    SealedData::<[u8]>::seal(key, Some(ARCHIVE_KEY_AAD))
+        .and_then(|sealed| sealed.into_bytes())
+        .map_err(|e| anyhow!("Cannot seal archive key: {:?}", e))
+}
+
+/// Unseal the key of an archive made on this platform. The storage enclaves
+/// on other platforms get it with the OpenArchiveKey RPC, which is only
+/// served to the attested inbound services of the storage service. They read
+/// and write every record anyway.
+pub(crate) fn unseal_archive_key(sealed_key: &[u8]) -> Result<Vec<u8>> {
+    let unsealed = SealedData::<[u8]>::from_bytes(sealed_key.to_vec())
+        .and_then(|sealed| sealed.unseal())
+        .map_err(|e| anyhow!("Cannot unseal archive key: {:?}", e))?;
+    ensure!(
+        unsealed.to_plaintext().len() == ARCHIVE_KEY_LENGTH,
+        "Invalid archive key length"
+    );
+    Ok(unsealed.to_plaintext().to_vec())
+}
+
+// Connects to the storage enclave which made an archive, and is verified to
+// run the audited storage service, as the follower connects to the primary.
+#[derive(Clone)]
+pub(crate) struct KeySource {
+    enclave_info: Arc<EnclaveInfo>,
+    attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
+}
+
+impl KeySource {
+    pub(crate) fn new(
+        enclave_info: Arc<EnclaveInfo>,
+        attested_tls_config: Arc<RwLock<AttestedTlsConfig>>,
+    ) -> Self {
+        Self {
+            enclave_info,
+            attested_tls_config,
+        }
+    }
+
+    /// Get the key of an archive from the storage enclave at the address, or
+    /// unseal it here if there is no address.
+    pub(crate) async fn open_archive_key(
+        &self,
+        source_address: &str,
+        sealed_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        if source_address.is_empty() {
+            return unseal_archive_key(sealed_key);
+        }
+        let endpoint = create_trusted_storage_endpoint(
+            source_address,
+            &self.enclave_info,
+            AS_ROOT_CA_CERT,
+            verifier::universal_quote_verifier,
+            self.attested_tls_config.clone(),
+        )?;
+        let channel = endpoint.connect().await?;
+        let mut client = TeaclaveStorageClient::new_with_builtin_config(channel);
+        let request = OpenArchiveKeyRequest::new(sealed_key);
+        let response = client.open_archive_key(request).await?.into_inner();
+        log::info!("Got the archive key from {}", source_address);
+        Ok(response.key)
+    }
+}
+
+/// Start a restore into the database, which must be empty but for the
+/// records of an interrupted restore.
+pub(crate) fn start_restore(db: &ConcurrentDB) -> Result<(), StorageServiceError> {
+    if restore_in_progress(db) {
+        log::warn!("Dropping an interrupted restore");
+        replication::clear(db, RESTORE_MARKER_KEY)?;
+    } else if db.new_iter()?.advance() {
+        bail!(StorageServiceError::Service(anyhow!(
+            "Database is not empty"
+        )));
+    }
+    let mut marker = WriteBatch::new();
+    marker.put(RESTORE_MARKER_KEY, b"");
+    db.write(marker, false)?;
+    Ok(())
+}
+
+pub(crate) fn restore_records(
+    db: &ConcurrentDB,
+    records: Vec<Record>,
+) -> Result<(), StorageServiceError> {
+    if !restore_in_progress(db) {
+        bail!(StorageServiceError::Service(anyhow!("No restore started")));
+    }
+    let mut batch = WriteBatch::new();
+    for (k, v) in records {
+        batch.put(&k, &v);
+    }
+    db.write(batch, false)?;
+    Ok(())
+}
+
+pub(crate) fn finish_restore(db: &ConcurrentDB) -> Result<(), StorageServiceError> {
+    if !restore_in_progress(db) {
+        bail!(StorageServiceError::Service(anyhow!("No restore started")));
+    }
+    let mut marker = WriteBatch::new();
+    marker.delete(RESTORE_MARKER_KEY);
+    db.write(marker, true)?;
+    db.flush()?;
+    Ok(())
+}
+
+// Leaves the database empty again.
+pub(crate) fn abort_restore(db: &ConcurrentDB) -> Result<(), StorageServiceError> {
+    if !restore_in_progress(db) {
+        return Ok(());
+    }
+    replication::clear(db, RESTORE_MARKER_KEY)?;
+    let mut marker = WriteBatch::new();
+    marker.delete(RESTORE_MARKER_KEY);
+    db.write(marker, true)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub(crate) fn restore_in_progress(db: &ConcurrentDB) -> bool {
+    db.get(RESTORE_MARKER_KEY).is_some()
+}
+
+pub(crate) fn check_restore_complete(db: &ConcurrentDB) -> Result<(), StorageServiceError> {
+    if restore_in_progress(db) {
+        bail!(StorageServiceError::RestoreInProgress);
+    }
+    Ok(())
+}
+
+fn seal_chunk(
+    key: &[u8],
+    base_iv: &[u8],
+    index: usize,
+    flag: u8,
+    records: &[u8],
+) -> Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(records.len() + 1);
+    chunk.push(flag);
+    chunk.extend_from_slice(records);
+    AesGcm256Key::new(key, &chunk_iv(base_iv, index))?.encrypt(&mut chunk)?;
+    Ok(chunk)
+}
+
+fn chunk_iv(base_iv: &[u8], index: usize) -> Vec<u8> {
+    let mut iv = base_iv.to_vec();
+    let counter = (index as u64).to_be_bytes();
+    for (b, c) in iv[ARCHIVE_IV_LENGTH - counter.len()..]
+        .iter_mut()
+        .zip(counter)
+    {
+        *b ^= c;
+    }
+    iv
+}
+
+fn push_record(records: &mut Vec<u8>, bytes: &[u8]) {
+    records.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    records.extend_from_slice(bytes);
+}
+
+fn pop_record(records: &mut &[u8]) -> Result<Vec<u8>> {
+    ensure!(records.len() >= 4, "Invalid record");
+    let (len, rest) = records.split_at(4);
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(len);
+    let len = u32::from_le_bytes(bytes) as usize;
+    ensure!(rest.len() >= len, "Invalid record");
+    let (record, rest) = rest.split_at(len);
+    *records = rest;
+    Ok(record.to_vec())
+}
@@ -26,6 +26,14 @@ pub(crate) enum StorageServiceError {
     Database(#[from] rusty_leveldb::Status),
     #[error("service internal error")]
     Service(#[from] anyhow::Error),
+    #[error("precondition failed")]
+    PreconditionFailed,
+    #[error("read-only follower")]
+    Follower,
+    #[error("snapshot in progress")]
+    SnapshotInProgress,
+    #[error("restore in progress")]
+    RestoreInProgress,
 }
 
 impl From<StorageServiceError> for teaclave_rpc::Status {
@@ -34,6 +42,10 @@ impl From<StorageServiceError> for teaclave_rpc::Status {
         let msg = error.to_string();
         let code = match error {
             StorageServiceError::Service(_) => Code::Internal,
+            StorageServiceError::PreconditionFailed => Code::Aborted,
+            StorageServiceError::Follower => Code::FailedPrecondition,
+            StorageServiceError::SnapshotInProgress => Code::Unavailable,
+            StorageServiceError::RestoreInProgress => Code::Unavailable,
             _ => Code::Unknown,
         };
         Status::new(code, msg)
@@ -0,0 +1,142 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::error::StorageServiceError;
+use rusty_leveldb::{ConcurrentDB, LdbIterator, WriteBatch};
+
+// expiry_deadline-key: u64 deadline of the key
+// expiry_index-deadline-key: empty; the deadline is u64 big endian, so that
+// the index is sorted by the deadline
+// An index entry without the matching deadline record is stale, e.g. after
+// the key is put again, and is only removed by the sweeper.
+const DEADLINE_PREFIX: &[u8] = b"expiry_deadline-";
+const INDEX_PREFIX: &[u8] = b"expiry_index-";
+
+fn get_deadline_key(key: &[u8]) -> Vec<u8> {
+    let mut deadline_key = DEADLINE_PREFIX.to_vec();
+    deadline_key.extend_from_slice(key);
+    deadline_key
+}
+
+fn get_index_key(deadline: u64, key: &[u8]) -> Vec<u8> {
+    let mut index_key = INDEX_PREFIX.to_vec();
+    index_key.extend_from_slice(&deadline.to_be_bytes());
+    index_key.extend_from_slice(b"-");
+    index_key.extend_from_slice(key);
+    index_key
+}
+
+fn split_index_key(index_key: &[u8]) -> Option<(u64, &[u8])> {
+    let rest = index_key.strip_prefix(INDEX_PREFIX)?;
+    if rest.len() < 9 {
+        return None;
+    }
+    let (deadline, key) = rest.split_at(8);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(deadline);
+    Some((u64::from_be_bytes(bytes), &key[1..]))
+}
+
+// A TTL of 0 means that the key does not expire.
+pub(crate) fn deadline(ttl_secs: u32, now: u64) -> Option<u64> {
+    if ttl_secs == 0 {
+        return None;
+    }
+    Some(now + ttl_secs as u64)
+}
+
+pub(crate) fn put_deadline_to_batch(batch: &mut WriteBatch, key: &[u8], deadline: u64) {
+    batch.put(&get_deadline_key(key), &deadline.to_le_bytes());
+    batch.put(&get_index_key(deadline, key), b"");
+}
+
+pub(crate) fn clear_deadline_to_batch(db: &ConcurrentDB, batch: &mut WriteBatch, key: &[u8]) {
+    let deadline_key = get_deadline_key(key);
+    if db.get(&deadline_key).is_some() {
+        batch.delete(&deadline_key);
+    }
+}
+
+// A key stays readable until it is swept, so reads check the deadline
+// themselves.
+pub(crate) fn is_expired(db: &ConcurrentDB, key: &[u8], now: u64) -> bool {
+    match db.get(&get_deadline_key(key)) {
+        Some(value) if value.len() == 8 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&value);
+            u64::from_le_bytes(bytes) <= now
+        }
+        _ => false,
+    }
+}
+
+// Set the deadline of a put key, or clear the one it had before.
+pub(crate) fn set_deadline_to_batch(
+    db: &ConcurrentDB,
+    batch: &mut WriteBatch,
+    key: &[u8],
+    deadline: Option<u64>,
+) {
+    match deadline {
+        Some(deadline) => put_deadline_to_batch(batch, key, deadline),
+        None => clear_deadline_to_batch(db, batch, key),
+    }
+}
+
+// Delete the keys whose deadline has passed, up to limit index entries at a
+// time. Returns the deleted keys.
+pub(crate) fn sweep(
+    db: &ConcurrentDB,
+    now: u64,
+    limit: usize,
+) -> Result<Vec<Vec<u8>>, StorageServiceError> {
+    let mut it = db.new_iter()?;
+    it.seek(INDEX_PREFIX);
+
+    let mut batch = WriteBatch::new();
+    let mut entries = 0;
+    let mut expired = Vec::new();
+    let mut index_key = Vec::new();
+    let mut value = Vec::new();
+    while entries < limit
+        && it.valid()
+        && it.current(&mut index_key, &mut value)
+        && index_key.starts_with(INDEX_PREFIX)
+    {
+        match split_index_key(&index_key) {
+            Some((deadline, _)) if deadline > now => break,
+            Some((deadline, key)) => {
+                let deadline_key = get_deadline_key(key);
+                if db.get(&deadline_key).as_deref() == Some(&deadline.to_le_bytes()[..]) {
+                    batch.delete(key);
+                    batch.delete(&deadline_key);
+                    expired.push(key.to_vec());
+                }
+            }
+            None => log::warn!("Invalid expiry index entry"),
+        }
+        batch.delete(&index_key);
+        entries += 1;
+        it.advance();
+    }
+
+    if entries > 0 {
+        db.write(batch, false)?;
+        db.flush()?;
+    }
+    Ok(expired)
+}
@@ -19,29 +19,39 @@
 extern crate log;
 extern crate sgx_types;
 
-use std::cell::RefCell;
+#[cfg(not(test_mode))]
+use std::path::Path;
+use std::sync::Arc;
 use std::thread;
 use tokio::sync::mpsc::unbounded_channel;
 
 use anyhow::{anyhow, Result};
-use rusty_leveldb::DB;
+use rusty_leveldb::ConcurrentDB;
 
 use teaclave_attestation::{verifier, AttestationConfig, RemoteAttestation};
 use teaclave_binder::proto::{
     ECallCommand, FinalizeEnclaveInput, FinalizeEnclaveOutput, InitEnclaveInput, InitEnclaveOutput,
-    StartServiceInput, StartServiceOutput,
+    RepairDatabaseInput, RepairDatabaseOutput, StartServiceInput, StartServiceOutput,
 };
 use teaclave_binder::{handle_ecall, register_ecall_handler};
 use teaclave_config::build::{AS_ROOT_CA_CERT, AUDITOR_PUBLIC_KEYS, STORAGE_INBOUND_SERVICES};
 use teaclave_config::RuntimeConfig;
 use teaclave_proto::teaclave_storage_service::TeaclaveStorageServer;
 use teaclave_rpc::config::SgxTrustedTlsServerConfig;
-use teaclave_service_enclave_utils::ServiceEnclave;
+#[cfg(not(test_mode))]
+use teaclave_service_enclave_utils::base_dir_for_db;
+use teaclave_service_enclave_utils::{create_trusted_storage_endpoint, ServiceEnclave};
 use teaclave_types::{EnclaveInfo, TeeServiceError, TeeServiceResult};
 
+mod backup;
 mod error;
+mod expiry;
+#[cfg_attr(test_mode, allow(dead_code))]
+mod persist;
 mod proxy;
+mod replication;
 mod service;
+mod watch;
 
 async fn start_service(config: &RuntimeConfig) -> Result<()> {
     info!("Starting Storage...");
@@ -54,11 +64,11 @@ async fn start_service(config: &RuntimeConfig) -> Result<()> {
         .ok_or_else(|| anyhow!("cannot get attested TLS config"))?;
     info!(" Starting Storage: Self attestation finished ...");
 
-    let enclave_info = EnclaveInfo::verify_and_new(
+    let enclave_info = Arc::new(EnclaveInfo::verify_and_new(
         &config.audit.enclave_info_bytes,
         AUDITOR_PUBLIC_KEYS,
         &config.audit.auditor_signatures_bytes,
-    )?;
+    )?);
     let accepted_enclave_attrs: Vec<teaclave_types::EnclaveAttr> = STORAGE_INBOUND_SERVICES
         .iter()
         .map(|service| match enclave_info.get_enclave_attr(service) {
@@ -77,21 +87,69 @@ async fn start_service(config: &RuntimeConfig) -> Result<()> {
             .into();
     info!(" Starting Storage: Server config setup finished ...");
 
+    // The mock database has a fixed key, which is not rotated.
+    #[cfg(test_mode)]
+    let key_dir = None;
+    #[cfg(not(test_mode))]
+    let (db_path, db_keys, key_dir) = {
+        let db_base = base_dir_for_db(config)?;
+        let db_keys = persist::load_or_create_db_keys(&db_base)?;
+        (db_base.join(persist::DB_NAME), db_keys, Some(db_base))
+    };
+
+    // With a primary, this service is a follower until it is promoted.
+    let primary_endpoint = match &config.storage.primary_address {
+        Some(primary_address) => Some(create_trusted_storage_endpoint(
+            primary_address,
+            &enclave_info,
+            AS_ROOT_CA_CERT,
+            verifier::universal_quote_verifier,
+            attested_tls_config.clone(),
+        )?),
+        None => None,
+    };
+    let follower = primary_endpoint.is_some();
+
+    info!(" Starting Storage: opening database ...");
+    #[cfg(test_mode)]
+    let db = test_mode::create_mock_db()?;
+    #[cfg(not(test_mode))]
+    let db = create_teaclave_db(&db_path, db_keys)?;
+    // Promoting by a restart, like the Promote RPC, needs a complete snapshot.
+    if !follower && replication::snapshot_in_progress(&db) {
+        anyhow::bail!("database holds an incomplete snapshot of the primary");
+    }
+    let service_db = db.clone();
+
     let (sender, receiver) = unbounded_channel();
+    let watchers = watch::Watchers::default();
+    let service_watchers = watchers.clone();
+    let replication = replication::Replication::default();
+    let service_replication = replication.clone();
+    let backups = backup::Backups::default();
+    let service_backups = backups.clone();
     let storage_handle = thread::spawn(move || {
-        info!(" Starting Storage: opening database ...");
-        #[cfg(test_mode)]
-        let db = test_mode::create_mock_db();
-        #[cfg(not(test_mode))]
-        let db = create_teaclave_db();
-
-        let mut storage_service = service::TeaclaveStorageService::new(RefCell::new(db), receiver);
+        let mut storage_service = service::TeaclaveStorageService::new(
+            service_db,
+            receiver,
+            service_watchers,
+            service_replication,
+            service_backups,
+            follower,
+            key_dir,
+        );
 
         info!(" Starting Storage: database loaded ...");
         storage_service.start();
     });
 
-    let service = proxy::ProxyService::new(sender);
+    if let Some(primary_endpoint) = primary_endpoint {
+        info!(" Starting Storage: following the primary ...");
+        tokio::spawn(replication::follow(primary_endpoint, sender.clone()));
+    }
+
+    let key_source = backup::KeySource::new(enclave_info, attested_tls_config);
+    let service = proxy::ProxyService::new(sender, db, watchers, replication, backups, key_source);
 
     info!(" Starting Storage: start listening ...");
 
@@ -106,26 +164,51 @@ async fn start_service(config: &RuntimeConfig) -> Result<()> {
 }
 
 #[cfg(not(test_mode))]
-pub(crate) fn create_teaclave_db() -> DB {
-    let opt = rusty_leveldb::in_memory();
-    DB::open("teaclave_db", opt).expect("cannot open teaclave_db")
+pub(crate) fn create_teaclave_db(
+    db_path: &Path,
+    keys: Vec<persist::DbKey>,
+) -> Result<ConcurrentDB> {
+    let opt = rusty_leveldb::Options::new_disk_db_with_keys(keys);
+    info!("open teaclave_db: {:?}", db_path);
+    ConcurrentDB::open(db_path, opt).map_err(|e| anyhow!("cannot open teaclave_db: {}", e))
+}
+
+// The database must not be open, so the service has to be stopped first.
+#[cfg_attr(test_mode, allow(unused_variables))]
+fn repair_database(config: &RuntimeConfig) -> Result<rusty_leveldb::RepairStats> {
+    #[cfg(test_mode)]
+    let (db_path, db_keys) = (
+        std::path::PathBuf::from(test_mode::MOCK_DB_NAME),
+        vec![test_mode::MOCK_DB_KEY],
+    );
+    #[cfg(not(test_mode))]
+    let (db_path, db_keys) = {
+        let db_base = base_dir_for_db(config)?;
+        let db_keys = persist::load_db_keys(&db_base)?;
+        (db_base.join(persist::DB_NAME), db_keys)
+    };
+
+    info!("repair teaclave_db: {:?}", db_path);
+    let opt = rusty_leveldb::Options::new_disk_db_with_keys(db_keys);
+    rusty_leveldb::repair(&db_path, opt).map_err(|e| anyhow!("cannot repair teaclave_db: {}", e))
 }
 
 #[cfg(test_mode)]
 mod test_mode {
     use super::*;
-    pub(crate) fn create_mock_db() -> DB {
-        let key = [
-            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0f, 0x0e, 0x0d, 0x0c, 0x0b, 0x0a,
-            0x09, 0x08,
-        ];
-        let opt = rusty_leveldb::Options::new_disk_db_with(key);
-        let mut database = DB::open("mock_db", opt).unwrap();
-        database.put(b"test_get_key", b"test_get_value").unwrap();
-        database
-            .put(b"test_delete_key", b"test_delete_value")
-            .unwrap();
-        database
+    pub(crate) const MOCK_DB_NAME: &str = "mock_db";
+    pub(crate) const MOCK_DB_KEY: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x0f, 0x0e, 0x0d, 0x0c, 0x0b, 0x0a, 0x09,
+        0x08,
+    ];
+
+    pub(crate) fn create_mock_db() -> Result<ConcurrentDB> {
+        let opt = rusty_leveldb::Options::new_disk_db_with(MOCK_DB_KEY);
+        let database = ConcurrentDB::open(MOCK_DB_NAME, opt)
+            .map_err(|e| anyhow!("cannot open mock_db: {}", e))?;
+        database.put(b"test_get_key", b"test_get_value")?;
+        database.put(b"test_delete_key", b"test_delete_value")?;
+        Ok(database)
     }
 }
 
@@ -146,6 +229,22 @@ fn handle_start_service(input: &StartServiceInput) -> TeeServiceResult<StartServ
     }
 }
 
+#[handle_ecall]
+fn handle_repair_database(input: &RepairDatabaseInput) -> TeeServiceResult<RepairDatabaseOutput> {
+    match repair_database(&input.config) {
+        Ok(stats) => Ok(RepairDatabaseOutput {
+            tables: stats.tables,
+            entries: stats.entries,
+            corrupted_records: stats.corrupted_records,
+            lost_files: stats.lost_files,
+        }),
+        Err(e) => {
+            error!("Failed to repair database: {}", e);
+            Err(TeeServiceError::ServiceError)
+        }
+    }
+}
+
 #[handle_ecall]
 fn handle_init_enclave(_: &InitEnclaveInput) -> TeeServiceResult<InitEnclaveOutput> {
     ServiceEnclave::init(env!("CARGO_PKG_NAME"))?;
@@ -161,6 +260,7 @@ fn handle_finalize_enclave(_: &FinalizeEnclaveInput) -> TeeServiceResult<Finaliz
 register_ecall_handler!(
     type ECallCommand,
     (ECallCommand::StartService, StartServiceInput, StartServiceOutput),
+    (ECallCommand::RepairDatabase, RepairDatabaseInput, RepairDatabaseOutput),
     (ECallCommand::InitEnclave, InitEnclaveInput, InitEnclaveOutput),
     (ECallCommand::FinalizeEnclave, FinalizeEnclaveInput, FinalizeEnclaveOutput),
 );
@@ -178,7 +278,24 @@ pub mod tests {
             service::tests::test_empty_value,
             service::tests::test_enqueue,
             service::tests::test_dequeue,
+            service::tests::test_dequeue_lease,
+            service::tests::test_lease_expiry,
             service::tests::test_get_keys_by_prefix,
+            service::tests::test_scan,
+            service::tests::test_batch,
+            service::tests::test_batch_precondition,
+            service::tests::test_backup_restore,
+            service::tests::test_restore_invalid_archive,
+            service::tests::test_restore_interrupted,
+            service::tests::test_watch,
+            service::tests::test_ttl,
+            service::tests::test_queue_ttl,
+            service::tests::test_replication,
+            service::tests::test_replication_partial_snapshot,
+            service::tests::test_promote,
+            service::tests::test_stats,
+            persist::tests::test_reopen_with_sealed_key,
+            persist::tests::test_rotate_db_key,
         )
     }
 }
@@ -0,0 +1,192 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use anyhow::{anyhow, ensure, Result};
+use rand::RngCore;
+use rusty_leveldb::ConcurrentDB;
+use sgx_tseal::seal::SealedData;
+use std::path::Path;
+use std::untrusted::{fs, path::PathEx};
+
+pub(crate) const DB_NAME: &str = "teaclave_db";
+const DB_KEY_FILE: &str = "teaclave_db.key.sealed";
+const DB_KEY_TMP_FILE: &str = "teaclave_db.key.sealed.tmp";
+const DB_KEY_AAD: &[u8] = b"teaclave_db";
+
+pub(crate) type DbKey = [u8; 16];
+
+/// Load the keys which encrypt the database files, or create one on the first
+/// launch. The keys are kept next to the database, sealed with the signer
+/// identity of the storage enclave so that upgraded enclaves can still open
+/// the database. They are ordered from the oldest to the current one.
+pub(crate) fn load_or_create_db_keys(db_base: &Path) -> Result<Vec<DbKey>> {
+    let key_path = db_base.join(DB_KEY_FILE);
+    if key_path.exists() {
+        return load_db_keys(db_base);
+    }
+
+    // Without its key, an existing database cannot be read anymore.
+    ensure!(
+        !db_base.join(DB_NAME).exists(),
+        "Sealed database key is missing: {}",
+        key_path.display()
+    );
+
+    let keys = vec![new_db_key()];
+    save_db_keys(db_base, &keys)?;
+    log::info!("Created database key: {}", key_path.display());
+    Ok(keys)
+}
+
+/// Load the keys of an existing database.
+pub(crate) fn load_db_keys(db_base: &Path) -> Result<Vec<DbKey>> {
+    let key_path = db_base.join(DB_KEY_FILE);
+    ensure!(
+        key_path.exists(),
+        "Sealed database key is missing: {}",
+        key_path.display()
+    );
+    let sealed = fs::read(&key_path)?;
+    unseal_db_keys(sealed)
+}
+
+/// Make a new key the current key of the database. The key list is saved
+/// before the database writes under the new key, so that a restart can read
+/// whatever it wrote. The earlier keys are kept, the tables written under them
+/// are only rewritten by compactions over time.
+pub(crate) fn rotate_db_key(db_base: &Path, db: &ConcurrentDB) -> Result<()> {
+    let mut keys = load_db_keys(db_base)?;
+    let key = new_db_key();
+    keys.push(key);
+    save_db_keys(db_base, &keys)?;
+    db.rotate_key(key)
+        .map_err(|e| anyhow!("Cannot rotate database key: {}", e))?;
+    log::info!("Rotated database key, {} keys in use", keys.len());
+    Ok(())
+}
+
+fn new_db_key() -> DbKey {
+    let mut key = DbKey::default();
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+// The key file is replaced at once, a crash leaves either the old or the new
+// key list.
+fn save_db_keys(db_base: &Path, keys: &[DbKey]) -> Result<()> {
+    let tmp_path = db_base.join(DB_KEY_TMP_FILE);
+    fs::write(&tmp_path, seal_db_keys(keys)?)?;
+    fs::rename(&tmp_path, db_base.join(DB_KEY_FILE))?;
+    Ok(())
+}
+
+fn seal_db_keys(keys: &[DbKey]) -> Result<Vec<u8>> {
+    let plaintext = keys.concat();
+    SealedData::<[u8]>::seal(&plaintext, Some(DB_KEY_AAD))
+        .and_then(|sealed| sealed.into_bytes())
+        .map_err(|e| anyhow!("Cannot seal database key: {:?}", e))
+}
+
+fn unseal_db_keys(sealed: Vec<u8>) -> Result<Vec<DbKey>> {
+    let unsealed = SealedData::<[u8]>::from_bytes(sealed)
+        .and_then(|sealed| sealed.unseal())
+        .map_err(|e| anyhow!("Cannot unseal database key: {:?}", e))?;
+
+    let plaintext = unsealed.to_plaintext();
+    let key_len = DbKey::default().len();
+    ensure!(
+        !plaintext.is_empty() && plaintext.len() % key_len == 0,
+        "Invalid database key length"
+    );
+    let keys = plaintext
+        .chunks(key_len)
+        .map(|chunk| {
+            let mut key = DbKey::default();
+            key.copy_from_slice(chunk);
+            key
+        })
+        .collect();
+    Ok(keys)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use rusty_leveldb::Options;
+
+    pub fn test_reopen_with_sealed_key() {
+        let db_base = Path::new("mock_db_reopen_test");
+        if db_base.exists() {
+            fs::remove_dir_all(db_base).unwrap();
+        }
+        fs::create_dir_all(db_base).unwrap();
+        let db_path = db_base.join(DB_NAME);
+
+        let keys = load_or_create_db_keys(db_base).unwrap();
+        assert_eq!(keys.len(), 1);
+        let database =
+            ConcurrentDB::open(&db_path, Options::new_disk_db_with_keys(keys.clone())).unwrap();
+        database
+            .put(b"test_reopen_key", b"test_reopen_value")
+            .unwrap();
+        database.flush().unwrap();
+        drop(database);
+
+        // the key is unsealed again instead of being created
+        let reloaded = load_or_create_db_keys(db_base).unwrap();
+        assert_eq!(reloaded, keys);
+        let database =
+            ConcurrentDB::open(&db_path, Options::new_disk_db_with_keys(reloaded)).unwrap();
+        assert_eq!(
+            database.get(b"test_reopen_key"),
+            Some(b"test_reopen_value".to_vec())
+        );
+        drop(database);
+
+        // an existing database is not opened with a new key
+        fs::remove_file(db_base.join(DB_KEY_FILE)).unwrap();
+        assert!(load_or_create_db_keys(db_base).is_err());
+        fs::remove_dir_all(db_base).unwrap();
+    }
+
+    pub fn test_rotate_db_key() {
+        let db_base = Path::new("mock_db_rotate_test");
+        if db_base.exists() {
+            fs::remove_dir_all(db_base).unwrap();
+        }
+        fs::create_dir_all(db_base).unwrap();
+        let db_path = db_base.join(DB_NAME);
+
+        let keys = load_or_create_db_keys(db_base).unwrap();
+        let database = ConcurrentDB::open(&db_path, Options::new_disk_db_with_keys(keys)).unwrap();
+        database.put(b"test_old_key", b"1").unwrap();
+        database.flush().unwrap();
+        rotate_db_key(db_base, &database).unwrap();
+        database.put(b"test_new_key", b"2").unwrap();
+        database.flush().unwrap();
+        drop(database);
+
+        // the rotated key list is sealed, and reads both generations
+        let keys = load_db_keys(db_base).unwrap();
+        assert_eq!(keys.len(), 2);
+        let database = ConcurrentDB::open(&db_path, Options::new_disk_db_with_keys(keys)).unwrap();
+        assert_eq!(database.get(b"test_old_key"), Some(b"1".to_vec()));
+        assert_eq!(database.get(b"test_new_key"), Some(b"2".to_vec()));
+        drop(database);
+        fs::remove_dir_all(db_base).unwrap();
+    }
+}
@@ -15,20 +15,96 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use crate::backup::{self, ArchiveReader, Backups, KeySource};
 use crate::error::StorageServiceError;
+use crate::replication::{self, Replication};
+use crate::service;
+use crate::watch::Watchers;
 use anyhow::anyhow;
+use rusty_leveldb::ConcurrentDB;
 use teaclave_proto::teaclave_storage_service::*;
-use teaclave_rpc::{Request, Response, Status};
+use teaclave_rpc::{Request, Response, Status, Streaming};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
 #[derive(Clone)]
 pub(crate) struct ProxyService {
     sender: UnboundedSender<ProxyRequest>,
+    database: ConcurrentDB,
+    watchers: Watchers,
+    replication: Replication,
+    backups: Backups,
+    key_source: KeySource,
 }
 
 impl ProxyService {
-    pub(crate) fn new(sender: UnboundedSender<ProxyRequest>) -> Self {
-        Self { sender }
+    pub(crate) fn new(
+        sender: UnboundedSender<ProxyRequest>,
+        database: ConcurrentDB,
+        watchers: Watchers,
+        replication: Replication,
+        backups: Backups,
+        key_source: KeySource,
+    ) -> Self {
+        Self {
+            sender,
+            database,
+            watchers,
+            replication,
+            backups,
+            key_source,
+        }
+    }
+
+    async fn send(
+        &self,
+        request: TeaclaveStorageRequest,
+    ) -> Result<TeaclaveStorageResponse, Status> {
+        let (sender, mut receiver) = unbounded_channel();
+        self.sender
+            .send(ProxyRequest {
+                sender,
+                request: Request::new(request),
+            })
+            .map_err(|_| StorageServiceError::Service(anyhow!("send ProxyRequest error")))?;
+        match receiver.recv().await {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(e)) => Err(e.into()),
+            None => Err(teaclave_rpc::Status::internal("invalid response")),
+        }
+    }
+
+    // The first request holds the header of the archive, and the address of
+    // the storage service which made it if it is on another platform.
+    async fn open_archive(&self, header: RestoreRequest) -> Result<ArchiveReader, Status> {
+        let open = async {
+            let (base_iv, sealed_key) = backup::parse_header(&header.data)?;
+            let key = self
+                .key_source
+                .open_archive_key(&header.source_address, sealed_key)
+                .await?;
+            ArchiveReader::new(base_iv, key)
+        };
+        open.await
+            .map_err(|e| StorageServiceError::Service(e).into())
+    }
+
+    async fn restore_chunks(
+        &self,
+        stream: &mut Streaming<RestoreRequest>,
+        reader: &mut ArchiveReader,
+    ) -> Result<(), Status> {
+        while let Some(chunk) = stream.message().await? {
+            let records = reader
+                .read_chunk(&chunk.data)
+                .map_err(StorageServiceError::Service)?;
+            self.send(TeaclaveStorageRequest::RestoreRecords(records))
+                .await?;
+        }
+        if !reader.finished() {
+            return Err(StorageServiceError::Service(anyhow!("Archive is truncated")).into());
+        }
+        self.send(TeaclaveStorageRequest::FinishRestore).await?;
+        Ok(())
     }
 }
 
@@ -45,15 +121,25 @@ macro_rules! send_request {
             .map_err(|_| StorageServiceError::Service(anyhow!("send ProxyRequest error")))?;
         match receiver.recv().await {
             Some(Ok(TeaclaveStorageResponse::$response(re))) => return Ok(Response::new(re)),
+            Some(Err(e)) => return Err(e.into()),
             _ => return Err(teaclave_rpc::Status::internal("invalid response")),
         }
     }};
 }
 
+// The plain reads are served right away, also on a follower, and do not wait
+// for the requests queued in the storage thread.
+macro_rules! read_request {
+    ($service: ident,$request:expr,$fun:path) => {{
+        let response = $fun(&$service.database, $request.into_inner())?;
+        Ok(Response::new(response))
+    }};
+}
+
 #[teaclave_rpc::async_trait]
 impl TeaclaveStorage for ProxyService {
     async fn get(&self, request: Request<GetRequest>) -> Result<Response<GetResponse>, Status> {
-        send_request!(self, request, Get, Get)
+        read_request!(self, request, service::get)
     }
 
     async fn put(&self, request: Request<PutRequest>) -> Result<Response<()>, Status> {
@@ -75,11 +161,154 @@ impl TeaclaveStorage for ProxyService {
         send_request!(self, request, Dequeue, Dequeue)
     }
 
+    async fn ack(&self, request: Request<AckRequest>) -> Result<Response<()>, Status> {
+        send_request!(self, request, Ack, Empty)
+    }
+
+    async fn nack(&self, request: Request<NackRequest>) -> Result<Response<()>, Status> {
+        send_request!(self, request, Nack, Empty)
+    }
+
+    async fn peek(&self, request: Request<PeekRequest>) -> Result<Response<PeekResponse>, Status> {
+        send_request!(self, request, Peek, Peek)
+    }
+
+    async fn queue_length(
+        &self,
+        request: Request<QueueLengthRequest>,
+    ) -> Result<Response<QueueLengthResponse>, Status> {
+        send_request!(self, request, QueueLength, QueueLength)
+    }
+
     async fn get_keys_by_prefix(
         &self,
         request: Request<GetKeysByPrefixRequest>,
     ) -> Result<Response<GetKeysByPrefixResponse>, Status> {
-        send_request!(self, request, GetKeysByPrefix, GetKeysByPrefix)
+        read_request!(self, request, service::get_keys_by_prefix)
+    }
+
+    async fn batch(&self, request: Request<BatchRequest>) -> Result<Response<()>, Status> {
+        send_request!(self, request, Batch, Empty)
+    }
+
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<ScanResponse>, Status> {
+        read_request!(self, request, service::scan)
+    }
+
+    type WatchStream = crate::watch::WatchStream;
+
+    // Watchers do not go through the storage thread, which only notifies them
+    // of the committed writes.
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let prefixes = request.into_inner().prefixes;
+        Ok(Response::new(self.watchers.watch(prefixes)))
+    }
+
+    type BackupStream = backup::BackupStream;
+
+    // The storage thread takes the snapshot, and the archive is read from it
+    // while it is streamed out chunk by chunk.
+    async fn backup(
+        &self,
+        request: Request<BackupRequest>,
+    ) -> Result<Response<Self::BackupStream>, Status> {
+        let request = TeaclaveStorageRequest::Backup(request.into_inner());
+        let snapshot = match self.send(request).await? {
+            TeaclaveStorageResponse::Backup(id) => self.backups.take(id),
+            _ => None,
+        };
+        match snapshot {
+            Some(snapshot) => Ok(Response::new(backup::archive_stream(
+                self.database.clone(),
+                snapshot,
+            ))),
+            None => Err(teaclave_rpc::Status::internal("invalid response")),
+        }
+    }
+
+    type ReplicateStream = replication::ReplicationStream;
+
+    // The follower is registered before the storage thread takes the
+    // snapshot, which then starts to publish the writes to it.
+    async fn replicate(
+        &self,
+        _request: Request<ReplicateRequest>,
+    ) -> Result<Response<Self::ReplicateStream>, Status> {
+        let (id, writes) = self.replication.register();
+        let response = self.send(TeaclaveStorageRequest::Replicate(id)).await;
+        let snapshot = match response {
+            Ok(TeaclaveStorageResponse::Empty(())) => self.replication.take_snapshot(id),
+            Ok(_) => None,
+            Err(e) => {
+                self.replication.unregister(id);
+                return Err(e);
+            }
+        };
+        match snapshot {
+            Some(snapshot) => Ok(Response::new(replication::replication_stream(
+                self.database.clone(),
+                snapshot,
+                writes,
+            ))),
+            None => {
+                self.replication.unregister(id);
+                Err(teaclave_rpc::Status::internal("invalid response"))
+            }
+        }
+    }
+
+    async fn promote(&self, request: Request<PromoteRequest>) -> Result<Response<()>, Status> {
+        send_request!(self, request, Promote, Empty)
+    }
+
+    // The archive is decrypted here, and written by the storage thread chunk
+    // by chunk as it is received. A failed restore is rolled back, also when
+    // the stream of the client ends early.
+    async fn restore(
+        &self,
+        request: Request<Streaming<RestoreRequest>>,
+    ) -> Result<Response<()>, Status> {
+        let mut stream = request.into_inner();
+        let header = stream
+            .message()
+            .await?
+            .ok_or_else(|| StorageServiceError::Service(anyhow!("Empty archive")))?;
+        let mut reader = self.open_archive(header).await?;
+        self.send(TeaclaveStorageRequest::StartRestore).await?;
+
+        let restored = self.restore_chunks(&mut stream, &mut reader).await;
+        if restored.is_err() {
+            if let Err(e) = self.send(TeaclaveStorageRequest::AbortRestore).await {
+                log::warn!("Cannot abort the restore: {:?}", e);
+            }
+        }
+        restored?;
+        Ok(Response::new(()))
+    }
+
+    // Hands the key of an archive made here over to another storage enclave,
+    // see backup::unseal_archive_key.
+    async fn open_archive_key(
+        &self,
+        request: Request<OpenArchiveKeyRequest>,
+    ) -> Result<Response<OpenArchiveKeyResponse>, Status> {
+        let sealed_key = request.into_inner().sealed_key;
+        let key = backup::unseal_archive_key(&sealed_key).map_err(StorageServiceError::Service)?;
+        Ok(Response::new(OpenArchiveKeyResponse { key }))
+    }
+
+    async fn stats(
+        &self,
+        request: Request<StatsRequest>,
+    ) -> Result<Response<StatsResponse>, Status> {
+        read_request!(self, request, service::stats)
+    }
+
+    async fn rotate_key(&self, request: Request<RotateKeyRequest>) -> Result<Response<()>, Status> {
+        send_request!(self, request, RotateKey, Empty)
     }
 }
 
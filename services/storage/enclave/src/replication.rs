@@ -0,0 +1,317 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+// A follower connects to the primary, and gets a snapshot of the database
+// followed by every batch the primary writes after the snapshot. The batches
+// are the serialized LevelDB write batches, so the follower applies exactly
+// what the primary committed, sweeps and queue leases included.
+//
+// The snapshot is read from the database while it is sent, and the follower
+// writes it batch by batch. A marker key is kept from its first to its last
+// batch, and the follower refuses the reads while it is there, also when it is
+// restarted in the middle of a snapshot.
+
+use crate::error::StorageServiceError;
+use crate::proxy::ProxyRequest;
+use anyhow::anyhow;
+use rusty_leveldb::{ConcurrentDB, LdbIterator, Snapshot, WriteBatch};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use teaclave_proto::teaclave_storage_service::{
+    ReplicateRequest, ReplicationEntry, TeaclaveStorageClient, TeaclaveStorageRequest,
+};
+use teaclave_rpc::transport::channel::Endpoint;
+use teaclave_rpc::{Request, Status};
+use teaclave_service_enclave_utils::bail;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+// Number of batches a follower can fall behind before it is dropped, it
+// starts over from a new snapshot when it reconnects.
+const REPLICATION_CHANNEL_SIZE: usize = 4096;
+// Number of snapshot batches read ahead of the follower
+const SNAPSHOT_CHANNEL_SIZE: usize = 4;
+const SNAPSHOT_BATCH_SIZE: usize = 1024 * 1024;
+const SNAPSHOT_MARKER_KEY: &[u8] = b"replication_snapshot_in_progress";
+// Sequence number and count of a serialized write batch
+const BATCH_HEADER_SIZE: usize = 12;
+const RECONNECT_INTERVAL_SECS: u64 = 5;
+
+pub(crate) type ReplicationStream =
+    Pin<Box<dyn Stream<Item = Result<ReplicationEntry, Status>> + Send>>;
+
+type EntrySender = mpsc::Sender<Result<ReplicationEntry, Status>>;
+
+#[derive(Default)]
+struct Followers {
+    next_id: u64,
+    // Registered by the proxy, waiting for the snapshot
+    pending: Vec<(u64, EntrySender)>,
+    live: Vec<EntrySender>,
+    // Taken by the proxy once the storage thread has started the follower
+    snapshots: HashMap<u64, Snapshot>,
+}
+
+// The followers are registered by the proxy, and start to get the writes of
+// the storage service once it has taken their snapshot.
+#[derive(Clone, Default)]
+pub(crate) struct Replication {
+    followers: Arc<Mutex<Followers>>,
+}
+
+impl Replication {
+    pub(crate) fn register(&self) -> (u64, ReceiverStream<Result<ReplicationEntry, Status>>) {
+        let (sender, receiver) = mpsc::channel(REPLICATION_CHANNEL_SIZE);
+        let mut followers = self.followers.lock().unwrap();
+        let id = followers.next_id;
+        followers.next_id += 1;
+        followers.pending.push((id, sender));
+        (id, ReceiverStream::new(receiver))
+    }
+
+    pub(crate) fn unregister(&self, id: u64) {
+        let mut followers = self.followers.lock().unwrap();
+        followers
+            .pending
+            .retain(|(pending_id, _)| *pending_id != id);
+    }
+
+    // Called in the storage thread right after taking the snapshot, so that
+    // the follower misses no write. Returns false if the follower is gone.
+    pub(crate) fn start(&self, id: u64, snapshot: Snapshot) -> bool {
+        let mut followers = self.followers.lock().unwrap();
+        let index = followers
+            .pending
+            .iter()
+            .position(|(pending_id, _)| *pending_id == id);
+        match index {
+            Some(index) => {
+                let (_, sender) = followers.pending.swap_remove(index);
+                followers.live.push(sender);
+                followers.snapshots.insert(id, snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn take_snapshot(&self, id: u64) -> Option<Snapshot> {
+        self.followers.lock().unwrap().snapshots.remove(&id)
+    }
+
+    pub(crate) fn publish(&self, batches: Vec<Vec<u8>>) {
+        if batches.is_empty() {
+            return;
+        }
+        let mut followers = self.followers.lock().unwrap();
+        followers.live.retain(|sender| {
+            !sender.is_closed()
+                && batches
+                    .iter()
+                    .all(|batch| send(sender, ReplicationEntry::new(batch.clone(), false)))
+        });
+    }
+}
+
+// Returns false if the follower cannot keep up and gets an error as its last
+// message.
+fn send(sender: &EntrySender, entry: ReplicationEntry) -> bool {
+    if sender.capacity() > 1 {
+        return sender.try_send(Ok(entry)).is_ok();
+    }
+    log::warn!("Dropping a follower which falls behind");
+    let status = Status::resource_exhausted("follower falls behind");
+    let _ = sender.try_send(Err(status));
+    false
+}
+
+// The snapshot entries go first, then the writes committed after it. The
+// snapshot is read in a thread of its own, which waits while the follower is
+// behind, and stops once it is gone.
+pub(crate) fn replication_stream(
+    db: ConcurrentDB,
+    snapshot: Snapshot,
+    writes: ReceiverStream<Result<ReplicationEntry, Status>>,
+) -> ReplicationStream {
+    let (sender, receiver) = mpsc::channel(SNAPSHOT_CHANNEL_SIZE);
+    thread::spawn(move || {
+        let sent = send_snapshot(&db, snapshot, |entry| {
+            sender.blocking_send(Ok(entry)).is_ok()
+        });
+        if let Err(e) = sent {
+            log::warn!("Cannot send a snapshot to a follower: {:?}", e);
+            let _ = sender.blocking_send(Err(e.into()));
+        }
+    });
+    Box::pin(ReceiverStream::new(receiver).chain(writes))
+}
+
+/// Send a consistent snapshot of the database as write batches, until send
+/// returns false. The first one resets the follower, the last one completes
+/// the snapshot, and there is at least one.
+pub(crate) fn send_snapshot(
+    db: &ConcurrentDB,
+    snapshot: Snapshot,
+    mut send: impl FnMut(ReplicationEntry) -> bool,
+) -> Result<(), StorageServiceError> {
+    let mut it = db.new_iter_at(snapshot)?;
+    it.seek_to_first();
+
+    let mut reset = true;
+    let mut batch = WriteBatch::new();
+    let mut size = 0;
+    let mut k = Vec::new();
+    let mut v = Vec::new();
+    while it.valid() && it.current(&mut k, &mut v) {
+        if size > 0 && size + k.len() + v.len() > SNAPSHOT_BATCH_SIZE {
+            if !send(ReplicationEntry::new(batch.encode(0), reset)) {
+                return Ok(());
+            }
+            reset = false;
+            batch = WriteBatch::new();
+            size = 0;
+        }
+        batch.put(&k, &v);
+        size += k.len() + v.len();
+        it.advance();
+    }
+    send(ReplicationEntry::new(batch.encode(0), reset).end_of_snapshot());
+
+    Ok(())
+}
+
+/// Apply an entry of the primary to the follower database. A snapshot
+/// deletes the old keys and is written batch by batch, with the marker set
+/// until its last batch, so that the reads never see a partial snapshot.
+pub(crate) fn apply(db: &ConcurrentDB, entry: ReplicationEntry) -> Result<(), StorageServiceError> {
+    if entry.batch.len() < BATCH_HEADER_SIZE {
+        bail!(StorageServiceError::Service(anyhow!(
+            "invalid replication entry"
+        )));
+    }
+    let mut batch = WriteBatch::new();
+    batch.set_contents(&entry.batch);
+
+    if entry.reset {
+        if snapshot_in_progress(db) {
+            log::warn!("Dropping an incomplete snapshot of the primary");
+        }
+        let mut marker = WriteBatch::new();
+        marker.put(SNAPSHOT_MARKER_KEY, b"");
+        db.write(marker, false)?;
+        clear(db, SNAPSHOT_MARKER_KEY)?;
+    } else if entry.end_of_snapshot && !snapshot_in_progress(db) {
+        bail!(StorageServiceError::Service(anyhow!(
+            "replication entry out of order"
+        )));
+    }
+    if entry.end_of_snapshot {
+        batch.delete(SNAPSHOT_MARKER_KEY);
+    }
+    db.write(batch, false)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub(crate) fn snapshot_in_progress(db: &ConcurrentDB) -> bool {
+    db.get(SNAPSHOT_MARKER_KEY).is_some()
+}
+
+// The reads of a follower fail while it receives a snapshot.
+pub(crate) fn check_snapshot_complete(db: &ConcurrentDB) -> Result<(), StorageServiceError> {
+    if snapshot_in_progress(db) {
+        bail!(StorageServiceError::SnapshotInProgress);
+    }
+    Ok(())
+}
+
+// Delete all keys of the database but the marker, in bounded batches
+pub(crate) fn clear(db: &ConcurrentDB, marker: &[u8]) -> Result<(), StorageServiceError> {
+    let mut it = db.new_iter()?;
+    it.seek_to_first();
+
+    let mut batch = WriteBatch::new();
+    let mut size = 0;
+    let mut k = Vec::new();
+    let mut v = Vec::new();
+    while it.valid() && it.current(&mut k, &mut v) {
+        if k != marker {
+            if size > 0 && size + k.len() > SNAPSHOT_BATCH_SIZE {
+                db.write(batch, false)?;
+                batch = WriteBatch::new();
+                size = 0;
+            }
+            batch.delete(&k);
+            size += k.len();
+        }
+        it.advance();
+    }
+    db.write(batch, false)?;
+    Ok(())
+}
+
+/// Replicate the writes of the primary to the storage service of the
+/// follower, which restarts from a new snapshot whenever the connection
+/// breaks. Stops once the follower fails to apply an entry, e.g. after it is
+/// promoted.
+pub(crate) async fn follow(primary: Endpoint, service: mpsc::UnboundedSender<ProxyRequest>) {
+    loop {
+        match replicate_from(&primary, &service).await {
+            Ok(()) => return,
+            Err(e) => log::warn!("Replication from the primary is broken: {:?}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(RECONNECT_INTERVAL_SECS)).await;
+    }
+}
+
+// Returns Ok if the storage service rejects an entry, and Err if the primary
+// cannot be reached.
+async fn replicate_from(
+    primary: &Endpoint,
+    service: &mpsc::UnboundedSender<ProxyRequest>,
+) -> anyhow::Result<()> {
+    let channel = primary.connect().await?;
+    let mut client = TeaclaveStorageClient::new_with_builtin_config(channel);
+    let mut stream = client
+        .replicate(ReplicateRequest::default())
+        .await?
+        .into_inner();
+    log::info!("Replicating from the primary");
+
+    while let Some(entry) = stream.message().await? {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        service
+            .send(ProxyRequest {
+                sender,
+                request: Request::new(TeaclaveStorageRequest::Replicated(entry)),
+            })
+            .map_err(|_| anyhow!("storage service is gone"))?;
+        match receiver.recv().await {
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                log::info!("Stopped replicating from the primary: {:?}", e);
+                return Ok(());
+            }
+            None => return Ok(()),
+        }
+    }
+    Err(anyhow!("replication stream is closed"))
+}
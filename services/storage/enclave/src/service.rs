@@ -15,36 +15,83 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use crate::backup::{self, Backups};
 use crate::error::StorageServiceError;
+use crate::expiry;
+use crate::persist;
 use crate::proxy::ProxyRequest;
+use crate::replication::{self, Replication};
+use crate::watch::Watchers;
 use anyhow::anyhow;
 use rusty_leveldb::LdbIterator;
-use rusty_leveldb::DB;
-use std::cell::RefCell;
+use rusty_leveldb::{ConcurrentDB, WriteBatch};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[allow(unused_imports)]
+use std::untrusted::time::SystemTimeEx;
 use teaclave_proto::teaclave_storage_service::*;
 use teaclave_service_enclave_utils::bail;
 use tokio::sync::mpsc::UnboundedReceiver;
 
+// Upper bound of the items in one scan response, also used if the request
+// does not set a limit.
+const SCAN_MAX_LIMIT: u32 = 1000;
+const SWEEP_INTERVAL_SECS: u64 = 10;
+// Upper bound of the expired keys deleted in one sweep
+const SWEEP_LIMIT: usize = 1000;
+
 pub(crate) struct TeaclaveStorageService {
-    // Current LevelDB implementation is not concurrent, so we need to wrap the
-    // DB with RefCell. This service is running in a single thread, it's safe to
-    // use RefCell.
-    database: RefCell<DB>,
+    // The writes go through this service one at a time, while the proxy
+    // serves the plain reads from its own handle of the database.
+    database: ConcurrentDB,
     receiver: UnboundedReceiver<ProxyRequest>,
+    watchers: Watchers,
+    replication: Replication,
+    backups: Backups,
+    // A follower only applies the writes of the primary, until it is promoted.
+    follower: Cell<bool>,
+    // Where the sealed database keys are kept, if they can be rotated
+    key_dir: Option<PathBuf>,
 }
 
 impl TeaclaveStorageService {
-    pub(crate) fn new(database: RefCell<DB>, receiver: UnboundedReceiver<ProxyRequest>) -> Self {
-        Self { database, receiver }
+    pub(crate) fn new(
+        database: ConcurrentDB,
+        receiver: UnboundedReceiver<ProxyRequest>,
+        watchers: Watchers,
+        replication: Replication,
+        backups: Backups,
+        follower: bool,
+        key_dir: Option<PathBuf>,
+    ) -> Self {
+        database.record_writes();
+        Self {
+            database,
+            receiver,
+            watchers,
+            replication,
+            backups,
+            follower: Cell::new(follower),
+            key_dir,
+        }
     }
 }
 
 // queue-key-head: u32; include element
 // queue-key-tail: u32; not include element; if head == tail, queue is empty
 // queue-key-index: Vec<u8>; elements
+// queue_lease-key-receipt: u64 deadline + Vec<u8>; leased elements, the
+// receipt is the index the element had in the queue
+// queue_lease_index-key-deadline-receipt: empty; the deadline is u64 big
+// endian, so that the expired leases are found without reading the others,
+// like the expiry index
+// Elements enqueued with a TTL are deleted by the sweeper once they expire,
+// the holes are skipped when the queue is dequeued.
 // Todo: what if there are errors when doing get_tail and get_head
 struct DBQueue<'a> {
-    database: &'a mut DB,
+    database: &'a ConcurrentDB,
     key: &'a [u8],
 }
 
@@ -68,6 +115,30 @@ impl<'a> DBQueue<'a> {
         element_key.extend_from_slice(&index.to_le_bytes());
         element_key
     }
+    fn get_lease_prefix(&self) -> Vec<u8> {
+        let mut lease_prefix = b"queue_lease-".to_vec();
+        lease_prefix.extend_from_slice(self.key);
+        lease_prefix.extend_from_slice(b"-");
+        lease_prefix
+    }
+    fn get_lease_key(&self, receipt: &[u8]) -> Vec<u8> {
+        let mut lease_key = self.get_lease_prefix();
+        lease_key.extend_from_slice(receipt);
+        lease_key
+    }
+    fn get_lease_index_prefix(&self) -> Vec<u8> {
+        let mut index_prefix = b"queue_lease_index-".to_vec();
+        index_prefix.extend_from_slice(self.key);
+        index_prefix.extend_from_slice(b"-");
+        index_prefix
+    }
+    fn get_lease_index_key(&self, deadline: u64, receipt: &[u8]) -> Vec<u8> {
+        let mut index_key = self.get_lease_index_prefix();
+        index_key.extend_from_slice(&deadline.to_be_bytes());
+        index_key.extend_from_slice(b"-");
+        index_key.extend_from_slice(receipt);
+        index_key
+    }
 
     fn get_head(&mut self) -> u32 {
         let head_key = self.get_head_key();
@@ -92,53 +163,214 @@ impl<'a> DBQueue<'a> {
         Some(u32::from_le_bytes(bytes))
     }
 
-    pub fn open(database: &'a mut DB, key: &'a [u8]) -> Self {
+    pub fn open(database: &'a ConcurrentDB, key: &'a [u8]) -> Self {
         DBQueue { database, key }
     }
 
-    pub fn enqueue(&mut self, value: &[u8]) -> Result<(), StorageServiceError> {
+    // Add the element to a write batch instead of the database. The tail is
+    // passed in and returned, so several elements can go into one batch.
+    fn enqueue_to_batch(
+        &mut self,
+        batch: &mut WriteBatch,
+        tail_index: u32,
+        value: &[u8],
+        deadline: Option<u64>,
+    ) -> u32 {
+        let element_key = self.get_element_key(tail_index);
+        batch.put(&element_key, value);
+        if let Some(deadline) = deadline {
+            expiry::put_deadline_to_batch(batch, &element_key, deadline);
+        }
+        let tail_index = tail_index.wrapping_add(1);
+        batch.put(&self.get_tail_key(), &tail_index.to_le_bytes());
+        tail_index
+    }
+
+    pub fn enqueue(
+        &mut self,
+        value: &[u8],
+        deadline: Option<u64>,
+    ) -> Result<(), StorageServiceError> {
+        let mut batch = WriteBatch::new();
         let tail_index = self.get_tail();
-        // put element
-        self.database
-            .put(&self.get_element_key(tail_index), value)?;
+        self.enqueue_to_batch(&mut batch, tail_index, value, deadline);
+        self.database.write(batch, false)?;
+        self.database.flush()?;
+        Ok(())
+    }
 
-        // update tail
-        let tail_index = tail_index.wrapping_add(1);
+    // Return the first element from the head and its index, skipping the
+    // expired ones.
+    fn first_element(&mut self) -> Option<(u32, Vec<u8>)> {
+        let tail_index = self.get_tail();
+        let mut index = self.get_head();
+        while index != tail_index {
+            if let Some(value) = self.database.get(&self.get_element_key(index)) {
+                return Some((index, value));
+            }
+            index = index.wrapping_add(1);
+        }
+        None
+    }
+
+    pub fn dequeue(&mut self) -> Result<Vec<u8>, StorageServiceError> {
+        let (head_index, result) = match self.first_element() {
+            Some(element) => element,
+            None => bail!(StorageServiceError::Service(anyhow!("queue is empty"))),
+        };
+        let element_key = self.get_element_key(head_index);
+
+        // update head
+        let head_index = head_index.wrapping_add(1);
         self.database
-            .put(&self.get_tail_key(), &tail_index.to_le_bytes())?;
+            .put(&self.get_head_key(), &head_index.to_le_bytes())?;
+        self.database.delete(&element_key)?;
+        Ok(result)
+    }
+
+    // Take the head element like dequeue, but keep it as a lease until the
+    // deadline. Returns the element and the receipt to ack or nack it.
+    pub fn lease(&mut self, deadline: u64) -> Result<(Vec<u8>, Vec<u8>), StorageServiceError> {
+        let (head_index, value) = match self.first_element() {
+            Some(element) => element,
+            None => bail!(StorageServiceError::Service(anyhow!("queue is empty"))),
+        };
+        let element_key = self.get_element_key(head_index);
+
+        let receipt = head_index.to_le_bytes().to_vec();
+        let mut lease = deadline.to_le_bytes().to_vec();
+        lease.extend_from_slice(&value);
+
+        let mut batch = WriteBatch::new();
+        batch.put(
+            &self.get_head_key(),
+            &head_index.wrapping_add(1).to_le_bytes(),
+        );
+        batch.delete(&element_key);
+        batch.put(&self.get_lease_key(&receipt), &lease);
+        batch.put(&self.get_lease_index_key(deadline, &receipt), b"");
+        self.database.write(batch, false)?;
+        self.database.flush()?;
+        Ok((value, receipt))
+    }
+
+    // Return the leased element and its deadline.
+    fn get_lease(&mut self, receipt: &[u8]) -> Result<(u64, Vec<u8>), StorageServiceError> {
+        // the lease has expired, and the element is back in the queue
+        let lease = match self.database.get(&self.get_lease_key(receipt)) {
+            Some(lease) => lease,
+            None => bail!(StorageServiceError::None),
+        };
+        match split_lease(&lease) {
+            Some((deadline, value)) => Ok((deadline, value.to_vec())),
+            None => bail!(StorageServiceError::Service(anyhow!("invalid lease"))),
+        }
+    }
+
+    fn delete_lease_to_batch(&mut self, batch: &mut WriteBatch, deadline: u64, receipt: &[u8]) {
+        batch.delete(&self.get_lease_key(receipt));
+        batch.delete(&self.get_lease_index_key(deadline, receipt));
+    }
 
+    pub fn ack(&mut self, receipt: &[u8]) -> Result<(), StorageServiceError> {
+        let (deadline, _) = self.get_lease(receipt)?;
+        let mut batch = WriteBatch::new();
+        self.delete_lease_to_batch(&mut batch, deadline, receipt);
+        self.database.write(batch, false)?;
         self.database.flush()?;
         Ok(())
     }
 
-    pub fn dequeue(&mut self) -> Result<Vec<u8>, StorageServiceError> {
-        let head_index = self.get_head();
+    // Put the leased element back at the tail of the queue, and return it.
+    pub fn nack(&mut self, receipt: &[u8]) -> Result<Vec<u8>, StorageServiceError> {
+        let (deadline, value) = self.get_lease(receipt)?;
+        let mut batch = WriteBatch::new();
+        self.delete_lease_to_batch(&mut batch, deadline, receipt);
         let tail_index = self.get_tail();
-        // check whether the queue is empty
-        if head_index == tail_index {
-            bail!(StorageServiceError::Service(anyhow!(
-                "head_index == tail_index"
-            )))
-        } else {
-            let element_key = self.get_element_key(head_index);
-            let result = match self.database.get(&element_key) {
-                Some(value) => value,
-                None => bail!(StorageServiceError::Service(anyhow!(
-                    "cannot get element_key"
-                ))),
-            };
+        self.enqueue_to_batch(&mut batch, tail_index, &value, None);
+        self.database.write(batch, false)?;
+        self.database.flush()?;
+        Ok(value)
+    }
+
+    // Put the elements whose lease has expired back at the tail of the queue.
+    // Only the index entries up to the first lease which has not expired are
+    // read.
+    pub fn release_expired(&mut self, now: u64) -> Result<(), StorageServiceError> {
+        let index_prefix = self.get_lease_index_prefix();
+        let mut it = self.database.new_iter()?;
+        it.seek(&index_prefix);
+
+        let mut expired = Vec::new();
+        let mut index_key = Vec::new();
+        let mut value = Vec::new();
+        while it.valid()
+            && it.current(&mut index_key, &mut value)
+            && index_key.starts_with(&index_prefix)
+        {
+            match split_lease_index_key(&index_key[index_prefix.len()..]) {
+                Some((deadline, _)) if deadline > now => break,
+                Some((deadline, receipt)) => expired.push((deadline, receipt.to_vec())),
+                None => log::warn!("Invalid lease index entry"),
+            }
+            it.advance();
+        }
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::new();
+        let mut tail_index = self.get_tail();
+        for (deadline, receipt) in expired.iter() {
+            if let Some(lease) = self.database.get(&self.get_lease_key(receipt)) {
+                if let Some((_, value)) = split_lease(&lease) {
+                    tail_index = self.enqueue_to_batch(&mut batch, tail_index, value, None);
+                }
+            }
+            self.delete_lease_to_batch(&mut batch, *deadline, receipt);
+        }
+        log::debug!("Released {} expired leases", expired.len());
+        self.database.write(batch, false)?;
+        self.database.flush()?;
+        Ok(())
+    }
+
+    // Only used to report the queue length, so the leases are counted by
+    // reading them.
+    fn leases(&mut self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageServiceError> {
+        let lease_prefix = self.get_lease_prefix();
+        let mut it = self.database.new_iter()?;
+        it.seek(&lease_prefix);
+
+        let mut leases = Vec::new();
+        let mut key = Vec::new();
+        let mut value = Vec::new();
+        while it.valid() && it.current(&mut key, &mut value) && key.starts_with(&lease_prefix) {
+            leases.push((key.clone(), value.clone()));
+            it.advance();
+        }
+        Ok(leases)
+    }
+
+    pub fn leased(&mut self) -> Result<u32, StorageServiceError> {
+        Ok(self.leases()?.len() as u32)
+    }
 
-            // update head
-            let head_index = head_index.wrapping_add(1);
-            self.database
-                .put(&self.get_head_key(), &head_index.to_le_bytes())?;
-            self.database.delete(&element_key)?;
-            self.database.compact_range(b"queue", b"queuf")?;
-            Ok(result)
+    // Return up to limit elements from the head, without taking them.
+    pub fn peek(&mut self, limit: u32) -> Vec<Vec<u8>> {
+        let tail_index = self.get_tail();
+        let mut index = self.get_head();
+        let mut values = Vec::new();
+        while index != tail_index && values.len() < limit as usize {
+            if let Some(value) = self.database.get(&self.get_element_key(index)) {
+                values.push(value);
+            }
+            index = index.wrapping_add(1);
         }
+        values
     }
 
-    #[allow(unused)]
+    // Expired elements are counted until the head passes them.
     pub fn len(&mut self) -> u32 {
         let head_index = self.get_head();
         let tail_index = self.get_tail();
@@ -151,25 +383,112 @@ impl<'a> DBQueue<'a> {
     }
 }
 
+fn split_lease(lease: &[u8]) -> Option<(u64, &[u8])> {
+    if lease.len() < 8 {
+        return None;
+    }
+    let (deadline, value) = lease.split_at(8);
+    let mut bytes: [u8; 8] = [0; 8];
+    bytes.copy_from_slice(deadline);
+    Some((u64::from_le_bytes(bytes), value))
+}
+
+// The part of a lease index key after the prefix of the queue.
+fn split_lease_index_key(index_key: &[u8]) -> Option<(u64, &[u8])> {
+    if index_key.len() < 9 {
+        return None;
+    }
+    let (deadline, receipt) = index_key.split_at(8);
+    let mut bytes: [u8; 8] = [0; 8];
+    bytes.copy_from_slice(deadline);
+    Some((u64::from_be_bytes(bytes), &receipt[1..]))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
 impl TeaclaveStorageService {
     pub(crate) fn start(&mut self) {
-        while let Some(request) = self.receiver.blocking_recv() {
-            let database_request = request.request;
-            let sender = request.sender;
-            let response = self.dispatch(database_request);
-
-            match sender.send(response) {
-                Ok(_) => (),
-                Err(e) => error!("mpsc send error: {}", e),
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("cannot build the storage runtime");
+        rt.block_on(self.serve());
+    }
+
+    // Requests are served one at a time, and expired keys are swept in
+    // between. Memtable flushes and compactions run in the background thread
+    // of the database.
+    async fn serve(&mut self) {
+        let mut sweep_interval = tokio::time::interval(Duration::from_secs(SWEEP_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                request = self.receiver.recv() => match request {
+                    Some(request) => self.handle(request),
+                    None => break,
+                },
+                // The follower gets the sweeps of the primary.
+                _ = sweep_interval.tick(), if !self.follower.get() => {
+                    if let Err(e) = self.sweep(now_secs()) {
+                        error!("Failed to sweep expired keys: {:?}", e);
+                    }
+                    self.publish();
+                }
             }
         }
     }
 
+    fn handle(&self, request: ProxyRequest) {
+        let database_request = request.request;
+        let sender = request.sender;
+        let response = self.dispatch(database_request);
+        self.publish();
+
+        match sender.send(response) {
+            Ok(_) => (),
+            Err(e) => error!("mpsc send error: {}", e),
+        }
+    }
+
+    // Send the batches written so far to the followers, also after a failed
+    // request, which may have written some of them.
+    fn publish(&self) {
+        let batches = self.database.take_recorded_writes();
+        self.replication.publish(batches);
+    }
+
+    fn sweep(&self, now: u64) -> std::result::Result<(), StorageServiceError> {
+        let expired = expiry::sweep(&self.database, now, SWEEP_LIMIT)?;
+        if expired.is_empty() {
+            return Ok(());
+        }
+        log::debug!("Swept {} expired keys", expired.len());
+        // The queue elements are not watched.
+        let events: Vec<_> = expired
+            .into_iter()
+            .filter(|key| !key.starts_with(b"queue-"))
+            .map(WatchEvent::delete)
+            .collect();
+        self.watchers.notify(&events);
+        Ok(())
+    }
+
     fn dispatch(
         &self,
         request: teaclave_rpc::Request<TeaclaveStorageRequest>,
     ) -> std::result::Result<TeaclaveStorageResponse, StorageServiceError> {
-        match request.into_inner() {
+        let request = request.into_inner();
+        if self.follower.get() && !allowed_on_follower(&request) {
+            bail!(StorageServiceError::Follower);
+        }
+        if !allowed_in_restore(&request) {
+            backup::check_restore_complete(&self.database)?;
+        }
+        match request {
             TeaclaveStorageRequest::Get(r) => self.get(r).map(TeaclaveStorageResponse::Get),
             TeaclaveStorageRequest::Put(r) => self.put(r).map(TeaclaveStorageResponse::Empty),
             TeaclaveStorageRequest::Delete(r) => self.delete(r).map(TeaclaveStorageResponse::Empty),
@@ -179,52 +498,258 @@ impl TeaclaveStorageService {
             TeaclaveStorageRequest::Dequeue(r) => {
                 self.dequeue(r).map(TeaclaveStorageResponse::Dequeue)
             }
+            TeaclaveStorageRequest::Ack(r) => self.ack(r).map(TeaclaveStorageResponse::Empty),
+            TeaclaveStorageRequest::Nack(r) => self.nack(r).map(TeaclaveStorageResponse::Empty),
+            TeaclaveStorageRequest::Peek(r) => self.peek(r).map(TeaclaveStorageResponse::Peek),
+            TeaclaveStorageRequest::QueueLength(r) => self
+                .queue_length(r)
+                .map(TeaclaveStorageResponse::QueueLength),
             TeaclaveStorageRequest::GetKeysByPrefix(r) => self
                 .get_keys_by_prefix(r)
                 .map(TeaclaveStorageResponse::GetKeysByPrefix),
+            TeaclaveStorageRequest::Batch(r) => self.batch(r).map(TeaclaveStorageResponse::Empty),
+            TeaclaveStorageRequest::Scan(r) => self.scan(r).map(TeaclaveStorageResponse::Scan),
+            TeaclaveStorageRequest::Backup(r) => {
+                self.backup(r).map(TeaclaveStorageResponse::Backup)
+            }
+            TeaclaveStorageRequest::StartRestore => {
+                self.start_restore().map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::RestoreRecords(r) => {
+                self.restore_records(r).map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::FinishRestore => {
+                self.finish_restore().map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::AbortRestore => {
+                self.abort_restore().map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::Replicate(id) => {
+                self.replicate(id).map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::Replicated(r) => {
+                self.replicated(r).map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::Promote(r) => {
+                self.promote(r).map(TeaclaveStorageResponse::Empty)
+            }
+            TeaclaveStorageRequest::RotateKey(r) => {
+                self.rotate_key(r).map(TeaclaveStorageResponse::Empty)
+            }
         }
     }
 }
 
+// A follower serves the reads which do not write to the database, e.g. unlike
+// peek, which releases the expired leases. Its own keys can still be rotated.
+fn allowed_on_follower(request: &TeaclaveStorageRequest) -> bool {
+    matches!(
+        request,
+        TeaclaveStorageRequest::Get(_)
+            | TeaclaveStorageRequest::GetKeysByPrefix(_)
+            | TeaclaveStorageRequest::Scan(_)
+            | TeaclaveStorageRequest::Backup(_)
+            | TeaclaveStorageRequest::Replicated(_)
+            | TeaclaveStorageRequest::Promote(_)
+            | TeaclaveStorageRequest::RotateKey(_)
+    )
+}
+
+// While an archive is restored, only its steps are served, and the writes of
+// the primary, which may be restoring one.
+fn allowed_in_restore(request: &TeaclaveStorageRequest) -> bool {
+    matches!(
+        request,
+        TeaclaveStorageRequest::StartRestore
+            | TeaclaveStorageRequest::RestoreRecords(_)
+            | TeaclaveStorageRequest::FinishRestore
+            | TeaclaveStorageRequest::AbortRestore
+            | TeaclaveStorageRequest::Replicated(_)
+    )
+}
+
+// The reads fail while a follower receives a snapshot, or while an archive is
+// restored.
+fn check_readable(database: &ConcurrentDB) -> std::result::Result<(), StorageServiceError> {
+    replication::check_snapshot_complete(database)?;
+    backup::check_restore_complete(database)
+}
+
+// The plain reads are also served by the proxy, without waiting for the
+// writes in the storage thread.
+pub(crate) fn get(
+    database: &ConcurrentDB,
+    request: GetRequest,
+) -> std::result::Result<GetResponse, StorageServiceError> {
+    get_at(database, request, now_secs())
+}
+
+// Expired keys are not served, even before the sweeper deletes them.
+fn get_at(
+    database: &ConcurrentDB,
+    request: GetRequest,
+    now: u64,
+) -> std::result::Result<GetResponse, StorageServiceError> {
+    check_readable(database)?;
+    if expiry::is_expired(database, &request.key, now) {
+        bail!(StorageServiceError::None);
+    }
+    match database.get(&request.key) {
+        Some(value) => Ok(GetResponse { value }),
+        None => bail!(StorageServiceError::None),
+    }
+}
+
+pub(crate) fn get_keys_by_prefix(
+    database: &ConcurrentDB,
+    request: GetKeysByPrefixRequest,
+) -> std::result::Result<GetKeysByPrefixResponse, StorageServiceError> {
+    check_readable(database)?;
+    let prefix = request.prefix;
+    let mut it = database.new_iter().map_err(StorageServiceError::Database)?;
+
+    let mut first_prefix = prefix.clone();
+    first_prefix.push(b'-');
+    let mut last_prefix = prefix;
+    last_prefix.push(b'.');
+
+    it.seek(&first_prefix[..]);
+    if !it.valid() {
+        return Ok(GetKeysByPrefixResponse::default());
+    }
+    let mut key = Vec::new();
+    let mut value = Vec::new();
+    let mut keys = Vec::new();
+    if !it.current(&mut key, &mut value) {
+        return Ok(GetKeysByPrefixResponse::default());
+    }
+    keys.push(key);
+
+    while let Some((k, _)) = it.next() {
+        if k >= last_prefix {
+            break;
+        }
+        keys.push(k);
+    }
+
+    Ok(GetKeysByPrefixResponse { keys })
+}
+
+pub(crate) fn scan(
+    database: &ConcurrentDB,
+    request: ScanRequest,
+) -> std::result::Result<ScanResponse, StorageServiceError> {
+    check_readable(database)?;
+    let limit = match request.limit {
+        0 => SCAN_MAX_LIMIT,
+        limit => limit.min(SCAN_MAX_LIMIT),
+    } as usize;
+
+    // The continuation token is the last key of the previous page, and
+    // the scan goes on right after it.
+    let mut start_key = request.start_key.max(request.prefix.clone());
+    if !request.continuation_token.is_empty() {
+        let mut next_key = request.continuation_token;
+        next_key.push(0);
+        start_key = start_key.max(next_key);
+    }
+    let end_key = request.end_key;
+    let in_range = |key: &[u8]| {
+        key.starts_with(&request.prefix) && (end_key.is_empty() || key < end_key.as_slice())
+    };
+
+    let mut it = database.new_iter().map_err(StorageServiceError::Database)?;
+    it.seek(&start_key);
+
+    let mut response = ScanResponse::default();
+    let mut key = Vec::new();
+    let mut value = Vec::new();
+    while it.valid() && it.current(&mut key, &mut value) && in_range(&key) {
+        if response.items.len() == limit {
+            response.continuation_token = response.items[limit - 1].key.clone();
+            break;
+        }
+        response.items.push(KeyValue {
+            key: key.clone(),
+            value: value.clone(),
+        });
+        it.advance();
+    }
+
+    Ok(response)
+}
+
+pub(crate) fn stats(
+    database: &ConcurrentDB,
+    request: StatsRequest,
+) -> std::result::Result<StatsResponse, StorageServiceError> {
+    let property = |name: &str| database.get_property(name).unwrap_or_default();
+    let files_at_level = (0..rusty_leveldb::NUM_LEVELS)
+        .map(|level| property(&format!("leveldb.num-files-at-level{}", level)))
+        .map(|files| files.parse().unwrap_or_default())
+        .collect();
+    let ranges: Vec<_> = request
+        .ranges
+        .iter()
+        .map(|range| (range.start.as_slice(), range.limit.as_slice()))
+        .collect();
+    let approximate_sizes = database
+        .get_approximate_sizes(&ranges)
+        .into_iter()
+        .map(|size| size as u64)
+        .collect();
+
+    Ok(StatsResponse {
+        stats: property("leveldb.stats"),
+        files_at_level,
+        sstables: property("leveldb.sstables"),
+        approximate_sizes,
+    })
+}
+
 impl TeaclaveStorageService {
     fn get(&self, request: GetRequest) -> std::result::Result<GetResponse, StorageServiceError> {
-        match self.database.borrow_mut().get(&request.key) {
-            Some(value) => Ok(GetResponse { value }),
-            None => bail!(StorageServiceError::None),
-        }
+        get(&self.database, request)
     }
 
     fn put(&self, request: PutRequest) -> std::result::Result<(), StorageServiceError> {
-        self.database
-            .borrow_mut()
-            .put(&request.key, &request.value)
-            .map_err(StorageServiceError::Database)?;
+        let db = &self.database;
+        let mut batch = WriteBatch::new();
+        batch.put(&request.key, &request.value);
+        let deadline = expiry::deadline(request.ttl_secs, now_secs());
+        expiry::set_deadline_to_batch(db, &mut batch, &request.key, deadline);
 
-        self.database
-            .borrow_mut()
-            .flush()
+        db.write(batch, false)
             .map_err(StorageServiceError::Database)?;
+        db.flush().map_err(StorageServiceError::Database)?;
+        self.watchers
+            .notify(&[WatchEvent::put(request.key, request.value)]);
         Ok(())
     }
 
     fn delete(&self, request: DeleteRequest) -> std::result::Result<(), StorageServiceError> {
-        self.database
-            .borrow_mut()
-            .delete(&request.key)
-            .map_err(StorageServiceError::Database)?;
+        let db = &self.database;
+        let mut batch = WriteBatch::new();
+        batch.delete(&request.key);
+        expiry::clear_deadline_to_batch(db, &mut batch, &request.key);
 
-        self.database
-            .borrow_mut()
-            .flush()
+        db.write(batch, false)
             .map_err(StorageServiceError::Database)?;
+        db.flush().map_err(StorageServiceError::Database)?;
+        self.watchers.notify(&[WatchEvent::delete(request.key)]);
         Ok(())
     }
 
     fn enqueue(&self, request: EnqueueRequest) -> std::result::Result<(), StorageServiceError> {
-        let mut db = self.database.borrow_mut();
-        let mut queue = DBQueue::open(&mut db, &request.key);
-        match queue.enqueue(&request.value) {
-            Ok(_) => Ok(()),
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        let deadline = expiry::deadline(request.ttl_secs, now_secs());
+        match queue.enqueue(&request.value, deadline) {
+            Ok(_) => {
+                self.watchers
+                    .notify(&[WatchEvent::put(request.key, request.value)]);
+                Ok(())
+            }
             Err(e) => bail!(e),
         }
     }
@@ -233,47 +758,215 @@ impl TeaclaveStorageService {
         &self,
         request: DequeueRequest,
     ) -> std::result::Result<DequeueResponse, StorageServiceError> {
-        let mut db = self.database.borrow_mut();
-        let mut queue = DBQueue::open(&mut db, &request.key);
-        match queue.dequeue() {
-            Ok(value) => Ok(DequeueResponse { value }),
-            Err(e) => bail!(e),
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        let now = now_secs();
+        queue.release_expired(now)?;
+        if request.lease_secs == 0 {
+            let value = queue.dequeue()?;
+            return Ok(DequeueResponse::new(value));
         }
+
+        let deadline = now + request.lease_secs as u64;
+        let (value, receipt) = queue.lease(deadline)?;
+        Ok(DequeueResponse { value, receipt })
+    }
+
+    fn ack(&self, request: AckRequest) -> std::result::Result<(), StorageServiceError> {
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        queue.release_expired(now_secs())?;
+        queue.ack(&request.receipt)
+    }
+
+    fn nack(&self, request: NackRequest) -> std::result::Result<(), StorageServiceError> {
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        queue.release_expired(now_secs())?;
+        let value = queue.nack(&request.receipt)?;
+        self.watchers.notify(&[WatchEvent::put(request.key, value)]);
+        Ok(())
+    }
+
+    fn peek(&self, request: PeekRequest) -> std::result::Result<PeekResponse, StorageServiceError> {
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        queue.release_expired(now_secs())?;
+        let values = queue.peek(request.limit);
+        Ok(PeekResponse { values })
+    }
+
+    fn queue_length(
+        &self,
+        request: QueueLengthRequest,
+    ) -> std::result::Result<QueueLengthResponse, StorageServiceError> {
+        let db = &self.database;
+        let mut queue = DBQueue::open(db, &request.key);
+        queue.release_expired(now_secs())?;
+        Ok(QueueLengthResponse {
+            length: queue.len(),
+            leased: queue.leased()?,
+        })
     }
 
     fn get_keys_by_prefix(
         &self,
         request: GetKeysByPrefixRequest,
     ) -> std::result::Result<GetKeysByPrefixResponse, StorageServiceError> {
-        let prefix = request.prefix;
-        let mut db = self.database.borrow_mut();
-        let mut it = db.new_iter().map_err(StorageServiceError::Database)?;
+        get_keys_by_prefix(&self.database, request)
+    }
+
+    fn scan(&self, request: ScanRequest) -> std::result::Result<ScanResponse, StorageServiceError> {
+        scan(&self.database, request)
+    }
+
+    // The proxy takes the snapshot from the backups by its id, and reads the
+    // archive from it while sending it.
+    fn backup(&self, _request: BackupRequest) -> std::result::Result<u64, StorageServiceError> {
+        replication::check_snapshot_complete(&self.database)?;
+        let snapshot = self.database.get_snapshot();
+        Ok(self.backups.insert(snapshot))
+    }
+
+    fn start_restore(&self) -> std::result::Result<(), StorageServiceError> {
+        backup::start_restore(&self.database)?;
+        info!("Restoring a backup archive");
+        Ok(())
+    }
+
+    fn restore_records(
+        &self,
+        records: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> std::result::Result<(), StorageServiceError> {
+        backup::restore_records(&self.database, records)
+    }
 
-        let mut first_prefix = prefix.clone();
-        first_prefix.push(b'-');
-        let mut last_prefix = prefix;
-        last_prefix.push(b'.');
+    fn finish_restore(&self) -> std::result::Result<(), StorageServiceError> {
+        backup::finish_restore(&self.database)?;
+        info!("Restored a backup archive");
+        Ok(())
+    }
 
-        it.seek(&first_prefix[..]);
-        if !it.valid() {
-            return Ok(GetKeysByPrefixResponse::default());
+    fn abort_restore(&self) -> std::result::Result<(), StorageServiceError> {
+        backup::abort_restore(&self.database)?;
+        info!("Aborted the restore of a backup archive");
+        Ok(())
+    }
+
+    // Followers are only added in the storage thread, so the snapshot and the
+    // writes published after it do not miss or overlap any write.
+    // The proxy takes the snapshot from the replication, and reads it while
+    // sending it to the follower.
+    fn replicate(&self, id: u64) -> std::result::Result<(), StorageServiceError> {
+        let snapshot = self.database.get_snapshot();
+        if !self.replication.start(id, snapshot) {
+            bail!(StorageServiceError::Service(anyhow!("follower is gone")));
         }
-        let mut key = Vec::new();
-        let mut value = Vec::new();
-        let mut keys = Vec::new();
-        if !it.current(&mut key, &mut value) {
-            return Ok(GetKeysByPrefixResponse::default());
+        info!("Replicating to a follower");
+        Ok(())
+    }
+
+    fn replicated(&self, entry: ReplicationEntry) -> std::result::Result<(), StorageServiceError> {
+        // Fails once promoted, which stops the replication from the primary.
+        if !self.follower.get() {
+            bail!(StorageServiceError::Service(anyhow!("not a follower")));
+        }
+        replication::apply(&self.database, entry)
+    }
+
+    // A follower in the middle of a snapshot has lost its old data, and cannot
+    // take over.
+    fn promote(&self, _request: PromoteRequest) -> std::result::Result<(), StorageServiceError> {
+        replication::check_snapshot_complete(&self.database)?;
+        if self.follower.replace(false) {
+            info!("Promoted the follower to primary");
         }
-        keys.push(key);
+        Ok(())
+    }
 
-        while let Some((k, _)) = it.next() {
-            if k >= last_prefix {
-                break;
+    fn rotate_key(
+        &self,
+        _request: RotateKeyRequest,
+    ) -> std::result::Result<(), StorageServiceError> {
+        match &self.key_dir {
+            Some(key_dir) => persist::rotate_db_key(key_dir, &self.database)?,
+            None => bail!(StorageServiceError::Service(anyhow!(
+                "database key cannot be rotated"
+            ))),
+        }
+        Ok(())
+    }
+
+    fn batch(&self, request: BatchRequest) -> std::result::Result<(), StorageServiceError> {
+        self.batch_at(request, now_secs())
+    }
+
+    fn batch_at(
+        &self,
+        request: BatchRequest,
+        now: u64,
+    ) -> std::result::Result<(), StorageServiceError> {
+        let db = &self.database;
+
+        // An expired key is absent, as it is for a get.
+        for precondition in request.preconditions {
+            let current = if expiry::is_expired(db, &precondition.key, now) {
+                None
+            } else {
+                db.get(&precondition.key)
+            };
+            let holds = match current {
+                Some(value) => precondition.exists && value == precondition.value,
+                None => !precondition.exists,
+            };
+            if !holds {
+                bail!(StorageServiceError::PreconditionFailed);
+            }
+        }
+
+        let mut batch = WriteBatch::new();
+        let mut tails: HashMap<Vec<u8>, u32> = HashMap::new();
+        // The last put or delete of a key decides its deadline.
+        let mut deadlines: HashMap<Vec<u8>, Option<u64>> = HashMap::new();
+        let mut events = Vec::new();
+        for operation in request.operations {
+            match operation.operation {
+                Some(BatchOperationKind::Put(r)) => {
+                    batch.put(&r.key, &r.value);
+                    deadlines.insert(r.key.clone(), expiry::deadline(r.ttl_secs, now));
+                    events.push(WatchEvent::put(r.key, r.value));
+                }
+                Some(BatchOperationKind::Delete(r)) => {
+                    batch.delete(&r.key);
+                    deadlines.insert(r.key.clone(), None);
+                    events.push(WatchEvent::delete(r.key));
+                }
+                Some(BatchOperationKind::Enqueue(r)) => {
+                    let mut queue = DBQueue::open(db, &r.key);
+                    let tail_index = match tails.get(&r.key) {
+                        Some(tail_index) => *tail_index,
+                        None => queue.get_tail(),
+                    };
+                    let deadline = expiry::deadline(r.ttl_secs, now);
+                    let tail_index =
+                        queue.enqueue_to_batch(&mut batch, tail_index, &r.value, deadline);
+                    tails.insert(r.key.clone(), tail_index);
+                    events.push(WatchEvent::put(r.key, r.value));
+                }
+                None => bail!(StorageServiceError::Service(anyhow!(
+                    "empty batch operation"
+                ))),
             }
-            keys.push(k);
+        }
+        for (key, deadline) in deadlines {
+            expiry::set_deadline_to_batch(db, &mut batch, &key, deadline);
         }
 
-        Ok(GetKeysByPrefixResponse { keys })
+        db.write(batch, false)
+            .map_err(StorageServiceError::Database)?;
+        db.flush().map_err(StorageServiceError::Database)?;
+        self.watchers.notify(&events);
+        Ok(())
     }
 }
 
@@ -289,15 +982,20 @@ pub mod tests {
             0x09, 0x08,
         ];
         let opt = rusty_leveldb::Options::new_disk_db_with(key);
-        let mut database = DB::open("mock_db_unit_test", opt).unwrap();
+        let database = ConcurrentDB::open("mock_db_unit_test", opt).unwrap();
         database.put(b"test_get_key", b"test_get_value").unwrap();
         database
             .put(b"test_delete_key", b"test_delete_value")
             .unwrap();
-        TeaclaveStorageService {
-            database: RefCell::new(database),
+        TeaclaveStorageService::new(
+            database,
             receiver,
-        }
+            Watchers::default(),
+            Replication::default(),
+            Backups::default(),
+            false,
+            None,
+        )
     }
 
     pub fn test_get_key() {
@@ -353,6 +1051,68 @@ pub mod tests {
         assert_eq!(service.dequeue(request).unwrap().value, b"2");
     }
 
+    pub fn test_dequeue_lease() {
+        let service = get_mock_service();
+        let request = EnqueueRequest::new("test_lease_key", "1");
+        assert!(service.enqueue(request).is_ok());
+        let request = EnqueueRequest::new("test_lease_key", "2");
+        assert!(service.enqueue(request).is_ok());
+
+        let request = DequeueRequest::new("test_lease_key").lease(60);
+        let response = service.dequeue(request).unwrap();
+        assert_eq!(response.value, b"1");
+        let request = QueueLengthRequest::new("test_lease_key");
+        let length = service.queue_length(request).unwrap();
+        assert_eq!((length.length, length.leased), (1, 1));
+
+        // a nacked element goes back to the tail
+        let request = NackRequest::new("test_lease_key", response.receipt.clone());
+        assert!(service.nack(request).is_ok());
+        let request = AckRequest::new("test_lease_key", response.receipt);
+        assert!(service.ack(request).is_err());
+        let request = PeekRequest::new("test_lease_key", 10);
+        let values = service.peek(request).unwrap().values;
+        assert_eq!(values, [b"2", b"1"]);
+
+        let request = DequeueRequest::new("test_lease_key").lease(60);
+        let response = service.dequeue(request).unwrap();
+        assert_eq!(response.value, b"2");
+        let request = AckRequest::new("test_lease_key", response.receipt);
+        assert!(service.ack(request).is_ok());
+        let request = QueueLengthRequest::new("test_lease_key");
+        let length = service.queue_length(request).unwrap();
+        assert_eq!((length.length, length.leased), (1, 0));
+    }
+
+    pub fn test_lease_expiry() {
+        let service = get_mock_service();
+        let request = EnqueueRequest::new("test_expiry_key", "1");
+        assert!(service.enqueue(request).is_ok());
+        let request = DequeueRequest::new("test_expiry_key").lease(60);
+        let response = service.dequeue(request).unwrap();
+
+        let mut queue = DBQueue::open(&service.database, b"test_expiry_key");
+        assert!(queue.release_expired(now_secs()).is_ok());
+        assert_eq!((queue.len(), queue.leased().unwrap()), (0, 1));
+        assert!(queue.release_expired(u64::MAX).is_ok());
+        assert_eq!((queue.len(), queue.leased().unwrap()), (1, 0));
+        assert!(queue.ack(&response.receipt).is_err());
+        assert_eq!(queue.peek(10), [b"1"]);
+
+        // only the leases up to the given time are released
+        let now = now_secs();
+        let (value, _) = queue.lease(now + 60).unwrap();
+        assert_eq!(value, b"1");
+        assert!(queue.enqueue(b"2", None).is_ok());
+        let (_, receipt) = queue.lease(now + 3600).unwrap();
+        assert!(queue.release_expired(now + 60).is_ok());
+        assert_eq!((queue.len(), queue.leased().unwrap()), (1, 1));
+        assert!(queue.ack(&receipt).is_ok());
+        assert_eq!((queue.len(), queue.leased().unwrap()), (1, 0));
+        assert!(queue.release_expired(u64::MAX).is_ok());
+        assert_eq!(queue.peek(10), [b"1"]);
+    }
+
     pub fn test_get_keys_by_prefix() {
         let service = get_mock_service();
         let request = PutRequest::new("function-1", "test_put_value");
@@ -378,4 +1138,478 @@ pub mod tests {
             ]
         );
     }
+
+    pub fn test_scan() {
+        let service = get_mock_service();
+        for key in ["scan-1", "scan-2", "scan-3", "scan-4", "scanner-1"] {
+            let request = PutRequest::new(key, key);
+            assert!(service.put(request).is_ok());
+        }
+
+        let request = ScanRequest::with_prefix("scan-").limit(3);
+        let response = service.scan(request).unwrap();
+        let keys: Vec<_> = response.items.iter().map(|i| i.key.as_slice()).collect();
+        assert_eq!(keys, [b"scan-1", b"scan-2", b"scan-3"]);
+        assert_eq!(response.items[0].value, b"scan-1");
+        assert_eq!(response.continuation_token, b"scan-3");
+
+        let request = ScanRequest::with_prefix("scan-")
+            .limit(3)
+            .continuation_token(response.continuation_token);
+        let response = service.scan(request).unwrap();
+        let keys: Vec<_> = response.items.iter().map(|i| i.key.as_slice()).collect();
+        assert_eq!(keys, [b"scan-4"]);
+        assert!(response.continuation_token.is_empty());
+
+        let request = ScanRequest::with_range("scan-2", "scan-4");
+        let response = service.scan(request).unwrap();
+        let keys: Vec<_> = response.items.iter().map(|i| i.key.as_slice()).collect();
+        assert_eq!(keys, [b"scan-2", b"scan-3"]);
+        assert!(response.continuation_token.is_empty());
+    }
+
+    pub fn test_batch() {
+        let service = get_mock_service();
+        let request = BatchRequest::new()
+            .put("test_batch_key", "test_batch_value")
+            .delete("test_delete_key")
+            .enqueue("test_batch_queue", "1")
+            .enqueue("test_batch_queue", "2");
+        assert!(service.batch(request).is_ok());
+
+        let request = GetRequest::new("test_batch_key");
+        assert_eq!(service.get(request).unwrap().value, b"test_batch_value");
+        let request = GetRequest::new("test_delete_key");
+        assert!(service.get(request).is_err());
+        let request = DequeueRequest::new("test_batch_queue");
+        assert_eq!(service.dequeue(request).unwrap().value, b"1");
+        let request = DequeueRequest::new("test_batch_queue");
+        assert_eq!(service.dequeue(request).unwrap().value, b"2");
+    }
+
+    pub fn test_batch_precondition() {
+        let service = get_mock_service();
+        let request = BatchRequest::new()
+            .expect_value("test_get_key", "test_other_value")
+            .put("test_precondition_key", "1");
+        assert!(matches!(
+            service.batch(request),
+            Err(StorageServiceError::PreconditionFailed)
+        ));
+        let request = GetRequest::new("test_precondition_key");
+        assert!(service.get(request).is_err());
+
+        let request = BatchRequest::new()
+            .expect_absent("test_get_key")
+            .put("test_precondition_key", "1");
+        assert!(service.batch(request).is_err());
+
+        let request = BatchRequest::new()
+            .expect_value("test_get_key", "test_get_value")
+            .expect_absent("test_precondition_key")
+            .put("test_precondition_key", "1");
+        assert!(service.batch(request).is_ok());
+        let request = GetRequest::new("test_precondition_key");
+        assert_eq!(service.get(request).unwrap().value, b"1");
+    }
+
+    fn get_empty_service() -> TeaclaveStorageService {
+        let (_sender, receiver) = unbounded_channel();
+        let opt = rusty_leveldb::in_memory();
+        let database = ConcurrentDB::open("mock_db_unit_test_restore", opt).unwrap();
+        TeaclaveStorageService::new(
+            database,
+            receiver,
+            Watchers::default(),
+            Replication::default(),
+            Backups::default(),
+            false,
+            None,
+        )
+    }
+
+    fn get_follower_service() -> TeaclaveStorageService {
+        let (_sender, receiver) = unbounded_channel();
+        let opt = rusty_leveldb::in_memory();
+        let database = ConcurrentDB::open("mock_db_unit_test_follower", opt).unwrap();
+        TeaclaveStorageService::new(
+            database,
+            receiver,
+            Watchers::default(),
+            Replication::default(),
+            Backups::default(),
+            true,
+            None,
+        )
+    }
+
+    fn backup_archive(service: &TeaclaveStorageService) -> Vec<Vec<u8>> {
+        let id = service.backup(BackupRequest::new()).unwrap();
+        let snapshot = service.backups.take(id).unwrap();
+        let mut archive = Vec::new();
+        let sent = backup::write_archive(&service.database, snapshot, |chunk| {
+            archive.push(chunk);
+            true
+        });
+        assert_eq!(sent.unwrap(), archive.len());
+        archive
+    }
+
+    // Restores the archive chunk by chunk like the proxy, with the key
+    // unsealed on this platform.
+    fn restore_archive(
+        service: &TeaclaveStorageService,
+        archive: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let (header, chunks) = archive
+            .split_first()
+            .ok_or_else(|| anyhow!("Empty archive"))?;
+        let (base_iv, sealed_key) = backup::parse_header(header)?;
+        let key = backup::unseal_archive_key(sealed_key)?;
+        let mut reader = backup::ArchiveReader::new(base_iv, key)?;
+        service.start_restore()?;
+        let restored = chunks
+            .iter()
+            .try_for_each(|chunk| -> anyhow::Result<()> {
+                let records = reader.read_chunk(chunk)?;
+                service.restore_records(records)?;
+                Ok(())
+            })
+            .and_then(|_| {
+                anyhow::ensure!(reader.finished(), "Archive is truncated");
+                service.finish_restore()?;
+                Ok(())
+            });
+        if restored.is_err() {
+            service.abort_restore()?;
+        }
+        restored
+    }
+
+    pub fn test_backup_restore() {
+        let service = get_mock_service();
+        let request = PutRequest::new("test_backup_key", "test_backup_value");
+        assert!(service.put(request).is_ok());
+        // large enough to span several chunks
+        let large_value = vec![1u8; 600 * 1024];
+        for i in 0..3 {
+            let request = PutRequest::new(format!("test_backup_large_{}", i), large_value.clone());
+            assert!(service.put(request).is_ok());
+        }
+        let archive = backup_archive(&service);
+        assert!(archive.len() > 3);
+
+        let restored = get_empty_service();
+        assert!(restore_archive(&restored, &archive).is_ok());
+        let request = GetRequest::new("test_backup_key");
+        assert_eq!(restored.get(request).unwrap().value, b"test_backup_value");
+        let request = GetRequest::new("test_get_key");
+        assert_eq!(restored.get(request).unwrap().value, b"test_get_value");
+        let request = GetRequest::new("test_backup_large_2");
+        assert_eq!(restored.get(request).unwrap().value, large_value);
+
+        // Restoring a valid archive into a database which is not empty is
+        // rejected, and leaves the database as it is.
+        let request = PutRequest::new("test_backup_key", "test_changed_value");
+        assert!(restored.put(request).is_ok());
+        assert!(restore_archive(&restored, &archive).is_err());
+        let request = GetRequest::new("test_backup_key");
+        assert_eq!(restored.get(request).unwrap().value, b"test_changed_value");
+    }
+
+    pub fn test_restore_invalid_archive() {
+        let service = get_mock_service();
+        let archive = backup_archive(&service);
+
+        let restored = get_empty_service();
+        assert!(restore_archive(&restored, &[]).is_err());
+
+        // Every archive has its own key, so the header of another archive
+        // cannot open the chunks.
+        let mut mixed = backup_archive(&service);
+        mixed.truncate(1);
+        mixed.extend_from_slice(&archive[1..]);
+        assert!(restore_archive(&restored, &mixed).is_err());
+
+        let mut forged = archive.clone();
+        let sealed_key = forged[0].len() - 1;
+        forged[0][sealed_key] ^= 1;
+        assert!(restore_archive(&restored, &forged).is_err());
+
+        // The records of the chunks written before the failure are dropped.
+        let mut truncated = archive.clone();
+        truncated.pop();
+        assert!(restore_archive(&restored, &truncated).is_err());
+
+        let mut tampered = archive;
+        if let Some(c) = tampered.last_mut() {
+            c[0] ^= 1;
+        }
+        assert!(restore_archive(&restored, &tampered).is_err());
+
+        let request = GetRequest::new("test_get_key");
+        assert!(matches!(
+            restored.get(request),
+            Err(StorageServiceError::None)
+        ));
+    }
+
+    pub fn test_restore_interrupted() {
+        let service = get_mock_service();
+        let archive = backup_archive(&service);
+
+        // A restore stopped in the middle refuses the other requests.
+        let restored = get_empty_service();
+        assert!(restored.start_restore().is_ok());
+        let records = vec![(b"test_interrupted_key".to_vec(), b"1".to_vec())];
+        assert!(restored.restore_records(records).is_ok());
+        let request = GetRequest::new("test_interrupted_key");
+        assert!(matches!(
+            restored.get(request),
+            Err(StorageServiceError::RestoreInProgress)
+        ));
+        let request = TeaclaveStorageRequest::Put(PutRequest::new("test_put_key", "1"));
+        assert!(matches!(
+            restored.dispatch(teaclave_rpc::Request::new(request)),
+            Err(StorageServiceError::RestoreInProgress)
+        ));
+
+        // The next restore drops it.
+        assert!(restore_archive(&restored, &archive).is_ok());
+        let request = GetRequest::new("test_interrupted_key");
+        assert!(matches!(
+            restored.get(request),
+            Err(StorageServiceError::None)
+        ));
+        let request = GetRequest::new("test_get_key");
+        assert_eq!(restored.get(request).unwrap().value, b"test_get_value");
+    }
+
+    pub fn test_watch() {
+        let service = get_mock_service();
+        let stream = service
+            .watchers
+            .watch(vec![b"test_watch".to_vec(), b"test_watch_queue".to_vec()]);
+        let mut receiver = stream.into_inner();
+
+        let request = PutRequest::new("test_watch_key", "1");
+        assert!(service.put(request).is_ok());
+        let request = PutRequest::new("test_unwatched_key", "1");
+        assert!(service.put(request).is_ok());
+        let request = BatchRequest::new()
+            .delete("test_watch_key")
+            .enqueue("test_watch_queue", "2");
+        assert!(service.batch(request).is_ok());
+
+        let event = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(event, WatchEvent::put("test_watch_key", "1"));
+        let event = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(event, WatchEvent::delete("test_watch_key"));
+        let event = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(event, WatchEvent::put("test_watch_queue", "2"));
+        assert!(receiver.try_recv().is_err());
+
+        // A failed write emits no event.
+        let request = BatchRequest::new()
+            .expect_absent("test_get_key")
+            .put("test_watch_key", "3");
+        assert!(service.batch(request).is_err());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    pub fn test_ttl() {
+        let service = get_mock_service();
+        let now = now_secs();
+        let request = PutRequest::new("test_ttl_key", "1").ttl(10);
+        assert!(service.put(request).is_ok());
+        let request = PutRequest::new("test_ttl_kept_key", "1").ttl(10);
+        assert!(service.put(request).is_ok());
+        // putting the key again without a TTL keeps it
+        let request = PutRequest::new("test_ttl_kept_key", "2");
+        assert!(service.put(request).is_ok());
+        let request = BatchRequest::new().put_with_ttl("test_ttl_batch_key", "1", 100);
+        assert!(service.batch(request).is_ok());
+
+        assert!(service.sweep(now).is_ok());
+        let request = GetRequest::new("test_ttl_key");
+        assert!(service.get(request).is_ok());
+        // an expired key is not served before it is swept
+        let request = GetRequest::new("test_ttl_key");
+        assert!(get_at(&service.database, request, now + 20).is_err());
+        let request = GetRequest::new("test_ttl_batch_key");
+        assert!(get_at(&service.database, request, now + 20).is_ok());
+        // and a batch takes it as absent
+        let request = BatchRequest::new()
+            .expect_value("test_ttl_key", "1")
+            .put("test_ttl_other_key", "1");
+        assert!(service.batch_at(request, now + 20).is_err());
+        let request = BatchRequest::new()
+            .expect_absent("test_ttl_key")
+            .put("test_ttl_other_key", "1");
+        assert!(service.batch_at(request, now + 20).is_ok());
+        let request = GetRequest::new("test_ttl_other_key");
+        assert!(service.get(request).is_ok());
+
+        assert!(service.sweep(now + 20).is_ok());
+        let request = GetRequest::new("test_ttl_key");
+        assert!(service.get(request).is_err());
+        let request = GetRequest::new("test_ttl_kept_key");
+        assert_eq!(service.get(request).unwrap().value, b"2");
+        let request = GetRequest::new("test_ttl_batch_key");
+        assert!(service.get(request).is_ok());
+    }
+
+    pub fn test_queue_ttl() {
+        let service = get_mock_service();
+        let now = now_secs();
+        let request = EnqueueRequest::new("test_queue_ttl", "1").ttl(10);
+        assert!(service.enqueue(request).is_ok());
+        let request = EnqueueRequest::new("test_queue_ttl", "2");
+        assert!(service.enqueue(request).is_ok());
+
+        assert!(service.sweep(now + 20).is_ok());
+        let request = DequeueRequest::new("test_queue_ttl");
+        assert_eq!(service.dequeue(request).unwrap().value, b"2");
+        let request = DequeueRequest::new("test_queue_ttl");
+        assert!(service.dequeue(request).is_err());
+    }
+
+    pub fn test_replication() {
+        let primary = get_mock_service();
+        let follower = get_follower_service();
+        // stale data of the follower is dropped with the snapshot
+        let request = PutRequest::new("test_stale_key", "1");
+        assert!(follower.put(request).is_ok());
+
+        let (id, writes) = primary.replication.register();
+        assert!(primary.replicate(id).is_ok());
+        let snapshot = primary.replication.take_snapshot(id).unwrap();
+        let mut writes = writes.into_inner();
+
+        let request = PutRequest::new("test_replication_key", "1");
+        assert!(primary.put(request).is_ok());
+        let request = BatchRequest::new()
+            .delete("test_get_key")
+            .enqueue("test_replication_queue", "2");
+        assert!(primary.batch(request).is_ok());
+        primary.publish();
+
+        // the snapshot is read after the writes, and does not contain them
+        let mut entries = Vec::new();
+        let sent = replication::send_snapshot(&primary.database, snapshot, |entry| {
+            entries.push(entry);
+            true
+        });
+        assert!(sent.is_ok());
+        assert!(entries[0].reset);
+        assert!(entries[entries.len() - 1].end_of_snapshot);
+        for entry in entries {
+            assert!(follower.replicated(entry).is_ok());
+        }
+        while let Ok(entry) = writes.try_recv() {
+            assert!(follower.replicated(entry.unwrap()).is_ok());
+        }
+
+        let request = GetRequest::new("test_stale_key");
+        assert!(follower.get(request).is_err());
+        let request = GetRequest::new("test_delete_key");
+        assert_eq!(follower.get(request).unwrap().value, b"test_delete_value");
+        let request = GetRequest::new("test_replication_key");
+        assert_eq!(follower.get(request).unwrap().value, b"1");
+        let request = GetRequest::new("test_get_key");
+        assert!(follower.get(request).is_err());
+
+        assert!(follower.promote(PromoteRequest::default()).is_ok());
+        let request = DequeueRequest::new("test_replication_queue");
+        assert_eq!(follower.dequeue(request).unwrap().value, b"2");
+    }
+
+    pub fn test_replication_partial_snapshot() {
+        let follower = get_follower_service();
+        let request = PutRequest::new("test_partial_old_key", "1");
+        assert!(follower.put(request).is_ok());
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"test_partial_new_key", b"1");
+        let entry = ReplicationEntry::new(batch.encode(0), true);
+        assert!(follower.replicated(entry).is_ok());
+        // no reads are served until the snapshot is complete
+        let request = GetRequest::new("test_partial_new_key");
+        assert!(matches!(
+            follower.get(request),
+            Err(StorageServiceError::SnapshotInProgress)
+        ));
+        let request = ScanRequest::with_prefix("test_partial_");
+        assert!(scan(&follower.database, request).is_err());
+        // and the follower cannot take over
+        assert!(matches!(
+            follower.promote(PromoteRequest::default()),
+            Err(StorageServiceError::SnapshotInProgress)
+        ));
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"test_partial_last_key", b"1");
+        let entry = ReplicationEntry::new(batch.encode(0), false).end_of_snapshot();
+        assert!(follower.replicated(entry).is_ok());
+        let request = GetRequest::new("test_partial_old_key");
+        assert!(matches!(
+            follower.get(request),
+            Err(StorageServiceError::None)
+        ));
+        let request = GetRequest::new("test_partial_new_key");
+        assert!(follower.get(request).is_ok());
+        let request = GetRequest::new("test_partial_last_key");
+        assert!(follower.get(request).is_ok());
+
+        // the end of a snapshot which has not started is rejected
+        let entry = ReplicationEntry::new(WriteBatch::new().encode(0), false).end_of_snapshot();
+        assert!(follower.replicated(entry).is_err());
+        assert!(follower.promote(PromoteRequest::default()).is_ok());
+    }
+
+    pub fn test_promote() {
+        let follower = get_follower_service();
+        let request = TeaclaveStorageRequest::Put(PutRequest::new("test_promote_key", "1"));
+        assert!(matches!(
+            follower.dispatch(teaclave_rpc::Request::new(request)),
+            Err(StorageServiceError::Follower)
+        ));
+        let request = TeaclaveStorageRequest::Replicate(0);
+        assert!(matches!(
+            follower.dispatch(teaclave_rpc::Request::new(request)),
+            Err(StorageServiceError::Follower)
+        ));
+        let request = TeaclaveStorageRequest::Get(GetRequest::new("test_promote_key"));
+        assert!(matches!(
+            follower.dispatch(teaclave_rpc::Request::new(request)),
+            Err(StorageServiceError::None)
+        ));
+
+        let request = TeaclaveStorageRequest::Promote(PromoteRequest::default());
+        assert!(follower
+            .dispatch(teaclave_rpc::Request::new(request))
+            .is_ok());
+        let request = TeaclaveStorageRequest::Put(PutRequest::new("test_promote_key", "1"));
+        assert!(follower
+            .dispatch(teaclave_rpc::Request::new(request))
+            .is_ok());
+        // the entries of the old primary are rejected once promoted
+        let entry = ReplicationEntry::new(WriteBatch::new().encode(0), true);
+        assert!(follower.replicated(entry).is_err());
+        let request = GetRequest::new("test_promote_key");
+        assert_eq!(follower.get(request).unwrap().value, b"1");
+    }
+
+    pub fn test_stats() {
+        let service = get_mock_service();
+        let request = StatsRequest::new()
+            .range("test_", "test`")
+            .range("test_get_key", "test_get_key");
+        let response = stats(&service.database, request).unwrap();
+        assert_eq!(response.files_at_level.len(), rusty_leveldb::NUM_LEVELS);
+        assert!(response.stats.contains("Block cache: "));
+        assert!(response.sstables.starts_with("--- level 0 ---\n"));
+        assert_eq!(response.approximate_sizes.len(), 2);
+        assert_eq!(response.approximate_sizes[1], 0);
+    }
 }
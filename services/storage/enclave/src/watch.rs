@@ -0,0 +1,77 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::sync::{Arc, Mutex};
+use teaclave_proto::teaclave_storage_service::WatchEvent;
+use teaclave_rpc::Status;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+// Number of events a watcher can fall behind before it is dropped
+const WATCH_CHANNEL_SIZE: usize = 1024;
+
+pub(crate) type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+struct Watcher {
+    prefixes: Vec<Vec<u8>>,
+    sender: mpsc::Sender<Result<WatchEvent, Status>>,
+}
+
+impl Watcher {
+    fn matches(&self, key: &[u8]) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| key.starts_with(p))
+    }
+
+    // Returns false if the watcher is gone, or cannot keep up and gets an
+    // error as its last message.
+    fn send(&self, event: &WatchEvent) -> bool {
+        if self.sender.capacity() > 1 {
+            return self.sender.try_send(Ok(event.clone())).is_ok();
+        }
+        log::warn!("Dropping a watcher which falls behind");
+        let status = Status::resource_exhausted("watcher falls behind");
+        let _ = self.sender.try_send(Err(status));
+        false
+    }
+}
+
+// The watchers are registered by the proxy, and notified by the storage
+// service once its writes are committed.
+#[derive(Clone, Default)]
+pub(crate) struct Watchers {
+    watchers: Arc<Mutex<Vec<Watcher>>>,
+}
+
+impl Watchers {
+    pub(crate) fn watch(&self, prefixes: Vec<Vec<u8>>) -> WatchStream {
+        let (sender, receiver) = mpsc::channel(WATCH_CHANNEL_SIZE);
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.push(Watcher { prefixes, sender });
+        ReceiverStream::new(receiver)
+    }
+
+    pub(crate) fn notify(&self, events: &[WatchEvent]) {
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.retain(|watcher| {
+            !watcher.sender.is_closed()
+                && events
+                    .iter()
+                    .filter(|event| watcher.matches(&event.key))
+                    .all(|event| watcher.send(event))
+        });
+    }
+}
@@ -198,6 +198,35 @@ async fn test_get_input_file() {
     assert!(response.is_err());
 }
 
+#[async_test_case]
+async fn test_list_files() {
+    let mut client = authorized_client().await;
+
+    let url = Url::parse("https://external-storage.com/filepath?presigned_token").unwrap();
+    let request = RegisterOutputFileRequest::new(url, FileCrypto::default());
+    let response = client
+        .register_output_file(request)
+        .await
+        .unwrap()
+        .into_inner();
+    let data_id = response.data_id;
+
+    let request = ListOutputFilesRequest::new();
+    let response = client
+        .list_output_files(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.files.iter().any(|file| file.data_id == data_id));
+
+    let request = ListInputFilesRequest::new();
+    client.list_input_files(request.clone()).await.unwrap();
+
+    let mut client = unauthorized_client().await;
+    let response = client.list_input_files(request).await;
+    assert!(response.is_err());
+}
+
 #[async_test_case]
 async fn test_register_function() {
     let request = RegisterFunctionRequestBuilder::new().build();
@@ -334,6 +363,33 @@ async fn test_get_task() {
     assert!(response.is_err());
 }
 
+#[async_test_case]
+async fn test_list_tasks() {
+    let mut client = authorized_client().await;
+    let function_id =
+        ExternalID::try_from("function-00000000-0000-0000-0000-000000000002").unwrap();
+
+    let request = CreateTaskRequest::new()
+        .function_id(function_id.clone())
+        .function_arguments(hashmap!("arg1" => "arg1_value"))
+        .executor(Executor::MesaPy)
+        .outputs_ownership(hashmap!("output" => vec!["frontend_user", "mock_user"]));
+    let response = client.create_task(request).await.unwrap().into_inner();
+    let task_id = response.task_id;
+
+    let request = ListTasksRequest::new(TaskRelation::Creator).function_id(function_id);
+    let response = client
+        .list_tasks(request.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.tasks.iter().any(|task| task.task_id == task_id));
+
+    let mut client = unauthorized_client().await;
+    let response = client.list_tasks(request).await;
+    assert!(response.is_err());
+}
+
 #[async_test_case]
 async fn test_assign_data() {
     let mut client = authorized_client().await;
@@ -17,8 +17,10 @@
 
 use crate::utils::*;
 use futures::FutureExt;
+use std::collections::HashMap;
 use std::convert::TryFrom;
-use teaclave_proto::teaclave_common::i32_from_task_status;
+use teaclave_proto::teaclave_common::{i32_from_task_priority, i32_from_task_status};
+use teaclave_proto::teaclave_frontend_service::i32_from_workflow_status;
 use teaclave_proto::teaclave_management_service::*;
 use teaclave_proto::teaclave_scheduler_service::*;
 use teaclave_rpc::CredentialService;
@@ -131,6 +133,146 @@ async fn test_get_input_file() {
     assert!(response.is_err());
 }
 
+#[async_test_case]
+async fn test_list_input_files() {
+    let url = Url::parse("https://external-storage.com/filepath?presigned_token").unwrap();
+    let cmac = FileAuthTag::mock();
+
+    let mut client = authorized_client("mock_user").await;
+    let mut data_ids = Vec::new();
+    for _ in 0..2 {
+        let request = RegisterInputFileRequest::new(url.clone(), cmac, FileCrypto::default());
+        let response = client.register_input_file(request).await.unwrap();
+        data_ids.push(response.into_inner().data_id);
+    }
+
+    // one file at a time across the pages
+    let mut listed = Vec::new();
+    let mut request = ListInputFilesRequest::new().page_size(1);
+    loop {
+        let response = client
+            .list_input_files(request.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.files.len() <= 1);
+        for file in response.files {
+            assert!(file.owner.contains(&"mock_user".to_string()));
+            listed.push(file.data_id);
+        }
+        if response.next_page_token.is_empty() {
+            break;
+        }
+        request = request.page_token(response.next_page_token);
+    }
+    assert!(data_ids.iter().all(|id| listed.contains(id)));
+
+    // the input registered from a fusion output is shared with the owners
+    let mut client = authorized_client("mock_user2").await;
+    let request = ListInputFilesRequest::new().owner("mock_user3");
+    let response = client.list_input_files(request).await.unwrap().into_inner();
+    let file = response
+        .files
+        .iter()
+        .find(|file| file.data_id == "input-00000000-0000-0000-0000-000000000002")
+        .unwrap();
+    assert_eq!(file.owner.len(), 2);
+    assert_eq!(file.cmac, cmac.to_bytes());
+    assert!(file.superseded_by.is_empty());
+
+    let mut client = authorized_client("mock_another_user").await;
+    let request = ListInputFilesRequest::new();
+    let response = client.list_input_files(request).await.unwrap().into_inner();
+    assert!(response
+        .files
+        .iter()
+        .all(|f| !data_ids.contains(&f.data_id)));
+
+    // the pages of a listing matching nothing are empty up to the last one
+    let mut request = ListInputFilesRequest::new().owner("mock_no_such_user");
+    loop {
+        let response = client
+            .list_input_files(request.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.files.is_empty());
+        if response.next_page_token.is_empty() {
+            break;
+        }
+        request = request.page_token(response.next_page_token);
+    }
+
+    let request =
+        ListInputFilesRequest::new().page_token("output-00000000-0000-0000-0000-000000000001");
+    let response = client.list_input_files(request).await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_list_output_files() {
+    let url = Url::parse("https://external-storage.com/filepath?presigned_token").unwrap();
+
+    let mut client = authorized_client("mock_user").await;
+    let request = RegisterOutputFileRequest::new(url, FileCrypto::default());
+    let response = client.register_output_file(request).await.unwrap();
+    let output_id = response.into_inner().data_id;
+    let request = RegisterFusionOutputRequest::new(vec!["mock_user", "mock_user_b"]);
+    let response = client.register_fusion_output(request).await.unwrap();
+    let fusion_id = response.into_inner().data_id;
+
+    let request = ListOutputFilesRequest::new().owner("mock_user_b");
+    let response = client
+        .list_output_files(request)
+        .await
+        .unwrap()
+        .into_inner();
+    let listed: Vec<_> = response.files.iter().map(|f| &f.data_id).collect();
+    assert!(listed.contains(&&fusion_id));
+    assert!(!listed.contains(&&output_id));
+
+    let mut client = authorized_client("mock_user_b").await;
+    let request = ListOutputFilesRequest::new();
+    let response = client
+        .list_output_files(request)
+        .await
+        .unwrap()
+        .into_inner();
+    let file = response
+        .files
+        .iter()
+        .find(|file| file.data_id == fusion_id)
+        .unwrap();
+    assert!(file.cmac.is_empty());
+
+    // the outputs written by a task have a cmac
+    let mut client = authorized_client("mock_user1").await;
+    let request = ListOutputFilesRequest::new().owner("frontend_user");
+    let response = client
+        .list_output_files(request)
+        .await
+        .unwrap()
+        .into_inner();
+    let file = response
+        .files
+        .iter()
+        .find(|file| file.data_id == "output-00000000-0000-0000-0000-000000000001")
+        .unwrap();
+    assert_eq!(file.cmac, FileAuthTag::mock().to_bytes());
+
+    let mut client = authorized_client("mock_another_user").await;
+    let request = ListOutputFilesRequest::new();
+    let response = client
+        .list_output_files(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response
+        .files
+        .iter()
+        .all(|f| f.data_id != output_id && f.data_id != fusion_id));
+}
+
 #[async_test_case]
 async fn test_register_function() {
     let function_input = FunctionInput::new("input", "input_desc", false);
@@ -432,6 +574,474 @@ async fn test_get_task() {
     }
 }
 
+#[async_test_case]
+async fn test_get_task_retry_budget() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = create_valid_task_request().max_retries(3);
+    let response = client.create_task(request).await.unwrap();
+    let task_id = ExternalID::try_from(response.into_inner().task_id).unwrap();
+
+    let request = GetTaskRequest::new(task_id);
+    let response = client.get_task(request).await.unwrap().into_inner();
+    assert_eq!(response.max_retries, 3);
+    assert!(response.attempts.is_empty());
+}
+
+#[async_test_case]
+async fn test_get_task_execution_limits() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = create_valid_task_request().max_execution_time(60);
+    let response = client.create_task(request).await.unwrap();
+    let task_id = ExternalID::try_from(response.into_inner().task_id).unwrap();
+
+    let request = GetTaskRequest::new(task_id);
+    let response = client.get_task(request).await.unwrap().into_inner();
+    assert_eq!(response.max_execution_time, 60);
+
+    // The time is capped by the platform, so that deadlines cannot overflow.
+    let request = create_valid_task_request().max_execution_time(u64::MAX);
+    let response = client.create_task(request).await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_create_task_retry_limit() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = create_valid_task_request().max_retries(10);
+    let response = client.create_task(request).await;
+    assert!(response.is_ok());
+
+    let request = create_valid_task_request().max_retries(11);
+    let response = client.create_task(request).await;
+    assert!(response.is_err());
+}
+
+// Goes through every page of the listing, and returns the listed task IDs.
+async fn list_task_ids(
+    client: &mut TeaclaveManagementClient<CredentialService>,
+    mut request: ListTasksRequest,
+) -> Vec<String> {
+    let mut task_ids = Vec::new();
+    loop {
+        let response = client
+            .list_tasks(request.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        task_ids.extend(response.tasks.into_iter().map(|task| task.task_id));
+        if response.next_page_token.is_empty() {
+            return task_ids;
+        }
+        request = request.page_token(response.next_page_token);
+    }
+}
+
+#[async_test_case]
+async fn test_list_tasks() {
+    let mut client = authorized_client("mock_user").await;
+    let function_id =
+        ExternalID::try_from("function-00000000-0000-0000-0000-000000000001").unwrap();
+
+    let mut task_ids = Vec::new();
+    for _ in 0..2 {
+        let request = create_valid_task_request();
+        let response = client.create_task(request).await.unwrap();
+        task_ids.push(response.into_inner().task_id);
+    }
+
+    let request = ListTasksRequest::new(TaskRelation::Creator)
+        .function_id(function_id.clone())
+        .page_size(1);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| listed.contains(id)));
+
+    let request = ListTasksRequest::new(TaskRelation::Creator).page_size(1);
+    let response = client.list_tasks(request).await.unwrap().into_inner();
+    assert_eq!(response.tasks.len(), 1);
+    assert_eq!(response.tasks[0].creator, "mock_user");
+    assert!(!response.next_page_token.is_empty());
+
+    let mut client = authorized_client("mock_user1").await;
+    let request = ListTasksRequest::new(TaskRelation::PendingApproval).status(TaskStatus::Created);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| listed.contains(id)));
+
+    let request = ListTasksRequest::new(TaskRelation::Creator);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| !listed.contains(id)));
+
+    let request = ListTasksRequest::new(TaskRelation::Participant).status(TaskStatus::Finished);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| !listed.contains(id)));
+
+    let request = ListTasksRequest::new(TaskRelation::Participant).created_before(1);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| !listed.contains(id)));
+
+    // not a participant
+    let mut client = authorized_client("mock_user_c").await;
+    let request = ListTasksRequest::new(TaskRelation::Participant);
+    let listed = list_task_ids(&mut client, request).await;
+    assert!(task_ids.iter().all(|id| !listed.contains(id)));
+
+    let request = ListTasksRequest::new(TaskRelation::Participant).page_token(function_id);
+    let response = client.list_tasks(request).await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_get_queue_state() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = create_valid_task_request().task_priority(TaskPriority::High);
+    let response = client.create_task(request).await.unwrap();
+    let task_id = ExternalID::try_from(response.into_inner().task_id).unwrap();
+
+    let request = GetTaskRequest::new(task_id);
+    let response = client.get_task(request).await.unwrap().into_inner();
+    assert_eq!(
+        response.priority,
+        i32_from_task_priority(TaskPriority::High)
+    );
+
+    // the mock user is PlatformAdmin
+    let response = client
+        .get_queue_state(GetQueueStateRequest::default())
+        .await;
+    assert!(response.is_ok());
+    let storage_queues = response.unwrap().into_inner().storage_queues;
+    let names: Vec<_> = storage_queues.iter().map(|q| q.name.as_str()).collect();
+    assert_eq!(names, [StagedTask::get_queue_key(), CANCEL_QUEUE_KEY]);
+}
+
+#[async_test_case]
+async fn test_create_workflow() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = CreateWorkflowRequest::new()
+        .node("a", create_valid_task_request())
+        .node("b", create_valid_task_request())
+        .edge("a", "output", "b", "input");
+    let response = client.create_workflow(request).await.unwrap().into_inner();
+    assert_eq!(response.tasks.len(), 2);
+    let workflow_id = ExternalID::try_from(response.workflow_id).unwrap();
+
+    let request = GetWorkflowRequest::new(workflow_id.clone());
+    let response = client.get_workflow(request).await.unwrap().into_inner();
+    assert_eq!(response.edges.len(), 1);
+    assert_eq!(
+        response.status,
+        i32_from_workflow_status(WorkflowStatus::Created)
+    );
+
+    // nodes are approved through the workflow
+    let task_id = ExternalID::try_from(response.tasks[0].task_id.clone()).unwrap();
+    let response = client.get_task(GetTaskRequest::new(task_id.clone())).await;
+    assert_eq!(
+        response.unwrap().into_inner().workflow_id,
+        workflow_id.to_string()
+    );
+    let mut client1 = authorized_client("mock_user1").await;
+    let response = client1.approve_task(ApproveTaskRequest::new(task_id)).await;
+    assert!(response.is_err());
+    // the data of the nodes is not assigned yet
+    let request = ApproveWorkflowRequest::new(workflow_id.clone());
+    let response = client1.approve_workflow(request).await;
+    assert!(response.is_err());
+
+    // only the creator can invoke the workflow
+    let request = InvokeWorkflowRequest::new(workflow_id);
+    let response = client1.invoke_workflow(request).await;
+    assert!(response.is_err());
+
+    // cycle
+    let request = CreateWorkflowRequest::new()
+        .node("a", create_valid_task_request())
+        .node("b", create_valid_task_request())
+        .edge("a", "output", "b", "input")
+        .edge("b", "output", "a", "input");
+    let response = client.create_workflow(request).await;
+    assert!(response.is_err());
+
+    // owners of the output and the input mismatch
+    let request = CreateWorkflowRequest::new()
+        .node("a", create_valid_task_request())
+        .node("b", create_valid_task_request())
+        .edge("a", "output2", "b", "input");
+    let response = client.create_workflow(request).await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_approve_workflow() {
+    let mut client = authorized_client("mock_user").await;
+    let mut client1 = authorized_client("mock_user1").await;
+
+    let request = CreateWorkflowRequest::new()
+        .node("a", create_valid_task_request())
+        .node("b", create_valid_task_request())
+        .edge("a", "output", "b", "input");
+    let response = client.create_workflow(request).await.unwrap().into_inner();
+    let workflow_id = ExternalID::try_from(response.workflow_id).unwrap();
+    let task_ids: HashMap<String, ExternalID> = response
+        .tasks
+        .into_iter()
+        .map(|task| (task.name, task.task_id.try_into().unwrap()))
+        .collect();
+
+    // The data connected by the edge is assigned by the platform.
+    assign_task_data(&task_ids["a"], &["input", "input2"], &["output2"]).await;
+    let request = ApproveWorkflowRequest::new(workflow_id.clone());
+    let response = client1.approve_workflow(request).await;
+    assert!(response.is_err());
+
+    assign_task_data(&task_ids["b"], &["input2"], &["output", "output2"]).await;
+    let request = ApproveWorkflowRequest::new(workflow_id.clone());
+    let response = client1.approve_workflow(request).await;
+    assert!(response.is_ok());
+
+    // The data cannot change once the workflow is approved by anyone.
+    let url = Url::parse("input://path").unwrap();
+    let request = RegisterInputFileRequest::new(url, FileAuthTag::mock(), FileCrypto::default());
+    let response = client1.register_input_file(request).await.unwrap();
+    let input_file_id: ExternalID = response.into_inner().data_id.try_into().unwrap();
+    let request = AssignDataRequest::new(
+        task_ids["a"].clone(),
+        hashmap!("input" => input_file_id),
+        hashmap!(),
+    );
+    let response = client1.assign_data(request).await;
+    assert!(response.is_err());
+}
+
+// Assigns the given data of a task created from create_valid_task_request,
+// each by one of its owners.
+async fn assign_task_data(task_id: &ExternalID, inputs: &[&str], outputs: &[&str]) {
+    let mut client1 = authorized_client("mock_user1").await;
+    let mut client2 = authorized_client("mock_user2").await;
+    let mut client3 = authorized_client("mock_user3").await;
+
+    if inputs.contains(&"input") {
+        let url = Url::parse("input://path").unwrap();
+        let request =
+            RegisterInputFileRequest::new(url, FileAuthTag::mock(), FileCrypto::default());
+        let response = client1.register_input_file(request).await.unwrap();
+        let input_file_id: ExternalID = response.into_inner().data_id.try_into().unwrap();
+        let request = AssignDataRequest::new(
+            task_id.clone(),
+            hashmap!("input" => input_file_id),
+            hashmap!(),
+        );
+        client1.assign_data(request).await.unwrap();
+    }
+    if inputs.contains(&"input2") {
+        let input_file_id =
+            ExternalID::try_from("input-00000000-0000-0000-0000-000000000002").unwrap();
+        let request = AssignDataRequest::new(
+            task_id.clone(),
+            hashmap!("input2" => input_file_id),
+            hashmap!(),
+        );
+        client2.assign_data(request).await.unwrap();
+    }
+    if outputs.contains(&"output") {
+        let url = Url::parse("https://output_file_path").unwrap();
+        let request = RegisterOutputFileRequest::new(url, FileCrypto::default());
+        let response = client1.register_output_file(request).await.unwrap();
+        let output_file_id: ExternalID = response.into_inner().data_id.try_into().unwrap();
+        let request = AssignDataRequest::new(
+            task_id.clone(),
+            hashmap!(),
+            hashmap!("output" => output_file_id),
+        );
+        client1.assign_data(request).await.unwrap();
+    }
+    if outputs.contains(&"output2") {
+        let request = RegisterFusionOutputRequest::new(vec!["mock_user2", "mock_user3"]);
+        let response = client3.register_fusion_output(request).await.unwrap();
+        let fusion_output = ExternalID::try_from(response.into_inner().data_id).unwrap();
+        let request = AssignDataRequest::new(
+            task_id.clone(),
+            hashmap!(),
+            hashmap!("output2" => fusion_output),
+        );
+        client3.assign_data(request).await.unwrap();
+    }
+}
+
+#[async_test_case]
+async fn test_list_and_drain_executors() {
+    let mut client = authorized_client("mock_user").await;
+
+    // unknown executor
+    let request = DrainExecutorRequest::new(Uuid::new_v4());
+    let response = client.drain_executor(request).await;
+    assert!(response.is_err());
+
+    // The executor can run no task, and shows up once it subscribes.
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: ["builtin-drain-test".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+    let mut scheduler_client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let _subscription = scheduler_client.subscribe(request).await.unwrap();
+
+    // the mock user is PlatformAdmin
+    let request = ListExecutorsRequest::default();
+    let response = client.list_executors(request).await.unwrap().into_inner();
+    let executor = response
+        .executors
+        .iter()
+        .find(|executor| executor.executor_id == executor_id.to_string())
+        .unwrap();
+    assert!(!executor.draining);
+    assert_eq!(executor.slots, 1);
+
+    let request = DrainExecutorRequest::new(executor_id);
+    let response = client.drain_executor(request).await;
+    assert!(response.is_ok());
+}
+
+// Creates a task of mock_user with all data assigned and approved by every
+// participant.
+async fn create_approved_task() -> ExternalID {
+    let mut client = authorized_client("mock_user").await;
+    let mut client1 = authorized_client("mock_user1").await;
+    let mut client2 = authorized_client("mock_user2").await;
+    let mut client3 = authorized_client("mock_user3").await;
+    let response = client.create_task(create_valid_task_request()).await;
+    let task_id: ExternalID = response.unwrap().into_inner().task_id.try_into().unwrap();
+
+    let url = Url::parse("input://path").unwrap();
+    let request = RegisterInputFileRequest::new(url, FileAuthTag::mock(), FileCrypto::default());
+    let response = client1.register_input_file(request).await.unwrap();
+    let input_file_id: ExternalID = response.into_inner().data_id.try_into().unwrap();
+    let url = Url::parse("https://output_file_path").unwrap();
+    let request = RegisterOutputFileRequest::new(url, FileCrypto::default());
+    let response = client1.register_output_file(request).await.unwrap();
+    let output_file_id: ExternalID = response.into_inner().data_id.try_into().unwrap();
+    let request = AssignDataRequest::new(
+        task_id.clone(),
+        hashmap!("input" => input_file_id),
+        hashmap!("output" => output_file_id),
+    );
+    client1.assign_data(request).await.unwrap();
+
+    let input_file_id = ExternalID::try_from("input-00000000-0000-0000-0000-000000000002").unwrap();
+    let request = AssignDataRequest::new(
+        task_id.clone(),
+        hashmap!("input2" => input_file_id),
+        hashmap!(),
+    );
+    client2.assign_data(request).await.unwrap();
+
+    let request = RegisterFusionOutputRequest::new(vec!["mock_user2", "mock_user3"]);
+    let response = client3.register_fusion_output(request).await.unwrap();
+    let fusion_output = ExternalID::try_from(response.into_inner().data_id).unwrap();
+    let request = AssignDataRequest::new(
+        task_id.clone(),
+        hashmap!(),
+        hashmap!("output2" => fusion_output),
+    );
+    client3.assign_data(request).await.unwrap();
+
+    let request = ApproveTaskRequest::new(task_id.clone());
+    client.approve_task(request).await.unwrap();
+    let request = ApproveTaskRequest::new(task_id.clone());
+    client1.approve_task(request).await.unwrap();
+    let request = ApproveTaskRequest::new(task_id.clone());
+    client2.approve_task(request).await.unwrap();
+    let request = ApproveTaskRequest::new(task_id.clone());
+    client3.approve_task(request).await.unwrap();
+    task_id
+}
+
+#[async_test_case]
+async fn test_recurring_task() {
+    let mut client = authorized_client("mock_user").await;
+    let mut client1 = authorized_client("mock_user1").await;
+
+    // task is not approved
+    let response = client.create_task(create_valid_task_request()).await;
+    let task_id: ExternalID = response.unwrap().into_inner().task_id.try_into().unwrap();
+    let schedule = TaskSchedule::Interval(3600);
+    let request = CreateRecurringTaskRequest::new(task_id, schedule);
+    let response = client.create_recurring_task(request).await;
+    assert!(response.is_err());
+
+    let task_id = create_approved_task().await;
+
+    // invalid schedules
+    let schedule = TaskSchedule::Cron("0 0 30 2 *".to_string());
+    let request = CreateRecurringTaskRequest::new(task_id.clone(), schedule);
+    let response = client.create_recurring_task(request).await;
+    assert!(response.is_err());
+    let request = CreateRecurringTaskRequest::new(task_id.clone(), TaskSchedule::Interval(0));
+    let response = client.create_recurring_task(request).await;
+    assert!(response.is_err());
+
+    // user_id != task.creator
+    let schedule = TaskSchedule::Cron("0 2 * * *".to_string());
+    let request = CreateRecurringTaskRequest::new(task_id.clone(), schedule.clone());
+    let response = client1.create_recurring_task(request).await;
+    assert!(response.is_err());
+
+    let request = CreateRecurringTaskRequest::new(task_id.clone(), schedule);
+    let response = client.create_recurring_task(request).await.unwrap();
+    let recurring_task_id = ExternalID::try_from(response.into_inner().recurring_task_id).unwrap();
+
+    // participants can get the recurring task
+    let request = GetRecurringTaskRequest::new(recurring_task_id.clone());
+    let response = client1
+        .get_recurring_task(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.task_id, task_id.to_string());
+    assert_eq!(response.cron, "0 2 * * *");
+    assert_eq!(response.interval_seconds, 0);
+    assert_eq!(response.runs, 0);
+    assert_eq!(response.participants.len(), 4);
+    assert_eq!(response.approved_users, vec!["mock_user".to_string()]);
+
+    // user_id not in recurring_task.participants
+    let mut client4 = authorized_client("mock_user4").await;
+    let request = ApproveRecurringTaskRequest::new(recurring_task_id.clone());
+    let response = client4.approve_recurring_task(request).await;
+    assert!(response.is_err());
+
+    for user in ["mock_user1", "mock_user2", "mock_user3"] {
+        let mut participant = authorized_client(user).await;
+        let request = ApproveRecurringTaskRequest::new(recurring_task_id.clone());
+        participant.approve_recurring_task(request).await.unwrap();
+    }
+    let request = GetRecurringTaskRequest::new(recurring_task_id.clone());
+    let response = client
+        .get_recurring_task(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.approved_users.len(), 4);
+
+    // user_id != recurring_task.creator
+    let request = DeleteRecurringTaskRequest::new(recurring_task_id.clone());
+    let response = client1.delete_recurring_task(request).await;
+    assert!(response.is_err());
+
+    let request = DeleteRecurringTaskRequest::new(recurring_task_id.clone());
+    let response = client.delete_recurring_task(request).await;
+    assert!(response.is_ok());
+    let request = GetRecurringTaskRequest::new(recurring_task_id);
+    let response = client.get_recurring_task(request).await;
+    assert!(response.is_err());
+}
+
 #[async_test_case]
 async fn test_assign_data() {
     let mut client = authorized_client("mock_user").await;
@@ -823,3 +1433,42 @@ async fn test_invoke_task() {
     let response = scheduler_client.pull_task(pull_task_request).await;
     assert!(response.is_ok());
 }
+
+#[async_test_case]
+async fn test_backup_and_restore_database() {
+    let mut client = authorized_client("mock_user").await;
+
+    // the mock user is PlatformAdmin
+    let request = BackupDatabaseRequest::new();
+    let mut stream = client.backup_database(request).await.unwrap().into_inner();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        chunks.push(chunk.data);
+    }
+    assert!(chunks.len() >= 2);
+
+    // The database is not empty, so the archive cannot be restored into it.
+    let requests: Vec<_> = chunks
+        .into_iter()
+        .map(RestoreDatabaseRequest::new)
+        .collect();
+    let response = client
+        .restore_database(futures::stream::iter(requests))
+        .await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_get_database_stats() {
+    let mut client = authorized_client("mock_user").await;
+
+    let request = GetDatabaseStatsRequest::new().range("", "\u{ff}");
+    let response = client
+        .get_database_stats(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!response.files_at_level.is_empty());
+    assert!(response.stats.contains("Block cache"));
+    assert_eq!(response.approximate_sizes.len(), 1);
+}
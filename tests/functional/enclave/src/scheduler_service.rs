@@ -17,6 +17,7 @@
 
 use crate::utils::*;
 use futures::FutureExt;
+use teaclave_proto::teaclave_common::{ExecutorCommand, ExecutorStatus};
 use teaclave_proto::teaclave_scheduler_service::*;
 use teaclave_proto::teaclave_storage_service::*;
 use teaclave_test_utils::async_test_case;
@@ -59,6 +60,169 @@ async fn test_pull_task() {
     assert_eq!(staged_task.function_id, function_id);
 }
 
+#[async_test_case]
+async fn test_subscribe() {
+    // The executor only runs the task of this test, so that it does not take
+    // tasks queued by other tests.
+    let function_name = "builtin-subscribe-test";
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: [function_name.to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let mut subscription = client.subscribe(request).await.unwrap().into_inner();
+
+    let task_id = Uuid::new_v4();
+    let staged_task = StagedTaskBuilder::new()
+        .task_id(task_id)
+        .function_name(function_name)
+        .function_id(Uuid::new_v4())
+        .executor(Executor::Builtin)
+        .build();
+    let publish_request = PublishTaskRequest {
+        staged_task: staged_task.to_vec().unwrap(),
+    };
+    let response = client.publish_task(publish_request).await;
+    assert!(response.is_ok());
+
+    // The task is pushed without waiting for the scheduler daemon.
+    let message = tokio::time::timeout(std::time::Duration::from_secs(1), subscription.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    log::debug!("subscription message: {:?}", message);
+
+    assert_eq!(message.command, i32::from(ExecutorCommand::NewTask));
+    let pushed_task = StagedTask::from_slice(&message.staged_task).unwrap();
+    assert_eq!(pushed_task.task_id, task_id);
+}
+
+#[async_test_case]
+async fn test_subscribe_with_capability() {
+    // Only tasks of this function can be pushed to the executor, other tasks
+    // left in the queue are skipped.
+    let function_name = "builtin-capability-test";
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: [function_name.to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let mut subscription = client.subscribe(request).await.unwrap().into_inner();
+
+    let task_id = Uuid::new_v4();
+    let staged_task = StagedTaskBuilder::new()
+        .task_id(task_id)
+        .function_name(function_name)
+        .function_id(Uuid::new_v4())
+        .executor(Executor::Builtin)
+        .build();
+    let publish_request = PublishTaskRequest {
+        staged_task: staged_task.to_vec().unwrap(),
+    };
+    let response = client.publish_task(publish_request).await;
+    assert!(response.is_ok());
+
+    let message = tokio::time::timeout(std::time::Duration::from_secs(1), subscription.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let pushed_task = StagedTask::from_slice(&message.staged_task).unwrap();
+    assert_eq!(pushed_task.task_id, task_id);
+}
+
+#[async_test_case]
+async fn test_subscribe_with_slots() {
+    // Both tasks are pushed to the executor at once, one to each slot.
+    let function_name = "builtin-slots-test";
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: [function_name.to_string()].into_iter().collect(),
+        slots: 2,
+        ..Default::default()
+    };
+
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let mut subscription = client.subscribe(request).await.unwrap().into_inner();
+
+    let task_ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+    for task_id in task_ids.iter() {
+        let staged_task = StagedTaskBuilder::new()
+            .task_id(*task_id)
+            .function_name(function_name)
+            .function_id(Uuid::new_v4())
+            .executor(Executor::Builtin)
+            .build();
+        let publish_request = PublishTaskRequest {
+            staged_task: staged_task.to_vec().unwrap(),
+        };
+        let response = client.publish_task(publish_request).await;
+        assert!(response.is_ok());
+    }
+
+    for task_id in task_ids.iter() {
+        let message =
+            tokio::time::timeout(std::time::Duration::from_secs(1), subscription.message())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        let pushed_task = StagedTask::from_slice(&message.staged_task).unwrap();
+        assert_eq!(pushed_task.task_id, *task_id);
+    }
+}
+
+#[async_test_case]
+async fn test_drain_executor() {
+    // No task can be pushed to the executor before it is drained.
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: ["builtin-drain-test".to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let mut subscription = client.subscribe(request).await.unwrap().into_inner();
+
+    let executor = ExecutorInfo {
+        executor_id,
+        last_heartbeat: std::time::SystemTime::now(),
+        executing: false,
+        task_ids: vec![],
+        draining: false,
+        slots: 1,
+    };
+    let mut storage_client = get_storage_client().await;
+    let enqueue_request =
+        EnqueueRequest::new(DRAIN_QUEUE_KEY.as_bytes(), executor.to_vec().unwrap());
+    storage_client.enqueue(enqueue_request).await.unwrap();
+
+    // The idle executor is shut down once the daemon pulls the drain queue.
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), subscription.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.command, i32::from(ExecutorCommand::Shutdown));
+
+    let request = HeartbeatRequest::new(executor_id, ExecutorStatus::Idle);
+    let response = client.heartbeat(request).await.unwrap().into_inner();
+    assert_eq!(response.command, i32::from(ExecutorCommand::Shutdown));
+}
+
 #[async_test_case]
 async fn test_update_task_status_result() {
     let task_id = Uuid::new_v4();
@@ -110,3 +274,214 @@ async fn test_update_task_status_result() {
     let response = client.update_task_result(request).await;
     assert!(response.is_ok());
 }
+
+#[async_test_case]
+async fn test_scheduler_state_persisted() {
+    let task_id = Uuid::new_v4();
+    let staged_task = StagedTaskBuilder::new()
+        .task_id(task_id)
+        .function_name("builtin-echo")
+        .function_id(Uuid::new_v4())
+        .executor(Executor::Builtin)
+        .build();
+
+    let mut storage_client = get_storage_client().await;
+    let enqueue_request = EnqueueRequest::new(
+        StagedTask::get_queue_key().as_bytes(),
+        staged_task.to_vec().unwrap(),
+    );
+    let _enqueue_response = storage_client.enqueue(enqueue_request).await.unwrap();
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+
+    std::thread::sleep(std::time::Duration::from_secs(2));
+
+    let pull_task_request = PullTaskRequest {
+        executor_id: executor_id.to_string(),
+    };
+    let response = client
+        .pull_task(pull_task_request)
+        .await
+        .unwrap()
+        .into_inner();
+    let pulled_task_id = StagedTask::from_slice(&response.staged_task)
+        .unwrap()
+        .task_id;
+
+    let state_key = SchedulerState::default().key();
+    let get_request = GetRequest::new(state_key.as_slice());
+    let get_response = storage_client.get(get_request).await.unwrap().into_inner();
+    let state = SchedulerState::from_slice(&get_response.value).unwrap();
+    assert!(state
+        .executors_tasks
+        .get(&executor_id)
+        .map_or(false, |task_ids| task_ids.contains(&pulled_task_id)));
+    assert!(!state.task_queue.contains(&pulled_task_id));
+
+    // The staged task is kept until the task ends.
+    let staged_key = ExternalID::new(StagedTask::key_prefix(), pulled_task_id);
+    let get_request = GetRequest::new(staged_key.to_bytes());
+    let get_response = storage_client.get(get_request).await;
+    assert!(get_response.is_ok());
+}
+
+#[async_test_case]
+async fn test_retry_task_of_lost_executor() {
+    // Only the executors of this test can run the task.
+    let function_name = "builtin-retry-test";
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: [function_name.to_string()].into_iter().collect(),
+        ..Default::default()
+    };
+
+    let task_id = Uuid::new_v4();
+    let ts = TaskState {
+        task_id,
+        status: TaskStatus::Staged,
+        max_retries: 1,
+        ..Default::default()
+    };
+    let mut storage_client = get_storage_client().await;
+    let put_request = PutRequest::new(ts.key().as_slice(), ts.to_vec().unwrap().as_slice());
+    storage_client.put(put_request).await.unwrap();
+
+    let staged_task = StagedTaskBuilder::new()
+        .task_id(task_id)
+        .function_name(function_name)
+        .function_id(Uuid::new_v4())
+        .executor(Executor::Builtin)
+        .build();
+    let mut client = get_scheduler_client().await;
+    let publish_request = PublishTaskRequest {
+        staged_task: staged_task.to_vec().unwrap(),
+    };
+    client.publish_task(publish_request).await.unwrap();
+
+    // The executor pulls the task and then stops sending heartbeats.
+    let lost_executor_id = Uuid::new_v4();
+    let request = HeartbeatRequest::new(lost_executor_id, ExecutorStatus::Idle)
+        .capability(capability.clone());
+    client.heartbeat(request).await.unwrap();
+    let pull_task_request = PullTaskRequest {
+        executor_id: lost_executor_id.to_string(),
+    };
+    let response = client
+        .pull_task(pull_task_request)
+        .await
+        .unwrap()
+        .into_inner();
+    let pulled_task = StagedTask::from_slice(&response.staged_task).unwrap();
+    assert_eq!(pulled_task.task_id, task_id);
+
+    // Once the executor is lost and the backoff has passed, the task is
+    // re-queued for another executor.
+    let executor_id = Uuid::new_v4();
+    let mut requeued = None;
+    for _ in 0..60 {
+        let request =
+            HeartbeatRequest::new(executor_id, ExecutorStatus::Idle).capability(capability.clone());
+        client.heartbeat(request).await.unwrap();
+        let pull_task_request = PullTaskRequest {
+            executor_id: executor_id.to_string(),
+        };
+        if let Ok(response) = client.pull_task(pull_task_request).await {
+            requeued = Some(StagedTask::from_slice(&response.into_inner().staged_task).unwrap());
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+    assert_eq!(requeued.unwrap().task_id, task_id);
+
+    let get_request = GetRequest::new(ts.key().as_slice());
+    let get_response = storage_client.get(get_request).await.unwrap().into_inner();
+    let ts = TaskState::from_slice(&get_response.value).unwrap();
+    assert_eq!(ts.status, TaskStatus::Staged);
+    assert_eq!(ts.attempts.len(), 1);
+    assert_eq!(ts.attempts[0].executor_id, lost_executor_id);
+}
+
+#[async_test_case]
+async fn test_stop_overdue_task() {
+    // The overdue task is stopped, while the task in the other slot of the
+    // executor keeps running.
+    let function_name = "builtin-deadline-test";
+    let capability = WorkerCapability {
+        executors: ["builtin".to_string()].into_iter().collect(),
+        builtin_functions: [function_name.to_string()].into_iter().collect(),
+        slots: 2,
+        ..Default::default()
+    };
+
+    let mut client = get_scheduler_client().await;
+    let executor_id = Uuid::new_v4();
+    let request = SubscribeRequest::new(executor_id).capability(capability);
+    let mut subscription = client.subscribe(request).await.unwrap().into_inner();
+
+    let mut storage_client = get_storage_client().await;
+    let overdue_task_id = Uuid::new_v4();
+    let running_task_id = Uuid::new_v4();
+    for (task_id, max_execution_time) in [(overdue_task_id, 1), (running_task_id, 3600)] {
+        let ts = TaskState {
+            task_id,
+            status: TaskStatus::Staged,
+            ..Default::default()
+        };
+        let put_request = PutRequest::new(ts.key().as_slice(), ts.to_vec().unwrap().as_slice());
+        storage_client.put(put_request).await.unwrap();
+
+        let staged_task = StagedTaskBuilder::new()
+            .task_id(task_id)
+            .function_name(function_name)
+            .function_id(Uuid::new_v4())
+            .executor(Executor::Builtin)
+            .max_execution_time(max_execution_time)
+            .build();
+        let publish_request = PublishTaskRequest {
+            staged_task: staged_task.to_vec().unwrap(),
+        };
+        client.publish_task(publish_request).await.unwrap();
+    }
+
+    for _ in 0..2 {
+        let message =
+            tokio::time::timeout(std::time::Duration::from_secs(1), subscription.message())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+        assert_eq!(message.command, i32::from(ExecutorCommand::NewTask));
+    }
+
+    let message = tokio::time::timeout(std::time::Duration::from_secs(10), subscription.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.command, i32::from(ExecutorCommand::Stop));
+    assert_eq!(message.task_id, overdue_task_id.to_string());
+
+    let ts = TaskState {
+        task_id: overdue_task_id,
+        ..Default::default()
+    };
+    let get_request = GetRequest::new(ts.key().as_slice());
+    let get_response = storage_client.get(get_request).await.unwrap().into_inner();
+    let ts = TaskState::from_slice(&get_response.value).unwrap();
+    assert_eq!(ts.status, TaskStatus::Failed);
+
+    // The state is saved after the stop command is pushed.
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    let state_key = SchedulerState::default().key();
+    let get_request = GetRequest::new(state_key.as_slice());
+    let get_response = storage_client.get(get_request).await.unwrap().into_inner();
+    let state = SchedulerState::from_slice(&get_response.value).unwrap();
+    let task_ids = state.executors_tasks.get(&executor_id).unwrap();
+    assert!(task_ids.contains(&running_task_id));
+    assert!(!task_ids.contains(&overdue_task_id));
+
+    // The result of the stopped task is dropped once its worker returns.
+    let task_outputs = TaskOutputs::new("return value", hashmap!(), vec![]);
+    let request = UpdateTaskResultRequest::new(overdue_task_id, Ok(task_outputs));
+    assert!(client.update_task_result(request).await.is_ok());
+}
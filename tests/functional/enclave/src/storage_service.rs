@@ -17,6 +17,7 @@
 
 use crate::utils::create_client_config;
 use futures::FutureExt;
+use std::time::Duration;
 use teaclave_config::RuntimeConfig;
 use teaclave_proto::teaclave_storage_service::*;
 use teaclave_rpc::{
@@ -25,12 +26,20 @@ use teaclave_rpc::{
 };
 use teaclave_test_utils::async_test_case;
 
+// The follower of the storage service, started by the test script
+const FOLLOWER_ADDRESS: &str = "https://localhost:17788";
+
 async fn get_client() -> TeaclaveStorageClient<CredentialService> {
+    let runtime_config = RuntimeConfig::from_toml("runtime.config.toml").expect("runtime");
+    let service_addr = runtime_config.internal_endpoints.storage.advertised_address;
+    get_client_at(&service_addr).await
+}
+
+async fn get_client_at(service_addr: &str) -> TeaclaveStorageClient<CredentialService> {
     let runtime_config = RuntimeConfig::from_toml("runtime.config.toml").expect("runtime");
     let enclave_info =
         teaclave_types::EnclaveInfo::from_bytes(&runtime_config.audit.enclave_info_bytes);
     let tls_config = create_client_config(&enclave_info, "teaclave_storage_service").unwrap();
-    let service_addr = runtime_config.internal_endpoints.storage.advertised_address;
     let channel = Channel::builder(service_addr.parse::<Uri>().unwrap())
         .tls_config(tls_config)
         .unwrap()
@@ -137,3 +146,239 @@ async fn test_dequeue_fail() {
     let response_result = client.dequeue(request).await;
     assert!(response_result.is_err());
 }
+
+#[async_test_case]
+async fn test_batch_success() {
+    let mut client = get_client().await;
+    let request = BatchRequest::new()
+        .expect_absent("test_batch_key")
+        .put("test_batch_key", "test_batch_value")
+        .enqueue("test_batch_queue", "1");
+    let response_result = client.batch(request).await;
+    assert!(response_result.is_ok());
+
+    let request = GetRequest::new("test_batch_key");
+    let response_result = client.get(request).await;
+    assert_eq!(
+        response_result.unwrap().into_inner().value,
+        b"test_batch_value"
+    );
+    let request = DequeueRequest::new("test_batch_queue");
+    let response_result = client.dequeue(request).await;
+    assert_eq!(response_result.unwrap().into_inner().value, b"1");
+}
+
+#[async_test_case]
+async fn test_batch_fail() {
+    let mut client = get_client().await;
+    let request = BatchRequest::new()
+        .expect_value("test_get_key", "test_other_value")
+        .put("test_batch_fail_key", "test_batch_value");
+    let response_result = client.batch(request).await;
+    assert_eq!(
+        response_result.unwrap_err().code(),
+        teaclave_rpc::Code::Aborted
+    );
+
+    let request = GetRequest::new("test_batch_fail_key");
+    let response_result = client.get(request).await;
+    assert!(response_result.is_err());
+}
+
+#[async_test_case]
+async fn test_scan_success() {
+    let mut client = get_client().await;
+    for key in ["test_scan-1", "test_scan-2", "test_scan-3"] {
+        let request = PutRequest::new(key, "test_scan_value");
+        assert!(client.put(request).await.is_ok());
+    }
+
+    let request = ScanRequest::with_prefix("test_scan-").limit(2);
+    let response = client.scan(request).await.unwrap().into_inner();
+    assert_eq!(response.items.len(), 2);
+    assert_eq!(response.items[0].key, b"test_scan-1");
+    assert_eq!(response.items[0].value, b"test_scan_value");
+
+    let request = ScanRequest::with_prefix("test_scan-")
+        .limit(2)
+        .continuation_token(response.continuation_token);
+    let response = client.scan(request).await.unwrap().into_inner();
+    assert_eq!(response.items.len(), 1);
+    assert_eq!(response.items[0].key, b"test_scan-3");
+    assert!(response.continuation_token.is_empty());
+}
+
+#[async_test_case]
+async fn test_dequeue_lease_success() {
+    let mut client = get_client().await;
+    let request = EnqueueRequest::new("test_lease_queue", "1");
+    assert!(client.enqueue(request).await.is_ok());
+
+    let request = DequeueRequest::new("test_lease_queue").lease(60);
+    let response = client.dequeue(request).await.unwrap().into_inner();
+    assert_eq!(response.value, b"1");
+    assert!(!response.receipt.is_empty());
+
+    let request = NackRequest::new("test_lease_queue", response.receipt);
+    assert!(client.nack(request).await.is_ok());
+    let request = PeekRequest::new("test_lease_queue", 10);
+    let response = client.peek(request).await.unwrap().into_inner();
+    assert_eq!(response.values, [b"1"]);
+
+    let request = DequeueRequest::new("test_lease_queue").lease(60);
+    let response = client.dequeue(request).await.unwrap().into_inner();
+    let request = AckRequest::new("test_lease_queue", response.receipt);
+    assert!(client.ack(request).await.is_ok());
+    let request = QueueLengthRequest::new("test_lease_queue");
+    let response = client.queue_length(request).await.unwrap().into_inner();
+    assert_eq!((response.length, response.leased), (0, 0));
+}
+
+#[async_test_case]
+async fn test_backup_success() {
+    let mut client = get_client().await;
+    let request = BackupRequest::new();
+    let mut stream = client.backup(request).await.unwrap().into_inner();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        chunks.push(chunk.data);
+    }
+    assert!(chunks.len() >= 2);
+
+    // The database is not empty, so the archive cannot be restored into it.
+    let requests: Vec<_> = chunks.into_iter().map(RestoreRequest::new).collect();
+    let response = client.restore(futures::stream::iter(requests)).await;
+    assert!(response.is_err());
+}
+
+#[async_test_case]
+async fn test_open_archive_key() {
+    let mut client = get_client().await;
+    let request = BackupRequest::new();
+    let mut stream = client.backup(request).await.unwrap().into_inner();
+    let header = stream.message().await.unwrap().unwrap().data;
+
+    // The sealed key follows the magic and the base iv in the header.
+    let request = OpenArchiveKeyRequest::new(&header[20..]);
+    let response = client.open_archive_key(request).await.unwrap().into_inner();
+    assert_eq!(response.key.len(), 32);
+
+    let request = OpenArchiveKeyRequest::new(&header[..20]);
+    assert!(client.open_archive_key(request).await.is_err());
+}
+
+#[async_test_case]
+async fn test_watch_success() {
+    let mut client = get_client().await;
+    let request = WatchRequest::new("test_watch_success");
+    let mut stream = client.watch(request).await.unwrap().into_inner();
+
+    let request = PutRequest::new("test_unwatched_key", "0");
+    assert!(client.put(request).await.is_ok());
+    let request = PutRequest::new("test_watch_success_key", "1");
+    assert!(client.put(request).await.is_ok());
+    let request = DeleteRequest::new("test_watch_success_key");
+    assert!(client.delete(request).await.is_ok());
+
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!(event, WatchEvent::put("test_watch_success_key", "1"));
+    let event = stream.message().await.unwrap().unwrap();
+    assert_eq!(event, WatchEvent::delete("test_watch_success_key"));
+}
+
+#[async_test_case]
+async fn test_put_ttl_success() {
+    let mut client = get_client().await;
+    let request = PutRequest::new("test_put_ttl_key", "1").ttl(3600);
+    assert!(client.put(request).await.is_ok());
+    let request = EnqueueRequest::new("test_enqueue_ttl_queue", "1").ttl(3600);
+    assert!(client.enqueue(request).await.is_ok());
+
+    // the keys are kept until they expire
+    let request = GetRequest::new("test_put_ttl_key");
+    let response = client.get(request).await.unwrap().into_inner();
+    assert_eq!(response.value, b"1");
+    let request = DequeueRequest::new("test_enqueue_ttl_queue");
+    let response = client.dequeue(request).await.unwrap().into_inner();
+    assert_eq!(response.value, b"1");
+}
+
+#[async_test_case]
+async fn test_replicate_success() {
+    let mut client = get_client().await;
+    let request = ReplicateRequest::default();
+    let mut stream = client.replicate(request).await.unwrap().into_inner();
+    let entry = stream.message().await.unwrap().unwrap();
+    assert!(entry.reset);
+
+    let request = PutRequest::new("test_replicate_key", "test_replicate_value");
+    assert!(client.put(request).await.is_ok());
+    loop {
+        let entry = stream.message().await.unwrap().unwrap();
+        let key = b"test_replicate_key";
+        if !entry.reset && entry.batch.windows(key.len()).any(|w| w == key) {
+            break;
+        }
+    }
+
+    // promoting the primary changes nothing
+    let request = PromoteRequest::default();
+    assert!(client.promote(request).await.is_ok());
+    let request = PutRequest::new("test_replicate_key", "1");
+    assert!(client.put(request).await.is_ok());
+}
+
+#[async_test_case]
+async fn test_stats_success() {
+    let mut client = get_client().await;
+    let request = StatsRequest::new()
+        .range("test_", "test`")
+        .range("test_", "test_");
+    let response = client.stats(request).await.unwrap().into_inner();
+    assert!(!response.files_at_level.is_empty());
+    assert!(response.sstables.starts_with("--- level 0 ---"));
+    assert_eq!(response.approximate_sizes.len(), 2);
+    // an empty range takes up no space
+    assert_eq!(response.approximate_sizes[1], 0);
+}
+
+#[async_test_case]
+async fn test_replicate_failover() {
+    let mut primary = get_client().await;
+    let mut follower = get_client_at(FOLLOWER_ADDRESS).await;
+    let request = PutRequest::new("test_failover_key", "1");
+    assert!(primary.put(request).await.is_ok());
+
+    // the follower catches up with the primary, and only serves reads
+    let mut replicated = false;
+    for _ in 0..10 {
+        let request = GetRequest::new("test_failover_key");
+        if let Ok(response) = follower.get(request).await {
+            if response.into_inner().value == b"1" {
+                replicated = true;
+                break;
+            }
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    assert!(replicated);
+    let request = PutRequest::new("test_failover_key", "2");
+    assert!(follower.put(request).await.is_err());
+
+    // fail over to the follower, which keeps the replicated data
+    let request = PromoteRequest::default();
+    assert!(follower.promote(request).await.is_ok());
+    let request = GetRequest::new("test_failover_key");
+    let response = follower.get(request).await.unwrap().into_inner();
+    assert_eq!(response.value, b"1");
+    let request = PutRequest::new("test_failover_key", "2");
+    assert!(follower.put(request).await.is_ok());
+
+    // the writes of the old primary no longer reach it
+    let request = PutRequest::new("test_failover_key", "3");
+    assert!(primary.put(request).await.is_ok());
+    std::thread::sleep(Duration::from_secs(2));
+    let request = GetRequest::new("test_failover_key");
+    let response = follower.get(request).await.unwrap().into_inner();
+    assert_eq!(response.value, b"2");
+}
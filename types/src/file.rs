@@ -36,6 +36,9 @@ pub struct TeaclaveInputFile {
     pub crypto_info: FileCrypto,
     pub owner: OwnerList,
     pub uuid: Uuid,
+    // set once the file is replaced by a newer version
+    #[serde(default)]
+    pub superseded_by: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -60,6 +63,7 @@ impl TeaclaveInputFile {
             crypto_info,
             owner: owner.into(),
             uuid: create_uuid(),
+            superseded_by: None,
         }
     }
 
@@ -72,6 +76,7 @@ impl TeaclaveInputFile {
             crypto_info: output.crypto_info,
             owner: output.owner,
             uuid: output.uuid,
+            superseded_by: None,
         };
         Ok(input)
     }
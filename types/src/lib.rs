@@ -25,6 +25,8 @@ mod file;
 mod file_agent;
 mod function;
 mod macros;
+mod recurring_task;
+mod scheduler;
 mod staged_file;
 mod staged_function;
 mod staged_task;
@@ -33,6 +35,7 @@ mod task;
 mod task_state;
 mod user;
 mod worker;
+mod workflow;
 
 pub use attestation::*;
 pub use audit::*;
@@ -42,6 +45,8 @@ pub use file::*;
 pub use file_agent::*;
 pub use function::*;
 pub use macros::*;
+pub use recurring_task::*;
+pub use scheduler::*;
 pub use staged_file::*;
 pub use staged_function::*;
 pub use staged_task::*;
@@ -50,12 +55,18 @@ pub use task::*;
 pub use task_state::*;
 pub use user::*;
 pub use worker::*;
+pub use workflow::*;
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
     use super::*;
 
     pub fn run_tests() -> bool {
-        worker::tests::run_tests()
+        recurring_task::tests::run_tests()
+            & scheduler::tests::run_tests()
+            & task_state::tests::run_tests()
+            & user::tests::run_tests()
+            & worker::tests::run_tests()
+            & workflow::tests::run_tests()
     }
 }
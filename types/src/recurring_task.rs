@@ -0,0 +1,444 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::*;
+use anyhow::{anyhow, bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use uuid::Uuid;
+
+const RECURRING_TASK_PREFIX: &str = "recurring";
+
+const SECS_PER_DAY: u64 = 86400;
+// Every date, including Feb 29th on a given weekday, comes back within 28
+// years.
+const MAX_CRON_SEARCH_DAYS: u64 = 366 * 28;
+
+/// When a recurring task runs.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub enum TaskSchedule {
+    // in seconds
+    Interval(u64),
+    // "minute hour day-of-month month day-of-week" in UTC
+    Cron(String),
+}
+
+impl TaskSchedule {
+    pub fn validate(&self) -> Result<()> {
+        self.next_after(UNIX_EPOCH).map(|_| ())
+    }
+
+    /// The first time strictly after the given time at which the task runs.
+    pub fn next_after(&self, time: SystemTime) -> Result<SystemTime> {
+        let secs = time.duration_since(UNIX_EPOCH)?.as_secs();
+        let next = match self {
+            TaskSchedule::Interval(interval) => {
+                ensure!(*interval > 0, "Schedule interval must be positive");
+                secs.checked_add(*interval)
+                    .ok_or_else(|| anyhow!("Schedule interval overflow"))?
+            }
+            TaskSchedule::Cron(expr) => CronExpr::parse(expr)?
+                .next_after(secs)
+                .ok_or_else(|| anyhow!("Cron expression never matches: {}", expr))?,
+        };
+        Ok(UNIX_EPOCH + Duration::from_secs(next))
+    }
+}
+
+// Each field is a bit set of the values it matches.
+struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    // Sunday is 0
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronExpr {
+    fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        ensure!(
+            fields.len() == 5,
+            "Cron expression must have 5 fields: {}",
+            expr
+        );
+
+        let mut weekdays = parse_cron_field(fields[4], 0, 7)?;
+        // both 0 and 7 stand for Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    // As in cron, a day matches either field if both fields are restricted.
+    fn matches_day(&self, days: u64) -> bool {
+        let (month, day) = month_and_day(days);
+        let weekday = (days + 4) % 7;
+        let day_matches = has_bit(self.days, day);
+        let weekday_matches = has_bit(self.weekdays, weekday);
+
+        has_bit(self.months, month)
+            && if self.days_restricted && self.weekdays_restricted {
+                day_matches || weekday_matches
+            } else {
+                day_matches && weekday_matches
+            }
+    }
+
+    fn next_after(&self, secs: u64) -> Option<u64> {
+        let start = secs / 60 * 60 + 60;
+        let first_day = start / SECS_PER_DAY;
+        for days in first_day..first_day + MAX_CRON_SEARCH_DAYS {
+            if !self.matches_day(days) {
+                continue;
+            }
+            let first_minute = if days == first_day {
+                start % SECS_PER_DAY / 60
+            } else {
+                0
+            };
+            for minute in first_minute..24 * 60 {
+                if has_bit(self.hours, minute / 60) && has_bit(self.minutes, minute % 60) {
+                    return Some(days * SECS_PER_DAY + minute * 60);
+                }
+            }
+        }
+        None
+    }
+}
+
+// A field is a comma separated list of "*", "a" or "a-b", each optionally
+// followed by "/step".
+fn parse_cron_field(field: &str, min: u64, max: u64) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>()?),
+            None => (part, 1),
+        };
+        ensure!(step > 0, "Invalid cron step: {}", part);
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse::<u64>()?, end.parse::<u64>()?)
+        } else {
+            let start = range.parse::<u64>()?;
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || start > end || end > max {
+            bail!("Invalid cron field: {}", field);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn has_bit(bits: u64, value: u64) -> bool {
+    bits & (1 << value) != 0
+}
+
+// Month and day of month of the given days since the Unix epoch, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn month_and_day(days: u64) -> (u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month, day)
+}
+
+/// An approved task which runs again on a schedule. Every run is a new task
+/// copied from the template task, with the input versions the participants
+/// have approved for the schedule and outputs of its own.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecurringTask {
+    pub id: Uuid,
+    pub task_id: Uuid,
+    pub creator: UserID,
+    pub schedule: TaskSchedule,
+    #[serde(default)]
+    pub participants: UserList,
+    #[serde(default)]
+    pub approved_users: UserList,
+    // map input name to the uuid of the approved version of the input file
+    #[serde(default)]
+    pub input_versions: HashMap<String, Uuid>,
+}
+
+impl Storable for RecurringTask {
+    fn key_prefix() -> &'static str {
+        RECURRING_TASK_PREFIX
+    }
+
+    fn uuid(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl RecurringTask {
+    pub fn new(creator: UserID, template: &TaskState, schedule: TaskSchedule) -> Result<Self> {
+        ensure!(
+            template.has_creator(&creator),
+            "Requestor is not the task creater"
+        );
+        ensure!(
+            template.workflow_id.is_none(),
+            "Workflow node cannot be a recurring task"
+        );
+        ensure!(
+            template.all_data_assigned() && template.everyone_approved(),
+            "Task is not approved"
+        );
+        schedule.validate()?;
+
+        let input_versions = template
+            .assigned_inputs
+            .clone()
+            .into_iter()
+            .map(|(fname, file)| (fname, file.uuid))
+            .collect();
+        let mut approved_users = UserList::default();
+        approved_users.insert(creator.clone());
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            task_id: template.task_id,
+            creator,
+            schedule,
+            participants: template.participants.clone(),
+            approved_users,
+            input_versions,
+        })
+    }
+
+    /// Approve the schedule with the given input versions. If they differ
+    /// from the versions approved so far, the previous approvals are
+    /// withdrawn and every participant has to approve again.
+    pub fn approve(
+        &mut self,
+        requester: &UserID,
+        input_versions: HashMap<String, Uuid>,
+    ) -> Result<()> {
+        ensure!(
+            self.participants.contains(requester),
+            "Unexpected user trying to approve a recurring task"
+        );
+        if self.input_versions != input_versions {
+            self.input_versions = input_versions;
+            self.approved_users = UserList::default();
+        }
+        self.approved_users.insert(requester.clone());
+        Ok(())
+    }
+
+    pub fn everyone_approved(&self) -> bool {
+        self.participants
+            .uids
+            .iter()
+            .all(|user| self.approved_users.contains(user))
+    }
+
+    /// Create a run of the template task with the approved versions of its
+    /// inputs and new outputs, see `run_output_url`. The run is approved on
+    /// behalf of the participants, who have approved the schedule, and
+    /// staged.
+    pub fn materialize(
+        &self,
+        template: &TaskState,
+        inputs: Vec<(String, TeaclaveInputFile)>,
+        function: Function,
+    ) -> Result<(TaskState, StagedTask)> {
+        ensure!(template.task_id == self.task_id, "Unexpected template task");
+        ensure!(
+            self.everyone_approved(),
+            "Recurring task is not approved by every participant"
+        );
+        ensure!(
+            inputs.len() == self.input_versions.len()
+                && inputs
+                    .iter()
+                    .all(|(fname, file)| self.input_versions.get(fname) == Some(&file.uuid)),
+            "Input files are not the approved versions"
+        );
+
+        let ts = TaskState {
+            task_id: Uuid::new_v4(),
+            creator: template.creator.clone(),
+            function_id: template.function_id.clone(),
+            function_arguments: template.function_arguments.clone(),
+            executor: template.executor,
+            inputs_ownership: template.inputs_ownership.clone(),
+            outputs_ownership: template.outputs_ownership.clone(),
+            function_owner: template.function_owner.clone(),
+            participants: template.participants.clone(),
+            approved_users: template.participants.clone(),
+            max_retries: template.max_retries,
+            max_execution_time: template.max_execution_time,
+            memory_size: template.memory_size,
+            priority: template.priority,
+            creator_attribute: template.creator_attribute.clone(),
+            created_at: crate::task_state::seconds_since_epoch(),
+            ..Default::default()
+        };
+
+        let run_id = ts.task_id;
+        let mut task: Task<Assign> = ts.try_into()?;
+        for (fname, file) in inputs {
+            task.assign_input_by_platform(&fname, file)?;
+        }
+        for (fname, file) in template.assigned_outputs.clone().into_iter() {
+            let url = run_output_url(&file.url, run_id)?;
+            let output = TeaclaveOutputFile::new(url, file.crypto_info, file.owner);
+            task.assign_output_by_platform(&fname, output)?;
+        }
+
+        let mut task: Task<Stage> = TaskState::from(task).try_into()?;
+        let staged_task = task.stage_for_running(&self.creator, function)?;
+        Ok((TaskState::from(task), staged_task))
+    }
+}
+
+/// Where a run writes the output which the template task writes to the given
+/// url, so that runs do not overwrite each other. A fusion output gets a new
+/// fusion file. Otherwise the run id is added to the file name, before its
+/// extension, e.g., "s3://bucket/result.enc" becomes
+/// "s3://bucket/result-<run_id>.enc".
+pub fn run_output_url(url: &Url, run_id: Uuid) -> Result<Url> {
+    if url.scheme() == "fusion" {
+        let url = format!("fusion:///TEACLAVE_FUSION_BASE/{}.fusion", Uuid::new_v4());
+        return Ok(Url::parse(&url)?);
+    }
+
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("Output url has no file name: {}", url))?;
+    let run_name = match name.split_once('.') {
+        Some((stem, extension)) => format!("{}-{}.{}", stem, run_id, extension),
+        None => format!("{}-{}", name, run_id),
+    };
+
+    let mut run_url = url.clone();
+    run_url
+        .path_segments_mut()
+        .map_err(|_| anyhow!("Invalid output url: {}", url))?
+        .pop()
+        .push(&run_name);
+    Ok(run_url)
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(
+            test_interval_schedule,
+            test_cron_schedule,
+            test_run_output_url,
+        )
+    }
+
+    fn next_after(schedule: &TaskSchedule, secs: u64) -> u64 {
+        schedule
+            .next_after(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn cron(expr: &str) -> TaskSchedule {
+        TaskSchedule::Cron(expr.to_string())
+    }
+
+    fn test_interval_schedule() {
+        let schedule = TaskSchedule::Interval(3600);
+        assert_eq!(next_after(&schedule, 100), 3700);
+        assert!(TaskSchedule::Interval(0).validate().is_err());
+    }
+
+    fn test_cron_schedule() {
+        // 1970-01-01 is a Thursday
+        assert_eq!(next_after(&cron("*/15 * * * *"), 0), 900);
+        assert_eq!(next_after(&cron("*/15 * * * *"), 900), 1800);
+        assert_eq!(next_after(&cron("0 2 * * *"), 0), 7200);
+        assert_eq!(next_after(&cron("0 2 * * *"), 7200), 7200 + SECS_PER_DAY);
+        assert_eq!(next_after(&cron("0 0 * * 1"), 0), 4 * SECS_PER_DAY);
+        assert_eq!(next_after(&cron("0 0 * * 7"), 0), 3 * SECS_PER_DAY);
+        assert_eq!(next_after(&cron("30 1 1 3 *"), 0), 59 * SECS_PER_DAY + 5400);
+        // 1972-02-29
+        assert_eq!(next_after(&cron("0 0 29 2 *"), 0), 789 * SECS_PER_DAY);
+        // either the 10th or a Monday
+        assert_eq!(next_after(&cron("0 0 10 * 1"), 0), 4 * SECS_PER_DAY);
+
+        assert!(cron("0 0 30 2 *").validate().is_err());
+        assert!(cron("60 * * * *").validate().is_err());
+        assert!(cron("* * * *").validate().is_err());
+        assert!(cron("*/0 * * * *").validate().is_err());
+        assert!(cron("5-1 * * * *").validate().is_err());
+    }
+
+    fn test_run_output_url() {
+        let run_id = Uuid::parse_str("00000000-0000-0000-0000-000000000001").unwrap();
+        let url = Url::parse("s3://bucket/path/result.enc?token=mock").unwrap();
+        assert_eq!(
+            run_output_url(&url, run_id).unwrap().as_str(),
+            "s3://bucket/path/result-00000000-0000-0000-0000-000000000001.enc?token=mock"
+        );
+        let url = Url::parse("file:///tmp/result").unwrap();
+        assert_eq!(
+            run_output_url(&url, run_id).unwrap().as_str(),
+            "file:///tmp/result-00000000-0000-0000-0000-000000000001"
+        );
+
+        let url = Url::parse("fusion:///TEACLAVE_FUSION_BASE/mock.fusion").unwrap();
+        let run_url = run_output_url(&url, run_id).unwrap();
+        assert_eq!(run_url.scheme(), "fusion");
+        assert_ne!(run_url, url);
+        assert_ne!(run_url, run_output_url(&url, run_id).unwrap());
+
+        let url = Url::parse("s3://bucket/").unwrap();
+        assert!(run_output_url(&url, run_id).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::{Storable, TaskPriority};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+const SCHEDULER_STATE_PREFIX: &str = "scheduler";
+const EXECUTOR_PREFIX: &str = "executor";
+
+/// Snapshot of the scheduler's bookkeeping which is persisted in the storage
+/// service, so that a restarted scheduler can pick up where it left off. The
+/// staged tasks themselves are saved separately under their own keys.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SchedulerState {
+    pub task_queue: VecDeque<Uuid>,
+    // map executor_id to the tasks running in its worker slots
+    #[serde(deserialize_with = "executors_tasks_from_state")]
+    pub executors_tasks: HashMap<Uuid, HashSet<Uuid>>,
+    pub tasks_to_cancel: HashSet<Uuid>,
+    // map task_id to the time when the task is re-queued
+    #[serde(default)]
+    pub pending_retries: HashMap<Uuid, SystemTime>,
+    // map task_id to the time when the running task is stopped
+    #[serde(default)]
+    pub task_deadlines: HashMap<Uuid, SystemTime>,
+    // queued tasks in the order they are handed out, for the admin queue view
+    #[serde(default)]
+    pub queued_tasks: Vec<QueuedTask>,
+    // map recurring_task_id to its runs
+    #[serde(default)]
+    pub recurring_runs: HashMap<Uuid, RecurringRun>,
+    // known executors, for the admin executor view
+    #[serde(default)]
+    pub executors: Vec<ExecutorInfo>,
+}
+
+/// A task waiting in the scheduler queue, together with what decides its turn:
+/// its priority class and the tasks of its share group which are running.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct QueuedTask {
+    pub task_id: Uuid,
+    pub creator: String,
+    pub priority: TaskPriority,
+    // creator or data owner attribute, executors are shared fairly by groups
+    pub share_group: String,
+    pub share_group_running: u32,
+}
+
+/// Runs of a recurring task so far, and when it is due to run next.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RecurringRun {
+    pub next_run: SystemTime,
+    pub last_task_id: Option<Uuid>,
+    pub runs: u32,
+}
+
+/// An executor known to the scheduler. A draining executor receives no new
+/// tasks, and is shut down once it finishes its current ones.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ExecutorInfo {
+    pub executor_id: Uuid,
+    pub last_heartbeat: SystemTime,
+    // whether the executor reported executing in its last heartbeat
+    pub executing: bool,
+    #[serde(default)]
+    pub task_ids: Vec<Uuid>,
+    pub draining: bool,
+    // number of tasks the executor runs at once
+    #[serde(default)]
+    pub slots: u32,
+}
+
+impl Storable for ExecutorInfo {
+    fn key_prefix() -> &'static str {
+        EXECUTOR_PREFIX
+    }
+
+    fn uuid(&self) -> Uuid {
+        self.executor_id
+    }
+}
+
+// States saved before executors had several worker slots map each executor to
+// a single task.
+fn executors_tasks_from_state<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<Uuid, HashSet<Uuid>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ExecutorTasks {
+        Single(Uuid),
+        Slots(HashSet<Uuid>),
+    }
+
+    let executors_tasks = HashMap::<Uuid, ExecutorTasks>::deserialize(deserializer)?;
+    Ok(executors_tasks
+        .into_iter()
+        .map(|(executor_id, tasks)| match tasks {
+            ExecutorTasks::Single(task_id) => (executor_id, std::iter::once(task_id).collect()),
+            ExecutorTasks::Slots(task_ids) => (executor_id, task_ids),
+        })
+        .collect())
+}
+
+impl Storable for SchedulerState {
+    fn key_prefix() -> &'static str {
+        SCHEDULER_STATE_PREFIX
+    }
+
+    // There is only one scheduler state in the platform.
+    fn uuid(&self) -> Uuid {
+        Uuid::nil()
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_executors_tasks_from_state,)
+    }
+
+    fn test_executors_tasks_from_state() {
+        let executor_id = Uuid::new_v4();
+        let task_id = Uuid::new_v4();
+        let state = serde_json::json!({
+            "task_queue": [],
+            "executors_tasks": { executor_id.to_string(): task_id.to_string() },
+            "tasks_to_cancel": [],
+        });
+        let state = SchedulerState::from_slice(state.to_string().as_bytes()).unwrap();
+        assert_eq!(
+            state.executors_tasks.get(&executor_id),
+            Some(&std::iter::once(task_id).collect())
+        );
+
+        let restored = SchedulerState::from_slice(&state.to_vec().unwrap()).unwrap();
+        assert_eq!(restored.executors_tasks, state.executors_tasks);
+    }
+}
@@ -23,7 +23,7 @@ use url::Url;
 use uuid::Uuid;
 
 use crate::{
-    Executor, ExecutorType, FileAuthTag, FileCrypto, FunctionArguments, Storable,
+    Executor, ExecutorType, FileAuthTag, FileCrypto, FunctionArguments, Storable, TaskPriority,
     TeaclaveInputFile, TeaclaveOutputFile,
 };
 
@@ -186,6 +186,17 @@ pub struct StagedTask {
     pub function_payload: Vec<u8>,
     pub input_data: FunctionInputFiles,
     pub output_data: FunctionOutputFiles,
+    // in seconds, 0 means the platform default
+    #[serde(default)]
+    pub max_execution_time: u64,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    // attribute of the user's data owner role
+    #[serde(default)]
+    pub user_attribute: String,
+    // memory the task needs in bytes, 0 means no requirement
+    #[serde(default)]
+    pub memory_size: u64,
 }
 
 impl Storable for StagedTask {
@@ -266,6 +277,26 @@ impl StagedTaskBuilder {
         self
     }
 
+    pub fn max_execution_time(mut self, max_execution_time: u64) -> Self {
+        self.task.max_execution_time = max_execution_time;
+        self
+    }
+
+    pub fn priority(mut self, priority: TaskPriority) -> Self {
+        self.task.priority = priority;
+        self
+    }
+
+    pub fn user_attribute(mut self, user_attribute: impl ToString) -> Self {
+        self.task.user_attribute = user_attribute.to_string();
+        self
+    }
+
+    pub fn memory_size(mut self, memory_size: u64) -> Self {
+        self.task.memory_size = memory_size;
+        self
+    }
+
     pub fn build(self) -> StagedTask {
         self.task
     }
@@ -20,6 +20,9 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 pub const CANCEL_QUEUE_KEY: &str = "cancel_queue";
+pub const DRAIN_QUEUE_KEY: &str = "drain_queue";
+// Recurring tasks which may be ready to run, e.g. after an approval
+pub const RECURRING_QUEUE_KEY: &str = "recurring_queue";
 
 pub trait Storable: Serialize + for<'de> Deserialize<'de> {
     fn key_prefix() -> &'static str;
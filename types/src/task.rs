@@ -152,6 +152,22 @@ impl Default for TaskStatus {
     }
 }
 
+/// Priority class of a task. Queued tasks of a higher class are always handed
+/// out before the ones of a lower class.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TaskPriority {
+    Low,
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Default for TaskPriority {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct OutputsTags {
     inner: HashMap<String, FileAuthTag>,
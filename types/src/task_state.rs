@@ -20,6 +20,7 @@ use anyhow::{bail, ensure, Error, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 const TASK_PREFIX: &str = "task";
@@ -40,6 +41,45 @@ pub struct TaskState {
     pub assigned_outputs: TaskFiles<TeaclaveOutputFile>,
     pub result: TaskResult,
     pub status: TaskStatus,
+    // number of times the task is re-queued after its execution is lost
+    #[serde(default)]
+    pub max_retries: u32,
+    #[serde(default)]
+    pub attempts: Vec<TaskAttempt>,
+    // in seconds, 0 means the platform default
+    #[serde(default)]
+    pub max_execution_time: u64,
+    #[serde(default)]
+    pub priority: TaskPriority,
+    // attribute of the creator's data owner role, used for fair sharing
+    #[serde(default)]
+    pub creator_attribute: String,
+    // set if the task is a node of a workflow
+    #[serde(default)]
+    pub workflow_id: Option<Uuid>,
+    // memory the task needs in bytes, 0 means no requirement
+    #[serde(default)]
+    pub memory_size: u64,
+    // seconds since the epoch, 0 for tasks created before it was recorded
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+/// An execution of the task which has been lost, e.g., the executor running
+/// it stopped sending heartbeats.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct TaskAttempt {
+    pub executor_id: Uuid,
+    pub reason: String,
+}
+
+impl TaskAttempt {
+    pub fn new(executor_id: Uuid, reason: impl ToString) -> Self {
+        Self {
+            executor_id,
+            reason: reason.to_string(),
+        }
+    }
 }
 
 impl Storable for TaskState {
@@ -82,6 +122,15 @@ impl TaskState {
         &self.creator == user_id
     }
 
+    // Nodes of a workflow are approved along with the workflow instead.
+    pub fn is_pending_approval_of(&self, user_id: &UserID) -> bool {
+        matches!(self.status, TaskStatus::Created | TaskStatus::DataAssigned)
+            && self.workflow_id.is_none()
+            && self.has_participant(user_id)
+            && !self.approved_users.contains(user_id)
+            && !self.everyone_approved()
+    }
+
     pub fn is_ended(&self) -> bool {
         matches!(
             self.status,
@@ -90,6 +139,13 @@ impl TaskState {
     }
 }
 
+pub(crate) fn seconds_since_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
 #[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct Task<S: StateTag> {
     state: TaskState,
@@ -106,6 +162,7 @@ impl StateTag for Finish {}
 impl StateTag for Done {}
 impl StateTag for Cancel {}
 impl StateTag for Fail {}
+impl StateTag for Retry {}
 
 impl Task<Create> {
     pub fn new(
@@ -185,6 +242,7 @@ impl Task<Create> {
             inputs_ownership: req_input_owners,
             outputs_ownership: req_output_owners,
             participants,
+            created_at: seconds_since_epoch(),
             ..Default::default()
         };
 
@@ -193,6 +251,34 @@ impl Task<Create> {
             extra: Create,
         })
     }
+
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.state.max_retries = max_retries;
+    }
+
+    pub fn set_max_execution_time(&mut self, max_execution_time: u64) {
+        self.state.max_execution_time = max_execution_time;
+    }
+
+    pub fn set_memory_size(&mut self, memory_size: u64) {
+        self.state.memory_size = memory_size;
+    }
+
+    // The requested priority must be allowed for the creator's role.
+    pub fn set_priority(&mut self, priority: TaskPriority, role: &UserRole) -> Result<()> {
+        ensure!(
+            role.allows_task_priority(priority),
+            "Task priority {:?} is not allowed for role {}",
+            priority,
+            role
+        );
+        self.state.priority = priority;
+        Ok(())
+    }
+
+    pub fn set_creator_attribute(&mut self, attribute: impl ToString) {
+        self.state.creator_attribute = attribute.to_string();
+    }
 }
 
 impl Task<Assign> {
@@ -237,6 +323,25 @@ impl Task<Assign> {
         self.state.assigned_outputs.assign(fname, file)?;
         Ok(())
     }
+
+    // Data carried between workflow nodes, or refreshed for a run of a
+    // recurring task, is assigned by the platform rather than by one of its
+    // owners.
+    pub fn assign_input_by_platform(&mut self, fname: &str, file: TeaclaveInputFile) -> Result<()> {
+        self.state.inputs_ownership.check(fname, &file.owner)?;
+        self.state.assigned_inputs.assign(fname, file)?;
+        Ok(())
+    }
+
+    pub fn assign_output_by_platform(
+        &mut self,
+        fname: &str,
+        file: TeaclaveOutputFile,
+    ) -> Result<()> {
+        self.state.outputs_ownership.check(fname, &file.owner)?;
+        self.state.assigned_outputs.assign(fname, file)?;
+        Ok(())
+    }
 }
 
 impl Task<Approve> {
@@ -291,6 +396,10 @@ impl Task<Stage> {
             function_arguments,
             input_data: self.state.assigned_inputs.clone().into(),
             output_data: self.state.assigned_outputs.clone().into(),
+            max_execution_time: self.state.max_execution_time,
+            priority: self.state.priority,
+            user_attribute: self.state.creator_attribute.clone(),
+            memory_size: self.state.memory_size,
         };
         Ok(staged_task)
     }
@@ -361,6 +470,29 @@ impl Task<Fail> {
     }
 }
 
+impl Task<Retry> {
+    pub fn new(ts: TaskState) -> Result<Self> {
+        let task = Task::<Retry> {
+            state: ts,
+            extra: Retry,
+        };
+        Ok(task)
+    }
+
+    pub fn record_attempt(&mut self, attempt: TaskAttempt) {
+        self.state.attempts.push(attempt);
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.state.attempts.len() as u32
+    }
+
+    // The first attempt is not a retry.
+    pub fn can_retry(&self) -> bool {
+        self.attempts() <= self.state.max_retries
+    }
+}
+
 impl Task<Cancel> {
     pub fn new(ts: TaskState) -> Result<Self> {
         let task = Task::<Cancel> {
@@ -508,13 +640,30 @@ impl std::convert::TryFrom<TaskState> for Task<Fail> {
 
     fn try_from(ts: TaskState) -> Result<Self> {
         let task = match ts.status {
-            TaskStatus::Running | TaskStatus::Staged => Task::<Fail>::new(ts)?,
+            // Nodes of a workflow fail before running when an upstream node fails.
+            TaskStatus::Running
+            | TaskStatus::Staged
+            | TaskStatus::Approved
+            | TaskStatus::Created
+            | TaskStatus::DataAssigned => Task::<Fail>::new(ts)?,
             _ => bail!("Cannot restore to Fail from saved state"),
         };
         Ok(task)
     }
 }
 
+impl std::convert::TryFrom<TaskState> for Task<Retry> {
+    type Error = Error;
+
+    fn try_from(ts: TaskState) -> Result<Self> {
+        let task = match ts.status {
+            TaskStatus::Running | TaskStatus::Staged => Task::<Retry>::new(ts)?,
+            _ => bail!("Cannot restore to Retry from saved state"),
+        };
+        Ok(task)
+    }
+}
+
 impl std::convert::TryFrom<TaskState> for Task<Cancel> {
     type Error = Error;
 
@@ -545,6 +694,14 @@ impl std::convert::From<Task<Fail>> for TaskState {
     }
 }
 
+// A retried task waits to be run again.
+impl std::convert::From<Task<Retry>> for TaskState {
+    fn from(mut task: Task<Retry>) -> TaskState {
+        task.state.status = TaskStatus::Staged;
+        task.state
+    }
+}
+
 impl std::convert::From<Task<Cancel>> for TaskState {
     fn from(mut task: Task<Cancel>) -> TaskState {
         task.state.status = TaskStatus::Canceled;
@@ -594,6 +751,8 @@ pub struct Done;
 pub struct Cancel;
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Fail;
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Retry;
 
 impl std::convert::From<Create> for TaskStatus {
     fn from(_tag: Create) -> TaskStatus {
@@ -636,3 +795,45 @@ impl std::convert::From<Done> for TaskStatus {
         TaskStatus::Finished
     }
 }
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_retry_budget,)
+    }
+
+    fn test_retry_budget() {
+        let ts = TaskState {
+            task_id: Uuid::new_v4(),
+            status: TaskStatus::Running,
+            max_retries: 2,
+            ..Default::default()
+        };
+        let executor_id = Uuid::new_v4();
+
+        let mut task: Task<Retry> = ts.try_into().unwrap();
+        task.record_attempt(TaskAttempt::new(executor_id, "Executor Timeout"));
+        assert!(task.can_retry());
+
+        // The retried task waits to run again, and keeps its attempts.
+        let ts = TaskState::from(task);
+        assert_eq!(ts.status, TaskStatus::Staged);
+        assert_eq!(ts.attempts.len(), 1);
+
+        let mut task: Task<Retry> = ts.try_into().unwrap();
+        task.record_attempt(TaskAttempt::new(executor_id, "Executor Timeout"));
+        assert!(task.can_retry());
+        task.record_attempt(TaskAttempt::new(executor_id, "Executor Timeout"));
+        assert!(!task.can_retry());
+
+        let ts = TaskState {
+            status: TaskStatus::Finished,
+            ..Default::default()
+        };
+        let task: Result<Task<Retry>> = ts.try_into();
+        assert!(task.is_err());
+    }
+}
@@ -14,6 +14,7 @@
 // KIND, either express or implied.  See the License for the
 // specific language governing permissions and limitations
 // under the License.
+use crate::TaskPriority;
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -72,6 +73,20 @@ impl UserRole {
     pub fn is_data_owner(&self) -> bool {
         matches!(self, UserRole::DataOwnerManager(_)) || matches!(self, UserRole::DataOwner(_))
     }
+
+    // Highest priority class of the tasks created by the role
+    pub fn max_task_priority(&self) -> TaskPriority {
+        match self {
+            UserRole::PlatformAdmin => TaskPriority::Urgent,
+            UserRole::DataOwnerManager(_) => TaskPriority::High,
+            UserRole::DataOwner(_) => TaskPriority::Normal,
+            _ => TaskPriority::Low,
+        }
+    }
+
+    pub fn allows_task_priority(&self, priority: TaskPriority) -> bool {
+        priority <= self.max_task_priority()
+    }
 }
 
 impl fmt::Display for UserRole {
@@ -109,3 +124,27 @@ impl std::fmt::Display for UserAuthClaims {
         write!(f, "{{ user: {}, role: {:?} }}", self.sub, self.get_role())
     }
 }
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_max_task_priority,)
+    }
+
+    fn test_max_task_priority() {
+        let admin = UserRole::PlatformAdmin;
+        let manager = UserRole::DataOwnerManager("org".to_string());
+        let owner = UserRole::DataOwner("org".to_string());
+
+        assert_eq!(admin.max_task_priority(), TaskPriority::Urgent);
+        assert_eq!(manager.max_task_priority(), TaskPriority::High);
+        assert_eq!(owner.max_task_priority(), TaskPriority::Normal);
+        assert_eq!(UserRole::Invalid.max_task_priority(), TaskPriority::Low);
+        assert!(owner.allows_task_priority(TaskPriority::Normal));
+        assert!(!owner.allows_task_priority(TaskPriority::High));
+        assert!(admin.allows_task_priority(TaskPriority::Urgent));
+    }
+}
@@ -15,7 +15,7 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::{FunctionArguments, FunctionRuntime, OutputsTags};
+use crate::{FunctionArguments, FunctionRuntime, OutputsTags, StagedTask};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::convert::TryInto;
@@ -132,10 +132,39 @@ impl std::fmt::Display for Executor {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
 pub struct WorkerCapability {
     pub runtimes: HashSet<String>,
     pub executors: HashSet<String>,
+    // names of the functions compiled into the builtin executor
+    pub builtin_functions: HashSet<String>,
+    // memory available to each worker slot in bytes
+    pub memory_size: u64,
+    // number of tasks the worker runs at once, 0 for workers which do not
+    // report it and run one task
+    #[serde(default)]
+    pub slots: u32,
+}
+
+impl WorkerCapability {
+    /// Whether a worker with this capability has the executor, the memory,
+    /// and for builtin tasks the function, which the task needs.
+    pub fn can_run(&self, task: &StagedTask) -> bool {
+        if !self.executors.contains(&task.executor.to_string())
+            || task.memory_size > self.memory_size
+        {
+            return false;
+        }
+        match task.executor {
+            Executor::Builtin => self.builtin_functions.contains(&task.function_name),
+            _ => true,
+        }
+    }
+
+    /// Number of tasks which a worker with this capability runs at once.
+    pub fn concurrent_tasks(&self) -> usize {
+        self.slots.max(1) as usize
+    }
 }
 
 #[derive(Debug, Default)]
@@ -146,7 +175,54 @@ pub struct ExecutionResult {
 
 #[cfg(feature = "enclave_unit_test")]
 pub mod tests {
+    use super::*;
+    use crate::StagedTaskBuilder;
+    use teaclave_test_utils::*;
+
     pub fn run_tests() -> bool {
-        true
+        run_tests!(test_worker_capability_can_run,)
+    }
+
+    fn test_worker_capability_can_run() {
+        let capability = WorkerCapability {
+            executors: ["builtin".to_string()].into_iter().collect(),
+            builtin_functions: ["builtin-echo".to_string()].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let task = StagedTaskBuilder::new()
+            .executor(Executor::Builtin)
+            .function_name("builtin-echo")
+            .build();
+        assert!(capability.can_run(&task));
+
+        let task = StagedTaskBuilder::new()
+            .executor(Executor::Builtin)
+            .function_name("builtin-gbdt-train")
+            .build();
+        assert!(!capability.can_run(&task));
+
+        let task = StagedTaskBuilder::new()
+            .executor(Executor::MesaPy)
+            .function_name("entrypoint")
+            .build();
+        assert!(!capability.can_run(&task));
+        let capability = WorkerCapability {
+            memory_size: 0x1000_0000,
+            ..capability
+        };
+        let task = StagedTaskBuilder::new()
+            .executor(Executor::Builtin)
+            .function_name("builtin-echo")
+            .memory_size(0x1000_0000)
+            .build();
+        assert!(capability.can_run(&task));
+
+        let task = StagedTaskBuilder::new()
+            .executor(Executor::Builtin)
+            .function_name("builtin-echo")
+            .memory_size(0x1000_0001)
+            .build();
+        assert!(!capability.can_run(&task));
     }
 }
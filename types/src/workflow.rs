@@ -0,0 +1,403 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::*;
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use uuid::Uuid;
+
+const WORKFLOW_PREFIX: &str = "workflow";
+
+/// A function invocation in a workflow, backed by an ordinary task.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct WorkflowNode {
+    pub name: String,
+    pub task_id: Uuid,
+}
+
+impl WorkflowNode {
+    pub fn new(name: impl ToString, task_id: Uuid) -> Self {
+        Self {
+            name: name.to_string(),
+            task_id,
+        }
+    }
+}
+
+/// Data flowing from an output of one node to an input of another one,
+/// through a fusion file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct WorkflowEdge {
+    pub from_node: String,
+    pub output: String,
+    pub to_node: String,
+    pub input: String,
+    // uuid of the fusion output file, registered with the workflow
+    pub data_id: Uuid,
+}
+
+impl WorkflowEdge {
+    pub fn new(
+        from_node: impl ToString,
+        output: impl ToString,
+        to_node: impl ToString,
+        input: impl ToString,
+    ) -> Self {
+        Self {
+            from_node: from_node.to_string(),
+            output: output.to_string(),
+            to_node: to_node.to_string(),
+            input: input.to_string(),
+            data_id: Uuid::nil(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum WorkflowStatus {
+    Created,
+    Running,
+    Finished,
+    Failed,
+}
+
+impl Default for WorkflowStatus {
+    fn default() -> Self {
+        Self::Created
+    }
+}
+
+/// A DAG of tasks which is approved once by all participants. The scheduler
+/// stages each node when all of its upstream nodes have finished.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Workflow {
+    pub id: Uuid,
+    pub creator: UserID,
+    pub nodes: Vec<WorkflowNode>,
+    pub edges: Vec<WorkflowEdge>,
+    pub participants: UserList,
+    pub approved_users: UserList,
+    pub status: WorkflowStatus,
+}
+
+impl Storable for Workflow {
+    fn key_prefix() -> &'static str {
+        WORKFLOW_PREFIX
+    }
+
+    fn uuid(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl Workflow {
+    pub fn new(
+        creator: UserID,
+        nodes: Vec<WorkflowNode>,
+        edges: Vec<WorkflowEdge>,
+    ) -> Result<Self> {
+        ensure!(!nodes.is_empty(), "Workflow has no nodes");
+
+        let names: HashSet<&String> = nodes.iter().map(|n| &n.name).collect();
+        ensure!(
+            names.len() == nodes.len(),
+            "Workflow node names are not unique"
+        );
+
+        let mut inputs = HashSet::new();
+        for edge in edges.iter() {
+            ensure!(
+                names.contains(&edge.from_node) && names.contains(&edge.to_node),
+                "Workflow edge refers to an unknown node. {:?}",
+                edge
+            );
+            ensure!(
+                inputs.insert((&edge.to_node, &edge.input)),
+                "Workflow node input is fed by more than one edge. {:?}",
+                edge
+            );
+        }
+
+        let workflow = Self {
+            id: Uuid::new_v4(),
+            creator,
+            nodes,
+            edges,
+            ..Default::default()
+        };
+        ensure!(workflow.is_acyclic(), "Workflow has a cycle");
+        Ok(workflow)
+    }
+
+    // Kahn's algorithm: every node is visited iff there is no cycle.
+    fn is_acyclic(&self) -> bool {
+        let mut in_degree: HashMap<&str, usize> =
+            self.nodes.iter().map(|n| (n.name.as_str(), 0)).collect();
+        for edge in self.edges.iter() {
+            *in_degree.entry(edge.to_node.as_str()).or_insert(0) += 1;
+        }
+
+        let mut ready: VecDeque<&str> = in_degree
+            .iter()
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(name, _)| *name)
+            .collect();
+        let mut visited = 0;
+        while let Some(name) = ready.pop_front() {
+            visited += 1;
+            for edge in self.edges.iter().filter(|e| e.from_node == name) {
+                let degree = in_degree.entry(edge.to_node.as_str()).or_insert(0);
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push_back(edge.to_node.as_str());
+                }
+            }
+        }
+        visited == self.nodes.len()
+    }
+
+    pub fn has_creator(&self, user_id: &UserID) -> bool {
+        &self.creator == user_id
+    }
+
+    pub fn has_participant(&self, user_id: &UserID) -> bool {
+        self.participants.contains(user_id)
+    }
+
+    pub fn everyone_approved(&self) -> bool {
+        self.participants.len() == 1 || self.participants == self.approved_users
+    }
+
+    pub fn approve(&mut self, requester: &UserID) -> Result<()> {
+        ensure!(
+            self.status == WorkflowStatus::Created,
+            "Workflow has already been invoked"
+        );
+        ensure!(
+            self.has_participant(requester),
+            "Unexpected user trying to approve a workflow: {:?}",
+            requester
+        );
+        self.approved_users.insert(requester.clone());
+        Ok(())
+    }
+
+    pub fn is_ended(&self) -> bool {
+        matches!(
+            self.status,
+            WorkflowStatus::Finished | WorkflowStatus::Failed
+        )
+    }
+
+    pub fn node(&self, name: &str) -> Option<&WorkflowNode> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    pub fn node_of_task(&self, task_id: &Uuid) -> Option<&WorkflowNode> {
+        self.nodes.iter().find(|n| &n.task_id == task_id)
+    }
+
+    // Nodes which do not wait for any other node
+    pub fn root_nodes(&self) -> Vec<&WorkflowNode> {
+        self.nodes
+            .iter()
+            .filter(|n| self.incoming_edges(&n.name).next().is_none())
+            .collect()
+    }
+
+    pub fn incoming_edges<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a WorkflowEdge> {
+        self.edges.iter().filter(move |e| e.to_node == name)
+    }
+
+    pub fn upstream_nodes(&self, name: &str) -> Vec<&WorkflowNode> {
+        let names: HashSet<&str> = self
+            .incoming_edges(name)
+            .map(|e| e.from_node.as_str())
+            .collect();
+        self.nodes
+            .iter()
+            .filter(|n| names.contains(n.name.as_str()))
+            .collect()
+    }
+
+    pub fn downstream_nodes(&self, name: &str) -> Vec<&WorkflowNode> {
+        let names: HashSet<&str> = self
+            .edges
+            .iter()
+            .filter(|e| e.from_node == name)
+            .map(|e| e.to_node.as_str())
+            .collect();
+        self.nodes
+            .iter()
+            .filter(|n| names.contains(n.name.as_str()))
+            .collect()
+    }
+
+    // All nodes which directly or indirectly wait for the node
+    pub fn descendants(&self, name: &str) -> Vec<&WorkflowNode> {
+        let mut visited: HashSet<&str> = HashSet::new();
+        let mut pending = vec![name];
+        while let Some(current) = pending.pop() {
+            for node in self.downstream_nodes(current) {
+                if visited.insert(node.name.as_str()) {
+                    pending.push(node.name.as_str());
+                }
+            }
+        }
+        self.nodes
+            .iter()
+            .filter(|n| visited.contains(n.name.as_str()))
+            .collect()
+    }
+
+    // Whether all data of the node task is assigned, except the inputs which
+    // its upstream nodes produce.
+    pub fn node_data_assigned(&self, name: &str, ts: &TaskState) -> bool {
+        let edge_inputs: HashSet<&String> = self.incoming_edges(name).map(|e| &e.input).collect();
+        let assigned_inputs: HashSet<&String> = ts.assigned_inputs.keys().collect();
+        let inputs_assigned = ts
+            .inputs_ownership
+            .keys()
+            .all(|k| edge_inputs.contains(k) || assigned_inputs.contains(k));
+
+        let assigned_outputs: HashSet<&String> = ts.assigned_outputs.keys().collect();
+        let outputs_assigned = ts
+            .outputs_ownership
+            .keys()
+            .all(|k| assigned_outputs.contains(k));
+
+        inputs_assigned && outputs_assigned
+    }
+
+    /// Assign the inputs produced by the upstream nodes to the node task,
+    /// approve it on behalf of its participants, who have approved the whole
+    /// workflow, and stage it for running.
+    pub fn stage_node(
+        &self,
+        ts: TaskState,
+        inputs: Vec<(String, TeaclaveInputFile)>,
+        function: Function,
+    ) -> Result<(TaskState, StagedTask)> {
+        ensure!(
+            self.everyone_approved(),
+            "Workflow is not approved by every participant"
+        );
+        let creator = ts.creator.clone();
+        let participants = ts.participants.clone();
+
+        let ts = if inputs.is_empty() {
+            ts
+        } else {
+            let mut task: Task<Assign> = ts.try_into()?;
+            for (fname, file) in inputs {
+                task.assign_input_by_platform(&fname, file)?;
+            }
+            TaskState::from(task)
+        };
+
+        let mut task: Task<Approve> = ts.try_into()?;
+        for user_id in participants {
+            task.approve(&user_id)?;
+        }
+
+        let mut task: Task<Stage> = TaskState::from(task).try_into()?;
+        let staged_task = task.stage_for_running(&creator, function)?;
+        Ok((TaskState::from(task), staged_task))
+    }
+
+    /// Fail a node task which will never run because one of its upstream
+    /// nodes has failed or been canceled.
+    pub fn fail_node(ts: TaskState, upstream: &str) -> Result<TaskState> {
+        let mut task: Task<Fail> = ts.try_into()?;
+        task.update_result(TaskResult::Err(TaskFailure::new(format!(
+            "Upstream node {} failed",
+            upstream
+        ))))?;
+        Ok(TaskState::from(task))
+    }
+}
+
+#[cfg(feature = "enclave_unit_test")]
+pub mod tests {
+    use super::*;
+    use teaclave_test_utils::*;
+
+    pub fn run_tests() -> bool {
+        run_tests!(test_workflow_validation, test_workflow_traversal,)
+    }
+
+    fn nodes(names: &[&str]) -> Vec<WorkflowNode> {
+        names
+            .iter()
+            .map(|name| WorkflowNode::new(name, Uuid::new_v4()))
+            .collect()
+    }
+
+    fn test_workflow_validation() {
+        let creator = UserID::from("mock_user");
+
+        let edges = vec![WorkflowEdge::new("a", "out", "b", "in")];
+        assert!(Workflow::new(creator.clone(), nodes(&["a", "b"]), edges).is_ok());
+
+        let edges = vec![WorkflowEdge::new("a", "out", "c", "in")];
+        assert!(Workflow::new(creator.clone(), nodes(&["a", "b"]), edges).is_err());
+
+        assert!(Workflow::new(creator.clone(), nodes(&["a", "a"]), vec![]).is_err());
+
+        let edges = vec![
+            WorkflowEdge::new("a", "out", "c", "in"),
+            WorkflowEdge::new("b", "out", "c", "in"),
+        ];
+        assert!(Workflow::new(creator.clone(), nodes(&["a", "b", "c"]), edges).is_err());
+
+        let edges = vec![
+            WorkflowEdge::new("a", "out", "b", "in"),
+            WorkflowEdge::new("b", "out", "a", "in"),
+        ];
+        assert!(Workflow::new(creator, nodes(&["a", "b"]), edges).is_err());
+    }
+
+    fn test_workflow_traversal() {
+        // a -> b -> d, a -> c
+        let edges = vec![
+            WorkflowEdge::new("a", "out", "b", "in"),
+            WorkflowEdge::new("a", "out", "c", "in"),
+            WorkflowEdge::new("b", "out", "d", "in"),
+        ];
+        let workflow = Workflow::new(
+            UserID::from("mock_user"),
+            nodes(&["a", "b", "c", "d"]),
+            edges,
+        )
+        .unwrap();
+
+        let names = |nodes: Vec<&WorkflowNode>| -> HashSet<String> {
+            nodes.into_iter().map(|n| n.name.clone()).collect()
+        };
+        assert_eq!(names(workflow.root_nodes()), hashset(&["a"]));
+        assert_eq!(names(workflow.upstream_nodes("d")), hashset(&["b"]));
+        assert_eq!(names(workflow.downstream_nodes("a")), hashset(&["b", "c"]));
+        assert_eq!(names(workflow.descendants("a")), hashset(&["b", "c", "d"]));
+        assert!(workflow.descendants("d").is_empty());
+    }
+
+    fn hashset(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+}
@@ -15,11 +15,11 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::format;
 
 use teaclave_runtime::DefaultRuntime;
-use teaclave_types::{Executor, ExecutorType, StagedFiles, StagedFunction};
+use teaclave_types::{Executor, ExecutorType, StagedFiles, StagedFunction, WorkerCapability};
 use teaclave_types::{TeaclaveExecutor, TeaclaveRuntime};
 
 type BoxedTeaclaveExecutor = Box<dyn TeaclaveExecutor + Send + Sync>;
@@ -81,6 +81,39 @@ impl Worker {
         self.executors.insert(key, builder);
     }
 
+    /// Runtimes, executors and builtin functions available in this worker.
+    /// The memory size is left to the caller, which knows its environment.
+    pub fn capability(&self) -> WorkerCapability {
+        let runtimes = self.runtimes.keys().cloned().collect();
+        let executors = self
+            .executors
+            .keys()
+            .map(|(_, executor)| executor.to_string())
+            .collect();
+
+        #[allow(unused_mut)]
+        let mut builtin_functions = HashSet::new();
+        #[cfg(executor_builtin)]
+        if self
+            .executors
+            .contains_key(&(ExecutorType::Builtin, Executor::Builtin))
+        {
+            builtin_functions.extend(
+                teaclave_executor::BuiltinFunctionExecutor::functions()
+                    .into_iter()
+                    .map(|name| name.to_string()),
+            );
+        }
+
+        WorkerCapability {
+            runtimes,
+            executors,
+            builtin_functions,
+            memory_size: 0,
+            slots: 0,
+        }
+    }
+
     pub fn invoke_function(&self, function: StagedFunction) -> anyhow::Result<String> {
         let executor = self.get_executor(function.executor_type, function.executor)?;
         let runtime = self.get_runtime(